@@ -332,6 +332,11 @@ struct rr_input_mask {
 #define MAP_SYNC  0x80000
 #endif
 
+// New in the 6.6 kernel
+#ifndef ARCH_SHSTK_ENABLE
+#define ARCH_SHSTK_ENABLE 0x5001
+#endif
+
 enum {
   BPF_MAP_CREATE,
   BPF_MAP_LOOKUP_ELEM,
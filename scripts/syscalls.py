@@ -1474,8 +1474,8 @@ get_robust_list = EmulatedSyscall(x86=312, x64=274, arg2="typename Arch::unsigne
 splice = IrregularEmulatedSyscall(x86=313, x64=275)
 
 sync_file_range = IrregularEmulatedSyscall(x86=314, x64=277)
-tee = UnsupportedSyscall(x86=315, x64=276)
-vmsplice = UnsupportedSyscall(x86=316, x64=278)
+tee = IrregularEmulatedSyscall(x86=315, x64=276)
+vmsplice = IrregularEmulatedSyscall(x86=316, x64=278)
 move_pages = UnsupportedSyscall(x86=317, x64=279)
 getcpu = EmulatedSyscall(x86=318, x64=309, arg1="unsigned int", arg2="unsigned int")
 epoll_pwait = IrregularEmulatedSyscall(x86=319, x64=281)
@@ -1688,19 +1688,21 @@ fsopen = UnsupportedSyscall(x86=430, x64=430)
 fsconfig = UnsupportedSyscall(x86=431, x64=431)
 fsmount = UnsupportedSyscall(x86=432, x64=432)
 fspick = UnsupportedSyscall(x86=433, x64=433)
+close_range = IrregularEmulatedSyscall(x86=436, x64=436)
 
 # restart_syscall is a little special.
 restart_syscall = RestartSyscall(x86=0, x64=219)
 
-rdcall_init_preload = IrregularEmulatedSyscall(x86=442, x64=442)
-rdcall_init_buffers = IrregularEmulatedSyscall(x86=443, x64=443)
-rdcall_notify_syscall_hook_exit = IrregularEmulatedSyscall(x86=444, x64=444)
-rdcall_notify_control_msg = IrregularEmulatedSyscall(x86=445, x64=445)
-rdcall_reload_auxv = IrregularEmulatedSyscall(x86=446, x64=446)
-rdcall_mprotect_record = IrregularEmulatedSyscall(x86=447, x64=447)
+rdcall_init_preload = IrregularEmulatedSyscall(x86=1000, x64=1000)
+rdcall_init_buffers = IrregularEmulatedSyscall(x86=1001, x64=1001)
+rdcall_notify_syscall_hook_exit = IrregularEmulatedSyscall(x86=1002, x64=1002)
+rdcall_notify_control_msg = IrregularEmulatedSyscall(x86=1003, x64=1003)
+rdcall_reload_auxv = IrregularEmulatedSyscall(x86=1004, x64=1004)
+rdcall_mprotect_record = IrregularEmulatedSyscall(x86=1005, x64=1005)
+rdcall_annotate = IrregularEmulatedSyscall(x86=1100, x64=1100)
 # Disable in rd for now. @TODO this is enabled in rr
-#rdcall_notify_stap_semaphore_added = IrregularEmulatedSyscall(x86=448, x64=448)
-#rdcall_notify_stap_semaphore_removed = IrregularEmulatedSyscall(x86=449, x64=449)
+#rdcall_notify_stap_semaphore_added = IrregularEmulatedSyscall(x86=1006, x64=1006)
+#rdcall_notify_stap_semaphore_removed = IrregularEmulatedSyscall(x86=1007, x64=1007)
 # End disabled
 
 # These syscalls are also subsumed under socketcall on x86.
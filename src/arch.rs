@@ -527,12 +527,14 @@ pub trait Architecture {
     const FSCONFIG: i32;
     const FSMOUNT: i32;
     const FSPICK: i32;
+    const CLOSE_RANGE: i32;
     const RDCALL_INIT_PRELOAD: i32;
     const RDCALL_INIT_BUFFERS: i32;
     const RDCALL_NOTIFY_SYSCALL_HOOK_EXIT: i32;
     const RDCALL_NOTIFY_CONTROL_MSG: i32;
     const RDCALL_RELOAD_AUXV: i32;
     const RDCALL_MPROTECT_RECORD: i32;
+    const RDCALL_ANNOTATE: i32;
     const VALID_SYSCALL_COUNT: i32;
     const INVALID_SYSCALL_COUNT: i32;
     // End list from generate_syscalls.py. See above.
@@ -546,6 +548,12 @@ pub trait Architecture {
     #[allow(non_camel_case_types)]
     type unsigned_long: Copy + From<u32> + TryFrom<usize, Error = TryFromIntError> + 'static;
 
+    #[allow(non_camel_case_types)]
+    type off_t: Copy + 'static;
+
+    #[allow(non_camel_case_types)]
+    type loff_t: Copy + 'static;
+
     #[allow(non_camel_case_types)]
     type iovec: Copy + Default + 'static;
 
@@ -555,6 +563,9 @@ pub trait Architecture {
     #[allow(non_camel_case_types)]
     type cmsghdr: Copy + Default + 'static;
 
+    #[allow(non_camel_case_types)]
+    type mmsghdr: Copy + Default + 'static;
+
     #[allow(non_camel_case_types)]
     type siginfo_t: 'static;
 
@@ -581,6 +592,30 @@ pub trait Architecture {
     #[allow(non_camel_case_types)]
     type user: Copy;
 
+    #[allow(non_camel_case_types)]
+    type msqid64_ds: Copy + Default + 'static;
+
+    #[allow(non_camel_case_types)]
+    type msginfo: Copy + Default + 'static;
+
+    #[allow(non_camel_case_types)]
+    type shmid64_ds: Copy + Default + 'static;
+
+    #[allow(non_camel_case_types)]
+    type shminfo64: Copy + Default + 'static;
+
+    #[allow(non_camel_case_types)]
+    type shm_info: Copy + Default + 'static;
+
+    #[allow(non_camel_case_types)]
+    type semid64_ds: Copy + Default + 'static;
+
+    #[allow(non_camel_case_types)]
+    type seminfo: Copy + Default + 'static;
+
+    #[allow(non_camel_case_types)]
+    type ipc_kludge_args: Copy + Default + 'static;
+
     fn get_k_sa_handler(k: &Self::kernel_sigaction) -> RemotePtr<Void>;
 
     fn get_sa_flags(k: &Self::kernel_sigaction) -> usize;
@@ -595,6 +630,20 @@ pub trait Architecture {
 
     fn get_iovec(msgdata: &Self::iovec) -> (RemotePtr<Void>, usize);
 
+    fn get_ipc_kludge_msgbuf(args: &Self::ipc_kludge_args) -> RemotePtr<Void>;
+
+    /// msg_name and msg_namelen
+    fn get_msghdr_name(msg: &Self::msghdr) -> (RemotePtr<Void>, usize);
+
+    /// msg_iov and msg_iovlen
+    fn get_msghdr_iov(msg: &Self::msghdr) -> (RemotePtr<Self::iovec>, usize);
+
+    /// msg_control and msg_controllen
+    fn get_msghdr_control(msg: &Self::msghdr) -> (RemotePtr<Void>, usize);
+
+    /// msg_hdr and msg_len
+    fn get_mmsghdr(msg: &Self::mmsghdr) -> (Self::msghdr, usize);
+
     fn set_msghdr(
         msg: &mut Self::msghdr,
         msg_control: RemotePtr<u8>,
@@ -605,10 +654,17 @@ pub trait Architecture {
 
     fn set_csmsghdr(msg: &mut Self::cmsghdr, cmsg_len: usize, cmsg_level: i32, cmsg_type: i32);
 
+    /// cmsg_len, cmsg_level and cmsg_type
+    fn get_cmsghdr(msg: &Self::cmsghdr) -> (usize, i32, i32);
+
     fn set_siginfo_for_waited_task(r: &RecordTask, si: &mut Self::siginfo_t);
 
     fn rdcall_init_preload_params_syscallbuf_enabled(d: &Self::rdcall_init_preload_params) -> bool;
 
+    fn rdcall_init_preload_params_syscallbuf_layout_hash(
+        d: &Self::rdcall_init_preload_params,
+    ) -> u64;
+
     fn rdcall_init_preload_params_globals(
         params: &Self::rdcall_init_preload_params,
     ) -> (RemotePtr<preload_globals>, RemoteCodePtr, usize);
@@ -1053,22 +1109,27 @@ impl Architecture for X86Arch {
     const FSCONFIG: i32 = 431;
     const FSMOUNT: i32 = 432;
     const FSPICK: i32 = 433;
-    const RDCALL_INIT_PRELOAD: i32 = 442;
-    const RDCALL_INIT_BUFFERS: i32 = 443;
-    const RDCALL_NOTIFY_SYSCALL_HOOK_EXIT: i32 = 444;
-    const RDCALL_NOTIFY_CONTROL_MSG: i32 = 445;
-    const RDCALL_RELOAD_AUXV: i32 = 446;
-    const RDCALL_MPROTECT_RECORD: i32 = 447;
-    const VALID_SYSCALL_COUNT: i32 = 419;
+    const CLOSE_RANGE: i32 = 436;
+    const RDCALL_INIT_PRELOAD: i32 = 1000;
+    const RDCALL_INIT_BUFFERS: i32 = 1001;
+    const RDCALL_NOTIFY_SYSCALL_HOOK_EXIT: i32 = 1002;
+    const RDCALL_NOTIFY_CONTROL_MSG: i32 = 1003;
+    const RDCALL_RELOAD_AUXV: i32 = 1004;
+    const RDCALL_MPROTECT_RECORD: i32 = 1005;
+    const RDCALL_ANNOTATE: i32 = 1100;
+    const VALID_SYSCALL_COUNT: i32 = 420;
     const INVALID_SYSCALL_COUNT: i32 = 17;
     // End list from generate_syscalls.py. See above.
 
     type kernel_sigaction = x86::kernel_sigaction;
     type signed_long = x86::signed_long;
     type unsigned_long = x86::unsigned_long;
+    type off_t = x86::off_t;
+    type loff_t = x86::loff_t;
     type iovec = x86::iovec;
     type msghdr = x86::msghdr;
     type cmsghdr = x86::cmsghdr;
+    type mmsghdr = x86::mmsghdr;
     type siginfo_t = x86::siginfo_t;
     type sockaddr_un = x86::sockaddr_un;
     type unsigned_word = x86::unsigned_word;
@@ -1076,6 +1137,14 @@ impl Architecture for X86Arch {
     type user_regs_struct = x86::user_regs_struct;
     type user_fpregs_struct = x86::user_fpregs_struct;
     type user = x86::user;
+    type msqid64_ds = x86::msqid64_ds;
+    type msginfo = x86::msginfo;
+    type shmid64_ds = x86::shmid64_ds;
+    type shminfo64 = x86::shminfo64;
+    type shm_info = x86::shm_info;
+    type semid64_ds = x86::semid64_ds;
+    type seminfo = x86::seminfo;
+    type ipc_kludge_args = x86::ipc_kludge_args;
 
     fn get_k_sa_handler(k: &Self::kernel_sigaction) -> RemotePtr<Void> {
         k.k_sa_handler.rptr()
@@ -1106,6 +1175,32 @@ impl Architecture for X86Arch {
         (msgdata.iov_base.rptr(), msgdata.iov_len as usize)
     }
 
+    fn get_ipc_kludge_msgbuf(args: &Self::ipc_kludge_args) -> RemotePtr<Void> {
+        RemotePtr::cast(args.msgbuf.rptr())
+    }
+
+    fn get_msghdr_name(msg: &Self::msghdr) -> (RemotePtr<Void>, usize) {
+        (
+            RemotePtr::cast(msg.msg_name.rptr()),
+            msg.msg_namelen as usize,
+        )
+    }
+
+    fn get_msghdr_iov(msg: &Self::msghdr) -> (RemotePtr<Self::iovec>, usize) {
+        (msg.msg_iov.rptr(), msg.msg_iovlen as usize)
+    }
+
+    fn get_msghdr_control(msg: &Self::msghdr) -> (RemotePtr<Void>, usize) {
+        (
+            RemotePtr::cast(msg.msg_control.rptr()),
+            msg.msg_controllen as usize,
+        )
+    }
+
+    fn get_mmsghdr(msg: &Self::mmsghdr) -> (Self::msghdr, usize) {
+        (msg.msg_hdr, msg.msg_len as usize)
+    }
+
     fn set_msghdr(
         msg: &mut Self::msghdr,
         msg_control: RemotePtr<u8>,
@@ -1119,6 +1214,14 @@ impl Architecture for X86Arch {
         msg.msg_iovlen = msg_iovlen.try_into().unwrap();
     }
 
+    fn get_cmsghdr(cmsghdr: &Self::cmsghdr) -> (usize, i32, i32) {
+        (
+            cmsghdr.cmsg_len as usize,
+            cmsghdr.cmsg_level,
+            cmsghdr.cmsg_type,
+        )
+    }
+
     fn set_csmsghdr(cmsghdr: &mut Self::cmsghdr, cmsg_len: usize, cmsg_level: i32, cmsg_type: i32) {
         cmsghdr.cmsg_len = cmsg_len.try_into().unwrap();
         cmsghdr.cmsg_level = cmsg_level;
@@ -1144,6 +1247,12 @@ impl Architecture for X86Arch {
         d.syscallbuf_enabled != 0
     }
 
+    fn rdcall_init_preload_params_syscallbuf_layout_hash(
+        d: &Self::rdcall_init_preload_params,
+    ) -> u64 {
+        d.syscallbuf_layout_hash.__val
+    }
+
     fn rdcall_init_preload_params_globals(
         params: &Self::rdcall_init_preload_params,
     ) -> (RemotePtr<preload_globals>, RemoteCodePtr, usize) {
@@ -1595,22 +1704,27 @@ impl Architecture for X64Arch {
     const FSCONFIG: i32 = 431;
     const FSMOUNT: i32 = 432;
     const FSPICK: i32 = 433;
-    const RDCALL_INIT_PRELOAD: i32 = 442;
-    const RDCALL_INIT_BUFFERS: i32 = 443;
-    const RDCALL_NOTIFY_SYSCALL_HOOK_EXIT: i32 = 444;
-    const RDCALL_NOTIFY_CONTROL_MSG: i32 = 445;
-    const RDCALL_RELOAD_AUXV: i32 = 446;
-    const RDCALL_MPROTECT_RECORD: i32 = 447;
-    const VALID_SYSCALL_COUNT: i32 = 350;
+    const CLOSE_RANGE: i32 = 436;
+    const RDCALL_INIT_PRELOAD: i32 = 1000;
+    const RDCALL_INIT_BUFFERS: i32 = 1001;
+    const RDCALL_NOTIFY_SYSCALL_HOOK_EXIT: i32 = 1002;
+    const RDCALL_NOTIFY_CONTROL_MSG: i32 = 1003;
+    const RDCALL_RELOAD_AUXV: i32 = 1004;
+    const RDCALL_MPROTECT_RECORD: i32 = 1005;
+    const RDCALL_ANNOTATE: i32 = 1100;
+    const VALID_SYSCALL_COUNT: i32 = 351;
     const INVALID_SYSCALL_COUNT: i32 = 86;
     // End list from generate_syscalls.py. See above.
 
     type kernel_sigaction = x64::kernel_sigaction;
     type signed_long = x64::signed_long;
     type unsigned_long = x64::unsigned_long;
+    type off_t = x64::off_t;
+    type loff_t = x64::loff_t;
     type iovec = x64::iovec;
     type msghdr = x64::msghdr;
     type cmsghdr = x64::cmsghdr;
+    type mmsghdr = x64::mmsghdr;
     type siginfo_t = x64::siginfo_t;
     type sockaddr_un = x64::sockaddr_un;
     type unsigned_word = x64::unsigned_word;
@@ -1618,6 +1732,14 @@ impl Architecture for X64Arch {
     type user_regs_struct = x64::user_regs_struct;
     type user_fpregs_struct = x64::user_fpregs_struct;
     type user = x64::user;
+    type msqid64_ds = x64::msqid64_ds;
+    type msginfo = x64::msginfo;
+    type shmid64_ds = x64::shmid64_ds;
+    type shminfo64 = x64::shminfo64;
+    type shm_info = x64::shm_info;
+    type semid64_ds = x64::semid64_ds;
+    type seminfo = x64::seminfo;
+    type ipc_kludge_args = x64::ipc_kludge_args;
 
     fn get_k_sa_handler(k: &Self::kernel_sigaction) -> RemotePtr<Void> {
         k.k_sa_handler.rptr()
@@ -1646,6 +1768,32 @@ impl Architecture for X64Arch {
         (msgdata.iov_base.rptr(), msgdata.iov_len as usize)
     }
 
+    fn get_ipc_kludge_msgbuf(args: &Self::ipc_kludge_args) -> RemotePtr<Void> {
+        RemotePtr::cast(args.msgbuf.rptr())
+    }
+
+    fn get_msghdr_name(msg: &Self::msghdr) -> (RemotePtr<Void>, usize) {
+        (
+            RemotePtr::cast(msg.msg_name.rptr()),
+            msg.msg_namelen as usize,
+        )
+    }
+
+    fn get_msghdr_iov(msg: &Self::msghdr) -> (RemotePtr<Self::iovec>, usize) {
+        (msg.msg_iov.rptr(), msg.msg_iovlen as usize)
+    }
+
+    fn get_msghdr_control(msg: &Self::msghdr) -> (RemotePtr<Void>, usize) {
+        (
+            RemotePtr::cast(msg.msg_control.rptr()),
+            msg.msg_controllen as usize,
+        )
+    }
+
+    fn get_mmsghdr(msg: &Self::mmsghdr) -> (Self::msghdr, usize) {
+        (msg.msg_hdr, msg.msg_len as usize)
+    }
+
     fn set_msghdr(
         msg: &mut Self::msghdr,
         msg_control: RemotePtr<u8>,
@@ -1659,6 +1807,14 @@ impl Architecture for X64Arch {
         msg.msg_iovlen = msg_iovlen as _;
     }
 
+    fn get_cmsghdr(cmsghdr: &Self::cmsghdr) -> (usize, i32, i32) {
+        (
+            cmsghdr.cmsg_len as usize,
+            cmsghdr.cmsg_level,
+            cmsghdr.cmsg_type,
+        )
+    }
+
     fn set_csmsghdr(cmsghdr: &mut Self::cmsghdr, cmsg_len: usize, cmsg_level: i32, cmsg_type: i32) {
         cmsghdr.cmsg_len = cmsg_len as _;
         cmsghdr.cmsg_level = cmsg_level;
@@ -1683,6 +1839,12 @@ impl Architecture for X64Arch {
     fn rdcall_init_preload_params_syscallbuf_enabled(d: &Self::rdcall_init_preload_params) -> bool {
         d.syscallbuf_enabled != 0
     }
+
+    fn rdcall_init_preload_params_syscallbuf_layout_hash(
+        d: &Self::rdcall_init_preload_params,
+    ) -> u64 {
+        d.syscallbuf_layout_hash.__val
+    }
     fn rdcall_init_preload_params_globals(
         params: &Self::rdcall_init_preload_params,
     ) -> (RemotePtr<preload_globals>, RemoteCodePtr, usize) {
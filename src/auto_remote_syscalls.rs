@@ -50,6 +50,7 @@ use crate::{
                 task_inner::WriteFlags,
                 ResumeRequest::{ResumeSinglestep, ResumeSyscall},
                 TicksRequest::ResumeNoTicks,
+                TraceeError,
                 WaitRequest::ResumeWait,
             },
             Task,
@@ -61,6 +62,7 @@ use crate::{
 use core::ffi::c_void;
 use libc::{
     pid_t,
+    EINTR,
     ESRCH,
     MREMAP_FIXED,
     MREMAP_MAYMOVE,
@@ -255,6 +257,8 @@ pub struct AutoRestoreMem<'a, 'b> {
     saved_sp: RemotePtr<Void>,
     /// Length of tmp mem
     len: usize,
+    /// Bytes of tmp mem handed out by `allocate_bytes()` so far.
+    allocated: usize,
 }
 
 impl<'a, 'b> Deref for AutoRestoreMem<'a, 'b> {
@@ -318,6 +322,7 @@ impl<'a, 'b> AutoRestoreMem<'a, 'b> {
             // We don't need an Option here because init will always add a value.
             saved_sp: 0usize.into(),
             len,
+            allocated: 0,
         };
         mem.map(|s| debug_assert_eq!(len, s.len()));
         result.init(mem);
@@ -342,6 +347,12 @@ impl<'a, 'b> AutoRestoreMem<'a, 'b> {
         .unwrap()
     }
 
+    /// Convenience constructor for pushing the bytes of `val`.
+    pub fn push<T>(remote: &'a mut AutoRemoteSyscalls<'b>, val: &T) -> AutoRestoreMem<'a, 'b> {
+        let bytes = unsafe { slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) };
+        Self::new(remote, Some(bytes), size_of::<T>())
+    }
+
     /// Get a pointer to the reserved memory.
     /// Returns None if we failed.
     pub fn get(&self) -> Option<RemotePtr<Void>> {
@@ -391,6 +402,44 @@ impl<'a, 'b> AutoRestoreMem<'a, 'b> {
     pub fn len(&self) -> usize {
         self.data.len()
     }
+
+    /// Hand out the next `size` bytes (rounded up to a word) of the reserved
+    /// memory, for building up several syscall arguments in one
+    /// `AutoRestoreMem`. Like everything else in it, they are restored on
+    /// drop. Called allocate() in rr.
+    pub fn allocate_bytes(&mut self, size: usize) -> RemotePtr<Void> {
+        let r = self.addr.unwrap() + self.allocated;
+        self.allocated += align_size(size);
+        if self.allocated > self.len {
+            fatal!("overflow");
+        }
+        r
+    }
+
+    pub fn allocate<T>(&mut self) -> RemotePtr<T> {
+        RemotePtr::cast(self.allocate_bytes(size_of::<T>()))
+    }
+
+    /// `allocate_bytes()` space for `data` and write it there. Returns
+    /// `None` if the write failed, e.g. because the task died.
+    pub fn push_bytes(&mut self, data: &[u8]) -> Option<RemotePtr<Void>> {
+        let addr = self.allocate_bytes(data.len());
+        let mut ok = true;
+        self.remote
+            .task_mut()
+            .write_bytes_helper(addr, data, Some(&mut ok), WriteFlags::empty());
+        if ok {
+            Some(addr)
+        } else {
+            None
+        }
+    }
+
+    /// `push_bytes()` for the bytes of `val`.
+    pub fn push_val<T>(&mut self, val: &T) -> Option<RemotePtr<T>> {
+        let bytes = unsafe { slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) };
+        self.push_bytes(bytes).map(RemotePtr::cast)
+    }
 }
 
 /// RAII helper to prepare a Task for remote syscalls and undo any
@@ -541,10 +590,15 @@ impl<'a> AutoRemoteSyscalls<'a> {
     ///  doing.  *ESPECIALLY* don't call this on a `t` other than
     ///  the one passed to the constructor, unless you really know
     ///  what you're doing.
-    pub fn restore_state_to(&mut self, maybe_other_task: Option<&'a mut dyn Task>) {
-        let some_t = maybe_other_task.unwrap_or(self.t);
-        // Unmap our scratch region if required
-        if self.scratch_mem_was_mapped {
+    pub fn restore_state_to(&mut self, maybe_other_task: Option<&mut dyn Task>) {
+        let some_t: &mut dyn Task = match maybe_other_task {
+            Some(other_t) => other_t,
+            None => &mut *self.t,
+        };
+        // Unmap our scratch region if required. A dead tracee's address space
+        // goes away with it (or stays with its other live tasks, which can't
+        // be told apart here), so don't try.
+        if self.scratch_mem_was_mapped && !some_t.is_dying() {
             let mut remote = AutoRemoteSyscalls::new(some_t);
             rd_infallible_syscall!(
                 remote,
@@ -589,6 +643,17 @@ impl<'a> AutoRemoteSyscalls<'a> {
         self.syscall_base(syscallno, &mut callregs)
     }
 
+    /// Like `syscall()`, but says why if the syscall couldn't be completed
+    /// rather than returning -ESRCH or -EINTR or asserting.
+    pub fn try_syscall(&mut self, syscallno: i32, args: &[usize]) -> Result<isize, TraceeError> {
+        let mut callregs = self.initial_regs.clone();
+        debug_assert!(args.len() <= 6);
+        for (i, arg) in args.iter().enumerate() {
+            callregs.set_arg(i + 1, *arg);
+        }
+        self.try_syscall_base(syscallno, &mut callregs)
+    }
+
     /// @TODO Can get a bit more performance by specializing this method. Leave as is for now.
     pub fn infallible_syscall(&mut self, syscallno: i32, args: &[usize]) -> isize {
         let ret = self.syscall(syscallno, args);
@@ -616,33 +681,42 @@ impl<'a> AutoRemoteSyscalls<'a> {
         // The first syscall argument is called "arg 1", so
         // our syscall-arg-index template parameter starts
         // with "1".
-        let ret: RemotePtr<Void> = if has_mmap2_syscall(self.arch()) {
-            let offset_pages_usize: usize = offset_pages.try_into().unwrap();
-            rd_infallible_syscall_ptr!(
-                self,
-                syscall_number_for_mmap2(self.arch()),
-                addr_hint.as_usize(),
-                length,
-                prot.bits(),
-                flags.bits(),
-                child_fd,
-                offset_pages_usize
-            )
+        let syscallno = if has_mmap2_syscall(self.arch()) {
+            syscall_number_for_mmap2(self.arch())
         } else {
-            let offset_usize: usize = (offset_pages * page_size() as u64).try_into().unwrap();
-            rd_infallible_syscall_ptr!(
-                self,
-                syscall_number_for_mmap(self.arch()),
-                addr_hint.as_usize(),
-                length,
-                prot.bits(),
-                flags.bits(),
-                child_fd,
-                offset_usize
-            )
+            syscall_number_for_mmap(self.arch())
+        };
+        let offset_arg: usize = if has_mmap2_syscall(self.arch()) {
+            offset_pages.try_into().unwrap()
+        } else {
+            (offset_pages * page_size() as u64).try_into().unwrap()
         };
+        let raw_ret = rd_syscall!(
+            self,
+            syscallno,
+            addr_hint.as_usize(),
+            length,
+            prot.bits(),
+            flags.bits(),
+            child_fd,
+            offset_arg
+        );
 
         if flags.contains(MapFlags::MAP_FIXED) {
+            // Replay depends on every mapping landing exactly where it was
+            // during recording, so say which address couldn't be reproduced
+            // rather than just which syscall failed.
+            ed_assert!(
+                self.t,
+                !(-4096 < raw_ret && raw_ret < 0),
+                "Kernel refused MAP_FIXED mapping of {} bytes at {}: {}. The address \
+                 space layout of the recording can't be reproduced here (is something \
+                 else mapped there, or is vm.mmap_min_addr higher than during recording?)",
+                length,
+                addr_hint,
+                errno_name(-raw_ret as i32)
+            );
+            let ret = RemotePtr::<Void>::from(raw_ret as usize);
             ed_assert!(
                 self.t,
                 addr_hint == ret,
@@ -650,17 +724,18 @@ impl<'a> AutoRemoteSyscalls<'a> {
                 addr_hint,
                 ret
             );
+            return ret;
         }
 
-        ret
+        self.check_syscall_result(raw_ret, syscallno);
+        RemotePtr::from(raw_ret as usize)
     }
 
     /// Note: offset is signed.
     pub fn infallible_lseek_syscall(&mut self, fd: i32, offset: i64, whence: i32) -> isize {
         match self.arch() {
             SupportedArch::X86 => {
-                let mut mem =
-                    AutoRestoreMem::new(self, Some(&offset.to_le_bytes()), size_of::<i64>());
+                let mut mem = AutoRestoreMem::push(self, &offset);
                 let arch = mem.arch();
                 let addr = mem.get().unwrap();
                 // AutoRestoreMem DerefMut-s to AutoRemoteSyscalls
@@ -723,18 +798,14 @@ impl<'a> AutoRemoteSyscalls<'a> {
             return ScopedFd::new();
         }
 
-        let mut sc_args_end: RemotePtr<Void> = remote_buf.get().unwrap();
         let mut maybe_sc_args: Option<RemotePtr<SocketcallArgs<Arch>>> = None;
         if has_socketcall_syscall(Arch::arch()) {
-            maybe_sc_args = Some(allocate::<SocketcallArgs<Arch>>(
-                &mut sc_args_end,
-                &remote_buf,
-            ));
+            maybe_sc_args = Some(remote_buf.allocate::<SocketcallArgs<Arch>>());
         }
 
         let child_sock = remote_buf.task().session().tracee_fd_number();
         let child_syscall_result: isize =
-            child_sendmsg(&mut remote_buf, maybe_sc_args, sc_args_end, child_sock, fd);
+            child_sendmsg(&mut remote_buf, maybe_sc_args, child_sock, fd);
         if child_syscall_result == -ESRCH as isize {
             return ScopedFd::new();
         }
@@ -756,6 +827,24 @@ impl<'a> AutoRemoteSyscalls<'a> {
     ///
     /// The syscall is finished in `t` and the result is returned.
     pub fn syscall_base(&mut self, syscallno: i32, callregs: &mut Registers) -> isize {
+        match self.try_syscall_base(syscallno, callregs) {
+            Ok(res) => res,
+            Err(TraceeError::TraceeDead) => -ESRCH as isize,
+            Err(TraceeError::Interrupted) => -EINTR as isize,
+            Err(e @ TraceeError::UnexpectedStop { .. }) => {
+                ed_assert!(self.t, false, "Remote syscall failed: {}", e);
+                unreachable!()
+            }
+        }
+    }
+
+    /// Like `syscall_base()`, but returns an error if the tracee died or
+    /// stopped unexpectedly before the syscall completed.
+    pub fn try_syscall_base(
+        &mut self,
+        syscallno: i32,
+        callregs: &mut Registers,
+    ) -> Result<isize, TraceeError> {
         log!(LogDebug, "syscall {}", syscall_name(syscallno, self.arch()));
 
         if callregs.arg1_signed() == SIGTRAP as isize
@@ -791,7 +880,7 @@ impl<'a> AutoRemoteSyscalls<'a> {
                     // We were interrupted by a signal before we even entered the syscall
                     continue;
                 }
-                ed_assert!(self.t, false, "Unexpected status {}", self.t.status());
+                return Err(self.unexpected_stop());
             }
         } else {
             self.t.enter_syscall();
@@ -844,20 +933,32 @@ impl<'a> AutoRemoteSyscalls<'a> {
                 // completed. Don't continue, we're done here.
                 break;
             }
-            ed_assert!(self.t, false, "Unexpected status {}", self.t.status());
-            break;
+            return Err(self.unexpected_stop());
         }
 
         if self.t.is_dying() {
             log!(LogDebug, "Task is dying, no status result");
-            -ESRCH as isize
+            Err(TraceeError::TraceeDead)
         } else {
             // IMPORTANT: Note unsigned syscall result.
             // Ensures that sign extention does NOT happen for x86
             let res = self.t.regs_ref().syscall_result();
             log!(LogDebug, "done, result={} ({:#x})", res, res);
             // Make signed now
-            res as isize
+            Ok(res as isize)
+        }
+    }
+
+    /// Classify a stop that an injected syscall didn't expect.
+    fn unexpected_stop(&self) -> TraceeError {
+        let status = self.t.status();
+        log!(LogDebug, "Unexpected status {} in remote syscall", status);
+        if self.t.is_dying() || status.exit_code().is_some() || status.fatal_sig().is_some() {
+            TraceeError::TraceeDead
+        } else if status.maybe_stop_sig().is_sig() {
+            TraceeError::Interrupted
+        } else {
+            TraceeError::UnexpectedStop { status }
         }
     }
 
@@ -928,6 +1029,17 @@ impl<'a> AutoRemoteSyscalls<'a> {
     }
 
     fn check_syscall_result(&mut self, ret: isize, syscallno: i32) {
+        if ret == -ESRCH as isize && self.t.is_dying() {
+            // Not a bug in rd: the tracee died under us. Let the caller carry
+            // on so that the session can tear the task down when it reaps it.
+            log!(
+                LogDebug,
+                "Tracee {} died during {}",
+                self.t.tid,
+                syscall_name(syscallno, self.arch())
+            );
+            return;
+        }
         if -4096 < ret && ret < 0 {
             let mut extra_msg: String = String::new();
             if is_open_syscall(syscallno, self.arch()) {
@@ -1317,32 +1429,11 @@ const fn align_size(size: usize) -> usize {
     (size + align_amount - 1) & !(align_amount - 1)
 }
 
-/// Called allocate() in rr
-fn allocate_bytes(
-    buf_end: &mut RemotePtr<Void>,
-    remote_buf: &AutoRestoreMem,
-    size: usize,
-) -> RemotePtr<Void> {
-    let r = *buf_end;
-    // Note the mutation of buf_end here. A sort of bump pointer.
-    *buf_end = *buf_end + align_size(size);
-    if (*buf_end - remote_buf.get().unwrap()) > remote_buf.len() {
-        fatal!("overflow");
-    }
-    // The data can be placed at r
-    r
-}
-
-fn allocate<T>(buf_end: &mut RemotePtr<Void>, remote_buf: &AutoRestoreMem) -> RemotePtr<T> {
-    RemotePtr::cast(allocate_bytes(buf_end, remote_buf, size_of::<T>()))
-}
-
 /// We don't need an AutoRemoteSyscall like rr does.
 /// AutoRestoreMem Deref-s/DerefMut-s to AutoRemoteSyscalls
 fn child_sendmsg<Arch: Architecture>(
     remote_buf: &mut AutoRestoreMem,
     sc_args: Option<RemotePtr<SocketcallArgs<Arch>>>,
-    mut buf_end: RemotePtr<Void>,
     child_sock: i32,
     fd: i32,
 ) -> isize {
@@ -1356,9 +1447,9 @@ fn child_sendmsg<Arch: Architecture>(
     // sent us (in which case we would deadlock with the tracee).
     // We call sendmsg on child socket, but first we have to prepare a lot of
     // data.
-    let remote_msg = allocate::<Arch::msghdr>(&mut buf_end, remote_buf);
-    let remote_msgdata = allocate::<Arch::iovec>(&mut buf_end, remote_buf);
-    let remote_cmsgbuf = allocate_bytes(&mut buf_end, remote_buf, cmsgbuf_size);
+    let remote_msg = remote_buf.allocate::<Arch::msghdr>();
+    let remote_msgdata = remote_buf.allocate::<Arch::iovec>();
+    let remote_cmsgbuf = remote_buf.allocate_bytes(cmsgbuf_size);
 
     let mut ok = true;
     let mut msg = Arch::msghdr::default();
@@ -0,0 +1,192 @@
+//! The cgroup rd runs in, as far as it affects recording. rd binds itself and
+//! its tracees to one CPU and relies on the desched and time-slice signals
+//! arriving promptly; a `cpu.max` quota of less than one CPU throttles that
+//! CPU and breaks those assumptions, and a cpuset may not include the CPU we
+//! would otherwise bind to. A description of the cgroup is recorded in the
+//! trace header for triage.
+
+use std::{fmt, fmt::Display, fs::read_to_string};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CpuMax {
+    pub quota_us: u64,
+    pub period_us: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CgroupContext {
+    /// 1 or 2, or 0 if we couldn't tell.
+    pub version: u32,
+    /// Relative to the cgroup root, e.g. `/user.slice/user-1000.slice`.
+    pub path: String,
+    /// The `cpu.max` (or v1 `cpu.cfs_*_us`) limit, if there is one.
+    pub cpu_max: Option<CpuMax>,
+    /// The CPUs the cpuset allows, if we could read them.
+    pub cpus: Option<Vec<u32>>,
+}
+
+impl CgroupContext {
+    /// The cgroup rd itself is in.
+    pub fn current() -> CgroupContext {
+        let proc_cgroup = read_to_string("/proc/self/cgroup").unwrap_or_default();
+        // cgroup v2 has the single line "0::<path>". With v1, each controller
+        // hierarchy has a line "<id>:<controllers>:<path>".
+        let mut v1_cpu_path = None;
+        let mut v1_cpuset_path = None;
+        for line in proc_cgroup.lines() {
+            let mut fields = line.splitn(3, ':');
+            let (id, controllers, path) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(id), Some(controllers), Some(path)) => (id, controllers, path),
+                _ => continue,
+            };
+            if id == "0" && controllers.is_empty() {
+                return Self::v2(path);
+            }
+            for controller in controllers.split(',') {
+                match controller {
+                    "cpu" => v1_cpu_path = Some(path.to_owned()),
+                    "cpuset" => v1_cpuset_path = Some(path.to_owned()),
+                    _ => (),
+                }
+            }
+        }
+        if v1_cpu_path.is_none() && v1_cpuset_path.is_none() {
+            return CgroupContext::default();
+        }
+        Self::v1(v1_cpu_path, v1_cpuset_path)
+    }
+
+    fn v2(path: &str) -> CgroupContext {
+        let dir = format!("{}{}", CGROUP_ROOT, path);
+        let cpu_max = read_to_string(format!("{}/cpu.max", dir))
+            .ok()
+            .and_then(|s| parse_cpu_max(&s));
+        let cpus = read_to_string(format!("{}/cpuset.cpus.effective", dir))
+            .ok()
+            .and_then(|s| parse_cpu_list(&s));
+        CgroupContext {
+            version: 2,
+            path: path.to_owned(),
+            cpu_max,
+            cpus,
+        }
+    }
+
+    fn v1(cpu_path: Option<String>, cpuset_path: Option<String>) -> CgroupContext {
+        let cpu_max = cpu_path.as_ref().and_then(|path| {
+            let dir = format!("{}/cpu{}", CGROUP_ROOT, path);
+            let quota = read_to_string(format!("{}/cpu.cfs_quota_us", dir)).ok()?;
+            let period = read_to_string(format!("{}/cpu.cfs_period_us", dir)).ok()?;
+            // A quota of -1 means unlimited.
+            let quota_us = quota.trim().parse::<u64>().ok()?;
+            let period_us = period.trim().parse::<u64>().ok()?;
+            Some(CpuMax {
+                quota_us,
+                period_us,
+            })
+        });
+        let cpus = cpuset_path.as_ref().and_then(|path| {
+            read_to_string(format!("{}/cpuset{}/cpuset.cpus", CGROUP_ROOT, path))
+                .ok()
+                .and_then(|s| parse_cpu_list(&s))
+        });
+        CgroupContext {
+            version: 1,
+            path: cpu_path.or(cpuset_path).unwrap_or_default(),
+            cpu_max,
+            cpus,
+        }
+    }
+
+    /// How many CPUs' worth of time the quota allows, if there's a quota.
+    pub fn cpu_limit(&self) -> Option<f64> {
+        self.cpu_max
+            .as_ref()
+            .filter(|m| m.period_us > 0)
+            .map(|m| m.quota_us as f64 / m.period_us as f64)
+    }
+
+    /// True if the quota would throttle the single CPU rd binds to.
+    pub fn is_throttled(&self) -> bool {
+        self.cpu_limit().map_or(false, |limit| limit < 1.0)
+    }
+
+    /// True if the cpuset allows `cpu`, or we don't know the cpuset.
+    pub fn allows_cpu(&self, cpu: u32) -> bool {
+        self.cpus.as_ref().map_or(true, |cpus| cpus.contains(&cpu))
+    }
+}
+
+impl Display for CgroupContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.version == 0 {
+            return write!(f, "unknown");
+        }
+        write!(f, "v{} {}", self.version, self.path)?;
+        if let Some(m) = &self.cpu_max {
+            write!(f, " cpu.max={}/{}", m.quota_us, m.period_us)?;
+        }
+        if let Some(cpus) = &self.cpus {
+            write!(f, " cpus={}", format_cpu_list(cpus))?;
+        }
+        Ok(())
+    }
+}
+
+/// `cpu.max` is "<quota> <period>" where quota may be "max".
+fn parse_cpu_max(s: &str) -> Option<CpuMax> {
+    let mut fields = s.split_whitespace();
+    let quota = fields.next()?;
+    let period_us = fields.next()?.parse::<u64>().ok()?;
+    if quota == "max" {
+        return None;
+    }
+    Some(CpuMax {
+        quota_us: quota.parse::<u64>().ok()?,
+        period_us,
+    })
+}
+
+/// Parse a list like "0-3,8,10-11".
+pub fn parse_cpu_list(s: &str) -> Option<Vec<u32>> {
+    let mut cpus = Vec::new();
+    for part in s.trim().split(',').filter(|p| !p.is_empty()) {
+        let mut range = part.splitn(2, '-');
+        let start = range.next()?.parse::<u32>().ok()?;
+        let end = match range.next() {
+            Some(end) => end.parse::<u32>().ok()?,
+            None => start,
+        };
+        if end < start {
+            return None;
+        }
+        cpus.extend(start..=end);
+    }
+    if cpus.is_empty() {
+        None
+    } else {
+        Some(cpus)
+    }
+}
+
+fn format_cpu_list(cpus: &[u32]) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < cpus.len() {
+        let start = cpus[i];
+        let mut end = start;
+        while i + 1 < cpus.len() && cpus[i + 1] == end + 1 {
+            end += 1;
+            i += 1;
+        }
+        if start == end {
+            parts.push(start.to_string());
+        } else {
+            parts.push(format!("{}-{}", start, end));
+        }
+        i += 1;
+    }
+    parts.join(",")
+}
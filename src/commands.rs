@@ -1,12 +1,22 @@
 use std::io;
 
 pub mod build_id_command;
+pub mod call_trace;
+pub mod check_install_command;
+pub mod cleanup_command;
+pub mod coverage_command;
+pub mod cpu_features_command;
+pub mod diff_command;
 pub mod dump_command;
+pub mod export_command;
 pub mod ps_command;
 pub mod rd_options;
 pub mod replay_command;
 pub mod rerun_command;
+pub mod stacks;
 pub mod trace_info_command;
+pub mod verify_command;
+pub mod watch_command;
 
 pub trait RdCommand {
     fn run(&mut self) -> io::Result<()>;
@@ -0,0 +1,257 @@
+//! Reconstructing function calls and returns from a singlestepped replay,
+//! for `rd rerun --call-trace`. We don't decode instructions: a step that
+//! pushed exactly one word holding an address just past the instruction we
+//! stepped, and didn't continue at that address, was a call. A frame is gone
+//! once the stack pointer is above the slot its return address was pushed
+//! to. It returned if we're then at its return address; otherwise it was
+//! unwound by longjmp() or an exception. A tail call reuses its caller's
+//! frame, so the callee's return is reported as the caller's.
+//!
+//! Functions are named from the ELF symbol tables of the mapped files.
+
+use crate::{
+    kernel_abi::SupportedArch,
+    remote_code_ptr::RemoteCodePtr,
+    remote_ptr::{RemotePtr, Void},
+    session::task::{task_common::read_val_mem, Task},
+    taskish_uid::TaskUid,
+    trace::trace_frame::FrameTime,
+};
+use goblin::elf::{program_header::PT_LOAD, Elf};
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs,
+    io,
+    io::Write,
+};
+
+/// x86 instructions are at most this long.
+const MAX_INSN_LEN: usize = 15;
+
+struct CallFrame {
+    /// Where the caller continues when this frame returns.
+    return_addr: usize,
+    /// Where the return address was pushed.
+    sp: usize,
+    function: String,
+}
+
+#[derive(Default)]
+pub struct CallTracer {
+    stacks: HashMap<TaskUid, Vec<CallFrame>>,
+    symbols: Symbolizer,
+}
+
+impl CallTracer {
+    pub fn new() -> CallTracer {
+        Default::default()
+    }
+
+    /// `t` has just executed one instruction, which was at `old_ip` with the
+    /// stack pointer at `old_sp`. Writes a line for each call, return or
+    /// unwound frame it made.
+    pub fn after_singlestep(
+        &mut self,
+        t: &mut dyn Task,
+        event: FrameTime,
+        old_ip: RemoteCodePtr,
+        old_sp: RemotePtr<Void>,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let ip = t.ip().register_value();
+        let sp = t.regs_ref().sp().as_usize();
+        let (pid, tid) = (t.tgid(), t.rec_tid);
+        let stack = self.stacks.entry(t.tuid()).or_default();
+
+        while stack.last().map_or(false, |f| sp > f.sp) {
+            let frame = stack.pop().unwrap();
+            let how = if ip == frame.return_addr {
+                "return"
+            } else {
+                "unwind"
+            };
+            write!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\n",
+                event, pid, tid, how, frame.function
+            )?;
+        }
+
+        let word_size = match t.arch() {
+            SupportedArch::X86 => 4,
+            SupportedArch::X64 => 8,
+        };
+        if sp + word_size != old_sp.as_usize() {
+            return Ok(());
+        }
+        let mut ok = true;
+        let pushed = match t.arch() {
+            SupportedArch::X86 => {
+                read_val_mem(t, RemotePtr::<u32>::new_from_val(sp), Some(&mut ok)) as usize
+            }
+            SupportedArch::X64 => {
+                read_val_mem(t, RemotePtr::<u64>::new_from_val(sp), Some(&mut ok)) as usize
+            }
+        };
+        let old_ip = old_ip.register_value();
+        if !ok || pushed <= old_ip || pushed > old_ip + MAX_INSN_LEN || ip == pushed {
+            return Ok(());
+        }
+
+        let function = self.symbols.name(t, ip);
+        write!(out, "{}\t{}\t{}\tcall\t{}\n", event, pid, tid, function)?;
+        self.stacks.get_mut(&t.tuid()).unwrap().push(CallFrame {
+            return_addr: pushed,
+            sp,
+            function,
+        });
+        Ok(())
+    }
+}
+
+/// What we need from one ELF file to name and unwind through its code.
+pub struct ElfSymbols {
+    /// (start, end, name) in file virtual addresses, sorted by start.
+    functions: Vec<(u64, u64, String)>,
+    /// (file offset, size in the file, virtual address) of the PT_LOAD segments.
+    loads: Vec<(u64, u64, u64)>,
+    pub eh_frame: Option<EhFrameSection>,
+}
+
+/// The contents of `.eh_frame` and the addresses its pointers are relative
+/// to.
+pub struct EhFrameSection {
+    pub data: Vec<u8>,
+    pub addr: u64,
+    pub text_addr: u64,
+}
+
+impl ElfSymbols {
+    pub fn read(file: &OsStr) -> Option<ElfSymbols> {
+        let data = fs::read(file).ok()?;
+        let elf = Elf::parse(&data).ok()?;
+        let mut functions = Vec::new();
+        for (syms, strtab) in &[(&elf.syms, &elf.strtab), (&elf.dynsyms, &elf.dynstrtab)] {
+            for sym in syms.iter() {
+                if !sym.is_function() || sym.st_value == 0 {
+                    continue;
+                }
+                if let Some(Ok(name)) = strtab.get(sym.st_name) {
+                    functions.push((
+                        sym.st_value,
+                        sym.st_value + sym.st_size.max(1),
+                        name.to_owned(),
+                    ));
+                }
+            }
+        }
+        functions.sort();
+        functions.dedup_by_key(|f| f.0);
+        let loads = elf
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .map(|ph| (ph.p_offset, ph.p_filesz, ph.p_vaddr))
+            .collect();
+
+        let section = |name: &str| {
+            elf.section_headers
+                .iter()
+                .find(|sh| matches!(elf.shdr_strtab.get(sh.sh_name), Some(Ok(n)) if n == name))
+        };
+        let eh_frame = section(".eh_frame").and_then(|sh| {
+            let start = sh.sh_offset as usize;
+            let bytes = data.get(start..start + sh.sh_size as usize)?;
+            Some(EhFrameSection {
+                data: bytes.to_vec(),
+                addr: sh.sh_addr,
+                text_addr: section(".text").map_or(0, |text| text.sh_addr),
+            })
+        });
+
+        Some(ElfSymbols {
+            functions,
+            loads,
+            eh_frame,
+        })
+    }
+
+    /// The virtual address in the file of what is at `offset` in it.
+    pub fn vaddr_of_offset(&self, offset: u64) -> Option<u64> {
+        let &(load_offset, _, vaddr) = self
+            .loads
+            .iter()
+            .find(|&&(start, size, _)| start <= offset && offset < start + size)?;
+        Some(vaddr + (offset - load_offset))
+    }
+
+    fn function_at(&self, addr: u64) -> Option<&str> {
+        self.function_and_offset(addr).map(|(name, _)| name)
+    }
+
+    /// The function containing `addr`, and how far into it `addr` is.
+    pub fn function_and_offset(&self, addr: u64) -> Option<(&str, u64)> {
+        let i = match self.functions.binary_search_by_key(&addr, |f| f.0) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let (start, end, name) = &self.functions[i];
+        if *start <= addr && addr < *end {
+            Some((name, addr - start))
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads the ELF files mapped in tracees, each once.
+#[derive(Default)]
+pub struct Symbolizer {
+    files: HashMap<OsString, Option<ElfSymbols>>,
+}
+
+impl Symbolizer {
+    pub fn new() -> Symbolizer {
+        Default::default()
+    }
+
+    /// The file mapped at `ip` in `t`, and `ip`'s virtual address in it.
+    pub fn lookup(&mut self, t: &dyn Task, ip: usize) -> Option<(&ElfSymbols, u64)> {
+        let addr = RemotePtr::<Void>::new_from_val(ip);
+        let (file, offset) = {
+            let m = t.vm().mapping_of(addr)?;
+            (
+                m.map.fsname().to_owned(),
+                m.map.file_offset_bytes() + (addr - m.map.start()) as u64,
+            )
+        };
+        let symbols = self
+            .files
+            .entry(file.clone())
+            .or_insert_with(|| ElfSymbols::read(&file))
+            .as_ref()?;
+        Some((symbols, symbols.vaddr_of_offset(offset)?))
+    }
+
+    /// The function at `ip` in `t`, or where it is if it's not in a symbol
+    /// table we can read.
+    pub fn name(&mut self, t: &dyn Task, ip: usize) -> String {
+        if let Some(name) = self
+            .lookup(t, ip)
+            .and_then(|(symbols, addr)| symbols.function_at(addr))
+        {
+            return name.to_owned();
+        }
+        let addr = RemotePtr::<Void>::new_from_val(ip);
+        match t.vm().mapping_of(addr) {
+            Some(m) if !m.map.fsname().is_empty() => format!(
+                "{}+{:#x}",
+                m.map.fsname().to_string_lossy(),
+                m.map.file_offset_bytes() + (addr - m.map.start()) as u64
+            ),
+            _ => format!("{:#x}", ip),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{
+    commands::RdCommand,
+    resource_path::{
+        has_required_resources,
+        resource_path,
+        resource_path_candidates,
+        resources,
+        Resource,
+    },
+};
+use std::{
+    ffi::OsStr,
+    io,
+    io::{stdout, ErrorKind, Write},
+    path::Path,
+};
+
+pub struct CheckInstallCommand;
+
+impl CheckInstallCommand {
+    pub fn new() -> CheckInstallCommand {
+        CheckInstallCommand
+    }
+}
+
+impl RdCommand for CheckInstallCommand {
+    fn run(&mut self) -> io::Result<()> {
+        self.check_install(&mut stdout())
+    }
+}
+
+impl CheckInstallCommand {
+    fn check_install(&self, out: &mut dyn Write) -> io::Result<()> {
+        let all_resources = resources();
+        for (path, source) in resource_path_candidates() {
+            write!(out, "{:?} ({}):\n", path, source)?;
+            for r in &all_resources {
+                write_resource_status(&path, r, out)?;
+            }
+        }
+
+        let chosen = resource_path();
+        write!(out, "Using resource path {:?}\n", chosen)?;
+        if has_required_resources(chosen) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Resource path {:?} is missing files rd needs. Build or install rr's \
+                     resources there, or pass --resource-path.",
+                    chosen
+                ),
+            ))
+        }
+    }
+}
+
+fn write_resource_status(
+    resource_path: &OsStr,
+    r: &Resource,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let found = Path::new(resource_path).join(&r.path).is_file();
+    write!(
+        out,
+        "  {:<8} {}{}\n",
+        if found { "found" } else { "missing" },
+        r.path,
+        if r.only_for_32_bit {
+            " (only needed for 32-bit tracees)"
+        } else {
+            ""
+        }
+    )
+}
@@ -0,0 +1,111 @@
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    session::{
+        runtime_dir::{find_stale_session_dirs, StaleSessionDir, TraceeEntry},
+        session_inner::session_inner::SessionInner,
+    },
+    util::tmp_dir,
+};
+use libc::pid_t;
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use std::{
+    fs,
+    io,
+    io::{stdout, Write},
+    path::{Path, PathBuf},
+};
+
+pub struct CleanupCommand {
+    dry_run: bool,
+}
+
+impl CleanupCommand {
+    pub fn new(options: &RdOptions) -> CleanupCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Cleanup { dry_run } => CleanupCommand { dry_run },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Cleanup` variant!"),
+        }
+    }
+}
+
+impl RdCommand for CleanupCommand {
+    fn run(&mut self) -> io::Result<()> {
+        self.cleanup(&mut stdout())
+    }
+}
+
+impl CleanupCommand {
+    fn cleanup(&self, out: &mut dyn Write) -> io::Result<()> {
+        let stale_dirs = find_stale_session_dirs();
+        if stale_dirs.is_empty() {
+            write!(out, "No leftovers from crashed rd sessions found.\n")?;
+            return Ok(());
+        }
+
+        for dir in &stale_dirs {
+            self.cleanup_session(dir, out)?;
+        }
+        Ok(())
+    }
+
+    fn cleanup_session(&self, dir: &StaleSessionDir, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "Session of rd process {}:\n", dir.rd_pid)?;
+        let tracees: Vec<TraceeEntry> = dir.tracees();
+
+        for tracee in tracees.iter().filter(|t| t.is_alive()) {
+            write!(out, "  killing leftover tracee {}\n", tracee.tid)?;
+            if !self.dry_run {
+                // The tracee may have exited in the meantime, so ignore errors.
+                kill(Pid::from_raw(tracee.tid), Signal::SIGKILL).unwrap_or(());
+            }
+        }
+
+        let tids: Vec<pid_t> = tracees.iter().map(|t| t.tid).collect();
+        for file in leftover_shared_files(&tids) {
+            write!(out, "  removing {:?}\n", file)?;
+            if !self.dry_run {
+                fs::remove_file(&file).unwrap_or(());
+            }
+        }
+
+        write!(out, "  removing {:?}\n", dir.path)?;
+        if !self.dry_run {
+            fs::remove_dir_all(&dir.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shared memory segments are normally unlinked right after they are created
+/// (see `AutoRemoteSyscalls::create_shared_mmap()`) but if rd dies in between
+/// they stay around. They are named `<prefix><name>-<tgid>-<nonce>`.
+fn leftover_shared_files(tids: &[pid_t]) -> Vec<PathBuf> {
+    let prefix = SessionInner::rd_mapping_prefix().trim_start_matches('/');
+    let entries = match fs::read_dir(Path::new(&tmp_dir())) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name();
+            let name = match name.to_str() {
+                Some(name) if name.starts_with(prefix) => name,
+                _ => return false,
+            };
+            let maybe_tgid = name.rsplitn(3, '-').nth(1).and_then(|s| s.parse().ok());
+            match maybe_tgid {
+                Some(tgid) => tids.contains(&tgid),
+                None => false,
+            }
+        })
+        .map(|e| e.path())
+        .collect()
+}
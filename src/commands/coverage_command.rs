@@ -0,0 +1,250 @@
+use crate::{
+    assert_prerequisites,
+    commands::{
+        call_trace::ElfSymbols,
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    log::LogLevel::LogInfo,
+    remote_ptr::{RemotePtr, Void},
+    session::{
+        replay_session,
+        replay_session::{ReplaySession, ReplayStatus},
+        session_inner::RunCommand,
+        SessionSharedPtr,
+    },
+    trace::trace_frame::FrameTime,
+    util::raise_resource_limits,
+};
+use gimli::{EndianSlice, LittleEndian, SectionId};
+use goblin::elf::Elf;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    fs,
+    fs::File,
+    io,
+    io::{stdout, BufWriter, Write},
+    path::PathBuf,
+};
+
+/// Replays a trace singlestepping and counts how often each instruction in a
+/// mapped file ran, then writes line coverage of the sources the files'
+/// DWARF line tables name. This gets coverage out of a recording of an
+/// ordinary build, without rebuilding with instrumentation.
+pub struct CoverageCommand {
+    format: String,
+    output: Option<PathBuf>,
+    trace_start: FrameTime,
+    trace_end: FrameTime,
+    cpu_unbound: bool,
+    trace_dir: Option<PathBuf>,
+}
+
+/// Execution counts by file offset, for each mapped file.
+type Hits = HashMap<OsString, HashMap<u64, u64>>;
+
+impl CoverageCommand {
+    pub fn new(options: &RdOptions) -> CoverageCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Coverage {
+                format,
+                output,
+                trace_start,
+                trace_end,
+                cpu_unbound,
+                trace_dir,
+            } => CoverageCommand {
+                format,
+                output,
+                trace_start: trace_start.unwrap_or(FrameTime::MIN),
+                trace_end: trace_end.unwrap_or(FrameTime::MAX),
+                cpu_unbound,
+                trace_dir,
+            },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Coverage` variant!"),
+        }
+    }
+
+    fn session_flags(&self) -> replay_session::Flags {
+        replay_session::Flags {
+            redirect_stdio: false,
+            share_private_mappings: false,
+            cpu_unbound: self.cpu_unbound,
+        }
+    }
+
+    fn collect(&self) -> Hits {
+        let session: SessionSharedPtr =
+            ReplaySession::create(self.trace_dir.as_ref(), self.session_flags());
+        let replay_session = session.as_replay().unwrap();
+        let mut hits = Hits::new();
+
+        raise_resource_limits();
+
+        while replay_session.trace_reader().time() < self.trace_end {
+            let before_time: FrameTime = replay_session.trace_reader().time();
+            let mut cmd = RunCommand::RunContinue;
+            if replay_session.done_initial_exec() && before_time >= self.trace_start {
+                if let Some(t) = replay_session.current_task() {
+                    cmd = RunCommand::RunSinglestepFastForward;
+                    let t = t.borrow();
+                    let ip = RemotePtr::<Void>::new_from_val(t.ip().register_value());
+                    if let Some(m) = t.vm().mapping_of(ip) {
+                        if !m.map.fsname().is_empty() {
+                            let offset = m.map.file_offset_bytes() + (ip - m.map.start()) as u64;
+                            *hits
+                                .entry(m.map.fsname().to_owned())
+                                .or_default()
+                                .entry(offset)
+                                .or_default() += 1;
+                        }
+                    }
+                }
+            }
+
+            let result = replay_session.replay_step(cmd);
+            if result.status == ReplayStatus::ReplayExited {
+                break;
+            }
+        }
+
+        log!(LogInfo, "Coverage replay finished");
+        hits
+    }
+}
+
+/// Line hit counts by source file, and instruction hit counts by virtual
+/// address for each mapped file, so code without line tables is covered too.
+#[derive(Default, Serialize)]
+struct Coverage {
+    sources: BTreeMap<String, BTreeMap<u64, u64>>,
+    binaries: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl Coverage {
+    fn new(hits: &Hits) -> Coverage {
+        let mut coverage = Coverage::default();
+        for (file, offsets) in hits {
+            let symbols = match ElfSymbols::read(file) {
+                Some(symbols) => symbols,
+                None => continue,
+            };
+            let by_addr: BTreeMap<u64, u64> = offsets
+                .iter()
+                .filter_map(|(&offset, &count)| Some((symbols.vaddr_of_offset(offset)?, count)))
+                .collect();
+            coverage.binaries.insert(
+                file.to_string_lossy().into_owned(),
+                by_addr
+                    .iter()
+                    .map(|(addr, count)| (format!("{:#x}", addr), *count))
+                    .collect(),
+            );
+            // Files without usable DWARF just don't contribute lines.
+            let _ = add_lines(&mut coverage.sources, file, &by_addr);
+        }
+        coverage
+    }
+
+    fn write_lcov(&self, out: &mut dyn Write) -> io::Result<()> {
+        for (source, lines) in &self.sources {
+            write!(out, "SF:{}\n", source)?;
+            for (line, count) in lines {
+                write!(out, "DA:{},{}\n", line, count)?;
+            }
+            write!(out, "LH:{}\n", lines.values().filter(|&&c| c > 0).count())?;
+            write!(out, "LF:{}\n", lines.len())?;
+            write!(out, "end_of_record\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Walks the line tables of `file`. A line's count is the highest count of
+/// the instructions attributed to it; lines none of whose instructions ran
+/// are reported with a count of 0.
+fn add_lines(
+    sources: &mut BTreeMap<String, BTreeMap<u64, u64>>,
+    file: &OsString,
+    by_addr: &BTreeMap<u64, u64>,
+) -> Result<(), gimli::Error> {
+    let data = fs::read(file).map_err(|_| gimli::Error::Io)?;
+    let elf = Elf::parse(&data).map_err(|_| gimli::Error::Io)?;
+    let section = |id: SectionId| -> Result<EndianSlice<LittleEndian>, gimli::Error> {
+        let bytes = elf
+            .section_headers
+            .iter()
+            .find(|sh| matches!(elf.shdr_strtab.get(sh.sh_name), Some(Ok(n)) if n == id.name()))
+            .and_then(|sh| data.get(sh.sh_offset as usize..(sh.sh_offset + sh.sh_size) as usize))
+            .unwrap_or(&[]);
+        Ok(EndianSlice::new(bytes, LittleEndian))
+    };
+    let dwarf = gimli::Dwarf::load(section, |_| Ok(EndianSlice::new(&[], LittleEndian)))?;
+
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let program = match unit.line_program.clone() {
+            Some(program) => program,
+            None => continue,
+        };
+        let mut rows = program.rows();
+        // The row whose instructions run up to the next row's address.
+        let mut prev: Option<(u64, String, u64)> = None;
+        while let Some((header, row)) = rows.next_row()? {
+            if let Some((start, source, line)) = prev.take() {
+                let count = by_addr
+                    .range(start..row.address().max(start))
+                    .map(|(_, &count)| count)
+                    .max()
+                    .unwrap_or(0);
+                let entry = sources.entry(source).or_default().entry(line).or_default();
+                *entry = (*entry).max(count);
+            }
+            if row.end_sequence() {
+                continue;
+            }
+            let line = match row.line() {
+                Some(line) => line,
+                None => continue,
+            };
+            let file = match row.file(header) {
+                Some(file) => file,
+                None => continue,
+            };
+            let mut path = PathBuf::new();
+            if let Some(comp_dir) = &unit.comp_dir {
+                path.push(comp_dir.to_string_lossy().as_ref());
+            }
+            if let Some(dir) = file.directory(header) {
+                path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+            }
+            path.push(
+                dwarf
+                    .attr_string(&unit, file.path_name())?
+                    .to_string_lossy()
+                    .as_ref(),
+            );
+            prev = Some((row.address(), path.to_string_lossy().into_owned(), line));
+        }
+    }
+    Ok(())
+}
+
+impl RdCommand for CoverageCommand {
+    fn run(&mut self) -> io::Result<()> {
+        assert_prerequisites(None);
+        let coverage = Coverage::new(&self.collect());
+        let mut out: Box<dyn Write> = match &self.output {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(stdout()),
+        };
+        if self.format == "json" {
+            write!(out, "{}\n", serde_json::to_string(&coverage).unwrap())
+        } else {
+            coverage.write_lcov(&mut out)
+        }
+    }
+}
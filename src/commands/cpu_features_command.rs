@@ -0,0 +1,38 @@
+use crate::{
+    commands::RdCommand,
+    hypervisor::host_hypervisor,
+    util::{cpuid, CPUID_GETEXTENDEDFEATURES, CPUID_GETFEATURES, CPUID_GETXSAVE},
+};
+use std::io;
+
+/// Prints `rd record` options that mask out the CPUID features this machine
+/// lacks, so that traces recorded with them can be replayed here.
+pub struct CpuFeaturesCommand;
+
+impl CpuFeaturesCommand {
+    pub fn new() -> CpuFeaturesCommand {
+        CpuFeaturesCommand
+    }
+}
+
+impl RdCommand for CpuFeaturesCommand {
+    fn run(&mut self) -> io::Result<()> {
+        let features = cpuid(CPUID_GETFEATURES, 0);
+        let features_ext = cpuid(CPUID_GETEXTENDEDFEATURES, 0);
+        let features_xsave = cpuid(CPUID_GETXSAVE, 1);
+        println!(
+            "--disable-cpuid-features {:#x},{:#x} \
+             --disable-cpuid-features-ext {:#x},{:#x},{:#x} \
+             --disable-cpuid-features-xsave {:#x}",
+            !features.ecx,
+            !features.edx,
+            !features_ext.ebx,
+            !features_ext.ecx,
+            !features_ext.edx,
+            !features_xsave.eax
+        );
+        // On stderr so that the options can be captured as they are.
+        eprintln!("hypervisor: {}", host_hypervisor());
+        Ok(())
+    }
+}
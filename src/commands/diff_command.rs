@@ -0,0 +1,184 @@
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    trace::{
+        trace_frame::TraceFrame,
+        trace_reader::{RawData, TraceReader},
+    },
+};
+use libc::pid_t;
+use std::{
+    collections::HashMap,
+    io,
+    io::{stdout, ErrorKind, Write},
+    path::PathBuf,
+};
+
+pub struct DiffCommand {
+    trace1: PathBuf,
+    trace2: PathBuf,
+}
+
+impl DiffCommand {
+    pub fn new(options: &RdOptions) -> DiffCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Diff { trace1, trace2 } => DiffCommand { trace1, trace2 },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Diff` variant!"),
+        }
+    }
+}
+
+impl RdCommand for DiffCommand {
+    fn run(&mut self) -> io::Result<()> {
+        let mut trace1 = TraceReader::new(Some(&self.trace1));
+        let mut trace2 = TraceReader::new(Some(&self.trace2));
+        if diff_traces(&mut trace1, &mut trace2, &mut stdout())? {
+            Ok(())
+        } else {
+            Err(io::Error::new(ErrorKind::Other, "Traces differ"))
+        }
+    }
+}
+
+/// Recorded tids differ between recordings, so we pair them up in the order
+/// they first appear.
+#[derive(Default)]
+struct TidMap {
+    one_to_two: HashMap<pid_t, pid_t>,
+    two_to_one: HashMap<pid_t, pid_t>,
+}
+
+impl TidMap {
+    /// False if either tid is already paired with a different one.
+    fn pair(&mut self, tid1: pid_t, tid2: pid_t) -> bool {
+        let a = *self.one_to_two.entry(tid1).or_insert(tid2);
+        let b = *self.two_to_one.entry(tid2).or_insert(tid1);
+        a == tid2 && b == tid1
+    }
+}
+
+/// Walks both traces event by event and reports the first event that
+/// differs. Returns true if the traces are the same.
+fn diff_traces(
+    trace1: &mut TraceReader,
+    trace2: &mut TraceReader,
+    out: &mut dyn Write,
+) -> io::Result<bool> {
+    let mut tids = TidMap::default();
+    loop {
+        match (trace1.at_end(), trace2.at_end()) {
+            (true, true) => {
+                write!(out, "Traces match ({} events)\n", trace1.time())?;
+                return Ok(true);
+            }
+            (true, false) | (false, true) => {
+                let (ended, n) = if trace1.at_end() { (1, 2) } else { (2, 1) };
+                write!(
+                    out,
+                    "Trace {} ends after event {}, trace {} goes on\n",
+                    ended,
+                    trace1.time(),
+                    n
+                )?;
+                return Ok(false);
+            }
+            (false, false) => (),
+        }
+
+        let frame1 = trace1.read_frame();
+        let frame2 = trace2.read_frame();
+        let data1 = read_data_records(trace1);
+        let data2 = read_data_records(trace2);
+
+        let mut differences = Vec::new();
+        if !tids.pair(frame1.tid(), frame2.tid()) {
+            differences.push("recorded by different tasks".to_owned());
+        }
+        if frame1.event().event_type() != frame2.event().event_type() {
+            differences.push("different events".to_owned());
+        } else if frame1.ticks() != frame2.ticks() {
+            differences.push(format!(
+                "ticks {} != {} ({:+})",
+                frame1.ticks(),
+                frame2.ticks(),
+                frame2.ticks() as i64 - frame1.ticks() as i64
+            ));
+        }
+        diff_registers(&frame1, &frame2, &mut differences);
+        diff_data_records(&data1, &data2, &mut differences);
+
+        if !differences.is_empty() {
+            write!(out, "Traces diverge at event {}:\n", frame1.time())?;
+            write_frame_summary(1, &frame1, out)?;
+            write_frame_summary(2, &frame2, out)?;
+            for d in &differences {
+                write!(out, "  {}\n", d)?;
+            }
+            return Ok(false);
+        }
+    }
+}
+
+fn read_data_records(trace: &mut TraceReader) -> Vec<RawData> {
+    let mut records = Vec::new();
+    while let Some(d) = trace.read_raw_data_for_frame() {
+        records.push(d);
+    }
+    records
+}
+
+fn write_frame_summary(n: u32, frame: &TraceFrame, out: &mut dyn Write) -> io::Result<()> {
+    write!(
+        out,
+        "  trace {}: tid {} {} ticks {}\n",
+        n,
+        frame.tid(),
+        frame.event(),
+        frame.ticks()
+    )
+}
+
+fn diff_registers(frame1: &TraceFrame, frame2: &TraceFrame, differences: &mut Vec<String>) {
+    let (regs1, regs2) = (frame1.regs_ref(), frame2.regs_ref());
+    if regs1.arch() != regs2.arch() {
+        differences.push(format!(
+            "architecture {:?} != {:?}",
+            regs1.arch(),
+            regs2.arch()
+        ));
+        return;
+    }
+    for (name, val1, val2) in regs1.mismatches(regs2) {
+        differences.push(format!("{} {:#x} != {:#x}", name, val1, val2));
+    }
+}
+
+fn diff_data_records(data1: &[RawData], data2: &[RawData], differences: &mut Vec<String>) {
+    if data1.len() != data2.len() {
+        differences.push(format!("{} data records != {}", data1.len(), data2.len()));
+    }
+    for (i, (d1, d2)) in data1.iter().zip(data2.iter()).enumerate() {
+        if d1.addr != d2.addr || d1.data.len() != d2.data.len() {
+            differences.push(format!(
+                "data record {}: {} bytes at {} != {} bytes at {}",
+                i,
+                d1.data.len(),
+                d1.addr,
+                d2.data.len(),
+                d2.addr
+            ));
+        } else if let Some(offset) = d1.data.iter().zip(&d2.data).position(|(a, b)| a != b) {
+            let differing = d1.data.iter().zip(&d2.data).filter(|(a, b)| a != b).count();
+            differences.push(format!(
+                "data record {} ({} bytes at {}): {} bytes differ, the first at offset {}",
+                i,
+                d1.data.len(),
+                d1.addr,
+                differing,
+                offset
+            ));
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
     commands::{
+        call_trace::ElfSymbols,
         rd_options::{RdOptions, RdSubCommand},
         RdCommand,
     },
@@ -14,15 +15,18 @@ use crate::{
     session::address_space::kernel_mapping::KernelMapping,
     trace::{
         trace_frame::{FrameTime, TraceFrame},
-        trace_reader::{TraceReader, ValidateSourceFile},
+        trace_mark::TraceMark,
+        trace_reader::{RawData, TraceReader, ValidateSourceFile},
         trace_stream,
         trace_stream::{MappedData, MappedDataSource},
-        trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
+        trace_task_event::{TaskFilter, TraceTaskEvent, TraceTaskEventVariant},
     },
 };
+use libc::{pid_t, CLONE_VM};
 use nix::sys::mman::{MapFlags, ProtFlags};
 use std::{
-    collections::HashMap,
+    cmp::min,
+    collections::{BTreeMap, HashMap},
     ffi::OsString,
     io,
     io::{stderr, stdout, Write},
@@ -36,9 +40,13 @@ pub struct DumpCommand {
     dump_task_events: bool,
     dump_recorded_data_metadata: bool,
     dump_mmaps: bool,
+    dump_raw_data: bool,
+    raw_data_limit: usize,
+    symbols: bool,
     raw_dump: bool,
     statistics: bool,
     only_tid: Option<libc::pid_t>,
+    only_pid: Option<libc::pid_t>,
     trace_dir: Option<PathBuf>,
     event_spec: Option<(FrameTime, Option<FrameTime>)>,
 }
@@ -51,9 +59,13 @@ impl DumpCommand {
                 task_events,
                 recorded_metadata,
                 mmaps,
+                raw_data,
+                raw_data_limit,
+                symbols,
                 raw_dump,
                 statistics,
                 only_tid,
+                only_pid,
                 trace_dir,
                 event_spec,
             } => DumpCommand {
@@ -61,9 +73,13 @@ impl DumpCommand {
                 dump_task_events: task_events,
                 dump_recorded_data_metadata: recorded_metadata,
                 dump_mmaps: mmaps,
+                dump_raw_data: raw_data,
+                raw_data_limit,
+                symbols,
                 raw_dump,
                 statistics,
                 only_tid,
+                only_pid,
                 trace_dir,
                 event_spec,
             },
@@ -119,42 +135,39 @@ impl DumpCommand {
             Some((s, Some(e))) => (s, e),
         };
 
-        let mut task_events: HashMap<FrameTime, TraceTaskEvent> = HashMap::new();
-        let mut last_time: FrameTime = 0;
-        loop {
-            let mut the_time: FrameTime = 0;
-            let maybe_r = trace.read_task_event(Some(&mut the_time));
-            if maybe_r.is_none() {
-                break;
-            }
-
-            if the_time < last_time {
-                fatal!(
-                    "TraceTaskEvent times non-monotonic (time:{}, last time:{})",
-                    the_time,
-                    last_time
-                );
-            }
+        let task_events: HashMap<FrameTime, TraceTaskEvent> =
+            trace.task_events().into_iter().collect();
 
-            let r = maybe_r.unwrap();
-            task_events.insert(the_time, r);
-            last_time = the_time;
+        let mut marks: HashMap<FrameTime, Vec<TraceMark>> = HashMap::new();
+        for mark in trace.marks() {
+            marks.entry(mark.time).or_default().push(mark);
         }
 
-        let process_raw_data = self.dump_syscallbuf || self.dump_recorded_data_metadata;
+        let filter = TaskFilter::new(&trace.tasks(), self.only_tid, self.only_pid);
+        let mut symbolizer = TraceSymbolizer::default();
+        let process_raw_data =
+            self.dump_syscallbuf || self.dump_recorded_data_metadata || self.dump_raw_data;
         while !trace.at_end() {
             let frame = trace.read_frame();
             if end < frame.time() {
                 return Ok(());
             }
-            if start <= frame.time()
-                && frame.time() <= end
-                && (self.only_tid.is_none() || self.only_tid.unwrap() == frame.tid())
-            {
+            if self.symbols {
+                if let Some(task_event) = task_events.get(&frame.time()) {
+                    symbolizer.task_event(task_event);
+                }
+            }
+            if start <= frame.time() && frame.time() <= end && filter.matches(frame.tid()) {
                 if self.raw_dump {
                     frame.dump_raw(Some(f))?;
                 } else {
                     frame.dump(Some(f))?;
+                    if self.symbols && frame.event().record_regs() {
+                        let ip = frame.regs_ref().ip().register_value();
+                        if let Some(name) = symbolizer.name(frame.tid(), ip) {
+                            write!(f, "  {{ ip:{:#x} <{}> }}\n", ip, name)?;
+                        }
+                    }
                 }
                 if self.dump_syscallbuf {
                     unsafe {
@@ -166,6 +179,14 @@ impl DumpCommand {
                         .get(&frame.time())
                         .map(|task_event| dump_task_event(f, task_event));
                 }
+                if !self.raw_dump {
+                    for mark in marks.get(&frame.time()).into_iter().flatten() {
+                        match mark.tid {
+                            Some(tid) => write!(f, "  Mark tid={} {:?}\n", tid, mark.label)?,
+                            None => write!(f, "  Mark {:?}\n", mark.label)?,
+                        }
+                    }
+                }
 
                 loop {
                     let mut data: trace_stream::MappedData = Default::default();
@@ -181,6 +202,9 @@ impl DumpCommand {
                     }
 
                     let km = maybe_km.unwrap();
+                    if self.symbols {
+                        symbolizer.add_mapping(frame.tid(), &km, &data);
+                    }
                     if self.dump_mmaps {
                         let mut prot_flags = Vec::<u8>::new();
                         prot_flags.extend_from_slice(b"rwxp");
@@ -223,6 +247,21 @@ impl DumpCommand {
                     }
                 }
 
+                while self.dump_raw_data {
+                    match trace.read_raw_data_for_frame() {
+                        Some(data) => {
+                            write!(
+                                f,
+                                "  {{ tid:{}, addr:{:#x}, length:{:#x} }}\n",
+                                data.rec_tid,
+                                data.addr.as_usize(),
+                                data.data.len()
+                            )?;
+                            dump_raw_data(f, &data, self.raw_data_limit)?;
+                        }
+                        None => break,
+                    }
+                }
                 while let Some(data) = trace.read_raw_data_metadata_for_frame() {
                     if self.dump_recorded_data_metadata {
                         // DIFF NOTE rr prints `(nil)` if addr is 0 or length is 0.
@@ -248,8 +287,10 @@ impl DumpCommand {
                         None,
                         None,
                     );
-                    if maybe_km.is_none() {
-                        break;
+                    match maybe_km {
+                        Some(km) if self.symbols => symbolizer.add_mapping(frame.tid(), &km, &data),
+                        Some(_) => (),
+                        None => break,
                     }
                 }
                 while process_raw_data && trace.read_raw_data_metadata_for_frame().is_some() {}
@@ -296,8 +337,128 @@ fn dump_task_event(out: &mut dyn Write, event: &TraceTaskEvent) -> io::Result<()
                 ev.exit_status().get(),
             )?;
         }
+        TraceTaskEventVariant::Rename(ev) => {
+            write!(
+                out,
+                "  TraceTaskEvent::RENAME tid={} name={}\n",
+                event.tid(),
+                ev.name().to_string_lossy()
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Follows the file mappings of each recorded address space through the
+/// trace, so that addresses can be named without a replay.
+#[derive(Default)]
+struct TraceSymbolizer {
+    /// Index into `spaces` of each tid's address space.
+    space_of: HashMap<pid_t, usize>,
+    /// Mapping start -> (end, file to read symbols from, offset in it).
+    spaces: Vec<BTreeMap<usize, (usize, OsString, u64)>>,
+    files: HashMap<OsString, Option<ElfSymbols>>,
+}
+
+impl TraceSymbolizer {
+    fn space(&mut self, tid: pid_t) -> usize {
+        let spaces = &mut self.spaces;
+        *self.space_of.entry(tid).or_insert_with(|| {
+            spaces.push(BTreeMap::new());
+            spaces.len() - 1
+        })
+    }
+
+    fn task_event(&mut self, event: &TraceTaskEvent) {
+        match event.event_variant() {
+            TraceTaskEventVariant::Clone(ev) => {
+                let parent_space = self.space(ev.parent_tid());
+                let space = if ev.clone_flags() & CLONE_VM != 0 {
+                    parent_space
+                } else {
+                    self.spaces.push(self.spaces[parent_space].clone());
+                    self.spaces.len() - 1
+                };
+                self.space_of.insert(event.tid(), space);
+            }
+            TraceTaskEventVariant::Exec(_) => {
+                self.spaces.push(BTreeMap::new());
+                self.space_of.insert(event.tid(), self.spaces.len() - 1);
+            }
+            _ => (),
+        }
     }
 
+    fn add_mapping(&mut self, tid: pid_t, km: &KernelMapping, data: &MappedData) {
+        let (file, offset) = match data.source {
+            MappedDataSource::SourceFile => (data.filename.clone(), data.data_offset_bytes as u64),
+            _ if !km.fsname().is_empty() => (km.fsname().to_os_string(), km.file_offset_bytes()),
+            _ => return,
+        };
+        let (start, end) = (km.start().as_usize(), km.end().as_usize());
+        let space = self.space(tid);
+        let mappings = &mut self.spaces[space];
+        let overlapping: Vec<usize> = mappings
+            .range(..end)
+            .filter(|(_, &(m_end, _, _))| m_end > start)
+            .map(|(&m_start, _)| m_start)
+            .collect();
+        for m_start in overlapping {
+            mappings.remove(&m_start);
+        }
+        mappings.insert(start, (end, file, offset));
+    }
+
+    /// `function+offset` for the code at `ip` in `tid`'s address space.
+    fn name(&mut self, tid: pid_t, ip: usize) -> Option<String> {
+        let space = self.space(tid);
+        let (&start, (end, file, offset)) = self.spaces[space].range(..=ip).next_back()?;
+        if ip >= *end {
+            return None;
+        }
+        let symbols = self
+            .files
+            .entry(file.clone())
+            .or_insert_with(|| ElfSymbols::read(file))
+            .as_ref()?;
+        let vaddr = symbols.vaddr_of_offset(offset + (ip - start) as u64)?;
+        let (function, function_offset) = symbols.function_and_offset(vaddr)?;
+        Some(format!("{}+{:#x}", function, function_offset))
+    }
+}
+
+/// Hexdump the first `limit` bytes of `data` (all of them if `limit` is 0), 16
+/// to a line, with their addresses and an ASCII gutter.
+fn dump_raw_data(out: &mut dyn Write, data: &RawData, limit: usize) -> io::Result<()> {
+    let len = if limit == 0 {
+        data.data.len()
+    } else {
+        min(limit, data.data.len())
+    };
+    for (i, line) in data.data[..len].chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        write!(
+            out,
+            "    {:#x}: {:<47}  |{}|\n",
+            data.addr.as_usize() + i * 16,
+            hex.join(" "),
+            ascii
+        )?;
+    }
+    if len < data.data.len() {
+        write!(out, "    ... {} more bytes\n", data.data.len() - len)?;
+    }
     Ok(())
 }
 
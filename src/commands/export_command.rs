@@ -0,0 +1,69 @@
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    trace::{trace_export::export_to_rr, trace_reader::TraceReader, trace_stream::Recorder},
+};
+use std::{
+    ffi::OsString,
+    io,
+    io::{stdout, Write},
+    path::PathBuf,
+};
+
+pub struct ExportCommand {
+    format: String,
+    output: Option<PathBuf>,
+    trace_dir: Option<PathBuf>,
+}
+
+impl ExportCommand {
+    pub fn new(options: &RdOptions) -> ExportCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Export {
+                format,
+                output,
+                trace_dir,
+            } => ExportCommand {
+                format,
+                output,
+                trace_dir,
+            },
+            _ => panic!("Unexpected RdSubCommand variant. Not an `Export` variant!"),
+        }
+    }
+}
+
+impl RdCommand for ExportCommand {
+    fn run(&mut self) -> io::Result<()> {
+        let trace = TraceReader::new(self.trace_dir.as_ref());
+        let output = match &self.output {
+            Some(output) => output.clone(),
+            None => {
+                let mut dir = OsString::from(trace.dir());
+                dir.push("-");
+                dir.push(&self.format);
+                PathBuf::from(dir)
+            }
+        };
+
+        let mut out = stdout();
+        if trace.recorded_by() == Recorder::Rr {
+            write!(out, "Trace was recorded by rr; there is nothing to convert\n")?;
+            return Ok(());
+        }
+        let report = export_to_rr(&trace, &output)?;
+        if report.decrypted {
+            write!(
+                out,
+                "Decrypted the trace; the exported copy is not encrypted\n"
+            )?;
+        }
+        for name in &report.dropped_files {
+            write!(out, "Left out {:?}\n", name)?;
+        }
+        write!(out, "Exported to {:?}\n", output)?;
+        Ok(())
+    }
+}
@@ -4,6 +4,8 @@ use crate::{
         RdCommand,
     },
     trace::{
+        trace_frame::FrameTime,
+        trace_mark::TraceMark,
         trace_reader::TraceReader,
         trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
     },
@@ -15,7 +17,8 @@ use std::{
     fmt::Write as fmtWrite,
     io,
     io::{stdout, Write},
-    path::PathBuf,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
 };
 
 pub struct PsCommand {
@@ -41,13 +44,11 @@ type TidPidMap = HashMap<pid_t, pid_t>;
 
 impl PsCommand {
     fn ps(&mut self, out: &mut dyn Write) -> io::Result<()> {
-        let mut trace = TraceReader::new(self.trace_dir.as_ref());
-        write!(out, "PID\tPPID\tEXIT\tCMD\n")?;
+        let trace = TraceReader::new(self.trace_dir.as_ref());
+        write!(out, "PID\tPPID\tEXIT\tNAME\tCMD\n")?;
 
-        let mut events: Vec<TraceTaskEvent> = Vec::new();
-        while let Some(r) = trace.read_task_event(None) {
-            events.push(r);
-        }
+        let (event_times, events): (Vec<FrameTime>, Vec<TraceTaskEvent>) =
+            trace.task_events().into_iter().unzip();
 
         let not_exec = match events[0].event_variant() {
             TraceTaskEventVariant::Exec(_) => false,
@@ -66,9 +67,10 @@ impl PsCommand {
         tid_to_pid.insert(initial_tid, initial_tid);
         write!(
             out,
-            "{}\t--\t{}\t",
+            "{}\t--\t{}\t{}\t",
             initial_tid,
-            find_exit_code(initial_tid, &events, &tid_to_pid)
+            find_exit_code(initial_tid, &events, &tid_to_pid),
+            find_prname(initial_tid, &events, 0, &tid_to_pid)
         )?;
         write_exec_cmd_line(&events[0], out)?;
 
@@ -97,9 +99,10 @@ impl PsCommand {
                         None => {
                             // The main thread exited. All other threads must too, so there
                             // is no more opportunity for e's pid to exec.
-                            write!(out, "(forked without exec)\n")?
+                            write!(out, "--\t(forked without exec)\n")?
                         }
                         Some(cmd_line_index) => {
+                            write!(out, "{}\t", find_prname(pid, &events, i, &tid_to_pid))?;
                             write_exec_cmd_line(&events[cmd_line_index], out)?;
                         }
                     }
@@ -107,10 +110,39 @@ impl PsCommand {
                 _ => (),
             }
         }
-        Ok(())
+
+        write_marks(&trace.marks(), &events, &event_times, out)
     }
 }
 
+/// List the marks tracees made with SYS_rdcall_annotate, with the pid of the
+/// annotating process at that point.
+fn write_marks(
+    marks: &[TraceMark],
+    events: &[TraceTaskEvent],
+    event_times: &[FrameTime],
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    if marks.iter().all(|m| m.tid.is_none()) {
+        return Ok(());
+    }
+
+    write!(out, "\nEVENT\tPID\tMARK\n")?;
+    let mut tid_to_pid = TidPidMap::new();
+    let mut next_event = 0;
+    for mark in marks {
+        while next_event < events.len() && event_times[next_event] <= mark.time {
+            update_tid_to_pid_map(&mut tid_to_pid, &events[next_event]);
+            next_event += 1;
+        }
+        if let Some(tid) = mark.tid {
+            let pid = tid_to_pid.get(&tid).copied().unwrap_or(tid);
+            write!(out, "{}\t{}\t{}\n", mark.time, pid, mark.label)?;
+        }
+    }
+    Ok(())
+}
+
 fn update_tid_to_pid_map(tid_to_pid: &mut TidPidMap, e: &TraceTaskEvent) {
     match e.event_variant() {
         TraceTaskEventVariant::Clone(c) => {
@@ -187,6 +219,48 @@ fn find_cmd_line(
     None
 }
 
+/// The name `pid` ends up with after its first exec at or after `current_event`:
+/// the last prctl(PR_SET_NAME) of its main thread, or the basename of the exec'd
+/// file (truncated the way the kernel does it) if the process never renamed itself.
+fn find_prname(
+    pid: pid_t,
+    events: &[TraceTaskEvent],
+    current_event: usize,
+    current_tid_to_pid: &TidPidMap,
+) -> String {
+    let mut tid_to_pid = current_tid_to_pid.clone();
+    let mut maybe_name: Option<Vec<u8>> = None;
+    for e in events.iter().skip(current_event) {
+        match e.event_variant() {
+            TraceTaskEventVariant::Exec(ex) if tid_to_pid.get(&e.tid()) == Some(&pid) => {
+                if maybe_name.is_some() {
+                    break;
+                }
+                let mut name = Path::new(ex.file_name())
+                    .file_name()
+                    .map_or(Vec::new(), |n| n.as_bytes().to_vec());
+                name.truncate(15);
+                maybe_name = Some(name);
+            }
+            TraceTaskEventVariant::Rename(r) if e.tid() == pid && maybe_name.is_some() => {
+                maybe_name = Some(r.name().as_bytes().to_vec());
+            }
+            TraceTaskEventVariant::Exit(_)
+                if (tid_to_pid.get(&e.tid()) == Some(&pid)
+                    && count_tids_for_pid(&tid_to_pid, pid) == 1) =>
+            {
+                break
+            }
+            _ => (),
+        }
+        update_tid_to_pid_map(&mut tid_to_pid, e);
+    }
+    match maybe_name {
+        Some(name) => String::from_utf8_lossy(&name).into_owned(),
+        None => "--".into(),
+    }
+}
+
 fn write_exec_cmd_line(event: &TraceTaskEvent, out: &mut dyn Write) -> io::Result<()> {
     let mut first = true;
     for word in event.exec_variant().cmd_line() {
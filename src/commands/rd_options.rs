@@ -1,5 +1,5 @@
 use crate::{
-    commands::rerun_command::TraceFields,
+    commands::{rerun_command::TraceFields, stacks::StackEvents},
     flags::{Checksum, DumpOn},
     trace::trace_frame::FrameTime,
 };
@@ -31,9 +31,16 @@ pub struct RdOptions {
     )]
     pub disable_ptrace_exit_events: bool,
 
+    #[structopt(
+        long,
+        help = "Behave as if there were no usable performance counters (as in many VMs)."
+    )]
+    pub force_no_perf: bool,
+
     /// Specify the paths that rd should use to find files such as rr_page_*.  These files
-    /// should be located in `<resource-path>/bin`, `<resource-path>/lib[64]`, and
-    /// `<resource-path>/share` as appropriate.
+    /// should be located in `<resource-path>/bin`, `<resource-path>/lib/rr`, and
+    /// `<resource-path>/share/rr` as appropriate. Overrides $RD_RESOURCE_PATH. See
+    /// `rd check-install`.
     #[structopt(parse(try_from_os_str = parse_resource_path), long)]
     pub resource_path: Option<PathBuf>,
 
@@ -162,6 +169,11 @@ pub enum RdSubCommand {
     #[structopt(name = "buildid")]
     BuildId,
 
+    /// Report where rd looks for the preload library and its other runtime resources,
+    /// and which of them it found. Fails if rd's resource path is missing any.
+    #[structopt(name = "check-install")]
+    CheckInstall,
+
     /// Print `rd record` command line options that will limit the tracee to CPU features
     /// this machine supports. Useful for trace portability: run `rd cpufeatures` on the machine
     /// you plan to replay on, then add those command-line parameters to `rd record` on the
@@ -169,6 +181,55 @@ pub enum RdSubCommand {
     #[structopt(name = "cpufeatures")]
     CpuFeatures,
 
+    /// Kill tracees and remove files left behind by rd sessions whose rd process
+    /// died without cleaning up, e.g. because it crashed.
+    #[structopt(name = "cleanup")]
+    Cleanup {
+        /// Only print what would be cleaned up
+        #[structopt(short = "n", long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Replay the trace singlestepping and write the line coverage of the recorded run,
+    /// using the DWARF line tables of the executable and libraries. Also reports which
+    /// instructions ran in files without line tables. No instrumented build is needed.
+    #[structopt(name = "coverage")]
+    Coverage {
+        /// `lcov` tracefile or `json`
+        #[structopt(long, default_value = "lcov", possible_values = &["lcov", "json"])]
+        format: String,
+
+        /// Write the coverage here instead of to stdout
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        #[structopt(short = "s", long, help = "Start counting at <trace-start>")]
+        trace_start: Option<FrameTime>,
+
+        #[structopt(short = "e", long, help = "Stop counting at <trace-end>")]
+        trace_end: Option<FrameTime>,
+
+        /// Allow replay to run on any CPU. Default is to run on the CPU stored in the trace.
+        /// Note that this may cause a diverge from the recording in some cases
+        #[structopt(short = "u", long)]
+        cpu_unbound: bool,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Compare two traces of the same program event by event and report the first event
+    /// where they differ: its registers, ticks and recorded data. Useful for finding where
+    /// two recordings diverged.
+    #[structopt(name = "diff")]
+    Diff {
+        #[structopt(parse(from_os_str))]
+        trace1: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        trace2: PathBuf,
+    },
+
     /// Dump data from the recorded trace
     #[structopt(name = "dump")]
     Dump {
@@ -188,6 +249,21 @@ pub enum RdSubCommand {
         #[structopt(short = "p", long)]
         mmaps: bool,
 
+        /// Dump the contents of the data recorded for each event, with its
+        /// address and length
+        #[structopt(long)]
+        raw_data: bool,
+
+        /// Print at most this many bytes of each data record with --raw-data.
+        /// 0 means no limit
+        #[structopt(long, default_value = "256")]
+        raw_data_limit: usize,
+
+        /// Name the function each event's ip is in, from the symbol tables of
+        /// the mapped binaries (the copies in the trace where there are any)
+        #[structopt(long)]
+        symbols: bool,
+
         /// Dump trace frames in a more easily machine-parseable
         /// format instead of the default human-readable format
         #[structopt(short = "r", long = "raw")]
@@ -201,6 +277,10 @@ pub enum RdSubCommand {
         #[structopt(short = "t", long = "tid")]
         only_tid: Option<libc::pid_t>,
 
+        /// Dump events only for the threads of the specified pid
+        #[structopt(long = "pid", conflicts_with = "only-tid")]
+        only_pid: Option<libc::pid_t>,
+
         /// Which directory is the trace data in? If omitted the latest trace dir is used
         trace_dir: Option<PathBuf>,
 
@@ -210,6 +290,23 @@ pub enum RdSubCommand {
         event_spec: Option<(FrameTime, Option<FrameTime>)>,
     },
 
+    /// Convert a trace into one another tool can replay, e.g. to compare rd and rr on
+    /// the same recording. rd-only parts of the trace are left out.
+    #[structopt(name = "export")]
+    Export {
+        /// The format to convert to
+        #[structopt(long, default_value = "rr", possible_values = &["rr"])]
+        format: String,
+
+        /// The directory to create for the converted trace. Defaults to the trace
+        /// directory with `-<format>` appended
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
     /// Replay a previously recorded trace.
     #[structopt(name = "replay")]
     Replay {
@@ -267,8 +364,9 @@ pub enum RdSubCommand {
         #[structopt(short = "k", long = "keep-listening")]
         keep_listening: bool,
 
-        /// When true make all private mappings shared with the tracee by default
-        /// to test the corresponding code.
+        /// Share the memory of private file mappings whose contents are stored in the
+        /// trace between all the tracees that map them, copying pages only when they
+        /// are written. Reduces memory use when many processes map the same files
         #[structopt(long = "share-private-mappings")]
         share_private_mappings: bool,
 
@@ -290,6 +388,25 @@ pub enum RdSubCommand {
         #[structopt(long = "stats", parse(try_from_str = parse_stats))]
         stats: Option<u32>,
 
+        /// With -a, wait out the wall-clock time that passed between events in the
+        /// recording, so that e.g. sleeps and idle epoll_waits take as long as they did.
+        /// By default replay skips them
+        #[structopt(long = "realtime")]
+        realtime: bool,
+
+        /// With -a, exit with status 0 instead of the recorded program's exit status (or
+        /// 128 + the signal that killed it)
+        #[structopt(long = "no-propagate-exit")]
+        no_propagate_exit: bool,
+
+        /// Only count the specified tid in --stats, and only step it with --trace
+        #[structopt(long = "tid")]
+        only_tid: Option<pid_t>,
+
+        /// Like --tid, for all the threads of the specified pid
+        #[structopt(long = "pid", conflicts_with = "only-tid")]
+        only_pid: Option<pid_t>,
+
         /// Which directory is the trace data in? If omitted the latest trace dir is used
         trace_dir: Option<PathBuf>,
         // @TODO There are extra debugger options also passed after a `--`
@@ -333,6 +450,26 @@ pub enum RdSubCommand {
         #[structopt(long = "singlestep", parse(try_from_str = crate::commands::rerun_command::parse_regs))]
         singlestep_regs: Option<TraceFields>,
 
+        /// Print a line for each function call and return: `event`, `pid`, `tid`,
+        /// `call`, `return` or `unwind` (for frames left by longjmp or an exception)
+        /// and the function, tab-separated. Functions are named from the ELF symbol
+        /// tables of the mapped files
+        #[structopt(long)]
+        call_trace: bool,
+
+        /// Print the stack of the task at each `syscall`, `signal` or (`all`) event, in
+        /// the folded format flamegraph tools read
+        #[structopt(long)]
+        stacks: Option<StackEvents>,
+
+        /// Only trace the specified tid
+        #[structopt(long = "tid")]
+        only_tid: Option<pid_t>,
+
+        /// Only trace the threads of the specified pid
+        #[structopt(long = "pid", conflicts_with = "only-tid")]
+        only_pid: Option<pid_t>,
+
         /// Which directory is the trace data in? If omitted the latest trace dir is used
         trace_dir: Option<PathBuf>,
     },
@@ -350,6 +487,53 @@ pub enum RdSubCommand {
         /// Which directory is the trace data in? If omitted the latest trace dir is used
         trace_dir: Option<PathBuf>,
     },
+
+    /// Check, without replaying it, whether a trace can be replayed. By default
+    /// checks that the trace files are complete and undamaged, reporting the last
+    /// event that can be replayed if they aren't.
+    #[structopt(name = "verify")]
+    Verify {
+        /// Instead check that this machine can replay the trace: CPU, performance
+        /// counters, kernel features. Prints a report with hints for anything that fails
+        #[structopt(long)]
+        host: bool,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Replay the trace singlestepping and print every change to a range of memory
+    /// as a JSON object per line: the event, pid, tid, ip of the instruction (or
+    /// syscall) that made it, and the old and new bytes in hex. Slow, but finds
+    /// whatever wrote to the range.
+    #[structopt(name = "watch")]
+    Watch {
+        /// Start of the range, e.g. 0x7ffd1234
+        #[structopt(short = "a", long, parse(try_from_str = parse_address))]
+        addr: usize,
+
+        /// Number of bytes to watch
+        #[structopt(short = "n", long, default_value = "8")]
+        size: usize,
+
+        /// Watch the range in this (recorded) process. Default is the first process
+        #[structopt(short = "p", long, parse(try_from_str = parse_pid))]
+        pid: Option<pid_t>,
+
+        #[structopt(short = "s", long, help = "Start watching at <trace-start>")]
+        trace_start: Option<FrameTime>,
+
+        #[structopt(short = "e", long, help = "Stop watching at <trace-end>")]
+        trace_end: Option<FrameTime>,
+
+        /// Allow replay to run on any CPU. Default is to run on the CPU stored in the trace.
+        /// Note that this may cause a diverge from the recording in some cases
+        #[structopt(short = "u", long)]
+        cpu_unbound: bool,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
 }
 
 fn parse_range(range_or_single: &str) -> Result<(FrameTime, Option<FrameTime>), ParseIntError> {
@@ -362,6 +546,15 @@ fn parse_range(range_or_single: &str) -> Result<(FrameTime, Option<FrameTime>),
     Ok((low, high))
 }
 
+fn parse_address(addr: &str) -> Result<usize, ParseIntError> {
+    let addr = addr.trim();
+    if addr.starts_with("0x") || addr.starts_with("0X") {
+        usize::from_str_radix(&addr[2..], 16)
+    } else {
+        addr.parse::<usize>()
+    }
+}
+
 fn parse_pid(maybe_pid: &str) -> Result<pid_t, Box<dyn Error>> {
     let pid = maybe_pid.trim().parse::<pid_t>()?;
     if pid < 1 {
@@ -11,14 +11,27 @@ use crate::{
         session_inner::{session_inner::Statistics, RunCommand},
         SessionSharedPtr,
     },
-    trace::trace_frame::FrameTime,
+    trace::{
+        trace_frame::FrameTime,
+        trace_task_event::{TaskFilter, TraceTask, TraceTaskEventVariant},
+    },
     util::running_under_rd,
+    wait_status::WaitStatus,
 };
 use io::stderr;
-use libc::pid_t;
+use libc::{pid_t, CLONE_THREAD};
 use nix::unistd::{getpid, getppid};
 use replay_session::{ReplaySession, ReplayStatus};
-use std::{ffi::OsString, io, io::Write, path::PathBuf, ptr};
+use std::{
+    ffi::OsString,
+    io,
+    io::Write,
+    path::PathBuf,
+    process::ExitCode,
+    ptr,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 enum CreatedHow {
@@ -77,6 +90,20 @@ pub struct ReplayCommand {
     /// When Some(_), display statistics every N steps.
     dump_interval: Option<u32>,
 
+    /// When true, with autopilot, don't run events earlier than they ran in
+    /// the recording (relative to the first event).
+    realtime: bool,
+
+    /// Count only these tasks in statistics, and singlestep only them.
+    only_tid: Option<pid_t>,
+    only_pid: Option<pid_t>,
+
+    /// With autopilot, exit with the recorded exit status of the initial
+    /// process.
+    propagate_exit: bool,
+    /// How the initial process ended, once autopilot replay has finished.
+    exit_status: Option<WaitStatus>,
+
     trace_dir: Option<PathBuf>,
 }
 
@@ -97,7 +124,12 @@ impl Default for ReplayCommand {
             cpu_unbound: false,
             share_private_mappings: false,
             dump_interval: None,
+            realtime: false,
+            only_tid: None,
+            only_pid: None,
             gdb_options: vec![],
+            propagate_exit: true,
+            exit_status: None,
             trace_dir: None,
         }
     }
@@ -123,6 +155,10 @@ impl ReplayCommand {
                 cpu_unbound,
                 gdb_x_file,
                 stats,
+                realtime,
+                no_propagate_exit,
+                only_tid,
+                only_pid,
                 trace_dir,
                 share_private_mappings,
             } => {
@@ -196,6 +232,9 @@ impl ReplayCommand {
                 if stats.is_some() {
                     flags.dump_interval = stats;
                 }
+                flags.realtime = realtime;
+                flags.only_tid = only_tid;
+                flags.only_pid = only_pid;
 
                 flags.cpu_unbound = cpu_unbound;
 
@@ -204,6 +243,7 @@ impl ReplayCommand {
                     flags.gdb_options.push(OsString::from(interpreter.unwrap()));
                 }
 
+                flags.propagate_exit = !no_propagate_exit;
                 flags.trace_dir = trace_dir;
 
                 flags
@@ -220,7 +260,9 @@ impl ReplayCommand {
         }
     }
 
-    fn serve_replay_no_debugger(&self, out: &mut dyn Write) -> io::Result<()> {
+    /// Returns how the initial process of the recording ended, see
+    /// `initial_process_exit_status()`.
+    fn serve_replay_no_debugger(&self, out: &mut dyn Write) -> io::Result<Option<WaitStatus>> {
         let session: SessionSharedPtr =
             ReplaySession::create(self.trace_dir.as_ref(), self.session_flags());
         let replay_session = session.as_replay().unwrap();
@@ -228,12 +270,31 @@ impl ReplayCommand {
         let mut last_dump_time = timeval::default();
         let mut last_dump_rectime: f64 = 0.0;
         let mut last_stats = Statistics::default();
+        // Of the steps of the tasks `filter` selects.
+        let mut stats = Statistics::default();
+        let tasks = replay_session.trace_reader().tasks();
+        let filter = TaskFilter::new(&tasks, self.only_tid, self.only_pid);
         unsafe { gettimeofday(&raw mut last_dump_time, ptr::null_mut()) };
+        let replay_start = Instant::now();
+        let first_rectime: f64 = replay_session.current_trace_frame().monotonic_time();
 
         loop {
+            if self.realtime {
+                // Events are emulated, so e.g. a recorded nanosleep() returns
+                // immediately. Put back the time the recording spent waiting.
+                let rectime = replay_session.current_trace_frame().monotonic_time();
+                let due = Duration::from_secs_f64((rectime - first_rectime).max(0.0));
+                let elapsed = replay_start.elapsed();
+                if due > elapsed {
+                    sleep(due - elapsed);
+                }
+            }
+
             let mut cmd = RunCommand::RunContinue;
+            let selected = filter.matches(replay_session.current_trace_frame().tid());
             if self.singlestep_to_event > 0
                 && replay_session.trace_reader().time() >= self.singlestep_to_event
+                && selected
             {
                 cmd = RunCommand::RunSinglestep;
                 write!(out, "Stepping from: ")?;
@@ -247,8 +308,16 @@ impl ReplayCommand {
             }
 
             let before_time: FrameTime = replay_session.trace_reader().time();
+            let before_stats = replay_session.statistics();
             let result = replay_session.replay_step(cmd);
             let after_time: FrameTime = replay_session.trace_reader().time();
+            if selected {
+                let after_stats = replay_session.statistics();
+                stats.ticks_processed += after_stats.ticks_processed - before_stats.ticks_processed;
+                stats.syscalls_performed +=
+                    after_stats.syscalls_performed - before_stats.syscalls_performed;
+                stats.bytes_written += after_stats.bytes_written - before_stats.bytes_written;
+            }
             debug_assert!(after_time >= before_time && after_time <= before_time + 1);
             if last_dump_rectime == 0.0 {
                 last_dump_rectime = replay_session.trace_reader().recording_time();
@@ -259,7 +328,6 @@ impl ReplayCommand {
                 unsafe { gettimeofday(&raw mut now, ptr::null_mut()) };
                 let rectime: f64 = replay_session.trace_reader().recording_time();
                 let elapsed_usec: u64 = to_microseconds(&now) - to_microseconds(&last_dump_time);
-                let stats: Statistics = replay_session.statistics();
                 write!(out,
           "[ReplayStatistics] ticks {} syscalls {} bytes_written {} microseconds {} %%realtime {:.0}%%\n",
           stats.ticks_processed - last_stats.ticks_processed,
@@ -285,11 +353,22 @@ impl ReplayCommand {
         }
 
         log!(LogInfo, "Replayer successfully finished");
-        Ok(())
+        Ok(initial_process_exit_status(&tasks))
+    }
+
+    /// The status rd should exit with: the recorded exit status of the initial
+    /// process after an autopilot replay (unless `--no-propagate-exit`), 0
+    /// otherwise.
+    pub fn exit_code(&self) -> ExitCode {
+        let code = match (self.propagate_exit, self.exit_status) {
+            (true, Some(status)) => status.shell_exit_code().unwrap_or(0),
+            _ => 0,
+        };
+        ExitCode::from(code as u8)
     }
 
     // DIFF NOTE: In rr a result code e.g. 0 is return. We simply return Ok(()) if there is no error.
-    fn replay(&self) -> io::Result<()> {
+    fn replay(&mut self) -> io::Result<()> {
         let mut target = gdb_server::Target::default();
         match self.process_created_how {
             CreatedHow::CreatedExec => {
@@ -309,7 +388,7 @@ impl ReplayCommand {
         // complicate the process tree and confuse users.
         if self.dont_launch_debugger {
             if target.event == FrameTime::MAX {
-                self.serve_replay_no_debugger(&mut stderr())?;
+                self.exit_status = self.serve_replay_no_debugger(&mut stderr())?;
             } else {
                 unimplemented!();
             }
@@ -341,6 +420,13 @@ impl RdCommand for ReplayCommand {
             ));
         }
 
+        if self.realtime && !self.dont_launch_debugger {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--realtime requires -a",
+            ));
+        }
+
         assert_prerequisites(None);
 
         if running_under_rd() {
@@ -373,6 +459,43 @@ impl RdCommand for ReplayCommand {
     }
 }
 
+/// How the initial process of the recording ended: the exit status of the
+/// last of its threads to exit. None if it was still running when the
+/// recording ended.
+fn initial_process_exit_status(tasks: &[TraceTask]) -> Option<WaitStatus> {
+    // `tasks` is in creation order, so a thread comes after its parent.
+    let mut in_initial_process = vec![false; tasks.len()];
+    let mut last_exit = None;
+    for (i, task) in tasks.iter().enumerate() {
+        in_initial_process[i] = match (task.parent, task.events.first()) {
+            (None, _) => i == 0,
+            (Some(parent), Some((_, e))) => match e.event_variant() {
+                TraceTaskEventVariant::Clone(c) => {
+                    c.clone_flags() & CLONE_THREAD != 0 && in_initial_process[parent]
+                }
+                _ => false,
+            },
+            (Some(_), None) => false,
+        };
+        if !in_initial_process[i] {
+            continue;
+        }
+        let exit = task
+            .events
+            .iter()
+            .find_map(|(time, e)| match e.event_variant() {
+                TraceTaskEventVariant::Exit(ex) => Some((*time, ex.exit_status())),
+                _ => None,
+            });
+        match (exit, last_exit) {
+            (None, _) => return None,
+            (Some((time, _)), Some((last_time, _))) if time < last_time => (),
+            (Some(exit), _) => last_exit = Some(exit),
+        }
+    }
+    last_exit.map(|(_, status)| status)
+}
+
 fn to_microseconds(tv: &timeval) -> u64 {
     (tv.tv_sec as u64) * 1000000 + (tv.tv_usec as u64)
 }
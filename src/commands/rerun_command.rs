@@ -6,7 +6,9 @@ use crate::{
     assert_prerequisites,
     bindings::kernel::user_regs_struct as native_user_regs_struct,
     commands::{
+        call_trace::CallTracer,
         rd_options::{RdOptions, RdSubCommand},
+        stacks::{StackEvents, Unwinder},
         RdCommand,
     },
     event::{Event, EventType},
@@ -16,7 +18,7 @@ use crate::{
     log::LogLevel::{LogDebug, LogInfo},
     registers::Registers,
     remote_code_ptr::RemoteCodePtr,
-    remote_ptr::RemotePtr,
+    remote_ptr::{RemotePtr, Void},
     session::{
         replay_session,
         replay_session::{ReplaySession, ReplayStatus},
@@ -26,9 +28,10 @@ use crate::{
         SessionSharedPtr,
     },
     taskish_uid::TaskUid,
-    trace::trace_frame::FrameTime,
+    trace::{trace_frame::FrameTime, trace_task_event::TaskFilter},
     util::{raise_resource_limits, running_under_rd},
 };
+use libc::pid_t;
 use nix::unistd::{getpid, getppid};
 use std::{
     fmt::Write as fmtWrite,
@@ -241,6 +244,10 @@ pub struct ReRunCommand {
     trace_end: FrameTime,
     function: Option<RemoteCodePtr>,
     singlestep_trace: Vec<TraceField>,
+    call_trace: bool,
+    stacks: Option<StackEvents>,
+    only_tid: Option<pid_t>,
+    only_pid: Option<pid_t>,
     raw_dump: bool,
     cpu_unbound: bool,
     trace_dir: Option<PathBuf>,
@@ -342,12 +349,20 @@ impl ReRunCommand {
                 cpu_unbound,
                 function_addr,
                 singlestep_regs,
+                call_trace,
+                stacks,
+                only_tid,
+                only_pid,
                 trace_dir,
             } => ReRunCommand {
                 trace_start: trace_start.unwrap_or(FrameTime::MIN),
                 trace_end: trace_end.unwrap_or(FrameTime::MAX),
                 function: function_addr.map(|a| a.into()),
                 singlestep_trace: singlestep_regs.map_or(Vec::new(), |r| r.0),
+                call_trace,
+                stacks,
+                only_tid,
+                only_pid,
                 raw_dump: raw,
                 cpu_unbound,
                 trace_dir,
@@ -356,6 +371,11 @@ impl ReRunCommand {
         }
     }
 
+    /// Stacks are taken at events, there's no need to singlestep for them.
+    fn only_stacks(&self) -> bool {
+        self.stacks.is_some() && self.singlestep_trace.is_empty() && !self.call_trace
+    }
+
     fn session_flags(&self) -> replay_session::Flags {
         replay_session::Flags {
             redirect_stdio: false,
@@ -371,6 +391,17 @@ impl ReRunCommand {
         let replay_session = session.as_replay().unwrap();
         let mut instruction_count_within_event: u64 = 0;
         let mut done_first_step = false;
+        let mut call_tracer = if self.call_trace {
+            Some(CallTracer::new())
+        } else {
+            None
+        };
+        let mut unwinder = self.stacks.map(|_| Unwinder::new());
+        let filter = TaskFilter::new(
+            &replay_session.trace_reader().tasks(),
+            self.only_tid,
+            self.only_pid,
+        );
 
         // Now that we've spawned the replay, raise our resource limits if possible.
         raise_resource_limits();
@@ -380,13 +411,18 @@ impl ReRunCommand {
 
             let before_time: FrameTime = replay_session.trace_reader().time();
             let done_initial_exec = replay_session.done_initial_exec();
+            let selected = filter.matches(replay_session.current_trace_frame().tid());
             let old_task_tuid: Option<TaskUid>;
             let old_ip: RemoteCodePtr;
+            let old_sp: RemotePtr<Void>;
             {
                 let old_task = replay_session.current_task();
                 old_task_tuid = old_task.as_ref().map(|t| t.borrow().tuid());
                 old_ip = old_task.as_ref().map_or(0.into(), |t| t.borrow().ip());
-                if done_initial_exec && before_time >= self.trace_start {
+                old_sp = old_task
+                    .as_ref()
+                    .map_or(RemotePtr::null(), |t| t.borrow().regs_ref().sp());
+                if done_initial_exec && before_time >= self.trace_start && selected {
                     if !done_first_step {
                         if self.function.is_some() {
                             self.run_diversion_function(
@@ -407,7 +443,9 @@ impl ReRunCommand {
                         }
                     }
 
-                    cmd = RunCommand::RunSinglestepFastForward;
+                    if !self.only_stacks() {
+                        cmd = RunCommand::RunSinglestepFastForward;
+                    }
                 }
             }
 
@@ -452,18 +490,46 @@ impl ReRunCommand {
                                 && treat_event_completion_as_singlestep_complete(&replayed_event)))
                     {
                         self.write_regs(
-                            old_task.unwrap().borrow_mut().as_mut(),
+                            old_task.as_ref().unwrap().borrow_mut().as_mut(),
                             before_time,
                             instruction_count_within_event,
                             &mut stdout(),
                         )?;
                     }
+                    if let (Some(tracer), Some(t), true) = (
+                        call_tracer.as_mut(),
+                        old_task.as_ref(),
+                        singlestep_really_complete,
+                    ) {
+                        tracer.after_singlestep(
+                            t.borrow_mut().as_mut(),
+                            before_time,
+                            old_ip,
+                            old_sp,
+                            &mut stdout(),
+                        )?;
+                    }
                 }
 
                 if singlestep_really_complete {
                     instruction_count_within_event += 1;
                 }
             }
+            if before_time < after_time
+                && before_time >= self.trace_start
+                && selected
+                && self.stacks.map_or(false, |s| s.matches(&replayed_event))
+            {
+                if let Some(t) =
+                    old_task_tuid.and_then(|id| replay_session.find_task_from_task_uid(id))
+                {
+                    unwinder.as_mut().unwrap().write_folded(
+                        t.borrow_mut().as_mut(),
+                        &replayed_event.str(),
+                        &mut stdout(),
+                    )?;
+                }
+            }
             if before_time < after_time {
                 log!(
                     LogDebug,
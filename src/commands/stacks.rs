@@ -0,0 +1,228 @@
+//! Stack traces of tracees, for `rd rerun --stacks`. Each frame is unwound
+//! with the `.eh_frame` call frame information of the file its code is in,
+//! falling back to following the frame pointer chain where there is none.
+//!
+//! Stacks are printed in the "folded" format flamegraph tools read: the
+//! functions from outermost to innermost separated by `;`, then a count.
+
+use crate::{
+    commands::call_trace::Symbolizer,
+    event::{Event, EventType, SyscallState},
+    kernel_abi::SupportedArch,
+    remote_ptr::RemotePtr,
+    session::task::{task_common::read_val_mem, Task},
+};
+use gimli::{
+    BaseAddresses,
+    CfaRule,
+    EhFrame,
+    LittleEndian,
+    Register,
+    RegisterRule,
+    UninitializedUnwindContext,
+    UnwindSection,
+    X86,
+    X86_64,
+};
+use std::{io, io::Write, str::FromStr};
+
+/// Stop unwinding after this many frames, in case of a loop we don't detect.
+const MAX_FRAMES: usize = 256;
+
+/// Which events to capture stacks at.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StackEvents {
+    Syscalls,
+    Signals,
+    All,
+}
+
+impl FromStr for StackEvents {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "syscall" | "syscalls" => Ok(StackEvents::Syscalls),
+            "signal" | "signals" => Ok(StackEvents::Signals),
+            "all" => Ok(StackEvents::All),
+            _ => Err(format!(
+                "`{}' is not one of `syscall', `signal' or `all'",
+                s
+            )),
+        }
+    }
+}
+
+impl StackEvents {
+    pub fn matches(self, ev: &Event) -> bool {
+        match self {
+            // Syscalls are recorded at entry and exit, take the stack once.
+            StackEvents::Syscalls => {
+                ev.event_type() == EventType::EvSyscall
+                    && ev.syscall_event().state == SyscallState::EnteringSyscall
+            }
+            StackEvents::Signals => ev.event_type() == EventType::EvSignal,
+            StackEvents::All => true,
+        }
+    }
+}
+
+/// The registers unwinding needs, for one frame.
+#[derive(Copy, Clone)]
+struct FrameRegs {
+    ip: usize,
+    sp: usize,
+    bp: usize,
+}
+
+pub struct Unwinder {
+    symbols: Symbolizer,
+}
+
+impl Unwinder {
+    pub fn new() -> Unwinder {
+        Unwinder {
+            symbols: Symbolizer::new(),
+        }
+    }
+
+    /// The return addresses on `t`'s stack, innermost (its ip) first.
+    pub fn unwind(&mut self, t: &mut dyn Task) -> Vec<usize> {
+        let regs = t.regs_ref();
+        let mut frame = FrameRegs {
+            ip: regs.ip().register_value(),
+            sp: regs.sp().as_usize(),
+            bp: regs.bp(),
+        };
+        let mut ips = vec![frame.ip];
+        while ips.len() < MAX_FRAMES {
+            // A return address is just past the call, which may be the last
+            // instruction of a function.
+            let lookup_ip = if ips.len() == 1 {
+                frame.ip
+            } else {
+                frame.ip - 1
+            };
+            let caller = match self.unwind_cfi(t, frame, lookup_ip) {
+                Some(caller) => Some(caller),
+                None => unwind_frame_pointer(t, frame),
+            };
+            match caller {
+                Some(caller) if caller.ip != 0 && caller.sp > frame.sp => {
+                    ips.push(caller.ip);
+                    frame = caller;
+                }
+                _ => break,
+            }
+        }
+        ips
+    }
+
+    /// The caller's registers according to `.eh_frame`, `None` if there is
+    /// no call frame information for `lookup_ip` or it uses registers we
+    /// don't track.
+    fn unwind_cfi(
+        &mut self,
+        t: &mut dyn Task,
+        frame: FrameRegs,
+        lookup_ip: usize,
+    ) -> Option<FrameRegs> {
+        let arch = t.arch();
+        let (sp_reg, bp_reg, ra_reg) = match arch {
+            SupportedArch::X86 => (X86::ESP, X86::EBP, X86::RA),
+            SupportedArch::X64 => (X86_64::RSP, X86_64::RBP, X86_64::RA),
+        };
+        let (symbols, addr) = self.symbols.lookup(t, lookup_ip)?;
+        let section = symbols.eh_frame.as_ref()?;
+        let eh_frame = EhFrame::new(&section.data, LittleEndian);
+        let bases = BaseAddresses::default()
+            .set_eh_frame(section.addr)
+            .set_text(section.text_addr);
+        let mut ctx = UninitializedUnwindContext::new();
+        let row = eh_frame
+            .unwind_info_for_address(&bases, &mut ctx, addr, EhFrame::cie_from_offset)
+            .ok()?;
+
+        let value_of = |reg: Register| {
+            if reg == sp_reg {
+                Some(frame.sp)
+            } else if reg == bp_reg {
+                Some(frame.bp)
+            } else {
+                None
+            }
+        };
+        let cfa = match row.cfa() {
+            CfaRule::RegisterAndOffset { register, offset } => {
+                (value_of(*register)? as i64 + offset) as usize
+            }
+            CfaRule::Expression(_) => return None,
+        };
+        let ip = match row.register(ra_reg) {
+            RegisterRule::Offset(offset) => read_word(t, (cfa as i64 + offset) as usize)?,
+            _ => return None,
+        };
+        let bp = match row.register(bp_reg) {
+            RegisterRule::Offset(offset) => read_word(t, (cfa as i64 + offset) as usize)?,
+            RegisterRule::Undefined | RegisterRule::SameValue => frame.bp,
+            _ => return None,
+        };
+        Some(FrameRegs { ip, sp: cfa, bp })
+    }
+
+    /// Writes `t`'s stack in folded format, with `leaf` as the innermost
+    /// frame.
+    pub fn write_folded(
+        &mut self,
+        t: &mut dyn Task,
+        leaf: &str,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let ips = self.unwind(t);
+        let mut names: Vec<String> = ips
+            .iter()
+            .rev()
+            .map(|&ip| self.symbols.name(t, ip))
+            .collect();
+        names.push(leaf.to_owned());
+        write!(out, "{} 1\n", names.join(";"))
+    }
+}
+
+/// The caller's registers, assuming the function saved the caller's frame
+/// pointer at `bp` with its return address above it.
+fn unwind_frame_pointer(t: &mut dyn Task, frame: FrameRegs) -> Option<FrameRegs> {
+    let word_size = word_size(t.arch());
+    if frame.bp < frame.sp {
+        return None;
+    }
+    Some(FrameRegs {
+        ip: read_word(t, frame.bp + word_size)?,
+        sp: frame.bp + 2 * word_size,
+        bp: read_word(t, frame.bp)?,
+    })
+}
+
+fn word_size(arch: SupportedArch) -> usize {
+    match arch {
+        SupportedArch::X86 => 4,
+        SupportedArch::X64 => 8,
+    }
+}
+
+fn read_word(t: &mut dyn Task, addr: usize) -> Option<usize> {
+    let mut ok = true;
+    let val = match t.arch() {
+        SupportedArch::X86 => {
+            read_val_mem(t, RemotePtr::<u32>::new_from_val(addr), Some(&mut ok)) as usize
+        }
+        SupportedArch::X64 => {
+            read_val_mem(t, RemotePtr::<u64>::new_from_val(addr), Some(&mut ok)) as usize
+        }
+    };
+    if ok {
+        Some(val)
+    } else {
+        None
+    }
+}
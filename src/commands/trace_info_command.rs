@@ -3,12 +3,9 @@ use crate::{
         rd_options::{RdOptions, RdSubCommand},
         RdCommand,
     },
-    perf_counters::TicksSemantics,
-    session::{
-        replay_session::{Flags, ReplaySession, ReplayStatus},
-        session_inner::RunCommand,
-    },
-    trace::trace_reader::TraceReader,
+    perf_counters::{pmu_available, TicksSemantics},
+    session::replay_session::{Flags, ReplaySession, ReplayStatus},
+    trace::{trace_reader::TraceReader, trace_stream::Recorder},
     util::read_env,
 };
 use serde::Serialize;
@@ -25,16 +22,41 @@ impl TraceInfoCommand {
             _ => panic!("Unexpected RdSubCommand variant. Not a `TraceInfo` variant!"),
         }
     }
+
+    /// The environment of the initial exec, found by replaying up to it.
+    fn initial_environ(&self, flags: Flags) -> io::Result<Vec<CString>> {
+        let session = ReplaySession::create(self.trace_dir.as_ref(), flags);
+        let replay_session = session.as_replay().unwrap();
+        match replay_session.replay_until(|s| s.done_initial_exec()) {
+            ReplayStatus::ReplayContinue => Ok(read_env(
+                replay_session.current_task().unwrap().borrow_mut().as_mut(),
+            )),
+            ReplayStatus::ReplayExited => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Replay finished before initial exec!",
+            )),
+        }
+    }
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct TraceHeader {
     uuid: [u8; 16],
+    recorded_by: String,
     xcr0: u64,
     bind_to_cpu: i32,
     cpuid_faulting: bool,
     ticks_semantics: String,
+    /// Only for traces that record it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hypervisor: Option<String>,
+    /// Only for traces that record it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cgroup: Option<String>,
+    /// Only for traces that record them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kernel_knobs: Option<String>,
     cpuid_records: Vec<[u32; 6]>,
     environ: Vec<String>,
 }
@@ -44,6 +66,10 @@ impl RdCommand for TraceInfoCommand {
         let trace = TraceReader::new(self.trace_dir.as_ref());
 
         let uuid_bytes = trace.uuid().bytes;
+        let recorded_by = match trace.recorded_by() {
+            Recorder::Rr => "rr".into(),
+            Recorder::Rd => "rd".into(),
+        };
         let xcr0 = trace.xcr0();
         let bind_to_cpu = trace.bound_to_cpu();
         let cpuid_faulting = trace.uses_cpuid_faulting();
@@ -52,6 +78,10 @@ impl RdCommand for TraceInfoCommand {
             TicksSemantics::TicksTakenBranches => "branches".into(),
         };
 
+        let hypervisor = trace.hypervisor().map(|h| h.to_string());
+        let cgroup = trace.cgroup().map(|c| c.to_owned());
+        let kernel_knobs = trace.kernel_knobs().map(|k| k.to_owned());
+
         let mut cpuid_records: Vec<[u32; 6]> = Vec::new();
         for r in trace.cpuid_records() {
             cpuid_records.push([
@@ -64,34 +94,27 @@ impl RdCommand for TraceInfoCommand {
             share_private_mappings: false,
             cpu_unbound: true,
         };
-        let session = ReplaySession::create(self.trace_dir.as_ref(), flags);
-        let replay_session = session.as_replay().unwrap();
-
-        let environ: Vec<CString>;
-        loop {
-            let result = replay_session.replay_step(RunCommand::RunContinue);
-            if replay_session.done_initial_exec() {
-                environ = read_env(replay_session.current_task().unwrap().borrow_mut().as_mut());
-                break;
-            }
-
-            if result.status == ReplayStatus::ReplayExited {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Replay finished before initial exec!",
-                ));
-            }
-        }
+        let environ: Vec<CString> = if pmu_available() {
+            self.initial_environ(flags)?
+        } else {
+            // Finding the environment means replaying to the initial exec.
+            eprintln!("rd: no usable performance counters; omitting the environment");
+            Vec::new()
+        };
         let environ_strings: Vec<String> = environ
             .iter()
             .map(|c_str| c_str.to_string_lossy().into_owned())
             .collect();
         let header = TraceHeader {
             uuid: uuid_bytes,
+            recorded_by,
             xcr0,
             bind_to_cpu: bind_to_cpu.map_or(-1, |c| c.try_into().unwrap()),
             cpuid_faulting,
             ticks_semantics,
+            hypervisor,
+            cgroup,
+            kernel_knobs,
             cpuid_records,
             environ: environ_strings,
         };
@@ -0,0 +1,285 @@
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    kernel_abi::common::preload_interface::syscallbuf_layout_hash,
+    kernel_knobs::KernelKnobs,
+    kernel_metadata::xsave_feature_string,
+    kernel_version,
+    perf_counters::{pmu_unavailable_reason, PerfCounters, TicksSemantics},
+    session::session_inner::session_inner::SessionInner,
+    trace::{
+        trace_reader::TraceReader,
+        trace_verify::{verify_trace, TraceVerification},
+    },
+    util::{cpuid_compatible, page_size, sysemu_supported, xcr0},
+    MIN_KERNEL_VERSION,
+};
+use nix::sys::utsname::uname;
+use std::{
+    io,
+    io::{stdout, ErrorKind, Write},
+    path::PathBuf,
+};
+
+/// rd and rr traces assume 4KB pages throughout.
+const TRACE_PAGE_SIZE: usize = 4096;
+
+pub struct VerifyCommand {
+    host: bool,
+    trace_dir: Option<PathBuf>,
+}
+
+impl VerifyCommand {
+    pub fn new(options: &RdOptions) -> VerifyCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Verify { host, trace_dir } => VerifyCommand { host, trace_dir },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Verify` variant!"),
+        }
+    }
+}
+
+impl RdCommand for VerifyCommand {
+    fn run(&mut self) -> io::Result<()> {
+        let trace = TraceReader::new(self.trace_dir.as_ref());
+        if self.host {
+            if verify_host(&trace, &mut stdout())? {
+                Ok(())
+            } else {
+                Err(io::Error::new(
+                    ErrorKind::Other,
+                    "This machine can't replay the trace",
+                ))
+            }
+        } else {
+            let verification = verify_trace(&trace);
+            write_integrity_report(&verification, &mut stdout())?;
+            if verification.is_intact() {
+                Ok(())
+            } else {
+                Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "The trace is damaged; replay failures past the last intact event \
+                     are not rd bugs",
+                ))
+            }
+        }
+    }
+}
+
+enum CheckResult {
+    Pass(String),
+    Fail { problem: String, hint: String },
+}
+
+/// Writes a report of each check to `out`. Returns true if all checks pass.
+fn verify_host(trace: &TraceReader, out: &mut dyn Write) -> io::Result<bool> {
+    let checks: [(&str, CheckResult); 7] = [
+        ("CPU", check_cpuid(trace)),
+        ("XSAVE", check_xsave(trace)),
+        ("Ticks", check_ticks(trace)),
+        ("Kernel", check_kernel()),
+        ("Kernel settings", check_kernel_knobs()),
+        ("Page size", check_page_size()),
+        ("Syscall buffer", check_syscallbuf(trace)),
+    ];
+
+    let mut ok = true;
+    for (name, result) in &checks {
+        match result {
+            CheckResult::Pass(detail) => write!(out, "PASS  {}: {}\n", name, detail)?,
+            CheckResult::Fail { problem, hint } => {
+                ok = false;
+                write!(out, "FAIL  {}: {}\n      hint: {}\n", name, problem, hint)?;
+            }
+        }
+    }
+    Ok(ok)
+}
+
+fn write_integrity_report(verification: &TraceVerification, out: &mut dyn Write) -> io::Result<()> {
+    if !verification.has_checksums {
+        write!(
+            out,
+            "Trace has no block checksums; only checking for truncation and undecodable \
+             blocks\n"
+        )?;
+    }
+    for s in &verification.substreams {
+        match &s.problem {
+            None => write!(out, "OK    {}: {} blocks\n", s.name, s.intact_blocks)?,
+            Some(problem) => write!(
+                out,
+                "BAD   {}: {} ({} intact blocks before it)\n",
+                s.name, problem, s.intact_blocks
+            )?,
+        }
+    }
+    if !verification.is_intact() {
+        write!(
+            out,
+            "Last intact event: {}\n",
+            verification.last_intact_event
+        )?;
+    }
+    Ok(())
+}
+
+/// Mirrors the checks in `ReplaySession::new()`.
+fn check_cpuid(trace: &TraceReader) -> CheckResult {
+    let has_faulting = SessionInner::has_cpuid_faulting();
+    if trace.uses_cpuid_faulting() {
+        if has_faulting {
+            CheckResult::Pass("trace recorded CPUID results; CPUID faulting available".into())
+        } else {
+            CheckResult::Fail {
+                problem: "trace was recorded with CPUID faulting but this machine doesn't \
+                          support it"
+                    .into(),
+                hint: "replay on an Intel CPU from Ivy Bridge on, outside a VM or in one \
+                       that passes CPUID faulting through"
+                    .into(),
+            }
+        }
+    } else if has_faulting {
+        CheckResult::Pass("CPUID results will be emulated from the trace".into())
+    } else if cpuid_compatible(trace.cpuid_records()) {
+        CheckResult::Pass("same CPU family and model as the recording".into())
+    } else {
+        CheckResult::Fail {
+            problem: "trace was recorded on a different CPU model and this machine doesn't \
+                      support CPUID faulting"
+                .into(),
+            hint: "replay on the same CPU model, or on a machine with CPUID faulting".into(),
+        }
+    }
+}
+
+fn check_xsave(trace: &TraceReader) -> CheckResult {
+    let missing = trace.xcr0() & !xcr0();
+    if missing == 0 {
+        CheckResult::Pass(format!(
+            "trace XCR0 {:#x}, ours {:#x}",
+            trace.xcr0(),
+            xcr0()
+        ))
+    } else {
+        CheckResult::Fail {
+            problem: format!(
+                "trace uses XSAVE features this machine doesn't have: {}",
+                xsave_feature_string(missing).trim_end()
+            ),
+            hint: "run `rd cpufeatures` here and record with the options it prints".into(),
+        }
+    }
+}
+
+fn check_ticks(trace: &TraceReader) -> CheckResult {
+    let semantics = trace.ticks_semantics();
+    let name = match semantics {
+        TicksSemantics::TicksRetiredConditionalBranches => "retired conditional branches",
+        TicksSemantics::TicksTakenBranches => "taken branches",
+    };
+    if let Some(reason) = pmu_unavailable_reason() {
+        return CheckResult::Fail {
+            problem: reason.into(),
+            hint: "replay on a machine with performance counters; in a VM, enable \
+                   performance counter virtualization"
+                .into(),
+        };
+    }
+    if PerfCounters::supports_ticks_semantics(semantics) {
+        CheckResult::Pass(format!("can count {}", name))
+    } else {
+        CheckResult::Fail {
+            problem: format!("this machine's performance counters can't count {}", name),
+            hint: "replay on a CPU from the same vendor and generation; in a VM, enable \
+                   performance counter virtualization"
+                .into(),
+        }
+    }
+}
+
+fn check_kernel() -> CheckResult {
+    let unm = uname();
+    let release = unm.release();
+    let version = match kernel_version(release) {
+        Some(version) => version,
+        None => {
+            return CheckResult::Fail {
+                problem: format!("can't parse kernel version `{}`", release),
+                hint: "check that this is a Linux kernel".into(),
+            }
+        }
+    };
+    if version < MIN_KERNEL_VERSION {
+        return CheckResult::Fail {
+            problem: format!("kernel {} lacks ptrace features rd needs", release),
+            hint: format!(
+                "use Linux {}.{} or later",
+                MIN_KERNEL_VERSION.0, MIN_KERNEL_VERSION.1
+            ),
+        };
+    }
+    if !KernelKnobs::read().seccomp {
+        return CheckResult::Fail {
+            problem: format!("kernel {} has no seccomp support", release),
+            hint: "use a kernel built with CONFIG_SECCOMP_FILTER".into(),
+        };
+    }
+    if sysemu_supported() {
+        CheckResult::Pass(format!("{} with PTRACE_SYSEMU and seccomp", release))
+    } else {
+        // Replay falls back to PTRACE_SYSCALL, which costs an extra stop per
+        // syscall but works.
+        CheckResult::Pass(format!(
+            "{} with seccomp (no PTRACE_SYSEMU: replay will be slower)",
+            release
+        ))
+    }
+}
+
+fn check_kernel_knobs() -> CheckResult {
+    let knobs = KernelKnobs::read();
+    let problems = knobs.problems();
+    match problems.first() {
+        None => CheckResult::Pass(knobs.to_string()),
+        Some(p) => CheckResult::Fail {
+            problem: p.problem.clone(),
+            hint: p.fix.clone(),
+        },
+    }
+}
+
+fn check_page_size() -> CheckResult {
+    if page_size() == TRACE_PAGE_SIZE {
+        CheckResult::Pass(format!("{} bytes", TRACE_PAGE_SIZE))
+    } else {
+        CheckResult::Fail {
+            problem: format!(
+                "page size is {} bytes, traces assume {}",
+                page_size(),
+                TRACE_PAGE_SIZE
+            ),
+            hint: format!("use a kernel with {} byte pages", TRACE_PAGE_SIZE),
+        }
+    }
+}
+
+fn check_syscallbuf(trace: &TraceReader) -> CheckResult {
+    match trace.syscallbuf_layout_hash() {
+        None => CheckResult::Pass("trace doesn't record the syscallbuf layout".into()),
+        Some(hash) if hash == syscallbuf_layout_hash() => {
+            CheckResult::Pass("same syscallbuf layout as this rd".into())
+        }
+        Some(hash) => CheckResult::Fail {
+            problem: format!(
+                "trace has syscallbuf layout {:#x}, this rd uses {:#x}",
+                hash,
+                syscallbuf_layout_hash()
+            ),
+            hint: "replay with the rd that recorded the trace".into(),
+        },
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    assert_prerequisites,
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    log::LogLevel::LogInfo,
+    remote_code_ptr::RemoteCodePtr,
+    remote_ptr::RemotePtr,
+    session::{
+        replay_session,
+        replay_session::{ReplaySession, ReplayStatus},
+        session_inner::RunCommand,
+        task::Task,
+        SessionSharedPtr,
+    },
+    taskish_uid::TaskUid,
+    trace::trace_frame::FrameTime,
+    util::raise_resource_limits,
+};
+use libc::pid_t;
+use serde::Serialize;
+use std::{
+    fmt::Write as fmtWrite,
+    io,
+    io::{stdout, Write},
+    path::PathBuf,
+};
+
+/// Replays a trace singlestepping, comparing a range of memory after every
+/// instruction, and prints a JSON object per line for each change. This
+/// works for ranges of any size, unlike hardware watchpoints, and also
+/// catches the kernel writing into the range during a syscall.
+pub struct WatchCommand {
+    addr: usize,
+    size: usize,
+    pid: Option<pid_t>,
+    trace_start: FrameTime,
+    trace_end: FrameTime,
+    cpu_unbound: bool,
+    trace_dir: Option<PathBuf>,
+}
+
+impl WatchCommand {
+    pub fn new(options: &RdOptions) -> WatchCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Watch {
+                addr,
+                size,
+                pid,
+                trace_start,
+                trace_end,
+                cpu_unbound,
+                trace_dir,
+            } => WatchCommand {
+                addr,
+                size,
+                pid,
+                trace_start: trace_start.unwrap_or(FrameTime::MIN),
+                trace_end: trace_end.unwrap_or(FrameTime::MAX),
+                cpu_unbound,
+                trace_dir,
+            },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Watch` variant!"),
+        }
+    }
+
+    fn session_flags(&self) -> replay_session::Flags {
+        replay_session::Flags {
+            redirect_stdio: false,
+            share_private_mappings: false,
+            cpu_unbound: self.cpu_unbound,
+        }
+    }
+
+    /// The contents of the range, `None` if any of it isn't mapped.
+    fn read_range(&self, t: &mut dyn Task) -> Option<Vec<u8>> {
+        let mut buf = vec![0u8; self.size];
+        match t.read_bytes_fallible(RemotePtr::new_from_val(self.addr), &mut buf) {
+            Ok(nread) if nread == self.size => Some(buf),
+            _ => None,
+        }
+    }
+
+    fn watch(&self, out: &mut dyn Write) -> io::Result<()> {
+        let session: SessionSharedPtr =
+            ReplaySession::create(self.trace_dir.as_ref(), self.session_flags());
+        let replay_session = session.as_replay().unwrap();
+        // Defaults to the first process once it has exec'ed.
+        let mut pid = self.pid;
+        let mut contents: Option<Vec<u8>> = None;
+
+        raise_resource_limits();
+
+        while replay_session.trace_reader().time() < self.trace_end {
+            let before_time: FrameTime = replay_session.trace_reader().time();
+            let mut cmd = RunCommand::RunContinue;
+            let mut stepping: Option<(TaskUid, RemoteCodePtr)> = None;
+            if let Some(t) = replay_session.current_task() {
+                let t = t.borrow();
+                if replay_session.done_initial_exec() {
+                    let watched = *pid.get_or_insert(t.tgid());
+                    if t.tgid() == watched {
+                        stepping = Some((t.tuid(), t.ip()));
+                    }
+                    if before_time >= self.trace_start {
+                        cmd = RunCommand::RunSinglestepFastForward;
+                    }
+                }
+            }
+
+            let result = replay_session.replay_step(cmd);
+            if result.status == ReplayStatus::ReplayExited {
+                break;
+            }
+
+            let (tuid, ip) = match stepping {
+                Some(s) => s,
+                None => continue,
+            };
+            let t = match replay_session.find_task_from_task_uid(tuid) {
+                Some(t) => t,
+                None => continue,
+            };
+            let mut t = t.borrow_mut();
+            let now = self.read_range(t.as_mut());
+            if cmd == RunCommand::RunSinglestepFastForward && contents != now {
+                if let (Some(old), Some(new)) = (&contents, &now) {
+                    let write = WatchedWrite {
+                        event: before_time,
+                        pid: t.tgid(),
+                        tid: t.rec_tid,
+                        ip: format!("{:#x}", ip.register_value()),
+                        old: hex(old),
+                        new: hex(new),
+                    };
+                    write!(out, "{}\n", serde_json::to_string(&write).unwrap())?;
+                }
+            }
+            contents = now;
+        }
+
+        log!(LogInfo, "Watch successfully finished");
+        Ok(())
+    }
+}
+
+/// One change to the watched range. `ip` is the instruction that made it, or
+/// the syscall instruction if the kernel did.
+#[derive(Serialize)]
+struct WatchedWrite {
+    event: FrameTime,
+    pid: pid_t,
+    tid: pid_t,
+    ip: String,
+    old: String,
+    new: String,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(s, "{:02x}", b).unwrap();
+    }
+    s
+}
+
+impl RdCommand for WatchCommand {
+    fn run(&mut self) -> io::Result<()> {
+        assert_prerequisites(None);
+        self.watch(&mut stdout())
+    }
+}
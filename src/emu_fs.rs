@@ -142,11 +142,17 @@ impl EmuFile {
         }
     }
 
-    /// Return a copy of this file.  See `create()` for the meaning
-    /// of `fs_tag`.
-    fn clone_file(&self) -> EmuFileSharedPtr {
+    /// Resize the backing file to exactly `size` bytes, e.g. because the
+    /// recorded file was ftruncate()d. Unlike `ensure_size()` this can shrink.
+    pub fn truncate(&mut self, size: u64) {
+        resize_shmem_segment(&self.file, size.try_into().unwrap());
+        self.size_ = size;
+    }
+
+    /// Return a copy of this file, owned by `owner`.
+    fn clone_file(&self, owner: EmuFsSharedWeakPtr) -> EmuFileSharedPtr {
         let f = EmuFile::create(
-            self.owner.clone(),
+            owner,
             &self.emu_path(),
             self.device(),
             self.inode(),
@@ -304,8 +310,11 @@ impl EmuFs {
             .is_some()
     }
 
+    /// Add a copy of `emu_file` (usually from another EmuFs) to this. Later
+    /// writes to either file aren't seen in the other, which is what a
+    /// checkpoint needs.
     pub fn clone_file(&mut self, emu_file: EmuFileSharedPtr) -> EmuFileSharedPtr {
-        let f = emu_file.borrow().clone_file();
+        let f = emu_file.borrow().clone_file(self.weak_self.clone());
         self.files
             .insert(FileId::from_emu_file(&emu_file.borrow()), Rc::downgrade(&f));
         f
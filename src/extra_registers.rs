@@ -31,6 +31,11 @@ const XMM_REG_SPACE: usize = 16;
 
 const XSAVE_FEATURE_PKRU: usize = 9;
 
+/// Set in XCOMP_BV when an XSAVE area uses the compacted format written by
+/// XSAVEC/XSAVES: only the components in XCOMP_BV are present, packed in
+/// feature order after the header.
+const XCOMP_BV_COMPACTED: u64 = 1 << 63;
+
 /// The Intel documentation says that the following layout is only valid in
 /// 32-bit mode, or when fxsave is executed in 64-bit mode without an
 /// appropriate REX prefix.  The kernel seems to only use fxsave with the
@@ -118,7 +123,12 @@ impl ExtraRegisters {
         // native XSAVE format. Be careful to handle possibly-corrupt input data.
 
         let native_layout = xsave_native_layout();
-        if data_from.len() != layout.full_size {
+        // Compacted data is never bigger than the standard layout, but is
+        // usually smaller.
+        let compacted = xcomp_bv(data_from) & XCOMP_BV_COMPACTED != 0;
+        if (compacted && data_from.len() > layout.full_size)
+            || (!compacted && data_from.len() != layout.full_size)
+        {
             log!(
                 LogError,
                 "Invalid XSAVE data length: {}, expected {}",
@@ -153,8 +163,8 @@ impl ExtraRegisters {
                     LogError,
                     "Unsupported CPU features found: got {:#x}\
                       ({}), supported: {:#x}({});\
-                      Consider using `rr cpufeatures` and \
-                      `rr record --disable-cpuid-features-(ext)`",
+                      Consider recording with \
+                      `rd record --disable-cpuid-features(-ext)`",
                     features,
                     xsave_feature_string(features),
                     native_layout.supported_feature_bits,
@@ -188,6 +198,10 @@ impl ExtraRegisters {
             .copy_from_slice(
                 &data_from[XSAVE_HEADER_OFFSET + 8..XSAVE_HEADER_OFFSET + XSAVE_HEADER_SIZE],
             );
+        if compacted {
+            // We convert to the standard format, so clear XCOMP_BV.
+            self.data_[XSAVE_HEADER_OFFSET + 8..XSAVE_HEADER_OFFSET + 16].fill(0);
+        }
 
         // Now copy each optional and present area into the right place in our struct
         for i in 2..64 {
@@ -202,13 +216,14 @@ impl ExtraRegisters {
                     return false;
                 }
                 let feature = layout.feature_layouts[i];
-                if feature.offset as usize + feature.size as usize > layout.full_size {
+                let feature_offset = feature_offset(data_from, &layout, i);
+                if feature_offset + feature.size as usize > data_from.len() {
                     log!(
                         LogError,
                         "Invalid feature region: {} + {} > {}",
-                        feature.offset,
+                        feature_offset,
                         feature.size,
-                        layout.full_size
+                        data_from.len()
                     );
                     return false;
                 }
@@ -231,7 +246,6 @@ impl ExtraRegisters {
                 );
 
                 let native_offset = native_feature.offset as usize;
-                let feature_offset = feature.offset as usize;
                 let feature_size = feature.size as usize;
 
                 self.data_[native_offset..native_offset + feature_size]
@@ -281,6 +295,29 @@ impl ExtraRegisters {
         Some(ret)
     }
 
+    /// Read PKRU, which holds the access rights of each memory protection
+    /// key. Returns None if the XSAVE data can't hold it. PKRU isn't saved
+    /// in its init state, so that reads as 0: every key allows all access.
+    pub fn read_pkru(&self) -> Option<u32> {
+        let layout = xsave_native_layout();
+        if self.format_ != Format::XSave || XSAVE_FEATURE_PKRU >= layout.feature_layouts.len() {
+            return None;
+        }
+
+        if xsave_features(&self.data_) & (1 << XSAVE_FEATURE_PKRU) == 0 {
+            return Some(0);
+        }
+        let offset = feature_offset(&self.data_, layout, XSAVE_FEATURE_PKRU);
+        if offset + size_of::<u32>() > self.data_.len() {
+            return None;
+        }
+        Some(u32::from_le_bytes(
+            self.data_[offset..offset + size_of::<u32>()]
+                .try_into()
+                .unwrap(),
+        ))
+    }
+
     /// Like `Registers::read_register()`, except attempts to read
     /// the value of an "extra register" (floating point / vector).
     pub fn read_register(&self, buf: &mut [u8], regno: GdbRegister) -> Option<usize> {
@@ -493,9 +530,9 @@ fn features_used(data: &[u8], layout: &XSaveLayout) -> u64 {
     let pkru_bit: u64 = 1u64 << XSAVE_FEATURE_PKRU;
     if features & pkru_bit != 0 && XSAVE_FEATURE_PKRU < layout.feature_layouts.len() {
         let fl: XSaveFeatureLayout = layout.feature_layouts[XSAVE_FEATURE_PKRU];
-        let fl_offset = fl.offset as usize;
+        let fl_offset = feature_offset(data, layout, XSAVE_FEATURE_PKRU);
         let fl_size = fl.size as usize;
-        if fl_offset + fl_size as usize <= layout.full_size
+        if fl_offset + fl_size as usize <= data.len()
             && all_zeros(&data[fl_offset..fl_offset + fl_size])
         {
             features = features & !pkru_bit
@@ -505,6 +542,44 @@ fn features_used(data: &[u8], layout: &XSaveLayout) -> u64 {
     features
 }
 
+fn xcomp_bv(data: &[u8]) -> u64 {
+    if data.len() < XSAVE_HEADER_END {
+        0
+    } else {
+        u64::from_le_bytes(
+            data[XSAVE_HEADER_OFFSET + 8..XSAVE_HEADER_OFFSET + 16]
+                .try_into()
+                .unwrap(),
+        )
+    }
+}
+
+/// Where feature `i` (which must have an entry in `layout.feature_layouts`)
+/// starts in `data`, which is either in the standard format for `layout` or
+/// in the compacted format.
+fn feature_offset(data: &[u8], layout: &XSaveLayout, i: usize) -> usize {
+    let bv = xcomp_bv(data);
+    if bv & XCOMP_BV_COMPACTED == 0 {
+        return layout.feature_layouts[i].offset as usize;
+    }
+
+    let align = |offset: usize, fl: &XSaveFeatureLayout| {
+        if fl.align_64 {
+            (offset + 63) & !63
+        } else {
+            offset
+        }
+    };
+    let mut offset = XSAVE_HEADER_END;
+    for j in 2..i {
+        if bv & (1 << j) != 0 {
+            let fl = layout.feature_layouts.get(j).copied().unwrap_or_default();
+            offset = align(offset, &fl) + fl.size as usize;
+        }
+    }
+    align(offset, &layout.feature_layouts[i])
+}
+
 fn all_zeros(data: &[u8]) -> bool {
     for d in data.iter() {
         if *d != 0 {
@@ -6,14 +6,9 @@ use crate::{
     remote_ptr::RemotePtr,
     session::{
         address_space::address_space::AddressSpace,
-        task::{
-            record_task::record_task::RecordTask,
-            replay_task::ReplayTask,
-            Task,
-            TaskSharedWeakPtr,
-        },
+        task::{record_task::record_task::RecordTask, replay_task::ReplayTask, Task},
     },
-    taskish_uid::AddressSpaceUid,
+    taskish_uid::{AddressSpaceUid, FdTableUid},
     weak_ptr_set::WeakPtrSet,
 };
 use nix::sys::stat::lstat;
@@ -30,6 +25,7 @@ pub type FdTableSharedWeakPtr = Weak<RefCell<FdTable>>;
 
 #[derive(Clone)]
 pub struct FdTable {
+    uid: FdTableUid,
     tasks: WeakPtrSet<Box<dyn Task>>,
     fds: HashMap<i32, FileMonitorSharedPtr>,
     /// Number of elements of `fds` that are >= SYSCALLBUF_FDS_DISABLED_SIZE
@@ -38,6 +34,13 @@ pub struct FdTable {
 
 /// We DO NOT want Copy or Clone traits
 impl FdTable {
+    /// Unique within the session. Copies made by `clone_into_task()`,
+    /// including those in cloned sessions, get their own uid; session cloning
+    /// finds the copy of a table by looking up the original's uid.
+    pub fn uid(&self) -> FdTableUid {
+        self.uid
+    }
+
     pub fn task_set(&self) -> &WeakPtrSet<Box<dyn Task>> {
         &self.tasks
     }
@@ -114,6 +117,18 @@ impl FdTable {
         }
     }
 
+    /// Like `did_write()`, for `length` bytes the kernel moved into `fd` without
+    /// them passing through tracee memory: sendfile(), splice() and
+    /// copy_file_range(). Only a mmapped file's monitor can do anything with
+    /// such a write; it records the affected mappings.
+    pub fn did_zero_copy_write(&self, fd: i32, length: usize, offset: &mut LazyOffset) {
+        if let Some(f) = self.fds.get(&fd) {
+            if let Some(m) = f.borrow_mut().as_mmapped_file_monitor_mut() {
+                m.did_write(&[Range::new(RemotePtr::null(), length)], offset);
+            }
+        }
+    }
+
     /// DIFF NOTE: Additional param `active_task` to solve borrow issues.
     pub fn did_dup(&mut self, from: i32, to: i32, active_task: &mut dyn Task) {
         if self.fds.contains_key(&from) {
@@ -140,9 +155,30 @@ impl FdTable {
         self.update_syscallbuf_fds_disabled(fd, active_task);
     }
 
+    /// close_range(first, last, 0): forget every fd we track in [first, last].
+    /// `last` may be ~0U, so callers pass it as a u32.
+    pub fn did_close_range(&mut self, first: u32, last: u32, active_task: &mut dyn Task) {
+        let to_close: Vec<i32> = self
+            .fds
+            .keys()
+            .copied()
+            .filter(|&fd| fd as u32 >= first && fd as u32 <= last)
+            .collect();
+        for fd in to_close {
+            self.did_close(fd, active_task);
+        }
+    }
+
+    /// A copy of this table used only by `t`, for a fork without CLONE_FILES,
+    /// unshare(CLONE_FILES) or exec. The copy shares our `FileMonitor`s: the
+    /// copied fds refer to the same open file descriptions, so e.g. their
+    /// file offsets are shared too. Closing or dup()ing an fd in either table
+    /// afterwards only affects that table.
+    ///
     /// Method is called clone() in rr
     pub fn clone_into_task(&self, t: &mut dyn Task) -> FdTableSharedPtr {
         let mut file_mon = FdTable {
+            uid: Self::next_uid(t),
             tasks: WeakPtrSet::new(),
             fds: self.fds.clone(),
             fd_count_beyond_limit: self.fd_count_beyond_limit,
@@ -152,17 +188,22 @@ impl FdTable {
         Rc::new(RefCell::new(file_mon))
     }
 
-    pub fn create(wt: TaskSharedWeakPtr) -> FdTableSharedPtr {
+    pub fn create(t: &mut dyn Task) -> FdTableSharedPtr {
         let mut file_mon = FdTable {
+            uid: Self::next_uid(t),
             tasks: WeakPtrSet::new(),
             fds: Default::default(),
             fd_count_beyond_limit: 0,
         };
 
-        file_mon.tasks.insert(wt);
+        file_mon.tasks.insert(t.weak_self_ptr());
         Rc::new(RefCell::new(file_mon))
     }
 
+    fn next_uid(t: &dyn Task) -> FdTableUid {
+        FdTableUid::new_with(t.rec_tid, t.session().next_fd_table_serial())
+    }
+
     pub fn is_monitoring(&self, fd: i32) -> bool {
         self.fds.contains_key(&fd)
     }
@@ -245,6 +286,7 @@ impl FdTable {
 
     fn new() -> FdTable {
         FdTable {
+            uid: Default::default(),
             tasks: Default::default(),
             fds: Default::default(),
             fd_count_beyond_limit: 0,
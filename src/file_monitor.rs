@@ -4,7 +4,7 @@ use crate::{
     kernel_abi::SupportedArch,
     registers::Registers,
     remote_ptr::{RemotePtr, Void},
-    session::task::{record_task::record_task::RecordTask, Task},
+    session::task::{record_task::record_task::RecordTask, task_common::read_val_mem, Task},
 };
 use mmapped_file_monitor::MmappedFileMonitor;
 use std::{
@@ -16,8 +16,10 @@ use std::{
 };
 
 pub mod base_file_monitor;
+pub mod event_fd_monitor;
 pub mod magic_save_data_monitor;
 pub mod mmapped_file_monitor;
+pub mod netlink_socket_monitor;
 pub mod preserve_file_monitor;
 pub mod proc_fd_dir_monitor;
 pub mod proc_mem_monitor;
@@ -33,8 +35,10 @@ pub struct FileMonitorInner;
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum FileMonitorType {
     Base,
+    EventFd,
     MagicSaveData,
     Mmapped,
+    NetlinkSocket,
     Preserve,
     ProcFd,
     ProcMem,
@@ -110,8 +114,16 @@ impl<'b, 'a: 'b> LazyOffset<'b, 'a> {
     }
 }
 
+/// Syscalls whose write offset can't be recomputed during replay, so it is
+/// saved in the trace instead. For the zero-copy syscalls the offset may be
+/// explicit, but *off_out has already been advanced when we look at it.
 fn is_implicit_offset_syscall_arch<Arch: Architecture>(syscallno: i32) -> bool {
-    syscallno == Arch::WRITEV || syscallno == Arch::WRITE
+    syscallno == Arch::WRITEV
+        || syscallno == Arch::WRITE
+        || syscallno == Arch::SENDFILE
+        || syscallno == Arch::SENDFILE64
+        || syscallno == Arch::SPLICE
+        || syscallno == Arch::COPY_FILE_RANGE
 }
 
 fn is_implict_offset_syscall(arch: SupportedArch, syscallno: i32) -> bool {
@@ -148,48 +160,7 @@ fn retrieve_offset_arch<Arch: Architecture>(
             "Can only read a file descriptor's offset while recording"
         );
         let fd: i32 = regs.arg1_signed() as i32;
-        // Get the offset from /proc/*/fdinfo/*
-        let fdinfo_path = format!("/proc/{}/fdinfo/{}", t.tid, fd);
-        let result = File::open(&fdinfo_path);
-        let mut f = match result {
-            Err(_) => {
-                fatal!("Failed to open {}", fdinfo_path);
-                unreachable!()
-            }
-            Ok(file) => BufReader::new(file),
-        };
-
-        let mut buf = String::new();
-        let mut maybe_offset: Option<u64> = None;
-        // @TODO do we need to use read_until() which will give a Vec<u8> instead?
-        // But buf being a String should be OK for now. The characters in fdinfo should be ASCII
-        // anyways.
-        while let Ok(nread) = f.read_line(&mut buf) {
-            if nread == 0 {
-                break;
-            }
-
-            let s = buf.trim();
-            let maybe_loc = s.find("pos:\t");
-            if maybe_loc.is_none() {
-                continue;
-            }
-            // 5 is length of str "pos:\t"
-            let loc = maybe_loc.unwrap() + 5;
-            // @TODO This is tricky. Are we sure that a negative offset won't appear in
-            // /proc/{}/fdinfo/{} ?
-            maybe_offset = Some(
-                s[loc..]
-                    .parse::<u64>()
-                    .expect("Unable to parse file offset"),
-            );
-        }
-
-        if maybe_offset.is_none() {
-            fatal!("Failed to read position");
-        }
-
-        let offset = maybe_offset.unwrap();
+        let offset = fd_position(t, fd);
         // The pos we just read, was after the write completed. Luckily, we do
         // know how many bytes were written.
         // DIFF NOTE: This is slightly different from the rr approach.
@@ -198,12 +169,85 @@ fn retrieve_offset_arch<Arch: Architecture>(
         } else {
             Some(offset - regs.syscall_result() as u64)
         }
+    } else if syscallno == Arch::SENDFILE
+        || syscallno == Arch::SENDFILE64
+        || syscallno == Arch::SPLICE
+        || syscallno == Arch::COPY_FILE_RANGE
+    {
+        ed_assert!(
+            t,
+            t.session().is_recording(),
+            "Can only read a file descriptor's offset while recording"
+        );
+        // sendfile() always writes at the out fd's file position. splice() and
+        // copy_file_range() write at *off_out instead if it's given. Either way
+        // it has been advanced past the data by now.
+        let (fd, off_out) = if syscallno == Arch::SENDFILE || syscallno == Arch::SENDFILE64 {
+            (regs.arg1_signed() as i32, RemotePtr::null())
+        } else {
+            (
+                regs.arg3_signed() as i32,
+                RemotePtr::<i64>::new_from_val(regs.arg4()),
+            )
+        };
+        let offset = if off_out.is_null() {
+            fd_position(t, fd)
+        } else {
+            read_val_mem(t, off_out, None) as u64
+        };
+        offset.checked_sub(regs.syscall_result() as u64)
     } else {
         ed_assert!(t, false, "Cannot retrieve offset for this system call");
         None
     }
 }
 
+/// The current file position of `fd` in `t`.
+fn fd_position(t: &dyn Task, fd: i32) -> u64 {
+    // Get the offset from /proc/*/fdinfo/*
+    let fdinfo_path = format!("/proc/{}/fdinfo/{}", t.tid, fd);
+    let result = File::open(&fdinfo_path);
+    let mut f = match result {
+        Err(_) => {
+            fatal!("Failed to open {}", fdinfo_path);
+            unreachable!()
+        }
+        Ok(file) => BufReader::new(file),
+    };
+
+    let mut buf = String::new();
+    let mut maybe_offset: Option<u64> = None;
+    // @TODO do we need to use read_until() which will give a Vec<u8> instead?
+    // But buf being a String should be OK for now. The characters in fdinfo should be ASCII
+    // anyways.
+    while let Ok(nread) = f.read_line(&mut buf) {
+        if nread == 0 {
+            break;
+        }
+
+        let s = buf.trim();
+        let maybe_loc = s.find("pos:\t");
+        if maybe_loc.is_none() {
+            continue;
+        }
+        // 5 is length of str "pos:\t"
+        let loc = maybe_loc.unwrap() + 5;
+        // @TODO This is tricky. Are we sure that a negative offset won't appear in
+        // /proc/{}/fdinfo/{} ?
+        maybe_offset = Some(
+            s[loc..]
+                .parse::<u64>()
+                .expect("Unable to parse file offset"),
+        );
+    }
+
+    if maybe_offset.is_none() {
+        fatal!("Failed to read position");
+    }
+
+    maybe_offset.unwrap()
+}
+
 fn retrieve_offset(t: &mut dyn Task, syscallno: i32, regs: &Registers) -> Option<u64> {
     let arch = t.arch();
     rd_arch_function_selfless!(retrieve_offset_arch, arch, t, syscallno, regs)
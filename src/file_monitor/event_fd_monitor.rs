@@ -0,0 +1,66 @@
+use crate::{
+    arch::Architecture,
+    file_monitor::{FileMonitor, FileMonitorType},
+    registers::Registers,
+};
+
+/// The kinds of fd whose reads return events generated by the kernel.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EventFdKind {
+    Inotify,
+    Fanotify,
+    Timerfd,
+    Signalfd,
+    Eventfd,
+}
+
+/// A FileMonitor for inotify, fanotify, timerfd, signalfd and eventfd fds.
+/// What reading one of these returns depends on things outside the tracee
+/// (file system activity, time, signals, other processes), so replay never
+/// creates them and emulates every read from the trace.
+///
+/// The mere existence of this monitor disables syscall buffering for the fd,
+/// so every read traps and what the kernel delivered can be recorded.
+pub struct EventFdMonitor {
+    kind: EventFdKind,
+}
+
+impl FileMonitor for EventFdMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::EventFd
+    }
+}
+
+impl EventFdMonitor {
+    pub fn new(kind: EventFdKind) -> EventFdMonitor {
+        EventFdMonitor { kind }
+    }
+
+    pub fn kind(&self) -> EventFdKind {
+        self.kind
+    }
+}
+
+/// If the successful syscall `syscallno` with registers `regs` created a new
+/// event fd, the kind of fd it is. signalfd() and signalfd4() only create one
+/// when passed an fd of -1; otherwise they change the mask of an existing one.
+pub fn event_fd_created_by<Arch: Architecture>(
+    syscallno: i32,
+    regs: &Registers,
+) -> Option<EventFdKind> {
+    if syscallno == Arch::INOTIFY_INIT || syscallno == Arch::INOTIFY_INIT1 {
+        Some(EventFdKind::Inotify)
+    } else if syscallno == Arch::FANOTIFY_INIT {
+        Some(EventFdKind::Fanotify)
+    } else if syscallno == Arch::TIMERFD_CREATE {
+        Some(EventFdKind::Timerfd)
+    } else if (syscallno == Arch::SIGNALFD || syscallno == Arch::SIGNALFD4)
+        && regs.arg1_signed() as i32 == -1
+    {
+        Some(EventFdKind::Signalfd)
+    } else if syscallno == Arch::EVENTFD || syscallno == Arch::EVENTFD2 {
+        Some(EventFdKind::Eventfd)
+    } else {
+        None
+    }
+}
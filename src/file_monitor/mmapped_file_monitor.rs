@@ -39,6 +39,25 @@ impl MmappedFileMonitor {
         self.dead_ = false;
     }
 
+    /// The file being monitored was ftruncate()d to `size` bytes. During
+    /// replay, resize the EmuFs file backing the shared mappings of it so that
+    /// they see the same file size as they did during recording.
+    pub fn did_ftruncate(&self, t: &dyn Task, size: u64) {
+        if !t.session().is_replaying() {
+            return;
+        }
+
+        let maybe_emu_file = t
+            .session()
+            .as_replay()
+            .unwrap()
+            .emufs()
+            .find(self.device_, self.inode_);
+        if let Some(emu_file) = maybe_emu_file {
+            emu_file.borrow_mut().truncate(size);
+        }
+    }
+
     pub fn new_from_emufile(t: &dyn Task, f: EmuFileSharedPtr) -> MmappedFileMonitor {
         ed_assert!(t, t.session().is_replaying());
         MmappedFileMonitor {
@@ -138,7 +157,7 @@ impl FileMonitor for MmappedFileMonitor {
                                 .borrow_mut()
                                 .ensure_size(local_offset + r.length as u64);
                         } else {
-                            ed_assert!(offset.t, !v.task_set().inner_hashset().is_empty());
+                            ed_assert!(offset.t, !v.task_set().is_empty());
                             // We will record multiple writes if the file is mapped multiple
                             // times. This is inefficient --- one is sufficient --- but not
                             // wrong.
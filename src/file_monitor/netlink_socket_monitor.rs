@@ -0,0 +1,29 @@
+use crate::file_monitor::{FileMonitor, FileMonitorType};
+
+/// A FileMonitor for AF_NETLINK sockets, used for route queries
+/// (getifaddrs(), `ip`), sock_diag and the like. The kernel's answers depend
+/// on the state of the machine, so replay never creates the socket and every
+/// message received from it is emulated from the trace.
+///
+/// Like `EventFdMonitor`, the monitor's existence disables syscall buffering
+/// for the fd so that every receive traps and gets recorded.
+pub struct NetlinkSocketMonitor {
+    protocol: i32,
+}
+
+impl FileMonitor for NetlinkSocketMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::NetlinkSocket
+    }
+}
+
+impl NetlinkSocketMonitor {
+    pub fn new(protocol: i32) -> NetlinkSocketMonitor {
+        NetlinkSocketMonitor { protocol }
+    }
+
+    /// The NETLINK_* protocol the socket was created with.
+    pub fn protocol(&self) -> i32 {
+        self.protocol
+    }
+}
@@ -47,6 +47,9 @@ pub struct Flags {
     /// Don't listen for PTRACE_EVENT_EXIT events, to test how rd handles
     /// missing PTRACE_EVENT_EXITs.
     pub disable_ptrace_exit_events: bool,
+    /// Behave as if there were no usable performance counters, to test
+    /// how rd copes on machines (e.g. VMs) without them.
+    pub force_no_perf: bool,
     /// User override for architecture detection, e.g. when running under valgrind.
     pub forced_uarch: Option<String>,
     /// User override for the path to page files and other resources.
@@ -73,6 +76,7 @@ pub fn init_flags() -> Flags {
         fatal_errors_and_warnings: options.fatal_errors,
         disable_cpuid_faulting: options.disable_cpuid_faulting,
         disable_ptrace_exit_events: options.disable_ptrace_exit_events,
+        force_no_perf: options.force_no_perf,
         forced_uarch: options.microarch,
         resource_path: options.resource_path,
     }
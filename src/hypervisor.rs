@@ -0,0 +1,111 @@
+//! Which hypervisor, if any, rd is running under. Hypervisors virtualize the
+//! performance counters with varying fidelity, so some need workarounds (see
+//! `Hypervisor::skid_size_multiplier()`) and some can only be warned about.
+//! The hypervisor is recorded in the trace header, so a replay failure can be
+//! related to where the trace was recorded.
+
+use crate::{
+    ticks::Ticks,
+    util::{cpuid, CPUID_GETFEATURES, CPUID_HYPERVISOR},
+};
+use std::{fmt, fmt::Display};
+
+/// CPUID.1:ECX bit set by all hypervisors we know of.
+const HYPERVISOR_PRESENT_FLAG: u32 = 1 << 31;
+
+lazy_static! {
+    static ref HOST_HYPERVISOR: Hypervisor = detect_hypervisor();
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Hypervisor {
+    /// Bare metal.
+    None,
+    Kvm,
+    HyperV,
+    Xen,
+    VMware,
+    /// The CPUID hypervisor bit is set but we don't recognize the vendor.
+    Other,
+}
+
+impl Display for Hypervisor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hypervisor::None => write!(f, "none"),
+            Hypervisor::Kvm => write!(f, "kvm"),
+            Hypervisor::HyperV => write!(f, "hyperv"),
+            Hypervisor::Xen => write!(f, "xen"),
+            Hypervisor::VMware => write!(f, "vmware"),
+            Hypervisor::Other => write!(f, "other"),
+        }
+    }
+}
+
+impl Hypervisor {
+    /// What to scale the microarch's skid size by. Counter overflow
+    /// interrupts reach a guest via the hypervisor; under Xen and Hyper-V
+    /// they've been seen to arrive well after the bare-metal skid.
+    pub fn skid_size_multiplier(self) -> Ticks {
+        match self {
+            Hypervisor::Xen | Hypervisor::HyperV => 2,
+            _ => 1,
+        }
+    }
+
+    /// Why ticks may not be deterministic under this hypervisor, if we know of
+    /// a reason. There's nothing rd can do about these.
+    pub fn ticks_warning(self) -> Option<&'static str> {
+        match self {
+            Hypervisor::VMware => Some(
+                "VMware guests need \"Virtualize CPU performance counters\" enabled,\n\
+                 and some VMware versions lose conditional branches next to CPUID.",
+            ),
+            Hypervisor::HyperV => Some(
+                "Hyper-V only gives guests working performance counters when PMU\n\
+                 virtualization is enabled for the VM (Set-VMProcessor -Perfmon).",
+            ),
+            Hypervisor::Xen => Some(
+                "Xen guests need vpmu enabled, and Xen can deliver performance\n\
+                 counter interrupts late.",
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// The hypervisor we're running under, or `Hypervisor::None`.
+pub fn host_hypervisor() -> Hypervisor {
+    *HOST_HYPERVISOR
+}
+
+fn detect_hypervisor() -> Hypervisor {
+    if cpuid(CPUID_GETFEATURES, 0).ecx & HYPERVISOR_PRESENT_FLAG == 0 {
+        return Hypervisor::None;
+    }
+    let base = hypervisor_at(CPUID_HYPERVISOR);
+    // Xen with Viridian extensions presents itself as Hyper-V at the usual
+    // leaf, and as Xen 0x100 leaves further on.
+    if base == Some(Hypervisor::HyperV)
+        && hypervisor_at(CPUID_HYPERVISOR + 0x100) == Some(Hypervisor::Xen)
+    {
+        return Hypervisor::Xen;
+    }
+    base.unwrap_or(Hypervisor::Other)
+}
+
+/// The vendor signature in EBX, ECX, EDX of CPUID leaf `leaf`.
+fn hypervisor_at(leaf: u32) -> Option<Hypervisor> {
+    let data = cpuid(leaf, 0);
+    let mut signature = [0u8; 12];
+    signature[0..4].copy_from_slice(&data.ebx.to_le_bytes());
+    signature[4..8].copy_from_slice(&data.ecx.to_le_bytes());
+    signature[8..12].copy_from_slice(&data.edx.to_le_bytes());
+    match &signature {
+        b"KVMKVMKVM\0\0\0" => Some(Hypervisor::Kvm),
+        b"Microsoft Hv" => Some(Hypervisor::HyperV),
+        b"XenVMMXenVMM" => Some(Hypervisor::Xen),
+        b"VMwareVMware" => Some(Hypervisor::VMware),
+        _ => None,
+    }
+}
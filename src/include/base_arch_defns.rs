@@ -425,7 +425,16 @@ pub struct seminfo {
 }
 
 
-// @TODO user_desc struct.
+/// Same layout for x86 and x64. The seg_32bit, contents, read_exec_only,
+/// limit_in_pages, seg_not_present and useable bitfields are packed into `flags`.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct user_desc {
+    pub entry_number: unsigned_int,
+    pub base_addr: unsigned_int,
+    pub limit: unsigned_int,
+    pub flags: unsigned_int,
+}
 
 
 #[repr(C)]
@@ -1,4 +1,5 @@
 use crate::bindings::kernel::PAGE_SIZE;
+use std::mem::size_of;
 
 /// Bump this whenever the interface between syscallbuf and rd changes in a way
 /// that would require changes to replay. So be very careful making changes to
@@ -61,36 +62,47 @@ pub const PRELOAD_THREAD_LOCALS_SIZE: usize = 104;
 /// NB: magic syscalls must be positive, because with at least linux
 /// 3.8.0 / eglibc 2.17, rd only gets a trap for the *entry* of invalid
 /// syscalls, not the exit.  rd can't handle that yet.
+///
+/// They start at 1000, like rr's, well past the kernel's own syscall
+/// numbers. rd's own magic syscalls start at 1100 so that they don't collide
+/// with the ones rr adds.
 
 /// The preload library calls SYS_rdcall_init_preload during its
 /// initialization.
-pub const SYS_rdcall_init_preload: u32 = 442;
+pub const SYS_rdcall_init_preload: u32 = 1000;
 
 /// The preload library calls SYS_rdcall_init_buffers in each thread that
 /// gets created (including the initial main thread).
-pub const SYS_rdcall_init_buffers: u32 = 443;
+pub const SYS_rdcall_init_buffers: u32 = 1001;
 
 /// The preload library calls SYS_rdcall_notify_syscall_hook_exit when
 /// unlocking the syscallbuf and notify_after_syscall_hook_exit has been set.
 /// The word at 4/8(sp) is returned in the syscall result and the word at
 /// 8/16(sp) is stored in original_syscallno.
-pub const SYS_rdcall_notify_syscall_hook_exit: u32 = 444;
+pub const SYS_rdcall_notify_syscall_hook_exit: u32 = 1002;
 
 /// When the preload library detects that control data has been received in a
 /// syscallbuf'ed recvmsg, it calls this syscall with a pointer to the
 /// 'struct msg' returned.
-pub const SYS_rdcall_notify_control_msg: u32 = 445;
+pub const SYS_rdcall_notify_control_msg: u32 = 1003;
 
 /// When rd replay has restored the auxv vectors for a new process (completing
 /// emulation of exec), it calls this syscall. It takes one parameter, the tid
 /// of the task that it has restored auxv vectors for.
-pub const SYS_rdcall_reload_auxv: u32 = 446;
+pub const SYS_rdcall_reload_auxv: u32 = 1004;
 
 /// When rd replay has flushed a syscallbuf 'mprotect' record, notify any outer
 /// rd of that flush. The first parameter is the tid of the task, the second
 /// parameter is the address, the third parameter is the length, and the
 /// fourth parameter is the prot.
-pub const SYS_rdcall_mprotect_record: u32 = 447;
+pub const SYS_rdcall_mprotect_record: u32 = 1005;
+
+/// The recorded program calls SYS_rdcall_annotate to add a named mark to the
+/// trace, e.g. at the start of handling a request. The first parameter is the
+/// address of the label, the second its length in bytes. Returns 0, or fails
+/// with EINVAL if the label can't be read. Outside of rd this fails with
+/// ENOSYS, so programs can call it unconditionally.
+pub const SYS_rdcall_annotate: u32 = 1100;
 
 /// To support syscall buffering, we replace syscall instructions with a "call"
 /// instruction that calls a hook in the preload library to handle the syscall.
@@ -276,6 +288,59 @@ pub enum syscallbuf_locked_why {
     SyscallbufLockedTracer = 0x2,
 }
 
+/// A hash of the layout of the structs above that rd and librdpreload share
+/// and that replay reads back from the trace: their sizes and the offsets of
+/// their fields, plus SYSCALLBUF_PROTOCOL_VERSION. librdpreload passes its
+/// own idea of this in `rdcall_init_preload_params` and the trace header
+/// stores the one rd recorded with, so that a mismatch is caught up front
+/// rather than showing up as a mysterious divergence.
+pub fn syscallbuf_layout_hash() -> u64 {
+    let layout = [
+        SYSCALLBUF_PROTOCOL_VERSION as usize,
+        size_of::<mprotect_record>(),
+        offset_of!(mprotect_record, start),
+        offset_of!(mprotect_record, size),
+        offset_of!(mprotect_record, prot),
+        size_of::<preload_globals>(),
+        offset_of!(preload_globals, in_replay),
+        offset_of!(preload_globals, in_diversion),
+        offset_of!(preload_globals, in_chaos),
+        offset_of!(preload_globals, desched_sig),
+        offset_of!(preload_globals, pretend_num_cores),
+        offset_of!(preload_globals, syscallbuf_fds_disabled),
+        offset_of!(preload_globals, mprotect_records),
+        offset_of!(preload_globals, random_seed),
+        size_of::<syscallbuf_record>(),
+        offset_of!(syscallbuf_record, ret),
+        offset_of!(syscallbuf_record, syscallno),
+        offset_of!(syscallbuf_record, desched),
+        offset_of!(syscallbuf_record, size),
+        offset_of!(syscallbuf_record, extra_data),
+        size_of::<syscallbuf_hdr>(),
+        offset_of!(syscallbuf_hdr, num_rec_bytes),
+        offset_of!(syscallbuf_hdr, mprotect_record_count),
+        offset_of!(syscallbuf_hdr, mprotect_record_count_completed),
+        offset_of!(syscallbuf_hdr, abort_commit),
+        offset_of!(syscallbuf_hdr, notify_on_syscall_hook_exit),
+        offset_of!(syscallbuf_hdr, locked),
+        offset_of!(syscallbuf_hdr, desched_signal_may_be_relevant),
+        offset_of!(syscallbuf_hdr, blocked_sigs),
+        offset_of!(syscallbuf_hdr, blocked_sigs_generation),
+        offset_of!(syscallbuf_hdr, in_sigprocmask_critical_section),
+        offset_of!(syscallbuf_hdr, failed_during_preparation),
+        offset_of!(syscallbuf_hdr, recs),
+    ];
+    // 64-bit FNV-1a
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for v in layout.iter() {
+        for b in (*v as u64).to_le_bytes().iter() {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
 /// Return the amount of space that a record of `length` will occupy in
 /// the buffer if committed, including padding.
 pub fn stored_record_size(length: u32) -> u32 {
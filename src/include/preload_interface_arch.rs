@@ -127,6 +127,10 @@ pub struct rdcall_init_preload_params {
     /// particular syscallbuf record.
     pub breakpoint_table: ptr<u8>,
     pub breakpoint_table_entry_size: int,
+    /// The preload library's `syscallbuf_layout_hash()`. rd refuses to
+    /// record with a preload library built against a different layout.
+    /// 8-byte aligned on both architectures.
+    pub syscallbuf_layout_hash: aligned_u64,
 }
 
 /// Packs up the inout parameters passed to `SYS_rdcall_init_buffers`.
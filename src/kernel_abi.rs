@@ -16,7 +16,7 @@ use std::{
     marker::PhantomData,
 };
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum SupportedArch {
     X86,
     X64,
@@ -126,6 +126,16 @@ include!(concat!(
     "/syscall_helper_functions_generated.rs"
 ));
 
+/// x32 ABI syscalls use the x64 syscall instruction but set this bit in the
+/// syscall number. See `__X32_SYSCALL_BIT` in <asm/unistd.h>.
+pub const X32_SYSCALL_BIT: isize = 0x40000000;
+
+/// rd does not support the x32 ABI. Use this to detect x32 syscalls so they can be
+/// rejected up front rather than being recorded with numbers nothing knows about.
+pub fn is_x32_syscall(syscallno: isize, arch: SupportedArch) -> bool {
+    arch == SupportedArch::X64 && syscallno >= 0 && syscallno & X32_SYSCALL_BIT != 0
+}
+
 #[cfg(target_arch = "x86_64")]
 pub const RD_NATIVE_ARCH: SupportedArch = SupportedArch::X64;
 
@@ -151,11 +161,9 @@ const INT80_INSN: [u8; 2] = [0xcd, 0x80];
 const SYSENTER_INSN: [u8; 2] = [0x0f, 0x34];
 const SYSCALL_INSN: [u8; 2] = [0x0f, 0x05];
 
-fn get_syscall_instruction_arch(
-    t: &mut dyn Task,
-    ptr: RemoteCodePtr,
-    arch: &mut SupportedArch,
-) -> bool {
+/// Return the arch of the syscall table that the syscall instruction at `ptr`
+/// dispatches to, or None if there is no syscall instruction at `ptr`.
+pub fn get_syscall_instruction_arch(t: &mut dyn Task, ptr: RemoteCodePtr) -> Option<SupportedArch> {
     // Lots of syscalls occur in the rd page and we know what it contains without
     // looking at it.
     // (Without this optimization we spend a few % of all CPU time in this
@@ -174,8 +182,7 @@ fn get_syscall_instruction_arch(
                         })
                 {
                     // rd-page syscalls are always the task's arch
-                    *arch = t.arch();
-                    return true;
+                    return Some(t.arch());
                 }
             }
             None => (),
@@ -185,7 +192,7 @@ fn get_syscall_instruction_arch(
     let mut ok = true;
     let code: Vec<u8> = read_mem(t, ptr.to_data_ptr::<u8>(), 2, Some(&mut ok));
     if !ok {
-        return false;
+        return None;
     }
     match t.arch() {
         // Compatibility mode switch can happen in user space (but even without
@@ -193,20 +200,18 @@ fn get_syscall_instruction_arch(
         // from 64bit processes).
         SupportedArch::X86 | SupportedArch::X64 => {
             if code == INT80_INSN || code == SYSENTER_INSN {
-                *arch = SupportedArch::X86;
+                Some(SupportedArch::X86)
             } else if code == SYSCALL_INSN {
-                *arch = SupportedArch::X64;
+                Some(SupportedArch::X64)
             } else {
-                return false;
+                None
             }
-            return true;
         }
     }
 }
 
 pub fn is_at_syscall_instruction(t: &mut dyn Task, ptr: RemoteCodePtr) -> bool {
-    let mut arch = SupportedArch::X64;
-    get_syscall_instruction_arch(t, ptr, &mut arch)
+    get_syscall_instruction_arch(t, ptr).is_some()
 }
 
 /// Return the code bytes of an invoke-syscall instruction. The vector must
@@ -611,9 +616,8 @@ pub mod x64 {
         assert_eq_size!(kernel::seminfo, seminfo);
         assert_eq_align!(kernel::seminfo, seminfo);
 
-        // @TODO.
-        // assert_eq_size!(kernel::user_desc, user_desc);
-        // assert_eq_align!(kernel::user_desc, user_desc);
+        assert_eq_size!(kernel::user_desc, user_desc);
+        assert_eq_align!(kernel::user_desc, user_desc);
 
         assert_eq_size!(kernel::__user_cap_header_struct, __user_cap_header_struct);
         assert_eq_align!(kernel::__user_cap_header_struct, __user_cap_header_struct);
@@ -1060,9 +1064,8 @@ pub mod x86 {
         assert_eq_size!(kernel::seminfo, seminfo);
         assert_eq_align!(kernel::seminfo, seminfo);
 
-        // @TODO.
-        // assert_eq_size!(kernel::user_desc, user_desc);
-        // assert_eq_align!(kernel::user_desc, user_desc);
+        assert_eq_size!(kernel::user_desc, user_desc);
+        assert_eq_align!(kernel::user_desc, user_desc);
 
         assert_eq_size!(kernel::__user_cap_header_struct, __user_cap_header_struct);
         assert_eq_align!(kernel::__user_cap_header_struct, __user_cap_header_struct);
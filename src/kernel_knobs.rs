@@ -0,0 +1,121 @@
+//! The /proc/sys settings rd depends on. They're checked before rd runs any
+//! tracees so that the user is told which sysctl to change, rather than just
+//! seeing perf_event_open() or PTRACE_SEIZE fail. They're also recorded in the
+//! trace header, to help with bug reports.
+
+use crate::{
+    bindings::kernel::{CAP_SYS_ADMIN, CAP_SYS_PTRACE},
+    util::has_effective_caps,
+};
+use libc::{prctl, PR_GET_SECCOMP};
+use std::{fmt, fmt::Display, fs::read_to_string};
+
+/// Not in older kernel headers.
+const CAP_PERFMON: u32 = 38;
+
+const PERF_EVENT_PARANOID: &str = "/proc/sys/kernel/perf_event_paranoid";
+const PTRACE_SCOPE: &str = "/proc/sys/kernel/yama/ptrace_scope";
+
+#[derive(Clone, Debug)]
+pub struct KernelKnobs {
+    /// kernel.perf_event_paranoid, if readable.
+    pub perf_event_paranoid: Option<i32>,
+    /// kernel.yama.ptrace_scope; `None` without the Yama LSM.
+    pub ptrace_scope: Option<i32>,
+    /// Whether the kernel was built with seccomp.
+    pub seccomp: bool,
+}
+
+pub struct KnobProblem {
+    pub problem: String,
+    pub fix: String,
+}
+
+impl KernelKnobs {
+    pub fn read() -> KernelKnobs {
+        KernelKnobs {
+            perf_event_paranoid: read_int(PERF_EVENT_PARANOID),
+            ptrace_scope: read_int(PTRACE_SCOPE),
+            // Returns the current seccomp mode, or fails with EINVAL if the
+            // kernel was built without seccomp.
+            seccomp: unsafe { prctl(PR_GET_SECCOMP, 0, 0, 0, 0) } >= 0,
+        }
+    }
+
+    /// What stops rd from running tracees here.
+    pub fn problems(&self) -> Vec<KnobProblem> {
+        let mut problems = Vec::new();
+
+        // 2 still lets us count our tracees' user-space events. Higher values
+        // (a Debian/Ubuntu extension) forbid unprivileged use entirely.
+        let max_paranoid = 2;
+        match self.perf_event_paranoid {
+            Some(paranoid) if paranoid > max_paranoid && !has_perf_privileges() => {
+                problems.push(KnobProblem {
+                    problem: format!(
+                        "kernel.perf_event_paranoid is {}; rd needs {} or less",
+                        paranoid, max_paranoid
+                    ),
+                    fix: format!(
+                        "run `sudo sysctl kernel.perf_event_paranoid={}`",
+                        max_paranoid
+                    ),
+                });
+            }
+            _ => (),
+        }
+
+        // rd PTRACE_SEIZEs the tracees it spawns. 1 allows that since they're
+        // our descendants, 2 needs CAP_SYS_PTRACE and 3 forbids it.
+        match self.ptrace_scope {
+            Some(3) => problems.push(KnobProblem {
+                problem: "kernel.yama.ptrace_scope is 3, which disables ptrace attach".into(),
+                fix: "set kernel.yama.ptrace_scope to 1 or less; 3 can only be changed by \
+                      rebooting"
+                    .into(),
+            }),
+            Some(2) if !has_effective_caps(1 << CAP_SYS_PTRACE) => problems.push(KnobProblem {
+                problem: "kernel.yama.ptrace_scope is 2, which needs CAP_SYS_PTRACE to attach"
+                    .into(),
+                fix: "run `sudo sysctl kernel.yama.ptrace_scope=1`".into(),
+            }),
+            _ => (),
+        }
+
+        problems
+    }
+}
+
+impl Display for KernelKnobs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "kernel.perf_event_paranoid={} kernel.yama.ptrace_scope={} seccomp={}",
+            self.perf_event_paranoid
+                .map_or("?".to_owned(), |v| v.to_string()),
+            self.ptrace_scope.map_or("?".to_owned(), |v| v.to_string()),
+            self.seccomp as i32
+        )
+    }
+}
+
+/// Exit, saying what to change, if the kernel settings don't let rd replay.
+pub fn check_kernel_knobs() {
+    let problems = KernelKnobs::read().problems();
+    if problems.is_empty() {
+        return;
+    }
+    let mut msg = String::new();
+    for p in &problems {
+        msg.push_str(&format!("\n  {}: {}", p.problem, p.fix));
+    }
+    clean_fatal!("The kernel's settings don't let rd run tracees:{}", msg);
+}
+
+fn has_perf_privileges() -> bool {
+    has_effective_caps(1 << CAP_SYS_ADMIN) || has_effective_caps(1 << CAP_PERFMON)
+}
+
+fn read_int(path: &str) -> Option<i32> {
+    read_to_string(path).ok()?.trim().parse::<i32>().ok()
+}
@@ -7,6 +7,10 @@ use crate::{
 use nix::sys::mman::ProtFlags;
 
 pub fn syscall_name(syscall: i32, arch: SupportedArch) -> String {
+    if kernel_abi::is_x32_syscall(syscall as isize, arch) {
+        let x64_syscall = syscall & !(kernel_abi::X32_SYSCALL_BIT as i32);
+        return format!("x32:{}", kernel_abi::x64::syscallname_arch(x64_syscall));
+    }
     rd_kernel_abi_arch_function!(syscallname_arch, arch, syscall)
 }
 
@@ -28,8 +28,10 @@ mod bindings;
 mod flags;
 mod kernel_metadata;
 mod perf_counters;
+mod property_table;
 #[macro_use]
 mod registers;
+mod cgroup;
 mod commands;
 mod core;
 mod cpuid_bug_detector;
@@ -41,6 +43,8 @@ mod fd_table;
 mod file_monitor;
 mod gdb_register;
 mod gdb_server;
+mod hypervisor;
+mod kernel_knobs;
 mod kernel_supplement;
 mod monitored_shared_memory;
 mod monkey_patcher;
@@ -48,55 +52,78 @@ mod rd;
 mod remote_code_ptr;
 mod remote_ptr;
 mod replay_syscall;
+mod resource_path;
 mod scheduler;
 mod scoped_fd;
 mod seccomp_bpf;
 mod seccomp_filter_rewriter;
 mod session;
+mod syscall_handler;
 mod taskish_uid;
 mod thread_group;
 mod ticks;
 mod trace;
 mod trace_capnp;
 mod util;
+mod vsyscall;
 mod wait_status;
 mod weak_ptr_set;
 
 use crate::{
     commands::{
         build_id_command::BuildIdCommand,
+        check_install_command::CheckInstallCommand,
+        cleanup_command::CleanupCommand,
+        coverage_command::CoverageCommand,
+        cpu_features_command::CpuFeaturesCommand,
+        diff_command::DiffCommand,
         dump_command::DumpCommand,
+        export_command::ExportCommand,
         ps_command::PsCommand,
         rd_options::{RdOptions, RdSubCommand},
         rerun_command::ReRunCommand,
         trace_info_command::TraceInfoCommand,
+        verify_command::VerifyCommand,
+        watch_command::WatchCommand,
         RdCommand,
     },
-    perf_counters::init_pmu,
+    kernel_knobs::check_kernel_knobs,
+    perf_counters::{init_pmu, pmu_available, require_pmu},
     util::raise_resource_limits,
 };
 use commands::replay_command::ReplayCommand;
 use nix::sys::utsname::uname;
-use std::io;
+use std::{io, process::ExitCode};
 use structopt::StructOpt;
 
-pub fn assert_prerequisites(maybe_use_syscall_buffer: Option<bool>) {
-    let use_syscall_buffer = maybe_use_syscall_buffer.unwrap_or(false);
-    let unm = uname();
-    let release = unm.release();
+/// PTRACE_SYSEMU, PTRACE_EVENT_EXIT etc. as rd uses them.
+pub const MIN_KERNEL_VERSION: (u32, u32) = (3, 4);
+
+/// The (major, minor) version in a kernel release string like `5.10.0-8-amd64`,
+/// or `None` if it can't be parsed.
+pub fn kernel_version(release: &str) -> Option<(u32, u32)> {
     let parts: Vec<&str> = release.split('.').collect();
     if parts.len() < 2 {
-        fatal!("Could not parse kernel version string. Got: `{}`", release);
+        return None;
     }
 
-    let maybe_major = parts[0].parse::<u32>();
-    let maybe_minor = parts[1].parse::<u32>();
-    if maybe_major.is_err() || maybe_minor.is_err() {
-        fatal!("Could not parse kernel version string. Got: `{}`", release);
-    }
+    let major = parts[0].parse::<u32>().ok()?;
+    let minor = parts[1].parse::<u32>().ok()?;
+    Some((major, minor))
+}
 
-    let (major, minor) = (maybe_major.unwrap(), maybe_minor.unwrap());
-    if (major, minor) < (3, 4) {
+pub fn assert_prerequisites(maybe_use_syscall_buffer: Option<bool>) {
+    let use_syscall_buffer = maybe_use_syscall_buffer.unwrap_or(false);
+    let unm = uname();
+    let release = unm.release();
+    let (major, minor) = match kernel_version(release) {
+        Some(version) => version,
+        None => {
+            fatal!("Could not parse kernel version string. Got: `{}`", release);
+            unreachable!()
+        }
+    };
+    if (major, minor) < MIN_KERNEL_VERSION {
         fatal!("Kernel doesn't support necessary ptrace functionality; need 3.4.0 or better.");
     }
 
@@ -105,21 +132,58 @@ pub fn assert_prerequisites(maybe_use_syscall_buffer: Option<bool>) {
     }
 }
 
-fn main() -> io::Result<()> {
+fn main() -> io::Result<ExitCode> {
     raise_resource_limits();
     let options = RdOptions::from_args();
 
-    init_pmu();
+    // Only commands that run tracees need performance counters. The rest
+    // must work on machines without them so traces can be inspected anywhere.
+    let needs_pmu = match &options.cmd {
+        RdSubCommand::Replay { .. } => Some("replay"),
+        RdSubCommand::ReRun { .. } => Some("rerun"),
+        RdSubCommand::Coverage { .. } => Some("collect coverage"),
+        RdSubCommand::Watch { .. } => Some("watch"),
+        _ => None,
+    };
+    if let Some(what) = needs_pmu {
+        require_pmu(what);
+        check_kernel_knobs();
+    }
+    if pmu_available() {
+        init_pmu();
+    }
     match &options.cmd {
-        RdSubCommand::BuildId => return BuildIdCommand::new().run(),
+        RdSubCommand::BuildId => {
+            BuildIdCommand::new().run()?;
+        }
+        RdSubCommand::CheckInstall => {
+            CheckInstallCommand::new().run()?;
+        }
+        RdSubCommand::Cleanup { .. } => {
+            CleanupCommand::new(&options).run()?;
+        }
+        RdSubCommand::Coverage { .. } => {
+            CoverageCommand::new(&options).run()?;
+        }
+        RdSubCommand::CpuFeatures => {
+            CpuFeaturesCommand::new().run()?;
+        }
+        RdSubCommand::Diff { .. } => {
+            DiffCommand::new(&options).run()?;
+        }
         RdSubCommand::Dump { .. } => {
             DumpCommand::new(&options).run()?;
         }
+        RdSubCommand::Export { .. } => {
+            ExportCommand::new(&options).run()?;
+        }
         RdSubCommand::ReRun { .. } => {
             ReRunCommand::new(&options).run()?;
         }
         RdSubCommand::Replay { .. } => {
-            ReplayCommand::new(&options).run()?;
+            let mut replay = ReplayCommand::new(&options);
+            replay.run()?;
+            return Ok(replay.exit_code());
         }
         RdSubCommand::TraceInfo { .. } => {
             TraceInfoCommand::new(&options).run()?;
@@ -127,9 +191,15 @@ fn main() -> io::Result<()> {
         RdSubCommand::Ps { .. } => {
             PsCommand::new(&options).run()?;
         }
+        RdSubCommand::Verify { .. } => {
+            VerifyCommand::new(&options).run()?;
+        }
+        RdSubCommand::Watch { .. } => {
+            WatchCommand::new(&options).run()?;
+        }
         _ => (),
     }
 
     // write!(stderr(), "{:?}\n", options)?;
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
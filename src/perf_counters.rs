@@ -14,7 +14,8 @@ use crate::{
         },
     },
     flags::Flags,
-    kernel_metadata::signal_name,
+    hypervisor::host_hypervisor,
+    kernel_metadata::{errno_name, signal_name},
     log::LogLevel::{LogDebug, LogInfo, LogWarn},
     scoped_fd::ScopedFd,
     session::task::task_inner::task_inner::TaskInner,
@@ -39,6 +40,68 @@ lazy_static! {
     static ref PMU_BRANCHES_ACCUMULATOR: Mutex<u32> = Mutex::new(0);
     static ref PMU_BUGS_AND_EXTRA: PmuBugsAndExtra = check_for_bugs_and_extra();
     static ref PMU_ATTRIBUTES: PmuAttributes = get_init_attributes();
+    static ref PMU_UNAVAILABLE_REASON: Option<String> = pmu_unavailable_reason_init();
+}
+
+/// Why rd can't use performance counters here, or None if it can. Without
+/// them rd can't record or replay, but commands that only read traces work.
+pub fn pmu_unavailable_reason() -> Option<&'static str> {
+    PMU_UNAVAILABLE_REASON.as_deref()
+}
+
+pub fn pmu_available() -> bool {
+    pmu_unavailable_reason().is_none()
+}
+
+/// Exit with a message explaining why, if `what` (e.g. "record") can't be
+/// done because there are no usable performance counters.
+pub fn require_pmu(what: &str) {
+    if let Some(reason) = pmu_unavailable_reason() {
+        clean_fatal!(
+            "Can't {}: {}.\n\
+             rd needs hardware performance counters to count tracee progress.\n\
+             Commands that only read a trace (e.g. dump, ps, traceinfo) still work.",
+            what,
+            reason
+        );
+    }
+}
+
+fn pmu_unavailable_reason_init() -> Option<String> {
+    if Flags::get().force_no_perf {
+        return Some("performance counters disabled by --force-no-perf".into());
+    }
+    // Counting cycles is the least we'd need. This doesn't depend on the
+    // microarch so it tells us whether there's a PMU at all, rather than
+    // whether we know how to use it.
+    let mut attr = new_perf_event_attr(PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES as u64);
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &mut attr as *mut perf_event_attr,
+            0,
+            -1,
+            -1,
+            0,
+        ) as RawFd
+    };
+    if fd >= 0 {
+        drop(ScopedFd::from_raw(fd));
+        return None;
+    }
+    let err = errno();
+    let reason = match err {
+        libc::ENOENT | libc::EOPNOTSUPP | libc::ENODEV => "no hardware performance counters \
+             are available (is this a VM or container without PMU virtualization?)"
+            .into(),
+        libc::EACCES | libc::EPERM => "not permitted to use perf_event_open (check \
+             /proc/sys/kernel/perf_event_paranoid)"
+            .into(),
+        libc::ENOSYS => "the kernel was built without perf events".into(),
+        _ => format!("perf_event_open failed with {}", errno_name(err)),
+    };
+    log!(LogWarn, "Performance counters unavailable: {}", reason);
+    Some(reason)
 }
 
 pub fn init_pmu() {
@@ -357,7 +420,21 @@ fn get_init_attributes() -> PmuAttributes {
         skid_size = RD_SKID_MAX;
         pmu_flags = pmu.flags & (PmuFlags::PMU_TICKS_RCB | PmuFlags::PMU_TICKS_TAKEN_BRANCHES);
     } else {
-        skid_size = pmu.skid_size;
+        let hypervisor = host_hypervisor();
+        skid_size = pmu.skid_size * hypervisor.skid_size_multiplier();
+        if let Some(warning) = hypervisor.ticks_warning() {
+            log!(LogWarn, "Running under {}", hypervisor);
+            if !Flags::get().suppress_environment_warnings {
+                write!(
+                    stderr(),
+                    "rd is running under a hypervisor ({}).\n{}\n\
+                     Traces recorded here may not replay reliably.\n",
+                    hypervisor,
+                    warning
+                )
+                .unwrap();
+            }
+        }
         pmu_flags = pmu.flags;
         ticks_attr = new_perf_event_attr(PERF_TYPE_RAW, pmu.rcb_cntr_event as u64);
         if pmu.minus_ticks_cntr_event != 0 {
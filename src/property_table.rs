@@ -0,0 +1,233 @@
+//! A PropertyTable lets subsystems (e.g. the monkeypatcher or file monitors)
+//! attach their own state to a Task or Session without having to add a field
+//! to TaskInner/SessionInner for every such piece of state.
+//!
+//! Properties are keyed by their Rust type, so each subsystem simply defines a
+//! struct for its state and implements `Property` for it.
+
+use crate::session::task::task_inner::CloneFlags;
+use std::{
+    any::{Any, TypeId},
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+    mem,
+};
+
+/// Per-object extension state. The lifecycle hooks are only invoked for
+/// properties attached to tasks. The defaults are: not inherited by clone()-d
+/// tasks, dropped at exec(), nothing special at exit.
+pub trait Property: Any {
+    /// Return the value the new task should get when the owning task is
+    /// cloned with `flags`, or None if the new task should not have this property.
+    fn on_clone(&self, _flags: CloneFlags) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Called after the owning task has exec()-ed. Return true to keep the
+    /// property.
+    fn on_exec(&mut self) -> bool {
+        false
+    }
+
+    /// Called when the owning task is being destroyed.
+    fn on_exit(&mut self) {}
+}
+
+/// Object safe version of `Property`. Implemented for all `Property`s.
+trait PropertyValue {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn clone_for_new_task(&self, flags: CloneFlags) -> Option<Box<dyn PropertyValue>>;
+    fn on_exec(&mut self) -> bool;
+    fn on_exit(&mut self);
+}
+
+impl<T: Property> PropertyValue for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn clone_for_new_task(&self, flags: CloneFlags) -> Option<Box<dyn PropertyValue>> {
+        match Property::on_clone(self, flags) {
+            Some(v) => Some(Box::new(v)),
+            None => None,
+        }
+    }
+
+    fn on_exec(&mut self) -> bool {
+        Property::on_exec(self)
+    }
+
+    fn on_exit(&mut self) {
+        Property::on_exit(self)
+    }
+}
+
+/// Interior mutability is used so that properties can be accessed from a `&Task`.
+/// As with any RefCell, don't hold on to a `Ref`/`RefMut` returned from here across
+/// calls that may access the same property.
+#[derive(Default)]
+pub struct PropertyTable {
+    values: RefCell<HashMap<TypeId, Box<dyn PropertyValue>>>,
+}
+
+impl PropertyTable {
+    pub fn new() -> PropertyTable {
+        Default::default()
+    }
+
+    pub fn contains<T: Property>(&self) -> bool {
+        self.values.borrow().contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn get<T: Property>(&self) -> Option<Ref<'_, T>> {
+        if !self.contains::<T>() {
+            return None;
+        }
+        Some(Ref::map(self.values.borrow(), |values| {
+            values[&TypeId::of::<T>()]
+                .as_any()
+                .downcast_ref::<T>()
+                .unwrap()
+        }))
+    }
+
+    pub fn get_mut<T: Property>(&self) -> Option<RefMut<'_, T>> {
+        if !self.contains::<T>() {
+            return None;
+        }
+        Some(RefMut::map(self.values.borrow_mut(), |values| {
+            values
+                .get_mut(&TypeId::of::<T>())
+                .unwrap()
+                .as_any_mut()
+                .downcast_mut::<T>()
+                .unwrap()
+        }))
+    }
+
+    pub fn get_or_create<T: Property + Default>(&self) -> RefMut<'_, T> {
+        if !self.contains::<T>() {
+            self.set(T::default());
+        }
+        self.get_mut::<T>().unwrap()
+    }
+
+    /// Set the property, replacing any existing value.
+    pub fn set<T: Property>(&self, value: T) {
+        self.values
+            .borrow_mut()
+            .insert(TypeId::of::<T>(), Box::new(value));
+    }
+
+    /// Returns true if the property was present.
+    pub fn remove<T: Property>(&self) -> bool {
+        self.values.borrow_mut().remove(&TypeId::of::<T>()).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.borrow().is_empty()
+    }
+
+    /// The properties a task cloned with `flags` from the owner of this table starts with.
+    pub fn clone_for_new_task(&self, flags: CloneFlags) -> PropertyTable {
+        let mut values: HashMap<TypeId, Box<dyn PropertyValue>> = HashMap::new();
+        for (type_id, value) in self.values.borrow().iter() {
+            if let Some(v) = value.clone_for_new_task(flags) {
+                values.insert(*type_id, v);
+            }
+        }
+        PropertyTable {
+            values: RefCell::new(values),
+        }
+    }
+
+    pub fn on_exec(&self) {
+        // Take the values out so that hooks are free to access this table.
+        let mut values = mem::take(&mut *self.values.borrow_mut());
+        values.retain(|_, v| v.on_exec());
+        let mut current = self.values.borrow_mut();
+        for (type_id, v) in values {
+            current.entry(type_id).or_insert(v);
+        }
+    }
+
+    /// Runs the exit hooks and drops all properties.
+    pub fn on_exit(&self) {
+        let values = mem::take(&mut *self.values.borrow_mut());
+        for (_, mut v) in values {
+            v.on_exit();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+
+    #[derive(Default)]
+    struct Counter(u32);
+
+    impl Property for Counter {
+        fn on_clone(&self, flags: CloneFlags) -> Option<Counter> {
+            if flags.contains(CloneFlags::CLONE_SHARE_VM) {
+                Some(Counter(self.0))
+            } else {
+                None
+            }
+        }
+    }
+
+    struct ExitFlag(Rc<Cell<bool>>);
+
+    impl Property for ExitFlag {
+        fn on_exec(&mut self) -> bool {
+            true
+        }
+
+        fn on_exit(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    #[test]
+    fn get_set_test() {
+        let table = PropertyTable::new();
+        assert!(table.get::<Counter>().is_none());
+        table.get_or_create::<Counter>().0 += 2;
+        table.get_or_create::<Counter>().0 += 3;
+        assert_eq!(5, table.get::<Counter>().unwrap().0);
+        assert!(table.remove::<Counter>());
+        assert!(!table.contains::<Counter>());
+    }
+
+    #[test]
+    fn lifecycle_test() {
+        let exited = Rc::new(Cell::new(false));
+        let table = PropertyTable::new();
+        table.set(Counter(7));
+        table.set(ExitFlag(exited.clone()));
+
+        let thread = table.clone_for_new_task(CloneFlags::CLONE_SHARE_VM);
+        assert_eq!(7, thread.get::<Counter>().unwrap().0);
+        assert!(!thread.contains::<ExitFlag>());
+        let process = table.clone_for_new_task(CloneFlags::empty());
+        assert!(process.is_empty());
+
+        table.on_exec();
+        assert!(!table.contains::<Counter>());
+        assert!(table.contains::<ExitFlag>());
+
+        table.on_exit();
+        assert!(exited.get());
+        assert!(table.is_empty());
+    }
+}
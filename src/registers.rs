@@ -158,7 +158,19 @@ impl Registers {
         regs2: &Registers,
         mismatch_behavior: MismatchBehavior,
     ) -> bool {
-        let mut match_ = true;
+        let mismatches = regs1.mismatches(regs2);
+        for &(regname, val1, val2) in &mismatches {
+            maybe_log_reg_mismatch(mismatch_behavior, regname, name1, val1, name2, val2);
+        }
+        mismatches.is_empty()
+    }
+
+    /// The registers that differ between `self` and `other` as (name, our
+    /// value, their value), ignoring bits that aren't meaningful to compare.
+    pub fn mismatches(&self, other: &Registers) -> Vec<(&'static str, u64, u64)> {
+        let regs1 = self;
+        let regs2 = other;
+        let mut mismatches = Vec::new();
         debug_assert!(regs1.arch() == regs2.arch());
         let regs_info = regs1.get_regs_info();
 
@@ -171,15 +183,11 @@ impl Registers {
                 // they reflect original syscall numbers, in which case both will be positive.
                 if regs1_x86.orig_eax >= 0 && regs2_x86.orig_eax > 0 {
                     if regs1_x86.orig_eax != regs2_x86.orig_eax {
-                        maybe_log_reg_mismatch(
-                            mismatch_behavior,
+                        mismatches.push((
                             "orig_eax",
-                            name1,
                             regs1_x86.orig_eax as u64,
-                            name2,
                             regs2_x86.orig_eax as u64,
-                        );
-                        match_ = false;
+                        ));
                     }
                 }
             }
@@ -188,15 +196,7 @@ impl Registers {
                 // See comment in the x86 case
                 if (regs1_x64.orig_rax as i64) >= 0 && (regs2_x64.orig_rax as i64) > 0 {
                     if regs1_x64.orig_rax != regs2_x64.orig_rax {
-                        maybe_log_reg_mismatch(
-                            mismatch_behavior,
-                            "orig_rax",
-                            name1,
-                            regs1_x64.orig_rax,
-                            name2,
-                            regs2_x64.orig_rax,
-                        );
-                        match_ = false;
+                        mismatches.push(("orig_rax", regs1_x64.orig_rax, regs2_x64.orig_rax));
                     }
                 }
             }
@@ -240,12 +240,11 @@ impl Registers {
             }
 
             if val1 & rv.comparison_mask != val2 & rv.comparison_mask {
-                maybe_log_reg_mismatch(mismatch_behavior, rv.name, name1, val1, name2, val2);
-                match_ = false;
+                mismatches.push((rv.name, val1, val2));
             }
         }
 
-        match_
+        mismatches
     }
 
     fn compare_register_files_internal(
@@ -876,27 +875,39 @@ impl Registers {
     }
 
     /// DIFF NOTE: rr returns a usize instead
+    /// 32-bit tracees don't have an fs_base register. Their TLS is reached
+    /// through the GDT entry selected by %gs. See `tls_entry_number()`.
     pub fn fs_base(&self) -> u64 {
-        let x64 = self.x64();
-        x64.fs_base
+        match self {
+            X86(_) => 0,
+            X64(regs) => regs.fs_base,
+        }
     }
 
     /// DIFF NOTE:: rr returns a usize instead
     pub fn gs_base(&self) -> u64 {
-        let x64 = self.x64();
-        x64.gs_base
+        match self {
+            X86(_) => 0,
+            X64(regs) => regs.gs_base,
+        }
     }
 
     /// DIFF NOTE: rr takes a usize instead
+    /// Setting a base on a 32-bit tracee is a no-op: the base comes from the
+    /// thread area the corresponding selector points to.
     pub fn set_fs_base(&mut self, fs_base: u64) {
-        let mut x64 = self.x64_mut();
-        x64.fs_base = fs_base;
+        match self {
+            X86(_) => debug_assert_eq!(fs_base, 0, "No fs_base register for x86"),
+            X64(regs) => regs.fs_base = fs_base,
+        }
     }
 
     /// DIFF NOTE: rr takes a usize instead
     pub fn set_gs_base(&mut self, gs_base: u64) {
-        let mut x64 = self.x64_mut();
-        x64.gs_base = gs_base;
+        match self {
+            X86(_) => debug_assert_eq!(gs_base, 0, "No gs_base register for x86"),
+            X64(regs) => regs.gs_base = gs_base,
+        }
     }
 
     /// DIFF NOTE: rr returns a u64 for these segment registers but we return a usize
@@ -919,6 +930,35 @@ impl Registers {
         rd_get_reg!(self, xgs, gs)
     }
 
+    pub fn set_cs(&mut self, value: usize) {
+        rd_set_reg!(self, xcs, cs, value)
+    }
+    pub fn set_ss(&mut self, value: usize) {
+        rd_set_reg!(self, xss, ss, value)
+    }
+    pub fn set_ds(&mut self, value: usize) {
+        rd_set_reg!(self, xds, ds, value)
+    }
+    pub fn set_es(&mut self, value: usize) {
+        rd_set_reg!(self, xes, es, value)
+    }
+    pub fn set_fs(&mut self, value: usize) {
+        rd_set_reg!(self, xfs, fs, value)
+    }
+    pub fn set_gs(&mut self, value: usize) {
+        rd_set_reg!(self, xgs, gs, value)
+    }
+
+    /// The GDT entry number a 32-bit tracee's TLS selector refers to, i.e. the
+    /// `entry_number` of the `user_desc` installed by set_thread_area().
+    /// glibc uses %gs for TLS on x86. Returns None for x64 tracees, for null
+    /// selectors and for selectors that point into the LDT.
+    pub fn tls_entry_number(&self) -> Option<u32> {
+        match self {
+            X86(_) => segment_selector_gdt_index(self.gs()),
+            X64(_) => None,
+        }
+    }
     pub fn write_register_file_for_trace_raw(&self, f: &mut dyn Write) -> io::Result<()> {
         let x86 = match self {
             X86(x86_regs) => *x86_regs,
@@ -1081,6 +1121,17 @@ impl Registers {
     }
 }
 
+/// Selector layout: bits 0-1 are the RPL, bit 2 is the table indicator
+/// (0 = GDT, 1 = LDT), and the remaining bits are the descriptor index.
+pub fn segment_selector_gdt_index(selector: usize) -> Option<u32> {
+    let index = (selector >> 3) as u32;
+    if selector & 0x4 != 0 || index == 0 {
+        None
+    } else {
+        Some(index)
+    }
+}
+
 fn to_x86_narrow(r32: &mut i32, r64: u64) {
     *r32 = r64 as i32;
 }
@@ -1517,3 +1568,31 @@ impl Default for Registers {
         Registers::X86(x86::user_regs_struct::default())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn x86_segment_roundtrip_test() {
+        let mut regs = Registers::new(SupportedArch::X86);
+        // GDT entry 12, RPL 3: what glibc loads into %gs on x86
+        regs.set_gs(0x63);
+        assert_eq!(0x63, regs.gs());
+        assert_eq!(Some(12), regs.tls_entry_number());
+        assert_eq!(0, regs.gs_base());
+        let widened = regs.get_ptrace();
+        let mut narrowed = Registers::new(SupportedArch::X86);
+        narrowed.set_from_ptrace(&widened);
+        assert_eq!(0x63, narrowed.gs());
+    }
+
+    #[test]
+    fn segment_selector_gdt_index_test() {
+        assert_eq!(None, segment_selector_gdt_index(0));
+        assert_eq!(None, segment_selector_gdt_index(0x3));
+        // LDT selector
+        assert_eq!(None, segment_selector_gdt_index(0x67));
+        assert_eq!(Some(12), segment_selector_gdt_index(0x63));
+    }
+}
@@ -28,7 +28,9 @@ use crate::{
     emu_fs::EmuFileSharedPtr,
     file_monitor::{
         base_file_monitor::BaseFileMonitor,
+        event_fd_monitor::{event_fd_created_by, EventFdMonitor},
         mmapped_file_monitor::MmappedFileMonitor,
+        netlink_socket_monitor::NetlinkSocketMonitor,
         proc_fd_dir_monitor::ProcFdDirMonitor,
         proc_mem_monitor::ProcMemMonitor,
         stdio_monitor::StdioMonitor,
@@ -40,6 +42,7 @@ use crate::{
         is_rdcall_notify_syscall_hook_exit_syscall,
         is_restart_syscall_syscall,
         is_write_syscall,
+        is_x32_syscall,
         syscall_number_for_close,
         syscall_number_for_execve,
         syscall_number_for_munmap,
@@ -55,6 +58,7 @@ use crate::{
     log::LogLevel::LogDebug,
     registers::{with_converted_registers, Registers},
     remote_ptr::{RemotePtr, Void},
+    resource_path::resource_path,
     scoped_fd::ScopedFd,
     seccomp_filter_rewriter::SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO,
     session::{
@@ -73,15 +77,22 @@ use crate::{
         },
         task::{
             replay_task::{ReplayTask, ReplayTaskIgnore},
-            task_common::{read_mem, read_val_mem, write_mem, write_val_mem},
+            task_common::{
+                read_mem,
+                read_val_mem,
+                set_syscallbuf_locked,
+                write_mem,
+                write_val_mem,
+            },
             task_inner::{task_inner::WriteFlags, ResumeRequest, TicksRequest, WaitRequest},
             Task,
             TaskSharedPtr,
         },
     },
+    syscall_handler::syscall_handler,
     trace::{
         trace_frame::FrameTime,
-        trace_reader::{TimeConstraint, ValidateSourceFile},
+        trace_reader::{RawData, TimeConstraint, ValidateSourceFile},
         trace_stream,
         trace_stream::MappedData,
         trace_task_event::{TraceTaskEvent, TraceTaskEventType},
@@ -90,13 +101,17 @@ use crate::{
         ceil_page_size,
         ceil_page_u64,
         clone_flags_to_task_flags,
+        cpuid,
         extract_clone_parameters,
         floor_page_size,
+        huge_page_size,
         is_proc_fd_dir,
         is_proc_mem_file,
         page_size,
-        resource_path,
+        read_meminfo_field,
         CloneParameters,
+        CPUID_GETEXTENDEDFEATURES,
+        OSPKE_FEATURE_FLAG,
     },
     wait_status::WaitStatus,
 };
@@ -107,6 +122,7 @@ use libc::{
     off_t,
     pid_t,
     syscall,
+    AF_NETLINK,
     CLONE_CHILD_CLEARTID,
     CLONE_NEWCGROUP,
     CLONE_NEWIPC,
@@ -121,6 +137,7 @@ use libc::{
     ENOENT,
     ENOSYS,
     MADV_DONTNEED,
+    MADV_FREE,
     MADV_REMOVE,
     MAP_SYNC,
     MREMAP_FIXED,
@@ -566,6 +583,17 @@ pub fn rep_prepare_run_to_syscall(t: &mut ReplayTask, step: &mut ReplayTraceStep
         .syscall_name();
     log!(LogDebug, "processing {} (entry)", sys_name);
 
+    if is_x32_syscall(sys_num as isize, sys_arch) {
+        clean_fatal!(
+            "Task {} made x32 ABI syscall `{}` (number {:#x}) at event {}. \
+             rd does not support replaying x32 programs.",
+            t.rec_tid,
+            sys_name,
+            sys_num,
+            t.current_trace_frame().time()
+        );
+    }
+
     if is_restart_syscall_syscall(sys_num, sys_arch) {
         ed_assert!(t, t.tick_count() == t.current_trace_frame().ticks());
         let regs = t.current_trace_frame().regs_ref().clone();
@@ -670,6 +698,10 @@ fn rep_process_syscall_arch<Arch: Architecture>(
         }
     }
 
+    if let Some(handler) = syscall_handler(Arch::arch(), nsys) {
+        return handler.replay(t, step);
+    }
+
     // Manual implementations of irregular syscalls that need to do more during
     // replay than just modify register and memory state.
     // Don't let a negative incoming syscall number be treated as a real
@@ -680,6 +712,25 @@ fn rep_process_syscall_arch<Arch: Architecture>(
         return process_execve(t, step);
     }
 
+    if let Some(kind) = event_fd_created_by::<Arch>(nsys, trace_regs) {
+        // The fd only exists in our fd table. Its reads are emulated.
+        let fd = trace_regs.syscall_result_signed() as i32;
+        t.fd_table_shr_ptr()
+            .borrow_mut()
+            .add_monitor(t, fd, Box::new(EventFdMonitor::new(kind)));
+        return;
+    }
+
+    if nsys == Arch::SOCKET && trace_regs.arg1_signed() as i32 == AF_NETLINK {
+        // Likewise, everything received from the socket is emulated.
+        let fd = trace_regs.syscall_result_signed() as i32;
+        let protocol = trace_regs.arg3_signed() as i32;
+        t.fd_table_shr_ptr()
+            .borrow_mut()
+            .add_monitor(t, fd, Box::new(NetlinkSocketMonitor::new(protocol)));
+        return;
+    }
+
     if nsys == Arch::BRK {
         return process_brk(t);
     }
@@ -725,9 +776,20 @@ fn rep_process_syscall_arch<Arch: Architecture>(
         return process_mremap(t, trace_regs, step);
     }
 
+    if nsys == Arch::FTRUNCATE {
+        return process_ftruncate(t, trace_regs.arg1_signed() as i32, trace_regs.arg2() as u64);
+    }
+
+    if nsys == Arch::FTRUNCATE64 {
+        // Only exists on x86, where the 64-bit length is split across two
+        // registers.
+        let length = trace_regs.arg2() as u32 as u64 | ((trace_regs.arg3() as u32 as u64) << 32);
+        return process_ftruncate(t, trace_regs.arg1_signed() as i32, length);
+    }
+
     if nsys == Arch::MADVISE {
         match t.regs_ref().arg3() as i32 {
-            MADV_DONTNEED | MADV_REMOVE => (),
+            MADV_DONTNEED | MADV_FREE | MADV_REMOVE => (),
             _ => return,
         }
     }
@@ -741,17 +803,30 @@ fn rep_process_syscall_arch<Arch: Architecture>(
         }
     }
 
+    if nsys == Arch::PKEY_ALLOC {
+        check_pkeys_supported(t);
+    }
+
     if nsys == Arch::MADVISE
         || nsys == Arch::ARCH_PRCTL
         || nsys == Arch::MUNMAP
         || nsys == Arch::MPROTECT
         || nsys == Arch::MODIFY_LDT
         || nsys == Arch::SET_THREAD_AREA
+        || nsys == Arch::PKEY_ALLOC
+        || nsys == Arch::PKEY_FREE
+        || nsys == Arch::PKEY_MPROTECT
     {
         // Using AutoRemoteSyscalls here fails for arch_prctl, not sure why.
         let mut r: Registers = t.regs_ref().clone();
         r.set_syscallno(t.regs_ref().original_syscallno());
         r.set_ip(r.ip().decrement_by_syscall_insn_length(r.arch()));
+        if nsys == Arch::MADVISE && r.arg3() as i32 == MADV_FREE {
+            // The kernel frees MADV_FREE pages lazily, when memory gets tight,
+            // so what the tracee reads back from them wouldn't replay. Free them
+            // right away instead.
+            r.set_arg3(MADV_DONTNEED as usize);
+        }
         t.set_regs(&r);
         if nsys == Arch::MPROTECT {
             t.vm_shr_ptr().fixup_mprotect_growsdown_parameters(t);
@@ -782,6 +857,10 @@ fn rep_process_syscall_arch<Arch: Architecture>(
             r2.set_arg2(r.arg2());
             r2.set_arg3(r.arg3());
             t.set_regs(&r2);
+        } else if nsys == Arch::MADVISE {
+            let mut r2: Registers = t.regs_ref().clone();
+            r2.set_arg3(trace_regs.arg3());
+            t.set_regs(&r2);
         }
         // The syscall modified registers. Re-emulate the syscall entry.
         t.canonicalize_regs(step.syscall().arch);
@@ -899,6 +978,24 @@ fn rep_process_syscall_arch<Arch: Architecture>(
     }
 }
 
+/// Files like memfds and /dev/shm segments that are mapped MAP_SHARED are
+/// backed by EmuFs files during replay. Keep their size in sync with the
+/// recorded file so that accesses beyond the end behave as during recording.
+fn process_ftruncate(t: &mut ReplayTask, fd: i32, length: u64) {
+    let maybe_mon = t.fd_table().get_monitor(fd);
+    if let Some(file_mon_shr_ptr) = maybe_mon {
+        if let Some(mon) = file_mon_shr_ptr.borrow().as_mmapped_file_monitor() {
+            log!(
+                LogDebug,
+                "  ftruncate of mmapped fd {} to {} bytes",
+                fd,
+                length
+            );
+            mon.did_ftruncate(t, length);
+        }
+    }
+}
+
 fn process_brk(t: &mut ReplayTask) {
     let mut data = MappedData::default();
     let km: KernelMapping = t
@@ -933,6 +1030,7 @@ fn process_brk(t: &mut ReplayTask) {
             None,
             None,
         );
+        assert_mapped_as_recorded(remote.task(), &km, Some(km.prot()));
     } else if km.size() > 0 {
         let arch = t.arch();
         let mut remote = AutoRemoteSyscalls::new(t);
@@ -946,9 +1044,35 @@ fn process_brk(t: &mut ReplayTask) {
             .task()
             .vm_shr_ptr()
             .unmap(remote.task(), km.start(), km.size());
+        ed_assert!(
+            remote.task(),
+            remote.task().vm().mapping_of(km.start()).is_none(),
+            "brk unmapped {} during recording but it is still mapped",
+            km
+        );
     }
 }
 
+/// Check the address space model against `range`, which a brk or mremap
+/// mapped during recording (with `prot`, if known), so that a bookkeeping bug
+/// is caught at the syscall that caused it rather than when the tracee next
+/// touches that memory.
+fn assert_mapped_as_recorded(t: &dyn Task, range: &MemoryRange, prot: Option<ProtFlags>) {
+    let maybe_map = t.vm().mapping_of(range.start()).map(|m| m.map.clone());
+    let matches = match &maybe_map {
+        Some(map) => map.contains(range) && prot.map_or(true, |prot| map.prot() == prot),
+        None => false,
+    };
+    ed_assert!(
+        t,
+        matches,
+        "Recorded mapping {} ({:?}) doesn't match the address space model's {:?}",
+        range,
+        prot,
+        maybe_map.map(|map| map.to_string())
+    );
+}
+
 fn non_negative_syscall(sys: i32) -> i32 {
     if sys < 0 {
         i32::MAX
@@ -998,9 +1122,10 @@ fn rep_after_enter_syscall_arch<Arch: Architecture>(t: &mut ReplayTask) {
                 | PTRACE_CONT
                 | PTRACE_DETACH => {
                     let command = t.regs_ref().arg1() as u32;
-                    target
-                        .borrow()
-                        .set_syscallbuf_locked(command != PTRACE_CONT && command != PTRACE_DETACH);
+                    set_syscallbuf_locked(
+                        target.borrow_mut().as_mut(),
+                        command != PTRACE_CONT && command != PTRACE_DETACH,
+                    );
                 }
                 PTRACE_SET_THREAD_AREA => {
                     let mut ok = true;
@@ -1464,7 +1589,7 @@ fn handle_opened_files(t: &mut ReplayTask, flags_raw: i32) {
 
     let opened = t.current_trace_frame().event().syscall().opened.clone();
     for o in &opened {
-        // This must be kept in sync with record_syscall's handle_opened_file.
+        // This must be kept in sync with the recorder's handling of opened files.
         let maybe_emu_file: Option<EmuFileSharedPtr> = t
             .session()
             .as_replay()
@@ -1507,7 +1632,9 @@ fn process_mmap(
     step: &mut ReplayTraceStep,
 ) {
     let prot = ProtFlags::from_bits(prot_raw).unwrap();
-    let flags = MapFlags::from_bits(flags_raw).unwrap();
+    // Drops the MAP_HUGE_* page size bits nix doesn't know. See
+    // `finish_anonymous_mmap()`.
+    let flags = MapFlags::from_bits_truncate(flags_raw);
 
     step.action = ReplayTraceStepType::TstepRetire;
 
@@ -1615,6 +1742,18 @@ fn process_mmap(
                         &km,
                         &data,
                     );
+                } else if data.source == MappedDataSource::SourceTrace
+                    && km.inode() != KernelMapping::NO_INODE
+                    && remote
+                        .task()
+                        .session()
+                        .as_replay()
+                        .unwrap()
+                        .flags()
+                        .share_private_mappings
+                {
+                    ed_assert!(remote.task(), extra_fds.is_empty());
+                    finish_shared_private_mmap(&mut remote, addr, length, prot, flags, km);
                 } else {
                     ed_assert!(remote.task(), extra_fds.is_empty());
                     finish_private_mmap(
@@ -1631,25 +1770,6 @@ fn process_mmap(
             }
         }
 
-        // This code is used to test the sharing functionality. It is in
-        // general a bad idea to indiscriminately share mappings between the
-        // tracer and the tracee. Instead, only mappings that have
-        // sufficiently many memory access from the tracer to require
-        // acceleration should be shared.
-        if !flags.contains(MapFlags::MAP_SHARED)
-            && remote
-                .task()
-                .session()
-                .as_replay()
-                .unwrap()
-                .flags()
-                .share_private_mappings
-        {
-            let vm_shr_ptr = remote.task().vm_shr_ptr();
-            let mapping = vm_shr_ptr.mapping_of(addr).unwrap().clone();
-            remote.make_private_shared(mapping);
-        }
-
         // Finally, we finish by emulating the return value.
         let syscall_result = remote
             .task()
@@ -1685,6 +1805,11 @@ fn finish_shared_mmap<'a>(
         .unwrap()
         .emufs_mut()
         .get_or_create(&km);
+    // Files like memfds are often sized with ftruncate() before they are
+    // mapped. Make sure the virtual file is as large as the recorded file.
+    emufile
+        .borrow_mut()
+        .ensure_size(data.file_size_bytes as u64);
     // Re-use the direct_map() machinery to map the virtual file.
     //
     // NB: the tracee will map the procfs link to our fd; there's
@@ -1836,6 +1961,71 @@ fn finish_private_mmap(
     );
 }
 
+/// Like `finish_private_mmap()`, but maps the recorded contents privately
+/// from a file shared with other mappings of the same contents, see
+/// `ReplaySession::private_mapping_file()`. Pages are only copied if they are
+/// written to.
+fn finish_shared_private_mmap(
+    remote: &mut AutoRemoteSyscalls,
+    rec_addr: RemotePtr<Void>,
+    length: usize,
+    prot: ProtFlags,
+    flags: MapFlags,
+    km: KernelMapping,
+) {
+    log!(
+        LogDebug,
+        "  finishing private mmap of {:?} from a shared copy",
+        km.fsname()
+    );
+
+    let buf: RawData = remote
+        .task_mut()
+        .as_replay_task_mut()
+        .unwrap()
+        .trace_reader_mut()
+        .read_raw_data();
+    ed_assert!(
+        remote.task(),
+        buf.addr == rec_addr && buf.data.len() <= length,
+        "Expected the contents of the mapping at {} in the trace",
+        rec_addr
+    );
+    let backing_file = remote
+        .task()
+        .session()
+        .as_replay()
+        .unwrap()
+        .private_mapping_file(&km, &buf.data, length);
+
+    let (real_file, real_file_name) = finish_direct_mmap(
+        remote,
+        rec_addr,
+        length,
+        prot,
+        flags,
+        &backing_file,
+        OFlag::O_RDONLY,
+        0,
+    );
+    remote.task().vm_shr_ptr().map(
+        remote.task(),
+        rec_addr,
+        length,
+        prot,
+        flags,
+        0,
+        real_file_name.as_os_str(),
+        real_file.st_dev,
+        real_file.st_ino,
+        None,
+        Some(&km),
+        None,
+        None,
+        None,
+    );
+}
+
 fn write_mapped_data(
     t: &mut ReplayTask,
     mut rec_addr: RemotePtr<Void>,
@@ -1907,6 +2097,16 @@ fn finish_anonymous_mmap(
         .trace_reader_mut()
         .read_mapped_region(Some(&mut data), None, None, None, None)
         .unwrap();
+    // Recording only lets through private MAP_HUGETLB mappings. Map them with
+    // huge pages too, or the kernel would merge them with their neighbors
+    // differently, but of this machine's default size. What counts is the
+    // range they cover.
+    let length = if flags.contains(MapFlags::MAP_HUGETLB) {
+        check_huge_pages_available(recorded_km.size());
+        recorded_km.size()
+    } else {
+        length
+    };
     let mut maybe_emu_file = None;
     if !flags.contains(MapFlags::MAP_SHARED) {
         remote.infallible_mmap_syscall(
@@ -1964,6 +2164,43 @@ fn finish_anonymous_mmap(
     );
 }
 
+/// Protection keys are allocated and applied to pages for real during replay,
+/// so that accesses the tracee's PKRU forbids fault like they did during
+/// recording. WRPKRU and RDPKRU just run. The kernel hands out the lowest
+/// free key, so replay gets the recorded keys.
+fn check_pkeys_supported(t: &ReplayTask) {
+    if cpuid(CPUID_GETEXTENDEDFEATURES, 0).ecx & OSPKE_FEATURE_FLAG == 0 {
+        clean_fatal!(
+            "Task {} allocated a memory protection key during recording, but this machine's \
+             CPU or kernel doesn't support them, so the recording can't be replayed here.",
+            t.rec_tid
+        );
+    }
+}
+
+/// Fail with a clear message instead of at the MAP_FIXED mmap() if this
+/// machine can't map `size` bytes of huge pages.
+fn check_huge_pages_available(size: usize) {
+    let huge_page_size = huge_page_size(0);
+    if size % huge_page_size != 0 {
+        clean_fatal!(
+            "The recording mapped {} bytes of huge pages, which isn't a multiple of this \
+             machine's huge page size of {} bytes, so it can't be replayed here.",
+            size,
+            huge_page_size
+        );
+    }
+    let free_pages = read_meminfo_field("HugePages_Free").unwrap_or(0);
+    if free_pages * huge_page_size < size {
+        clean_fatal!(
+            "Replaying this recording needs {} free huge pages but there are {}. Reserve \
+             more by raising /proc/sys/vm/nr_hugepages.",
+            size / huge_page_size,
+            free_pages
+        );
+    }
+}
+
 /// DIFF NOTE: Take trace_regs as param. rr takes trace_frame instead.
 fn process_mremap(t: &mut ReplayTask, trace_regs: &Registers, step: &mut ReplayTraceStep) {
     step.action = ReplayTraceStepType::TstepRetire;
@@ -2115,6 +2352,7 @@ fn process_mremap(t: &mut ReplayTask, trace_regs: &Registers, step: &mut ReplayT
         }
         None => (),
     }
+    assert_mapped_as_recorded(t, &MemoryRange::new_range(new_addr, new_size), None);
 
     t.validate_regs(ReplayTaskIgnore::default());
 }
@@ -0,0 +1,147 @@
+//! rd needs some files at runtime that are not part of the rd binary: the
+//! preload library, the exec stubs and the rd page files. They all live under
+//! a single "resource path", in `bin/`, `lib/rr/` and `share/rr/`. We look for
+//! it, in order, in
+//! - the directory given with `--resource-path`
+//! - the directory in `$RD_RESOURCE_PATH`
+//! - the parent of the directory the rd binary is in, i.e. a build or install
+//!   tree that rd is being run from
+//! - the standard install prefixes `/usr/local` and `/usr`
+//!
+//! `rd check-install` reports what was found where.
+
+use crate::{
+    flags::Flags,
+    kernel_abi::common::preload_interface::{SYSCALLBUF_LIB_FILENAME, SYSCALLBUF_LIB_FILENAME_32},
+    util::read_exe_dir,
+};
+use std::{
+    env::var_os,
+    ffi::{OsStr, OsString},
+    fmt,
+    fmt::Display,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+/// Set this env var to override where rd looks for its resources. Overridden
+/// in turn by `--resource-path`.
+pub const RD_RESOURCE_PATH_ENV_VAR: &str = "RD_RESOURCE_PATH";
+
+const INSTALL_PREFIXES: [&str; 2] = ["/usr/local/", "/usr/"];
+
+lazy_static! {
+    static ref RESOURCE_PATH: OsString = find_resource_path();
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ResourceSource {
+    CommandLine,
+    Environment,
+    RdBinary,
+    InstallPrefix,
+}
+
+impl Display for ResourceSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceSource::CommandLine => write!(f, "--resource-path"),
+            ResourceSource::Environment => write!(f, "${}", RD_RESOURCE_PATH_ENV_VAR),
+            ResourceSource::RdBinary => write!(f, "relative to the rd binary"),
+            ResourceSource::InstallPrefix => write!(f, "install prefix"),
+        }
+    }
+}
+
+/// A file rd needs at runtime, relative to the resource path.
+pub struct Resource {
+    pub path: String,
+    /// Only needed to record or replay 32-bit programs on a 64-bit rd.
+    pub only_for_32_bit: bool,
+}
+
+impl Resource {
+    fn new(path: String, only_for_32_bit: bool) -> Resource {
+        Resource {
+            path,
+            only_for_32_bit,
+        }
+    }
+}
+
+pub fn resources() -> Vec<Resource> {
+    vec![
+        Resource::new(format!("lib/rr/{}", SYSCALLBUF_LIB_FILENAME), false),
+        Resource::new(format!("lib/rr/{}", SYSCALLBUF_LIB_FILENAME_32), true),
+        Resource::new("bin/rr_exec_stub".into(), false),
+        Resource::new("bin/rr_exec_stub_32".into(), true),
+        Resource::new("share/rr/rr_page_64".into(), false),
+        Resource::new("share/rr/rr_page_64_replay".into(), false),
+        Resource::new("share/rr/rr_page_32".into(), true),
+        Resource::new("share/rr/rr_page_32_replay".into(), true),
+    ]
+}
+
+/// Every place we would look for resources, in order of preference. Each
+/// path ends in a `/`.
+pub fn resource_path_candidates() -> Vec<(OsString, ResourceSource)> {
+    let mut result = Vec::new();
+    if let Some(path) = Flags::get().resource_path.as_ref() {
+        // Already canonicalized and `/`-terminated by the option parser.
+        result.push((path.as_os_str().to_owned(), ResourceSource::CommandLine));
+    }
+    match var_os(RD_RESOURCE_PATH_ENV_VAR) {
+        Some(path) if !path.is_empty() => {
+            result.push((with_trailing_slash(&path), ResourceSource::Environment))
+        }
+        _ => (),
+    }
+    let mut rd_relative = read_exe_dir();
+    rd_relative.push("../");
+    result.push((rd_relative, ResourceSource::RdBinary));
+    for prefix in &INSTALL_PREFIXES {
+        result.push((OsString::from(prefix), ResourceSource::InstallPrefix));
+    }
+    result
+}
+
+/// Return true if all the resources needed for 64-bit tracees are in `path`.
+pub fn has_required_resources(path: &OsStr) -> bool {
+    resources()
+        .iter()
+        .filter(|r| !r.only_for_32_bit)
+        .all(|r| Path::new(path).join(&r.path).is_file())
+}
+
+/// The resource path rd uses, ending in a `/`.
+pub fn resource_path() -> &'static OsStr {
+    RESOURCE_PATH.as_os_str()
+}
+
+/// An explicitly given resource path always wins, so that a broken one is
+/// reported rather than silently replaced. Otherwise use the first candidate
+/// that has everything we need, falling back to the rd binary's tree.
+fn find_resource_path() -> OsString {
+    let candidates = resource_path_candidates();
+    for (path, source) in &candidates {
+        match source {
+            ResourceSource::CommandLine | ResourceSource::Environment => return path.clone(),
+            _ if has_required_resources(path) => return path.clone(),
+            _ => (),
+        }
+    }
+
+    candidates
+        .into_iter()
+        .find(|(_, source)| *source == ResourceSource::RdBinary)
+        .map(|(path, _)| path)
+        .unwrap()
+}
+
+fn with_trailing_slash(path: &OsStr) -> OsString {
+    let mut result = path.to_owned();
+    if !path.as_bytes().ends_with(b"/") {
+        result.push("/");
+    }
+    result
+}
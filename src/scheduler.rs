@@ -35,7 +35,11 @@
 //! The main parameter to the scheduler is `max_ticks`, which controls the
 //! length of each timeslice.
 
-use crate::{session::task::record_task::record_task::RecordTask, ticks::Ticks};
+use crate::{
+    session::task::record_task::record_task::RecordTask,
+    taskish_uid::TaskUid,
+    ticks::Ticks,
+};
 use libc::cpu_set_t;
 use std::{
     cell::RefCell,
@@ -115,4 +119,16 @@ impl Scheduler {
     pub fn expire_timeslice(&mut self) {
         self.current_timeslice_end_ = 0;
     }
+
+    /// The task `tuid` is being destroyed. The task queues only hold weak
+    /// pointers, so this just makes sure it isn't the current task anymore.
+    pub fn on_destroy(&mut self, tuid: TaskUid) {
+        let is_current = match &self.current_ {
+            Some(t) => t.try_borrow().map_or(false, |t| t.tuid() == tuid),
+            None => false,
+        };
+        if is_current {
+            self.current_ = None;
+        }
+    }
 }
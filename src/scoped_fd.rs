@@ -1,10 +1,17 @@
+use libc::pid_t;
 use nix::{
+    errno::Errno,
     fcntl::{open, OFlag},
     sys::stat::Mode,
-    unistd::close,
+    unistd::{close, dup2},
+    Error,
     NixPath,
 };
-use std::{cell::RefCell, os::unix::io::RawFd, rc::Rc};
+use std::{
+    cell::RefCell,
+    os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
+    rc::Rc,
+};
 
 pub type ScopedFdSharedPtr = Rc<RefCell<ScopedFd>>;
 
@@ -28,16 +35,33 @@ impl ScopedFd {
         ScopedFd { fd }
     }
 
+    /// The fd is always O_CLOEXEC: rd's own fds must not leak into tracees.
+    /// Use `dup_over()` to hand one to a child on purpose.
     pub fn open_path<P: ?Sized + NixPath>(path: &P, oflag: OFlag) -> Self {
-        let rawfd = open(path, oflag, Mode::empty()).unwrap_or(-1);
-        ScopedFd { fd: rawfd }
+        Self::open_path_with_mode(path, oflag, Mode::empty())
     }
 
+    /// See `open_path()`.
     pub fn open_path_with_mode<P: ?Sized + NixPath>(path: &P, oflag: OFlag, mode: Mode) -> Self {
-        let rawfd = open(path, oflag, mode).unwrap_or(-1);
+        let rawfd = open(path, oflag | OFlag::O_CLOEXEC, mode).unwrap_or(-1);
         ScopedFd { fd: rawfd }
     }
 
+    /// Open `/proc/<tid>/<name>`, e.g. `mem` or `fd/3`.
+    pub fn open_proc(tid: pid_t, name: &str, oflag: OFlag) -> Self {
+        Self::open_path(format!("/proc/{}/{}", tid, name).as_str(), oflag)
+    }
+
+    /// Make `target` a copy of this fd, closing whatever `target` was. The
+    /// copy doesn't have O_CLOEXEC, so it is inherited across exec; this is
+    /// how fds are set up at fixed numbers in a child before it execs.
+    pub fn dup_over(&self, target: RawFd) -> nix::Result<()> {
+        match dup2(self.fd, target)? {
+            fd if fd == target => Ok(()),
+            _ => Err(Error::Sys(Errno::EBADF)),
+        }
+    }
+
     pub fn close(&mut self) {
         if self.fd >= 0 {
             // We swallow any error on close
@@ -70,6 +94,25 @@ impl ScopedFd {
     }
 }
 
+impl AsRawFd for ScopedFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl IntoRawFd for ScopedFd {
+    /// Like `extract()`, but consumes `self`.
+    fn into_raw_fd(mut self) -> RawFd {
+        self.extract()
+    }
+}
+
+impl FromRawFd for ScopedFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        ScopedFd { fd }
+    }
+}
+
 impl Drop for ScopedFd {
     fn drop(&mut self) {
         self.close()
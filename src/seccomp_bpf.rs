@@ -1,10 +1,34 @@
 use crate::{
-    bindings::kernel::{sock_filter, BPF_ABS, BPF_JEQ, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W},
+    bindings::kernel::{
+        sock_filter,
+        BPF_ABS,
+        BPF_JEQ,
+        BPF_JMP,
+        BPF_JSET,
+        BPF_K,
+        BPF_LD,
+        BPF_RET,
+        BPF_W,
+    },
+    kernel_abi::{SupportedArch, X32_SYSCALL_BIT},
     kernel_supplement::{seccomp_data, SECCOMP_RET_ALLOW, SECCOMP_RET_DATA, SECCOMP_RET_TRACE},
     remote_code_ptr::RemoteCodePtr,
 };
 use std::convert::TryInto;
 
+/// `AUDIT_ARCH_X86_64` and `AUDIT_ARCH_I386` from <linux/audit.h>: the
+/// `seccomp_data::arch` of a syscall made with the 64-bit and 32-bit syscall
+/// conventions respectively. A 64-bit tracee can make either kind.
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+const AUDIT_ARCH_I386: u32 = 0x4000_0003;
+
+fn audit_arch(arch: SupportedArch) -> u32 {
+    match arch {
+        SupportedArch::X64 => AUDIT_ARCH_X86_64,
+        SupportedArch::X86 => AUDIT_ARCH_I386,
+    }
+}
+
 // Copyright notice as in rr's `src/seccomp-bpf.h` (see https://github.com/mozilla/rr)
 /*
  * seccomp example for x86 (32-bit and 64-bit) with BPF macros
@@ -70,4 +94,64 @@ impl SeccompFilter {
             .push(bpf_jump((BPF_JMP + BPF_JEQ + BPF_K) as u16, v, 0, 1));
         self.allow()
     }
+
+    /// The filter rd installs in recorded tracees: syscalls made from
+    /// `callsites` (the untraced syscall instructions in the rd page) are
+    /// allowed and everything else traps to rd. There is a block for each of
+    /// `arches`, since the callsite checks only hold for syscalls made with
+    /// the syscall conventions rd knows; anything else (an unknown arch, or
+    /// an x32 syscall) is traced. The upper half of the instruction pointer
+    /// is checked too, so that a 64-bit callsite can't be spoofed from an
+    /// address that only matches in its lower half.
+    pub fn for_recording(arches: &[SupportedArch], callsites: &[RemoteCodePtr]) -> SeccompFilter {
+        let mut blocks: Vec<SeccompFilter> = Vec::new();
+        for &arch in arches {
+            let mut block = SeccompFilter::new();
+            if arch == SupportedArch::X64 {
+                // No callsite is an x32 callsite; skip to the final `trace()`.
+                block.load(offset_of!(seccomp_data, nr) as u32);
+                let to_trace = 3 + 2 * callsites.len();
+                block.jump(BPF_JSET, X32_SYSCALL_BIT as u32, to_trace, 0);
+            }
+            block.load(offset_of!(seccomp_data, instruction_pointer) as u32 + 4);
+            block.jump(BPF_JEQ, 0, 0, 1 + 2 * callsites.len());
+            block.load(offset_of!(seccomp_data, instruction_pointer) as u32);
+            for &ip in callsites {
+                let v: u32 = ip.register_value().try_into().unwrap();
+                block.jump(BPF_JEQ, v, 0, 1);
+                block.allow();
+            }
+            block.trace();
+            blocks.push(block);
+        }
+
+        let mut f = SeccompFilter::new();
+        f.load(offset_of!(seccomp_data, arch) as u32);
+        // Each arch check jumps over the remaining checks, the `trace()` for
+        // unknown arches and the blocks before its own.
+        let mut to_block = arches.len();
+        for (i, &arch) in arches.iter().enumerate() {
+            f.jump(BPF_JEQ, audit_arch(arch), to_block - i, 0);
+            to_block += blocks[i].filters.len();
+        }
+        f.trace();
+        for block in blocks {
+            f.filters.extend(block.filters);
+        }
+        f
+    }
+
+    fn load(&mut self, offset: u32) {
+        self.filters
+            .push(bpf_stmt((BPF_LD + BPF_W + BPF_ABS) as u16, offset));
+    }
+
+    fn jump(&mut self, op: u32, k: u32, jt: usize, jf: usize) {
+        self.filters.push(bpf_jump(
+            (BPF_JMP + op + BPF_K) as u16,
+            k,
+            jt.try_into().unwrap(),
+            jf.try_into().unwrap(),
+        ));
+    }
 }
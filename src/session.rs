@@ -1,28 +1,53 @@
 use crate::{
-    auto_remote_syscalls::AutoRemoteSyscalls,
+    auto_remote_syscalls::{AutoRemoteSyscalls, AutoRestoreMem},
     emu_fs::EmuFs,
-    kernel_abi::SupportedArch,
+    fd_table::FdTableSharedPtr,
+    kernel_abi::{
+        common::preload_interface::syscallbuf_hdr,
+        syscall_number_for_close,
+        syscall_number_for_munmap,
+        syscall_number_for_open,
+        SupportedArch,
+    },
+    log::LogLevel::LogDebug,
     remote_ptr::{RemotePtr, Void},
     session::{
-        address_space::{address_space::AddressSpaceSharedPtr, MappingFlags},
+        address_space::{
+            address_space::{AddressSpace, AddressSpaceSharedPtr, Mapping},
+            kernel_mapping::KernelMapping,
+            MappingFlags,
+        },
         diversion_session::DiversionSession,
         record_session::RecordSession,
         replay_session::ReplaySession,
-        session_inner::session_inner::{AddressSpaceMap, SessionInner, TaskMap, ThreadGroupMap},
+        runtime_dir::SessionRuntimeDir,
+        session_inner::session_inner::{
+            AddressSpaceClone,
+            AddressSpaceMap,
+            CloneCompletion,
+            SessionInner,
+            TaskMap,
+            ThreadGroupMap,
+        },
         task::{
             task_common,
+            task_common::{read_mem, read_val_mem},
             task_inner::{task_inner::WriteFlags, CloneFlags},
             Task,
             TaskSharedPtr,
         },
     },
-    taskish_uid::{AddressSpaceUid, TaskUid, ThreadGroupUid},
+    taskish_uid::{AddressSpaceUid, FdTableUid, TaskUid, ThreadGroupUid},
     thread_group::{ThreadGroup, ThreadGroupSharedPtr},
     trace::trace_stream::TraceStream,
+    util::page_size,
 };
 use libc::pid_t;
+use nix::{fcntl::OFlag, sys::mman::MapFlags};
 use std::{
     cell::{Ref, RefMut},
+    collections::HashMap,
+    mem::size_of,
     ops::DerefMut,
     rc::{Rc, Weak},
 };
@@ -32,9 +57,12 @@ use task_common::clone_task_common;
 pub mod address_space;
 pub mod diversion_session;
 pub mod record_session;
+pub mod replay_observer;
 pub mod replay_session;
+pub mod runtime_dir;
 pub mod session_common;
 pub mod session_inner;
+pub mod spawn_error;
 pub mod task;
 
 /// Note that this is NOT Rc<RefCell<Box<dyn Session>>>
@@ -112,13 +140,117 @@ pub trait Session: DerefMut<Target = SessionInner> {
     }
 
     fn on_create(&self, t: TaskSharedPtr) {
-        let rec_tid = t.borrow().rec_tid;
+        let (tid, rec_tid) = {
+            let t_ref = t.borrow();
+            (t_ref.tid, t_ref.rec_tid)
+        };
+        SessionRuntimeDir::note_tracee(self.as_session_inner(), tid);
         self.task_map.borrow_mut().insert(rec_tid, t);
     }
 
+    /// Fork a copy of every address space of this session into `dest`. The
+    /// forks share their memory with the original copy-on-write, so this is
+    /// cheap however much memory the tracees use. Only the leaders are
+    /// created here; the other threads are recreated by
+    /// `finish_initializing()` when `dest` is first used.
+    ///
+    /// Shared mappings of files in `emu_fs` are remapped to copies of those
+    /// files in `dest_emu_fs`, so that writes to the files by either session
+    /// aren't seen by the other.
+    ///
     /// NOTE: called Session::copy_state_to() in rr.
-    fn copy_state_to_session(&self, _dest: &SessionInner, _emu_fs: &EmuFs, _dest_emu_fs: EmuFs) {
-        unimplemented!()
+    fn copy_state_to_session(
+        &self,
+        dest: SessionSharedPtr,
+        emu_fs: &EmuFs,
+        dest_emu_fs: &mut EmuFs,
+    ) {
+        self.assert_fully_initialized();
+        debug_assert!(dest.clone_completion.borrow().is_none());
+
+        let mut completion = CloneCompletion {
+            address_spaces: Vec::new(),
+        };
+        for vm in self.vms() {
+            // Pick an arbitrary task to be group leader. The actual group leader
+            // might have died already.
+            let group_leader = vm.task_set().iter().next().unwrap();
+            log!(
+                LogDebug,
+                "  forking tg {} (real: {})",
+                group_leader.borrow().tgid(),
+                group_leader.borrow().real_tgid()
+            );
+
+            let clone_leader =
+                task_common::os_fork_into(group_leader.borrow_mut().as_mut(), dest.clone());
+            dest.on_create(clone_leader.clone());
+            log!(
+                LogDebug,
+                "  forked new group leader {}",
+                clone_leader.borrow().tid
+            );
+
+            let mut captured_memory = Vec::new();
+            let mut member_states = Vec::new();
+            let mut fd_tables = HashMap::new();
+            {
+                let mut clone_leader_ref = clone_leader.borrow_mut();
+                let mut remote = AutoRemoteSyscalls::new(clone_leader_ref.as_mut());
+                let mut shared_maps_to_clone = Vec::new();
+                for (_, m) in &remote.vm().maps() {
+                    // Special case the syscallbuf as a performance optimization. The amount
+                    // of data we need to capture is usually significantly smaller than the
+                    // size of the mapping, so allocating the whole mapping here would be
+                    // wasteful.
+                    if m.flags.contains(MappingFlags::IS_SYSCALLBUF) {
+                        captured_memory.push((
+                            m.map.start(),
+                            capture_syscallbuf(&m.map, group_leader.borrow_mut().as_mut()),
+                        ));
+                    } else if m.local_addr.is_some() {
+                        ed_assert!(
+                            remote.task(),
+                            m.map.start() == AddressSpace::preload_thread_locals_start()
+                        );
+                    } else if m.recorded_map.flags().contains(MapFlags::MAP_SHARED)
+                        && emu_fs.has_file_for(&m.recorded_map)
+                    {
+                        shared_maps_to_clone.push(m.clone());
+                    }
+                }
+                // Do this in a separate loop to avoid iteration invalidation issues
+                for m in shared_maps_to_clone {
+                    remap_shared_mmap(&mut remote, emu_fs, dest_emu_fs, m);
+                }
+
+                for t in group_leader
+                    .borrow()
+                    .thread_group()
+                    .task_set()
+                    .iter_except(Rc::downgrade(&group_leader))
+                {
+                    log!(LogDebug, "    cloning {}", t.borrow().rec_tid);
+                    let state = task_common::capture_state(t.borrow_mut().as_mut());
+                    if state.fd_table_uid != group_leader.borrow().fd_table().uid() {
+                        fd_tables.insert(state.fd_table_uid, t.borrow().fd_table_shr_ptr());
+                    }
+                    member_states.push(state);
+                }
+            }
+
+            let clone_leader_state = task_common::capture_state(group_leader.borrow_mut().as_mut());
+            completion.address_spaces.push(AddressSpaceClone {
+                clone_leader: Rc::downgrade(&clone_leader),
+                clone_leader_state,
+                member_states,
+                captured_memory,
+                fd_tables,
+            });
+        }
+        *dest.clone_completion.borrow_mut() = Some(Box::new(completion));
+
+        debug_assert!(dest.vms().len() > 0);
     }
 
     /// Call this before doing anything that requires access to the full set
@@ -131,6 +263,8 @@ pub trait Session: DerefMut<Target = SessionInner> {
         // DIFF NOTE: We're setting clone completion to None here instead of at the end of the
         // method.
         let cc = self.clone_completion.replace(None).unwrap();
+        // Source fd table uid -> the corresponding table in this session
+        let mut fd_tables: HashMap<FdTableUid, FdTableSharedPtr> = HashMap::new();
         for tgleader in &cc.address_spaces {
             let rc = tgleader.clone_leader.upgrade().unwrap();
             let mut leader = rc.borrow_mut();
@@ -161,19 +295,42 @@ pub trait Session: DerefMut<Target = SessionInner> {
             }
 
             {
+                let leader_fd_table_uid = tgleader.clone_leader_state.fd_table_uid;
+                fd_tables
+                    .entry(leader_fd_table_uid)
+                    .or_insert_with(|| leader.fd_table_shr_ptr());
                 let mut remote2 = AutoRemoteSyscalls::new(leader.as_mut());
                 for tgmember in &tgleader.member_states {
                     let t_clone = task_common::os_clone_into(tgmember, &mut remote2);
+                    if tgmember.fd_table_uid != leader_fd_table_uid {
+                        // os_clone_into() shares the leader's fd table, but this
+                        // member had its own (e.g. after unshare(CLONE_FILES)).
+                        let mut tc = t_clone.borrow_mut();
+                        let weak_tc = tc.weak_self_ptr();
+                        tc.fd_table_shr_ptr()
+                            .borrow_mut()
+                            .task_set_mut()
+                            .erase(weak_tc.clone());
+                        let fds = match fd_tables.get(&tgmember.fd_table_uid) {
+                            Some(fds) => {
+                                fds.borrow_mut().task_set_mut().insert(weak_tc);
+                                fds.clone()
+                            }
+                            None => {
+                                let fds = tgleader.fd_tables[&tgmember.fd_table_uid]
+                                    .borrow()
+                                    .clone_into_task(tc.as_mut());
+                                fd_tables.insert(tgmember.fd_table_uid, fds.clone());
+                                fds
+                            }
+                        };
+                        tc.fds = Some(fds);
+                    }
                     self.on_create(t_clone);
                 }
             }
 
-            tgleader
-                .clone_leader
-                .upgrade()
-                .unwrap()
-                .borrow_mut()
-                .copy_state(&tgleader.clone_leader_state);
+            task_common::copy_state(leader.as_mut(), &tgleader.clone_leader_state);
         }
         // Don't need to set clone completion to `None`. Its already been done!
     }
@@ -219,7 +376,9 @@ pub trait Session: DerefMut<Target = SessionInner> {
 
     /// NOTE: Method is simply called Session::find task() in rr
     fn find_task_from_task_uid(&self, tuid: TaskUid) -> Option<TaskSharedPtr> {
+        // The tid may have been recycled by a newer task
         self.find_task_from_rec_tid(tuid.tid())
+            .filter(|t| t.borrow().tuid() == tuid)
     }
 
     /// Return the thread group whose unique ID is `tguid`, or None if no such
@@ -261,7 +420,7 @@ pub trait Session: DerefMut<Target = SessionInner> {
         if self.weak_self.ptr_eq(tg.borrow().session_weak_ptr()) {
             ThreadGroup::new(
                 self.weak_self.clone(),
-                tg.borrow().parent_weak_ptr(),
+                Some(Rc::downgrade(&tg)),
                 t.rec_tid,
                 t.tid,
                 t.own_namespace_tid(),
@@ -339,3 +498,92 @@ pub trait Session: DerefMut<Target = SessionInner> {
         self.spawned_task_error_fd_.borrow_mut().close();
     }
 }
+
+/// The used part of the syscallbuf mapped at `km` in `clone_leader`.
+fn capture_syscallbuf(km: &KernelMapping, clone_leader: &mut dyn Task) -> Vec<u8> {
+    let start: RemotePtr<u8> = RemotePtr::cast(km.start());
+    let hdr: syscallbuf_hdr = read_val_mem(clone_leader, RemotePtr::cast(start), None);
+    let data_size = if hdr.locked != 0 {
+        // There may be an incomplete syscall record after num_rec_bytes that
+        // we need to capture here. We don't know how big that record is,
+        // so just record the entire buffer. This should not be common.
+        km.size()
+    } else {
+        hdr.num_rec_bytes as usize + size_of::<syscallbuf_hdr>()
+    };
+    read_mem(clone_leader, start, data_size, None)
+}
+
+/// Replace the shared mapping `m` of an emulated file in the task of `remote`
+/// with the same mapping of a copy of that file in `dest_emu_fs`.
+fn remap_shared_mmap(
+    remote: &mut AutoRemoteSyscalls,
+    emu_fs: &EmuFs,
+    dest_emu_fs: &mut EmuFs,
+    m: Mapping,
+) {
+    log!(
+        LogDebug,
+        "    remapping shared region at {}-{}",
+        m.map.start(),
+        m.map.end()
+    );
+    let arch = remote.arch();
+    rd_infallible_syscall!(
+        remote,
+        syscall_number_for_munmap(arch),
+        m.map.start().as_usize(),
+        m.map.size()
+    );
+
+    // Several mappings can share one file. Copy it the first time only.
+    let emu_file = match dest_emu_fs.at(&m.recorded_map) {
+        Some(emu_file) => emu_file,
+        None => dest_emu_fs.clone_file(emu_fs.at(&m.recorded_map).unwrap()),
+    };
+    // TODO: this duplicates some code in replay_syscall.rs, but
+    // it's somewhat nontrivial to factor that code out.
+    let remote_fd: i32;
+    {
+        let path = emu_file.borrow().proc_path();
+        let mut child_path = AutoRestoreMem::push_cstr(remote, path.as_str());
+        let child_addr = child_path.get().unwrap();
+        // Always open the emufs file O_RDWR, even if the current mapping prot
+        // is read-only. We might mprotect it to read-write later.
+        remote_fd = rd_infallible_syscall!(
+            child_path,
+            syscall_number_for_open(arch),
+            child_addr.as_usize(),
+            OFlag::O_RDWR.bits()
+        ) as i32;
+    }
+    remote.infallible_mmap_syscall(
+        Some(m.map.start()),
+        m.map.size(),
+        m.map.prot(),
+        (m.map.flags() & !MapFlags::MAP_ANONYMOUS) | MapFlags::MAP_FIXED,
+        remote_fd,
+        m.map.file_offset_bytes() / page_size() as u64,
+    );
+
+    let real_file = remote.task().stat_fd(remote_fd);
+    let real_file_name = remote.task().file_name_of_fd(remote_fd);
+    rd_infallible_syscall!(remote, syscall_number_for_close(arch), remote_fd);
+
+    remote.task().vm_shr_ptr().map(
+        remote.task(),
+        m.map.start(),
+        m.map.size(),
+        m.map.prot(),
+        m.map.flags(),
+        m.map.file_offset_bytes(),
+        &real_file_name,
+        real_file.st_dev,
+        real_file.st_ino,
+        None,
+        Some(&m.recorded_map),
+        Some(emu_file),
+        None,
+        None,
+    );
+}
@@ -16,6 +16,7 @@ use crate::{
     log::LogLevel::LogError,
     remote_code_ptr::RemoteCodePtr,
     remote_ptr::{RemotePtr, Void},
+    resource_path::resource_path,
     scoped_fd::ScopedFd,
     session::{
         address_space::{
@@ -26,7 +27,7 @@ use crate::{
         },
         task::Task,
     },
-    util::{find, resource_path},
+    util::find,
 };
 use libc::{dev_t, pid_t};
 use nix::{
@@ -191,6 +192,17 @@ impl WatchConfig {
     }
 }
 
+/// A watchpoint that fired. See `AddressSpace::consume_watchpoint_changes()`.
+#[derive(Clone, Debug)]
+pub struct WatchpointHit {
+    pub config: WatchConfig,
+    /// For watchpoints on a range that is watched for writes, the contents of
+    /// the range before and after it changed. Bytes that could not be read are
+    /// 0xFF. None for read and exec watchpoints.
+    pub old_value: Option<Vec<u8>>,
+    pub new_value: Option<Vec<u8>>,
+}
+
 pub mod address_space {
     use super::*;
     use crate::{
@@ -199,6 +211,7 @@ pub mod address_space {
         emu_fs::EmuFileSharedPtr,
         kernel_abi::{
             common::preload_interface::{
+                syscallbuf_layout_hash,
                 PRELOAD_THREAD_LOCALS_SIZE,
                 RD_PAGE_ADDR,
                 RD_PAGE_FF_BYTES,
@@ -226,6 +239,7 @@ pub mod address_space {
                 BreakpointType::BkptNone,
                 MappingFlags,
             },
+            replay_observer::MappingChange,
             task::{
                 record_task::record_task::RecordTask,
                 task_common::{read_mem, read_val_mem, write_val_mem, write_val_mem_with_flags},
@@ -269,9 +283,10 @@ pub mod address_space {
         },
         ffi::{OsStr, OsString},
         ops::{
-            Bound::{Included, Unbounded},
+            Bound::{Excluded, Included, Unbounded},
             Drop,
         },
+        mem::replace,
         ptr::NonNull,
         rc::{Rc, Weak},
         sync::atomic::{AtomicUsize, Ordering},
@@ -373,6 +388,29 @@ pub mod address_space {
             }
         }
 
+        /// All maps that intersect `range`.
+        pub fn intersecting(outer: &'a AddressSpace, range: MemoryRange) -> Maps<'a> {
+            Maps {
+                memory_map: outer.mem.borrow(),
+                lower_bound: Included(MemoryRangeKey(MemoryRange::new_range(range.start(), 1))),
+                // A 0 size range compares Equal to a map starting at range.end() and
+                // Greater than every map starting before it.
+                upper_bound: Excluded(MemoryRangeKey(MemoryRange::from_range(
+                    range.end(),
+                    range.end(),
+                ))),
+            }
+        }
+
+        /// Iterate over only those maps that have all of `flags` set.
+        pub fn with_flags<'b>(
+            &'b self,
+            flags: MappingFlags,
+        ) -> impl Iterator<Item = (&'b MemoryRangeKey, &'b Mapping)> + 'b {
+            self.into_iter()
+                .filter(move |(_, m)| m.flags.contains(flags))
+        }
+
         pub fn into_mem(self) -> Ref<'a, MemoryMap> {
             self.memory_map
         }
@@ -539,6 +577,8 @@ pub mod address_space {
         /// @TODO might we want to have some of these as Option types?
         pub debug_regs_for_exec_read: Vec<u8>,
         pub value_bytes: Vec<u8>,
+        /// `value_bytes` from before the watchpoint last became `changed`.
+        pub old_value_bytes: Vec<u8>,
         pub valid: bool,
         pub changed: bool,
     }
@@ -551,7 +591,8 @@ pub mod address_space {
                 write_count: 0,
                 // @TODO is this default what we really need?
                 debug_regs_for_exec_read: Vec::new(),
-                value_bytes: Vec::with_capacity(num_bytes),
+                value_bytes: vec![0; num_bytes],
+                old_value_bytes: vec![0; num_bytes],
                 valid: false,
                 changed: false,
             }
@@ -595,12 +636,6 @@ pub mod address_space {
         }
     }
 
-    #[derive(Copy, Clone, Eq, PartialEq)]
-    enum WatchPointFilter {
-        AllWatchpoints,
-        ChangedWatchpoints,
-    }
-
     #[derive(Copy, Clone, Eq, PartialEq)]
     enum WillSetTaskState {
         SettingTaskState,
@@ -973,6 +1008,7 @@ pub mod address_space {
                 monitored,
             );
 
+            self.notify_mapping_change(t, MappingChange::Mapped(m.clone()));
             m
         }
 
@@ -1009,6 +1045,26 @@ pub mod address_space {
             }
         }
 
+        /// Return the first mapping that intersects `range`, if any.
+        pub fn find_mapping(&self, range: MemoryRange) -> Option<Ref<Mapping>> {
+            let maps = Maps::intersecting(self, range);
+            match maps.into_iter().next() {
+                Some((&k, _)) => {
+                    let mem_ref = maps.into_mem();
+
+                    Some(Ref::map(mem_ref, |memory_map: &MemoryMap| {
+                        memory_map.get(&k).unwrap()
+                    }))
+                }
+                None => None,
+            }
+        }
+
+        /// Return true iff some mapping contains the byte at `addr`.
+        pub fn has_mapping(&self, addr: RemotePtr<Void>) -> bool {
+            self.mapping_of(addr).is_some()
+        }
+
         /// Detach local mapping and return it.
         pub fn detach_local_mapping(&self, addr: RemotePtr<Void>) -> Option<NonNull<c_void>> {
             match self.mapping_of_mut(addr) {
@@ -1027,6 +1083,13 @@ pub mod address_space {
             RefMut::map(self.mapping_of_mut(addr).unwrap(), |m| &mut m.flags)
         }
 
+        /// Return the flags of the mapping at this address.
+        ///
+        /// Assume a mapping exists at addr, otherwise panics.
+        pub fn mapping_flags_of(&self, addr: RemotePtr<Void>) -> MappingFlags {
+            self.mapping_of(addr).unwrap().flags
+        }
+
         /// If the given memory region is mapped into the local address space, obtain
         /// the local address from which the `size` bytes at `addr` can be accessed.
         pub fn local_mapping_mut(&self, addr: RemotePtr<Void>, size: usize) -> Option<&mut [u8]> {
@@ -1077,6 +1140,11 @@ pub mod address_space {
             Maps::containing_or_after(self, start)
         }
 
+        /// All maps that intersect `range`, in address order.
+        pub fn maps_in_range(&self, range: MemoryRange) -> Maps {
+            Maps::intersecting(self, range)
+        }
+
         pub fn monitored_addrs(&self) -> Ref<HashSet<RemotePtr<Void>>> {
             self.monitored_mem.borrow()
         }
@@ -1196,6 +1264,10 @@ pub mod address_space {
                 }
                 None => (),
             }
+            self.notify_mapping_change(
+                t,
+                MappingChange::Protected(MemoryRange::new_range(addr, num_bytes), prot),
+            );
         }
 
         /// Fix up mprotect registers parameters to take account of PROT_GROWSDOWN.
@@ -1303,6 +1375,22 @@ pub mod address_space {
                 None,
                 None,
             );
+            self.notify_mapping_change(
+                t,
+                MappingChange::Remapped {
+                    from: MemoryRange::new_range(old_addr, old_num_bytes),
+                    to: MemoryRange::new_range(new_addr, new_num_bytes),
+                },
+            );
+        }
+
+        /// Tell the observers of a replay, if any. See `ReplayObserver`.
+        fn notify_mapping_change(&self, t: &dyn Task, change: MappingChange) {
+            if let Some(session) = t.try_session() {
+                if let Some(replay) = session.as_replay() {
+                    replay.notify_observers(|o| o.on_mapping_change(t, &change));
+                }
+            }
         }
 
         /// Notify that data was written to this address space by rd or
@@ -1488,7 +1576,11 @@ pub mod address_space {
             self.allocate_watchpoints(active_task, maybe_cloned_from_thread);
         }
         pub fn all_watchpoints(&self) -> Vec<WatchConfig> {
-            self.get_watchpoints_internal(WatchPointFilter::AllWatchpoints)
+            let mut result: Vec<WatchConfig> = Vec::new();
+            for (r, v) in self.watchpoints.borrow().iter() {
+                result.extend(Self::watch_configs_for(r, v.watched_bits()));
+            }
+            result
         }
 
         /// Save all watchpoint state onto a stack.
@@ -1536,6 +1628,9 @@ pub mod address_space {
                         || k.start() == address_of_singlestep_start.to_data_ptr::<Void>())
                     && watchpoint_triggered(debug_status, &w.debug_regs_for_exec_read);
                 if read_triggered || exec_triggered {
+                    if !w.changed {
+                        w.old_value_bytes = w.value_bytes.clone();
+                    }
                     w.changed = true;
                     triggered = true;
                 }
@@ -1579,9 +1674,24 @@ pub mod address_space {
             false
         }
 
-        /// Return all changed watchpoints in `watches` and clear their changed flags.
-        pub fn consume_watchpoint_changes(&self) -> Vec<WatchConfig> {
-            self.get_watchpoints_internal(WatchPointFilter::ChangedWatchpoints)
+        /// Return all changed watchpoints and clear their changed flags.
+        pub fn consume_watchpoint_changes(&self) -> Vec<WatchpointHit> {
+            let mut result: Vec<WatchpointHit> = Vec::new();
+            for (r, v) in self.watchpoints.borrow_mut().iter_mut() {
+                if !v.changed {
+                    continue;
+                }
+                v.changed = false;
+                let watches_writes = v.write_count > 0;
+                for config in Self::watch_configs_for(r, v.watched_bits()) {
+                    result.push(WatchpointHit {
+                        config,
+                        old_value: watches_writes.then(|| v.old_value_bytes.clone()),
+                        new_value: watches_writes.then(|| v.value_bytes.clone()),
+                    });
+                }
+            }
+            result
         }
 
         pub fn set_shm_size(&self, addr: RemotePtr<Void>, bytes: usize) {
@@ -1612,6 +1722,10 @@ pub mod address_space {
             );
 
             self.unmap_internal(t, addr, num_bytes);
+            self.notify_mapping_change(
+                t,
+                MappingChange::Unmapped(MemoryRange::new_range(addr, num_bytes)),
+            );
         }
 
         /// Notification of madvise call.
@@ -1990,6 +2104,37 @@ pub mod address_space {
             }
         }
 
+        /// Call this before resuming a task in this address space. Since it
+        /// last ran, the tracee may have overwritten breakpoints in writable or
+        /// shared memory with its own stores, e.g. when a JIT regenerates code.
+        /// Note the new code and set those breakpoints again.
+        pub fn revalidate_breakpoints(&self, t: &mut dyn Task) {
+            let addrs: Vec<RemoteCodePtr> = self.breakpoints.borrow().keys().copied().collect();
+            for addr in addrs {
+                if self.is_breakpoint_in_private_read_only_memory(addr, t) {
+                    continue;
+                }
+                let bp_addr = addr.to_data_ptr::<u8>();
+                let mut ok = true;
+                let current = read_val_mem::<u8>(t, bp_addr, Some(&mut ok));
+                if ok && current != Self::BREAKPOINT_INSN {
+                    log!(LogDebug, "Code at breakpoint {} was rewritten", addr);
+                    self.breakpoints
+                        .borrow_mut()
+                        .get_mut(&addr)
+                        .unwrap()
+                        .overwritten_data = current;
+                    write_val_mem_with_flags::<u8>(
+                        t,
+                        bp_addr,
+                        &Self::BREAKPOINT_INSN,
+                        None,
+                        WriteFlags::IS_BREAKPOINT_RELATED,
+                    );
+                }
+            }
+        }
+
         /// Call this to ensure that the mappings in `range` during replay has the same length
         /// and is collapsed to a single mapping. The caller guarantees that all the
         /// mappings in the range can be coalesced (because they corresponded to a single
@@ -2195,7 +2340,9 @@ pub mod address_space {
                 child_mem_fd: Default::default(),
                 dont_fork: Default::default(),
                 task_set: Default::default(),
-                // Is TaskUid::new() what we want?
+                // No task's locals are in the clone's preload_thread_locals area
+                // yet: it's recreated empty by `post_vm_clone()`, and each task's
+                // locals are copied by `copy_state()` and activated when it runs.
                 thread_locals_tuid_: Default::default(),
                 saved_watchpoints: Default::default(),
             };
@@ -2543,8 +2690,11 @@ pub mod address_space {
             let mut mbm = self.watchpoints.borrow_mut();
             let mut watchpoint_original_mut = mbm.get_mut(watchpoint_range).unwrap();
             watchpoint_original_mut.valid = valid;
-            watchpoint_original_mut.value_bytes = value_bytes;
+            let old_value_bytes = replace(&mut watchpoint_original_mut.value_bytes, value_bytes);
             if mark_changed_if_changed && changed {
+                if !watchpoint_original_mut.changed {
+                    watchpoint_original_mut.old_value_bytes = old_value_bytes;
+                }
                 watchpoint_original_mut.changed = true;
             }
 
@@ -2564,28 +2714,16 @@ pub mod address_space {
                 // We do nothing to track kernel reads of read-write watchpoints...
             }
         }
-        fn get_watchpoints_internal(&self, filter: WatchPointFilter) -> Vec<WatchConfig> {
+        /// The configs reported for a watchpoint on `r` watching `watching`.
+        fn watch_configs_for(r: &MemoryRange, watching: RwxBits) -> Vec<WatchConfig> {
             let mut result: Vec<WatchConfig> = Vec::new();
-            for (r, v) in self.watchpoints.borrow_mut().iter_mut() {
-                if filter == WatchPointFilter::ChangedWatchpoints {
-                    if !v.changed {
-                        continue;
-                    }
-                    v.changed = false;
-                }
-                let watching = v.watched_bits();
-                if watching.contains(RwxBits::EXEC_BIT) {
-                    result.push(WatchConfig::new(r.start(), r.size(), WatchType::WatchExec));
-                }
-                if watching.contains(RwxBits::READ_BIT) {
-                    result.push(WatchConfig::new(
-                        r.start(),
-                        r.size(),
-                        WatchType::WatchReadWrite,
-                    ));
-                } else if watching.contains(RwxBits::WRITE_BIT) {
-                    result.push(WatchConfig::new(r.start(), r.size(), WatchType::WatchWrite));
-                }
+            if watching.contains(RwxBits::EXEC_BIT) {
+                result.push(WatchConfig::new(r.start(), r.size(), WatchType::WatchExec));
+            }
+            if watching.contains(RwxBits::READ_BIT) {
+                result.push(WatchConfig::new(r.start(), r.size(), WatchType::WatchReadWrite));
+            } else if watching.contains(RwxBits::WRITE_BIT) {
+                result.push(WatchConfig::new(r.start(), r.size(), WatchType::WatchWrite));
             }
             result
         }
@@ -2789,14 +2927,18 @@ pub mod address_space {
                 .get(&addr)
                 .unwrap()
                 .overwritten_data;
-            log!(LogDebug, "Writing back {:#x} at {}", data, addr);
-            write_val_mem_with_flags::<u8>(
-                active_task,
-                addr.to_data_ptr::<u8>(),
-                &data,
-                None,
-                WriteFlags::IS_BREAKPOINT_RELATED,
-            );
+            // If the tracee overwrote the breakpoint since it was last
+            // revalidated, its code is newer than `data`. Leave it alone.
+            if Self::is_breakpoint_instruction(active_task, addr) {
+                log!(LogDebug, "Writing back {:#x} at {}", data, addr);
+                write_val_mem_with_flags::<u8>(
+                    active_task,
+                    addr.to_data_ptr::<u8>(),
+                    &data,
+                    None,
+                    WriteFlags::IS_BREAKPOINT_RELATED,
+                );
+            }
             self.breakpoints.borrow_mut().remove(&addr);
         }
 
@@ -2940,6 +3082,20 @@ pub mod address_space {
             };
 
             if t.session().is_recording() {
+                let tracee_layout_hash =
+                    Arch::rdcall_init_preload_params_syscallbuf_layout_hash(&params);
+                if tracee_layout_hash != syscallbuf_layout_hash() {
+                    clean_fatal!(
+                        "The preload library loaded into {} has syscallbuf layout {:#x} but this \
+                         rd expects {:#x}.\nThe preload library was built from different sources \
+                         than rd. Reinstall rd, or point --resource-path at the directory its \
+                         preload library was installed to.",
+                        t.tid,
+                        tracee_layout_hash,
+                        syscallbuf_layout_hash()
+                    );
+                }
+
                 let tracer_syscallbuf_enabled =
                     t.session().as_record().unwrap().use_syscall_buffer();
                 let tracer_syscallbuf_status = if tracer_syscallbuf_enabled {
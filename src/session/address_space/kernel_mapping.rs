@@ -10,6 +10,7 @@ use libc::{
     stat,
     MAP_ANONYMOUS,
     MAP_GROWSDOWN,
+    MAP_HUGETLB,
     MAP_NORESERVE,
     MAP_PRIVATE,
     MAP_SHARED,
@@ -59,9 +60,16 @@ impl KernelMapping {
     /// between adjacent segments.  For example, the kernel
     /// considers a NORESERVE anonynmous mapping that's adjacent to
     /// a non-NORESERVE mapping distinct, even if all other
-    /// metadata are the same.  See `is_adjacent_mapping()`.
+    /// metadata are the same.  See `is_adjacent_mapping()`. Likewise a
+    /// MAP_HUGETLB mapping is never merged with a regular one.
     pub const MAP_FLAGS_MASK: MapFlags = MapFlags::from_bits_truncate(
-        MAP_ANONYMOUS | MAP_NORESERVE | MAP_PRIVATE | MAP_SHARED | MAP_STACK | MAP_GROWSDOWN,
+        MAP_ANONYMOUS
+            | MAP_NORESERVE
+            | MAP_PRIVATE
+            | MAP_SHARED
+            | MAP_STACK
+            | MAP_GROWSDOWN
+            | MAP_HUGETLB,
     );
 
     pub fn new() -> KernelMapping {
@@ -206,6 +214,11 @@ impl KernelMapping {
     pub fn is_vsyscall(&self) -> bool {
         self.fsname() == "[vsyscall]"
     }
+    /// Return true if this mapping is made of huge pages, so its size is a
+    /// multiple of the huge page size rather than just the page size.
+    pub fn is_hugetlb(&self) -> bool {
+        self.flags_.contains(MapFlags::MAP_HUGETLB)
+    }
 
     pub fn fake_stat(&self) -> stat {
         let mut fake_stat: stat = unsafe { zeroed() };
@@ -152,7 +152,7 @@ mod test {
     use crate::session::address_space::memory_range::{MemoryRange, MemoryRangeKey};
     use std::{
         collections::{BTreeMap, BTreeSet},
-        ops::Bound::{Included, Unbounded},
+        ops::Bound::{Excluded, Included, Unbounded},
     };
 
     #[test]
@@ -278,6 +278,28 @@ mod test {
         assert_eq!(k2_prime.end(), k2.end());
     }
 
+    #[test]
+    pub fn test_bounded_range_iter() {
+        let mut m: BTreeMap<MemoryRangeKey, usize> = BTreeMap::new();
+        for &(s, e) in &[(0usize, 10usize), (10, 15), (20, 30), (30, 40)] {
+            m.insert(MemoryRangeKey(MemoryRange::from_range(s.into(), e.into())), s);
+        }
+
+        // Maps intersecting [12, 30)
+        let lower = MemoryRangeKey(MemoryRange::new_range(12usize.into(), 1));
+        let upper = MemoryRangeKey(MemoryRange::from_range(30usize.into(), 30usize.into()));
+        let found: Vec<usize> = m
+            .range((Included(lower), Excluded(upper)))
+            .map(|(_, &v)| v)
+            .collect();
+        assert_eq!(found, vec![10, 20]);
+
+        // Empty range
+        let lower = MemoryRangeKey(MemoryRange::new_range(15usize.into(), 1));
+        let upper = MemoryRangeKey(MemoryRange::from_range(15usize.into(), 15usize.into()));
+        assert_eq!(m.range((Included(lower), Excluded(upper))).count(), 0);
+    }
+
     #[test]
     pub fn test_map_iter() {
         let mut m: BTreeMap<MemoryRangeKey, usize> = BTreeMap::new();
@@ -35,7 +35,13 @@ pub struct DiversionSession {
 
 impl Drop for DiversionSession {
     fn drop(&mut self) {
-        unimplemented!()
+        // We won't permanently leak any OS resources by not ensuring
+        // we've cleaned up here, but sessions can be created and
+        // destroyed many times, and we don't want to temporarily hog
+        // resources.
+        self.kill_all_tasks();
+        debug_assert!(self.task_map.borrow().is_empty());
+        debug_assert!(self.vm_map.borrow().is_empty());
     }
 }
 
@@ -1,12 +1,15 @@
-use super::session_common::kill_all_tasks;
+use super::session_common::{forget_vms_and_thread_groups, kill_all_tasks};
 use crate::{
+    bindings::ptrace::PTRACE_DETACH,
     event::Switchable,
     kernel_abi::SupportedArch,
+    log::LogLevel::LogDebug,
+    remote_ptr::RemotePtr,
     scheduler::Scheduler,
     seccomp_filter_rewriter::SeccompFilterRewriter,
     session::{
         session_inner::session_inner::SessionInner,
-        task::{Task, TaskSharedPtr},
+        task::{task_inner::task_inner::PtraceData, Task, TaskSharedPtr},
         Session,
     },
     taskish_uid::TaskUid,
@@ -14,7 +17,7 @@ use crate::{
     trace::{trace_stream::TraceStream, trace_writer::TraceWriter},
     util::{good_random, CPUIDData, CPUID_GETEXTENDEDFEATURES, CPUID_GETFEATURES, CPUID_GETXSAVE},
 };
-use libc::pid_t;
+use libc::{pid_t, SIGSTOP};
 use std::{
     cell::{Ref, RefCell, RefMut},
     ops::{Deref, DerefMut},
@@ -112,7 +115,7 @@ impl TraceUuid {
 
 pub struct RecordSession {
     session_inner: SessionInner,
-    trace_out: TraceWriter,
+    trace_out: RefCell<TraceWriter>,
     scheduler_: RefCell<Scheduler>,
     initial_thread_group: ThreadGroupSharedPtr,
     seccomp_filter_rewriter_: SeccompFilterRewriter,
@@ -139,7 +142,10 @@ pub struct RecordSession {
 
 impl Drop for RecordSession {
     fn drop(&mut self) {
-        unimplemented!()
+        // Tracees are normally gone by now, via `detach_all_tasks()`, but if
+        // recording was aborted some may be left. Don't let them run on
+        // unrecorded.
+        self.kill_all_tasks();
     }
 }
 
@@ -161,11 +167,41 @@ impl RecordSession {
     pub fn use_syscall_buffer(&self) -> bool {
         self.use_syscall_buffer_
     }
-    pub fn trace_stream(&self) -> Option<&TraceStream> {
-        Some(&self.trace_out)
+    pub fn trace_writer(&self) -> Ref<'_, TraceWriter> {
+        self.trace_out.borrow()
     }
-    pub fn trace_stream_mut(&mut self) -> Option<&mut TraceStream> {
-        Some(&mut self.trace_out)
+    pub fn trace_writer_mut(&self) -> RefMut<'_, TraceWriter> {
+        self.trace_out.borrow_mut()
+    }
+
+    /// Stop tracing all tracees without killing them. All tracees must be
+    /// stopped. They are left stopped by SIGSTOP, so that another debugger
+    /// can attach: rd's seccomp filter stays installed in them, so their
+    /// syscalls would fail if they ran on without a tracer. `tasks()` is
+    /// empty when this returns.
+    pub fn detach_all_tasks(&self) {
+        let tasks: Vec<TaskSharedPtr> = self.task_map.borrow().values().cloned().collect();
+        for t in &tasks {
+            let t = t.borrow();
+            if t.was_reaped || !t.is_stopped {
+                continue;
+            }
+            log!(LogDebug, "detaching from {} ...", t.tid);
+            t.fallible_ptrace(
+                PTRACE_DETACH,
+                RemotePtr::null(),
+                PtraceData::ReadWord(SIGSTOP as usize),
+            );
+            // We won't see its exit, so don't wait for it when dropping it.
+            t.unstable.set(true);
+        }
+        drop(tasks);
+        while !self.task_map.borrow().is_empty() {
+            // Drop each task with the task map unborrowed.
+            let (_, t) = self.task_map.borrow_mut().pop_last().unwrap();
+            drop(t);
+        }
+        forget_vms_and_thread_groups(self);
     }
 }
 
@@ -189,8 +225,8 @@ impl Session for RecordSession {
         kill_all_tasks(self)
     }
 
-    fn on_destroy_task(&self, _t: TaskUid) {
-        unimplemented!()
+    fn on_destroy_task(&self, tuid: TaskUid) {
+        self.scheduler_mut().on_destroy(tuid);
     }
 
     fn as_session_inner(&self) -> &SessionInner {
@@ -201,6 +237,20 @@ impl Session for RecordSession {
         &mut self.session_inner
     }
 
+    fn as_record(&self) -> Option<&RecordSession> {
+        Some(self)
+    }
+
+    fn trace_stream(&self) -> Option<Ref<'_, TraceStream>> {
+        let r = self.trace_out.borrow();
+        Some(Ref::map(r, |t| t.deref()))
+    }
+
+    fn trace_stream_mut(&self) -> Option<RefMut<'_, TraceStream>> {
+        let r = self.trace_out.borrow_mut();
+        Some(RefMut::map(r, |t| t.deref_mut()))
+    }
+
     fn new_task(
         &self,
         _tid: pid_t,
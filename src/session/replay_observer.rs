@@ -0,0 +1,49 @@
+//! Observing a replay without driving it. Tools that reconstruct coverage or
+//! analyze memory accesses need to see every replayed event, but replaying
+//! is the job of `ReplaySession::replay_step()` and its callers. Such tools
+//! implement `ReplayObserver` and register it with
+//! `ReplaySession::add_observer()`; the session then calls it as the replay
+//! progresses, whoever drives it.
+//!
+//! Observers are called while the session is in the middle of a step, so
+//! they must not step the session or change tracee state.
+
+use crate::{
+    bindings::signal::siginfo_t,
+    session::{
+        address_space::{kernel_mapping::KernelMapping, memory_range::MemoryRange},
+        task::{replay_task::ReplayTask, Task},
+    },
+    trace::trace_frame::TraceFrame,
+};
+use nix::sys::mman::ProtFlags;
+use std::rc::Rc;
+
+pub type ReplayObserverSharedPtr = Rc<dyn ReplayObserver>;
+
+/// A change to the mappings of an address space during replay.
+#[derive(Clone, Debug)]
+pub enum MappingChange {
+    Mapped(KernelMapping),
+    Unmapped(MemoryRange),
+    Protected(MemoryRange, ProtFlags),
+    Remapped { from: MemoryRange, to: MemoryRange },
+}
+
+/// All methods do nothing by default, so implement only the ones you need.
+pub trait ReplayObserver {
+    /// `frame` has been replayed completely by `t`. `t` is None if the frame
+    /// was the exit of its task.
+    fn on_frame(&self, _t: Option<&ReplayTask>, _frame: &TraceFrame) {}
+
+    /// The signal `siginfo` is being delivered to `t`.
+    fn on_signal(&self, _t: &ReplayTask, _siginfo: &siginfo_t) {}
+
+    /// The mappings of `t`'s address space changed.
+    fn on_mapping_change(&self, _t: &dyn Task, _change: &MappingChange) {}
+
+    /// The session is killing `t` before it replayed its exit, e.g. because
+    /// the trace ends first or the session is being dropped. Stands in for
+    /// the `on_frame()` call for its exit that won't happen.
+    fn on_synthesized_exit(&self, _t: &ReplayTask) {}
+}
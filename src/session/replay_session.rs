@@ -20,15 +20,21 @@ use crate::{
     fast_forward::{fast_forward_through_instruction, FastForwardStatus},
     flags::Flags as ProgramFlags,
     kernel_abi::{
-        common::preload_interface::syscallbuf_hdr,
+        common::preload_interface::{
+            mprotect_record,
+            preload_globals,
+            syscallbuf_hdr,
+            syscallbuf_layout_hash,
+            SYS_rdcall_mprotect_record,
+        },
         is_execve_syscall,
         syscall_number_for_exit,
         SupportedArch,
     },
-    kernel_metadata::{signal_name, syscall_name},
+    kernel_metadata::{signal_name, syscall_name, xsave_feature_string},
     log::LogLevel::{LogDebug, LogError},
     perf_counters,
-    perf_counters::{PerfCounters, TIME_SLICE_SIGNAL},
+    perf_counters::{PerfCounters, TicksSemantics, TIME_SLICE_SIGNAL},
     registers::{MismatchBehavior, Registers},
     remote_code_ptr::RemoteCodePtr,
     remote_ptr::RemotePtr,
@@ -47,13 +53,14 @@ use crate::{
             Traced,
         },
         diversion_session::DiversionSessionSharedPtr,
+        replay_observer::{ReplayObserver, ReplayObserverSharedPtr},
         replay_session::ReplayTraceStepType::TstepNone,
         session_inner::{session_inner::SessionInner, BreakStatus, RunCommand},
         task::{
             replay_task::ReplayTask,
-            task_common::write_val_mem,
+            task_common::{read_mem, read_val_mem, write_val_mem},
             task_inner::{
-                task_inner::{SaveTraceeFdNumber, TaskInner},
+                task_inner::{SaveTraceeFdNumber, TaskInner, WriteFlags},
                 ResumeRequest,
                 TicksRequest,
                 WaitRequest,
@@ -76,9 +83,13 @@ use crate::{
         cpuid_compatible,
         default_action,
         find_cpuid_record,
+        read_to_end,
+        resize_shmem_segment,
+        running_under_rd,
         should_dump_memory,
         trapped_instruction_at,
         trapped_instruction_len,
+        write_all,
         xcr0,
         xsave_enabled,
         CPUIDData,
@@ -90,18 +101,29 @@ use crate::{
         OSXSAVE_FEATURE_FLAG,
         XSAVEC_FEATURE_FLAG,
     },
+    vsyscall::{host_vsyscall_mode, vsyscall_entry_syscallno, VsyscallMode},
     wait_status::WaitStatus,
 };
-use libc::{pid_t, ENOSYS, SIGBUS, SIGSEGV, SIGTRAP};
-use nix::sys::mman::MapFlags;
+use libc::{dev_t, ino_t, pid_t, syscall, ENOSYS, SIGBUS, SIGSEGV, SIGTRAP};
+use nix::{
+    sys::{
+        memfd::{memfd_create, MemFdCreateFlag},
+        mman::{MapFlags, ProtFlags},
+    },
+    unistd::getpid,
+};
 use std::{
     cell::{Cell, Ref, RefCell, RefMut},
     cmp::min,
+    collections::{hash_map::DefaultHasher, HashMap},
     convert::TryInto,
-    ffi::{OsStr, OsString},
+    ffi::{CString, OsStr, OsString},
+    hash::{Hash, Hasher},
     io,
     io::Write,
+    mem::size_of,
     ops::{Deref, DerefMut},
+    ptr,
     rc::Rc,
 };
 
@@ -357,11 +379,27 @@ pub struct ReplaySession {
     syscall_bp_vm: RefCell<Option<AddressSpaceSharedPtr>>,
     // @TODO Set to the 0 address on init. More principled solution?!
     syscall_bp_addr: Cell<RemoteCodePtr>,
+    observers: RefCell<Vec<ReplayObserverSharedPtr>>,
+    /// Ticks counted by Intel PT during recording, by event time. See
+    /// `trace_intel_pt`.
+    pt_ticks: HashMap<FrameTime, (pid_t, Ticks)>,
+    /// See `private_mapping_file()`.
+    private_mapping_files: RefCell<HashMap<PrivateMappingKey, Vec<ScopedFd>>>,
+    /// See `set_max_events_per_step()`.
+    max_events_per_step: Cell<u32>,
 }
 
+/// The recorded file, offset and size of a private mapping and the length
+/// and a hash of its contents.
+type PrivateMappingKey = (dev_t, ino_t, u64, usize, usize, u64);
+
 #[derive(Copy, Clone)]
 pub struct Flags {
     pub redirect_stdio: bool,
+    /// Back private file mappings whose contents were saved in the trace with
+    /// one rd-owned copy per distinct contents, mapped privately by every
+    /// tracee that maps them, instead of giving each its own anonymous copy.
+    /// Writes (including from data records) copy-on-write as usual.
     pub share_private_mappings: bool,
     pub cpu_unbound: bool,
 }
@@ -402,14 +440,59 @@ impl ReplaySession {
     /// to keep a session around inactive, keep the clone and not the original
     /// session. Partially initialized sessions automatically finish
     /// initializing when necessary.
-    pub fn clone_replay(&self) -> ReplaySessionSharedPtr {
-        unimplemented!()
+    ///
+    /// The tracees of the clone are fork()s of ours, so their memory is
+    /// shared with ours copy-on-write rather than copied up front.
+    pub fn clone_replay(&self) -> SessionSharedPtr {
+        log!(LogDebug, "Deepforking ReplaySession {:p} ...", self);
+        self.finish_initializing();
+        if self.syscall_bp_vm.borrow().is_some() {
+            let t = self.current_task().unwrap();
+            self.clear_syscall_bp(t.borrow_mut().as_mut());
+        }
+
+        let session = ReplaySession {
+            session_inner: self.session_inner.clone_for_checkpoint(),
+            emu_fs: EmuFs::create(),
+            trace_in: RefCell::new(self.trace_in.borrow().clone()),
+            trace_frame: RefCell::new(self.trace_frame.borrow().clone()),
+            current_step: Cell::new(self.current_step.get()),
+            ticks_at_start_of_event: Cell::new(self.ticks_at_start_of_event.get()),
+            cpuid_bug_detector: Default::default(),
+            last_siginfo_: Cell::new(self.last_siginfo_.get()),
+            flags_: self.flags_,
+            fast_forward_status: Cell::new(self.fast_forward_status.get()),
+            trace_start_time: Cell::new(self.trace_start_time.get()),
+            syscall_bp_vm: Default::default(),
+            syscall_bp_addr: Default::default(),
+            observers: Default::default(),
+            pt_ticks: self.pt_ticks.clone(),
+            private_mapping_files: Default::default(),
+            max_events_per_step: Cell::new(self.max_events_per_step.get()),
+        };
+        let emu_fs = session.emu_fs.clone();
+        let mut rc: SessionSharedPtr = Rc::new(Box::new(session));
+        let weak_self = Rc::downgrade(&rc);
+        // We never change the weak_self pointer so its a good idea to use
+        // a bit of unsafe here.
+        unsafe { Rc::get_mut_unchecked(&mut rc) }.weak_self = weak_self;
+        log!(
+            LogDebug,
+            "  deepfork session is {:p}",
+            rc.as_replay().unwrap()
+        );
+
+        self.copy_state_to_session(rc.clone(), &self.emufs(), &mut emu_fs.borrow_mut());
+        rc
     }
 
     /// Return true if we're in a state where it's OK to clone. For example,
     /// we can't clone in some syscalls.
     pub fn can_clone(&self) -> bool {
-        unimplemented!()
+        self.finish_initializing();
+        self.current_task().is_some()
+            && self.done_initial_exec()
+            && can_checkpoint_at(&self.current_trace_frame())
     }
 
     /// Like `clone()`, but return a session in "diversion" mode,
@@ -469,6 +552,88 @@ impl ReplaySession {
         &self.flags_
     }
 
+    /// Let each `replay_step(RunCommand::RunContinue)` replay up to `events`
+    /// trace events instead of one, to cut per-step overhead for callers that
+    /// only care about where replay ends up. A step still returns early on
+    /// any break, task exit or the end of the replay. Default is 1.
+    pub fn set_max_events_per_step(&self, events: u32) {
+        debug_assert!(events > 0);
+        self.max_events_per_step.set(events);
+    }
+
+    /// A file holding `contents` followed by zeroes up to `size` bytes, for
+    /// tracees to map privately in place of the private mapping `km` of a
+    /// recorded file. Mappings with the same contents share one file, and so
+    /// their pages until they are written to. See
+    /// `Flags::share_private_mappings`.
+    pub fn private_mapping_file(
+        &self,
+        km: &KernelMapping,
+        contents: &[u8],
+        size: usize,
+    ) -> OsString {
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        let key = (
+            km.device(),
+            km.inode(),
+            km.file_offset_bytes(),
+            size,
+            contents.len(),
+            hasher.finish(),
+        );
+        let mut files = self.private_mapping_files.borrow_mut();
+        let candidates = files.entry(key).or_default();
+        // Different contents can have the same hash.
+        let existing = candidates.iter().find(|fd| {
+            let mut buf = vec![0u8; contents.len()];
+            read_to_end(fd, 0, &mut buf).map_or(false, |n| n == buf.len()) && buf == contents
+        });
+        if let Some(fd) = existing {
+            return format!("/proc/{}/fd/{}", getpid(), fd.as_raw()).into();
+        }
+        let name = CString::new(format!(
+            "rd-private-mapping-{}-dev-{}-inode-{}-offset-{}",
+            getpid(),
+            key.0,
+            key.1,
+            key.2
+        ))
+        .unwrap();
+        let fd = match memfd_create(&name, MemFdCreateFlag::empty()) {
+            Ok(fd) => ScopedFd::from_raw(fd),
+            Err(e) => {
+                fatal!("Can't create a file for a private mapping: {}", e);
+                unreachable!()
+            }
+        };
+        resize_shmem_segment(&fd, size);
+        write_all(fd.as_raw(), contents);
+        let path: OsString = format!("/proc/{}/fd/{}", getpid(), fd.as_raw()).into();
+        candidates.push(fd);
+        path
+    }
+
+    /// Call `observer` as replay progresses, see `ReplayObserver`.
+    pub fn add_observer(&self, observer: ReplayObserverSharedPtr) {
+        self.observers.borrow_mut().push(observer);
+    }
+
+    pub fn remove_observer(&self, observer: &ReplayObserverSharedPtr) {
+        self.observers
+            .borrow_mut()
+            .retain(|o| !Rc::ptr_eq(o, observer));
+    }
+
+    /// Calls `f` on each observer. The list is copied first so observers can
+    /// add or remove observers.
+    pub fn notify_observers(&self, f: impl Fn(&dyn ReplayObserver)) {
+        let observers = self.observers.borrow().clone();
+        for o in &observers {
+            f(o.as_ref());
+        }
+    }
+
     fn new<T: AsRef<OsStr>>(dir: Option<&T>, flags: Flags) -> ReplaySession {
         let mut rs = ReplaySession {
             emu_fs: EmuFs::create(),
@@ -484,10 +649,18 @@ impl ReplaySession {
             fast_forward_status: Default::default(),
             syscall_bp_vm: Default::default(),
             syscall_bp_addr: Default::default(),
+            observers: Default::default(),
+            pt_ticks: Default::default(),
+            private_mapping_files: Default::default(),
+            max_events_per_step: Cell::new(1),
         };
 
         let semantics = rs.trace_in.borrow().ticks_semantics();
         rs.ticks_semantics_ = semantics;
+        // PT sees conditional branches, so can only check ticks that count those.
+        if let TicksSemantics::TicksRetiredConditionalBranches = semantics {
+            rs.pt_ticks = rs.trace_in.borrow().pt_ticks();
+        }
         rs.advance_to_next_trace_frame();
         rs.trace_start_time
             .set(rs.current_trace_frame().monotonic_time());
@@ -513,6 +686,17 @@ impl ReplaySession {
             );
         }
 
+        if let Some(hash) = rs.trace_in.borrow().syscallbuf_layout_hash() {
+            if hash != syscallbuf_layout_hash() {
+                clean_fatal!(
+                    "Trace was recorded with syscallbuf layout {:#x} but this rd uses {:#x};\n\
+                          replay would diverge. Replay it with the rd that recorded it.",
+                    hash,
+                    syscallbuf_layout_hash()
+                );
+            }
+        }
+
         check_xsave_compatibility(&rs.trace_in.borrow());
         rs
     }
@@ -670,6 +854,8 @@ impl ReplaySession {
                     }
                 }
                 ReplayTraceStepType::TstepDeliverSignal => {
+                    let siginfo = self.current_trace_frame().event().signal_event().siginfo;
+                    self.notify_observers(|o| o.on_signal(&*t, &siginfo));
                     // When we deliver a terminating signal, do not let the singlestep
                     // complete; proceed on to report our synthetic SIGKILL or task death.
                     if constraints.is_singlestep()
@@ -720,6 +906,11 @@ impl ReplaySession {
 
             self.check_for_watchpoint_changes(t, &mut result.break_status);
             self.check_approaching_ticks_target(t, &constraints, &mut result.break_status);
+
+            self.notify_observers(|o| o.on_frame(Some(&*t), &frame));
+        } else {
+            let frame = self.current_trace_frame();
+            self.notify_observers(|o| o.on_frame(None, &frame));
         }
 
         self.advance_to_next_trace_frame();
@@ -794,8 +985,9 @@ impl ReplaySession {
             }
             EventType::EvSyscallbufFlush => {
                 current_step.action = ReplayTraceStepType::TstepFlushSyscallbuf;
-
-                self.prepare_syscallbuf_records(t);
+                current_step.data = ReplayTraceStepData::Flush(ReplayFlushBufferedSyscallState {
+                    stop_breakpoint_addr: self.prepare_syscallbuf_records(t),
+                });
             }
             EventType::EvSyscallbufReset => {
                 // Reset syscallbuf_hdr->num_rec_bytes and zero out the recorded data.
@@ -895,8 +1087,46 @@ impl ReplaySession {
         t_shr_ptr
     }
 
-    fn prepare_syscallbuf_records(&self, _t: &ReplayTask) {
-        unimplemented!()
+    /// Read the recorded syscall buffer back into the buffer region. Returns
+    /// the address in the stopping breakpoint table at which the preload code
+    /// will stop once it has replayed all the buffered syscalls.
+    /// The buffer is usually only partially filled: only the `num_rec_bytes`
+    /// of records in the recorded header are written back, so the rest of the
+    /// buffer is left as the last reset left it.
+    fn prepare_syscallbuf_records(&self, t: &mut ReplayTask) -> usize {
+        let buf = self.trace_reader_mut().read_raw_data();
+        ed_assert!(t, buf.data.len() >= size_of::<syscallbuf_hdr>());
+        ed_assert!(t, buf.addr == RemotePtr::cast(t.syscallbuf_child));
+
+        let recorded_hdr: syscallbuf_hdr =
+            unsafe { ptr::read_unaligned(buf.data.as_ptr().cast::<syscallbuf_hdr>()) };
+        let num_rec_bytes = recorded_hdr.num_rec_bytes as usize;
+        let records_end = size_of::<syscallbuf_hdr>() + num_rec_bytes;
+        ed_assert!(
+            t,
+            records_end <= buf.data.len(),
+            "Recorded syscallbuf has {} bytes of records but only {} bytes of data",
+            num_rec_bytes,
+            buf.data.len()
+        );
+        ed_assert!(
+            t,
+            records_end <= t.syscallbuf_size,
+            "Recorded syscallbuf has {} bytes of records but the syscallbuf is only {} bytes",
+            num_rec_bytes,
+            t.syscallbuf_size
+        );
+        // Don't overwrite syscallbuf_hdr. That needs to keep tracking the current
+        // syscallbuf state.
+        t.write_bytes_helper(
+            RemotePtr::cast(t.syscallbuf_child + 1usize),
+            &buf.data[size_of::<syscallbuf_hdr>()..records_end],
+            None,
+            WriteFlags::empty(),
+        );
+
+        t.stopping_breakpoint_table.as_usize()
+            + (num_rec_bytes / 8) * t.stopping_breakpoint_table_entry_size
     }
 
     fn revive_task_for_exec(&self, ev: &Event, trace_frame_tid: pid_t) -> TaskSharedPtr {
@@ -937,7 +1167,44 @@ impl ReplaySession {
     }
 
     pub fn replay_step(&self, command: RunCommand) -> ReplayResult {
-        self.replay_step_with_constraints(StepConstraints::new(command))
+        let mut result = self.replay_step_with_constraints(StepConstraints::new(command));
+        if command != RunCommand::RunContinue {
+            return result;
+        }
+        for _ in 1..self.max_events_per_step.get() {
+            if result.status != ReplayStatus::ReplayContinue
+                || result.break_status.any_break()
+                || result.break_status.task_exit
+            {
+                break;
+            }
+            result = self.replay_step_with_constraints(StepConstraints::new(command));
+        }
+        result
+    }
+
+    /// Replay until `stop` returns true for this session (checked before
+    /// each step), or all tracees are dead.
+    pub fn replay_until(&self, mut stop: impl FnMut(&ReplaySession) -> bool) -> ReplayStatus {
+        while !stop(self) {
+            if self.replay_step(RunCommand::RunContinue).status == ReplayStatus::ReplayExited {
+                return ReplayStatus::ReplayExited;
+            }
+        }
+        ReplayStatus::ReplayContinue
+    }
+
+    /// Replay until `time` is the next event to be replayed, or all tracees
+    /// are dead. Does nothing if replay is already at or past `time`.
+    pub fn replay_to_event(&self, time: FrameTime) -> ReplayStatus {
+        while self.current_frame_time() < time {
+            let mut constraints = StepConstraints::new(RunCommand::RunContinue);
+            constraints.stop_at_time = time;
+            if self.replay_step_with_constraints(constraints).status == ReplayStatus::ReplayExited {
+                return ReplayStatus::ReplayExited;
+            }
+        }
+        ReplayStatus::ReplayContinue
     }
 
     fn emulate_signal_delivery(&self, t: &mut ReplayTask, sig: i32) -> Completion {
@@ -1041,6 +1308,9 @@ impl ReplaySession {
                 if self.handle_unrecorded_cpuid_fault(t, constraints) {
                     return Completion::Incomplete;
                 }
+                if self.handle_vsyscall_fault(t) {
+                    return Completion::Complete;
+                }
             }
             SIGTRAP => {
                 return Completion::Incomplete;
@@ -1112,6 +1382,20 @@ impl ReplaySession {
                     .regs_ref()
                     .ip()
                     .decrement_by_syscall_insn_length(t.arch());
+                let recorded_ip = self.current_trace_frame().regs_ref().ip();
+                ed_assert!(
+                    t,
+                    vsyscall_entry_syscallno(t.arch(), recorded_ip).is_none()
+                        || host_vsyscall_mode() == VsyscallMode::None,
+                    "Trace contains a vsyscall at {} (recorded with vsyscall={}), which can only \
+                     be replayed on a kernel booted with vsyscall=none, not vsyscall={}",
+                    recorded_ip,
+                    self.trace_in
+                        .borrow()
+                        .vsyscall_mode()
+                        .map_or("unknown".to_owned(), |m| m.to_string()),
+                    host_vsyscall_mode()
+                );
                 // If the breakpoint already exists, it must have been from a previous
                 // invocation of this function for the same event (once the event
                 // completes, the breakpoint is cleared).
@@ -1167,7 +1451,11 @@ impl ReplaySession {
                 ed_assert!(t, self.syscall_bp_vm.borrow().is_none());
                 t.canonicalize_regs(self.current_trace_frame().event().syscall_event().arch());
                 t.validate_regs(Default::default());
-                t.finish_emulated_syscall();
+                // A faulted vsyscall never entered the kernel, so there is
+                // nothing to finish.
+                if vsyscall_entry_syscallno(t.arch(), t.ip()).is_none() {
+                    t.finish_emulated_syscall();
+                }
             }
         }
 
@@ -1184,6 +1472,15 @@ impl ReplaySession {
 
         t.apply_all_data_records_from_trace();
         t.set_return_value_from_trace();
+        if vsyscall_entry_syscallno(t.arch(), t.ip()).is_some() {
+            // Do the `ret` that ends a vsyscall, like the kernel does when it
+            // emulates one.
+            let mut r = t.regs_ref().clone();
+            let ret_addr: u64 = read_val_mem(t, RemotePtr::<u64>::cast(r.sp()), None);
+            r.set_ip(RemoteCodePtr::from(ret_addr as usize));
+            r.set_sp(r.sp() + size_of::<u64>());
+            t.set_regs(&r);
+        }
 
         let mut flags = ReplayTaskIgnore::IgnoreNone;
         if t.arch() == SupportedArch::X86
@@ -1206,6 +1503,29 @@ impl ReplaySession {
         Completion::Complete
     }
 
+    /// On a vsyscall=none kernel, calling a vsyscall entry point raises
+    /// SIGSEGV with the ip at the entry point. Recording kernels that emulate
+    /// vsyscalls report the entry at the same ip, with the syscall number in
+    /// the original syscallno, so make the fault look like that.
+    fn handle_vsyscall_fault(&self, t: &mut ReplayTask) -> bool {
+        let syscallno = match vsyscall_entry_syscallno(t.arch(), t.ip()) {
+            Some(syscallno) => syscallno,
+            None => return false,
+        };
+        log!(
+            LogDebug,
+            "Emulating vsyscall {} at {}",
+            syscall_name(syscallno, t.arch()),
+            t.ip()
+        );
+        let mut r = t.regs_ref().clone();
+        r.set_original_syscallno(syscallno as isize);
+        r.set_syscall_result_signed(-ENOSYS as isize);
+        t.set_regs(&r);
+        // Clear SIGSEGV status since we're handling it
+        t.set_status(WaitStatus::default());
+        true
+    }
     fn handle_unrecorded_cpuid_fault(
         &self,
         t: &mut ReplayTask,
@@ -1255,6 +1575,19 @@ impl ReplaySession {
         let ticks_now = t.tick_count();
         let trace_ticks = self.current_trace_frame().ticks();
 
+        let time = self.current_trace_frame().time();
+        if let Some(&(tid, pt_ticks)) = self.pt_ticks.get(&time) {
+            ed_assert!(
+                t,
+                tid != t.rec_tid || pt_ticks == trace_ticks,
+                "Intel PT counted {} ticks for '{}' but the recorded count is {}; \
+                 the performance counter miscounted while recording",
+                pt_ticks,
+                ev,
+                trace_ticks
+            );
+        }
+
         ed_assert!(
             t,
             ticks_now == trace_ticks,
@@ -1681,8 +2014,85 @@ impl ReplaySession {
             guard_overshoot(t, &regs, ticks, ticks_left, mismatched_regs.as_ref());
         }
     }
-    fn flush_syscallbuf(&self, _t: &ReplayTask, _constraints: &StepConstraints) -> Completion {
-        unimplemented!();
+    fn flush_syscallbuf(&self, t: &mut ReplayTask, constraints: &StepConstraints) -> Completion {
+        let stop_breakpoint_addr =
+            RemoteCodePtr::from_val(self.current_step.get().flush().stop_breakpoint_addr);
+        let mut user_breakpoint_at_addr;
+
+        loop {
+            let mut next_rec = t.next_syscallbuf_record();
+            let skip_mprotect_records = read_val_mem(
+                t,
+                RemotePtr::<u32>::cast(
+                    RemotePtr::<u8>::cast(t.syscallbuf_child)
+                        + offset_of!(syscallbuf_hdr, mprotect_record_count_completed),
+                ),
+                None,
+            );
+
+            let mut ticks_request: TicksRequest = Default::default();
+            if !compute_ticks_request(t, constraints, &mut ticks_request) {
+                return Completion::Incomplete;
+            }
+
+            let added = t.vm_shr_ptr().add_breakpoint(
+                t,
+                stop_breakpoint_addr,
+                BreakpointType::BkptInternal,
+            );
+            ed_assert!(t, added);
+            let complete = self.continue_or_step(
+                t,
+                constraints,
+                ticks_request,
+                Some(ResumeRequest::ResumeCont),
+            );
+            user_breakpoint_at_addr = t.vm().get_breakpoint_type_at_addr(stop_breakpoint_addr)
+                != BreakpointType::BkptInternal;
+            t.vm_shr_ptr()
+                .remove_breakpoint(stop_breakpoint_addr, BreakpointType::BkptInternal, t);
+
+            // Account for buffered syscalls just completed
+            let end_rec = t.next_syscallbuf_record();
+            while next_rec != end_rec {
+                self.accumulate_syscall_performed();
+                let rec_size = t.stored_record_size(next_rec) as usize;
+                next_rec = RemotePtr::cast(RemotePtr::<u8>::cast(next_rec) + rec_size);
+            }
+
+            // Apply the mprotect records we just completed.
+            apply_mprotect_records(t, skip_mprotect_records);
+
+            if t.maybe_stop_sig() == TIME_SLICE_SIGNAL {
+                // This would normally be triggered by constraints.ticks_target but it's
+                // also possible to get stray signals here.
+                return Completion::Incomplete;
+            }
+
+            if complete == Completion::Complete
+                && !ReplaySession::is_ignored_signal(t.maybe_stop_sig().get_raw_repr())
+            {
+                break;
+            }
+        }
+
+        ed_assert!(
+            t,
+            t.maybe_stop_sig() == SIGTRAP,
+            "Replay got unexpected signal (or none) {}",
+            t.maybe_stop_sig()
+        );
+        if t.ip().decrement_by_bkpt_insn_length(t.arch()) == stop_breakpoint_addr
+            && !user_breakpoint_at_addr
+        {
+            let mut r: Registers = t.regs_ref().clone();
+            r.set_ip(stop_breakpoint_addr);
+            t.set_regs(&r);
+
+            return Completion::Complete;
+        }
+
+        Completion::Incomplete
     }
     fn patch_next_syscall(&self, t: &mut ReplayTask, constraints: &StepConstraints) -> Completion {
         if self.cont_syscall_boundary(t, constraints) == Completion::Incomplete {
@@ -1748,6 +2158,10 @@ impl ReplaySession {
     /// step was made, or `Incomplete` if there was a trap or step needs
     /// more work.
     fn try_one_trace_step(&self, t: &mut ReplayTask, constraints: &StepConstraints) -> Completion {
+        if t.vm().has_breakpoints() {
+            t.vm_shr_ptr().revalidate_breakpoints(t);
+        }
+
         if constraints.ticks_target > 0
             && !self.trace_frame.borrow().event().has_ticks_slop()
             && t.current_trace_frame().ticks() > constraints.ticks_target
@@ -1818,6 +2232,21 @@ impl ReplaySession {
     }
 }
 
+fn can_checkpoint_at(frame: &TraceFrame) -> bool {
+    let ev = frame.event();
+    if ev.has_ticks_slop() {
+        return false;
+    }
+    match ev.event_type() {
+        // At exits, we can't clone the exiting tasks, so
+        // don't even bother trying to clone a session here.
+        EventType::EvExit => false,
+        // A checkpoint at the reset would not restore the syscallbuf properly.
+        EventType::EvSyscallbufReset => false,
+        _ => true,
+    }
+}
+
 /// Task death during replay always goes through here (except for
 /// Session::kill_all_tasks when we forcibly kill all tasks in the session at
 /// once). `exit` and `exit_group` syscalls are both emulated so the real
@@ -1867,6 +2296,13 @@ impl DerefMut for ReplaySession {
 impl Session for ReplaySession {
     /// Forwarded method
     fn kill_all_tasks(&self) {
+        let live: Vec<TaskSharedPtr> = self.task_map.borrow().values().cloned().collect();
+        for t in live {
+            let tb = t.borrow();
+            if !tb.was_reaped {
+                self.notify_observers(|o| o.on_synthesized_exit(tb.as_replay_task().unwrap()));
+            }
+        }
         kill_all_tasks(self)
     }
 
@@ -1953,6 +2389,20 @@ fn check_xsave_compatibility(trace_in: &TraceReader) {
         .unwrap();
     }
 
+    // Recorded register state that uses features we don't have can't be
+    // restored, and the tracee's own XSAVEs would write state we can't produce.
+    let missing_features = tracee_xcr0 & !our_xcr0;
+    if missing_features != 0 {
+        clean_fatal!(
+            "Trace uses XSAVE features not enabled on this machine: {}(XCR0 {:#x} here, \
+             {:#x} in the trace).\n\
+             Record with `rd record --disable-cpuid-features(-ext)` to avoid them.",
+            xsave_feature_string(missing_features),
+            our_xcr0,
+            tracee_xcr0
+        );
+    }
+
     if tracee_xcr0 != our_xcr0 {
         if !ProgramFlags::get().suppress_environment_warnings {
             // If the tracee used XSAVE instructions which write different components
@@ -1975,15 +2425,22 @@ fn check_xsave_compatibility(trace_in: &TraceReader) {
         }
         let maybe_record = find_cpuid_record(trace_in.cpuid_records(), CPUID_GETXSAVE, feature);
         let data = cpuid(CPUID_GETXSAVE, feature);
-        if maybe_record.is_none()
-            || maybe_record.unwrap().out.eax != data.eax
-            || maybe_record.unwrap().out.ebx != data.ebx
-            || (check_alignment && (maybe_record.unwrap().out.ecx & 2u32) != (data.ecx & 2u32))
-        {
+        let difference = match maybe_record {
+            None => Some("no CPUID record in the trace"),
+            Some(rec) if rec.out.eax != data.eax => Some("size"),
+            Some(rec) if rec.out.ebx != data.ebx => Some("offset"),
+            Some(rec) if check_alignment && (rec.out.ecx & 2u32) != (data.ecx & 2u32) => {
+                Some("alignment")
+            }
+            Some(_) => None,
+        };
+        if let Some(difference) = difference {
             clean_fatal!(
-                "XSAVE offset/size/alignment differs for feature {};\n\
+                "XSAVE {} differs for feature {} ({});\n\
                     H. Peter Anvin said this would never happen!",
-                feature
+                difference,
+                feature,
+                xsave_feature_string(1u64 << feature)
             );
         }
     }
@@ -2008,6 +2465,55 @@ fn perform_interrupted_syscall(_t: &mut ReplayTask) {
     unimplemented!()
 }
 
+/// Buffered mprotect syscalls don't trap, so the preload code notes them in
+/// `preload_globals.mprotect_records` and we update our view of the address
+/// space from there. Records before `skip_mprotect_records` have already
+/// been applied.
+fn apply_mprotect_records(t: &mut ReplayTask, skip_mprotect_records: u32) {
+    let final_mprotect_record_count = read_val_mem(
+        t,
+        RemotePtr::<u32>::cast(
+            RemotePtr::<u8>::cast(t.syscallbuf_child)
+                + offset_of!(syscallbuf_hdr, mprotect_record_count),
+        ),
+        None,
+    );
+    if skip_mprotect_records >= final_mprotect_record_count {
+        return;
+    }
+
+    let records_ptr = RemotePtr::<mprotect_record>::cast(
+        RemotePtr::<u8>::cast(t.preload_globals.unwrap())
+            + offset_of!(preload_globals, mprotect_records),
+    ) + skip_mprotect_records as usize;
+    let records = read_mem(
+        t,
+        records_ptr,
+        (final_mprotect_record_count - skip_mprotect_records) as usize,
+        None,
+    );
+    for r in &records {
+        t.vm_shr_ptr().protect(
+            t,
+            RemotePtr::new_from_val(r.start as usize),
+            r.size as usize,
+            ProtFlags::from_bits_truncate(r.prot),
+        );
+        if running_under_rd() {
+            // Let an outer rd that is recording us know about the mprotect too.
+            unsafe {
+                syscall(
+                    SYS_rdcall_mprotect_record as i64,
+                    t.tid,
+                    r.start as usize,
+                    r.size as usize,
+                    r.prot,
+                )
+            };
+        }
+    }
+}
+
 /// Why a skid region?  Interrupts generated by perf counters don't
 /// fire at exactly the programmed point (as of 2013 kernel/HW);
 /// there's a variable slack region, which is technically unbounded.
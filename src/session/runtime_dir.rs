@@ -0,0 +1,167 @@
+//! Every session that spawns tracees gets a runtime directory named
+//! `<pid>-<n>`, where `<pid>` is the pid of the rd process that owns it and
+//! `<n>` tells apart the sessions of that process, e.g. checkpoints and
+//! diversions of a replay. We note down each tracee there so that if rd dies
+//! without cleaning up (e.g. it crashed or was SIGKILLed), `rd cleanup` can
+//! find and kill leftover tracees and remove leftover files. A session that
+//! shuts down normally removes its runtime directory.
+
+use crate::{
+    log::LogLevel::LogWarn,
+    property_table::Property,
+    session::session_inner::session_inner::SessionInner,
+    util::tmp_dir,
+};
+use libc::pid_t;
+use nix::unistd::{getpid, getuid};
+use std::{
+    env::var_os,
+    fs,
+    fs::{DirBuilder, OpenOptions},
+    io::Write,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+const TRACEES_FILE: &str = "tracees";
+
+/// Sessions of this rd process that have created a runtime directory so far.
+static SESSION_DIR_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// The directory under which all per-session runtime directories live.
+/// `$XDG_RUNTIME_DIR/rd` if available, otherwise a per-user directory in
+/// `tmp_dir()`.
+pub fn runtime_dir_base() -> PathBuf {
+    match var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => Path::new(&dir).join("rd"),
+        _ => Path::new(&tmp_dir()).join(format!("rd-runtime-{}", getuid())),
+    }
+}
+
+/// A tracee noted down in a session runtime directory. We also keep its start
+/// time so that we never kill an unrelated process that reused the pid.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TraceeEntry {
+    pub tid: pid_t,
+    pub start_time: u64,
+}
+
+impl TraceeEntry {
+    /// Return true if `tid` is still the very same process we noted down.
+    pub fn is_alive(&self) -> bool {
+        proc_start_time(self.tid) == Some(self.start_time)
+    }
+}
+
+pub struct SessionRuntimeDir {
+    path: PathBuf,
+}
+
+impl Property for SessionRuntimeDir {}
+
+impl SessionRuntimeDir {
+    /// Record that `tid` is a tracee of `session`, creating the session's
+    /// runtime directory if necessary.
+    pub fn note_tracee(session: &SessionInner, tid: pid_t) {
+        if !session.properties().contains::<SessionRuntimeDir>() {
+            match SessionRuntimeDir::create() {
+                Some(dir) => session.properties().set(dir),
+                // Not being able to clean up later is not a reason to fail now.
+                None => return,
+            }
+        }
+
+        let dir = session.properties().get::<SessionRuntimeDir>().unwrap();
+        let start_time = match proc_start_time(tid) {
+            Some(start_time) => start_time,
+            None => return,
+        };
+        let res = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.path.join(TRACEES_FILE))
+            .and_then(|mut f| write!(f, "{} {}\n", tid, start_time));
+        if let Err(e) = res {
+            log!(
+                LogWarn,
+                "Could not note tracee {} in {:?}: {}",
+                tid,
+                dir.path,
+                e
+            );
+        }
+    }
+
+    fn create() -> Option<SessionRuntimeDir> {
+        let n = SESSION_DIR_COUNT.fetch_add(1, Ordering::SeqCst);
+        let path = runtime_dir_base().join(format!("{}-{}", getpid(), n));
+        match DirBuilder::new().recursive(true).mode(0o700).create(&path) {
+            Ok(()) => Some(SessionRuntimeDir { path }),
+            Err(e) => {
+                log!(LogWarn, "Could not create runtime dir {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for SessionRuntimeDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).unwrap_or(());
+    }
+}
+
+/// A runtime directory whose owning rd process no longer exists.
+pub struct StaleSessionDir {
+    pub rd_pid: pid_t,
+    pub path: PathBuf,
+}
+
+impl StaleSessionDir {
+    pub fn tracees(&self) -> Vec<TraceeEntry> {
+        let contents = fs::read_to_string(self.path.join(TRACEES_FILE)).unwrap_or_default();
+        contents
+            .lines()
+            .filter_map(|line| {
+                let mut it = line.split_whitespace();
+                let tid = it.next()?.parse::<pid_t>().ok()?;
+                let start_time = it.next()?.parse::<u64>().ok()?;
+                Some(TraceeEntry { tid, start_time })
+            })
+            .collect()
+    }
+}
+
+/// Find the runtime directories left behind by rd processes that are gone.
+pub fn find_stale_session_dirs() -> Vec<StaleSessionDir> {
+    let mut result = Vec::new();
+    let entries = match fs::read_dir(runtime_dir_base()) {
+        Ok(entries) => entries,
+        Err(_) => return result,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let maybe_pid = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.split('-').next()?.parse::<pid_t>().ok());
+        if let Some(rd_pid) = maybe_pid {
+            if rd_pid != getpid().as_raw() && !Path::new(&format!("/proc/{}", rd_pid)).exists() {
+                result.push(StaleSessionDir {
+                    rd_pid,
+                    path: entry.path(),
+                });
+            }
+        }
+    }
+    result
+}
+
+/// The start time of `tid` in clock ticks after boot, from /proc/<tid>/stat.
+fn proc_start_time(tid: pid_t) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", tid)).ok()?;
+    // The command name may contain spaces, so skip past its closing paren.
+    // starttime is field 22; the field after the paren is field 3.
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(19)?.parse::<u64>().ok()
+}
@@ -14,6 +14,10 @@ use nix::errno::errno;
 ///
 pub(super) fn kill_all_tasks<S: Session>(sess: &S) {
     for (_, t) in sess.task_map.borrow().iter() {
+        if t.borrow().was_reaped {
+            // Already gone, and its tid may belong to someone else now.
+            continue;
+        }
         if !t.borrow().is_stopped {
             // During recording we might be aborting the recording, in which case
             // one or more tasks might not be stopped. We haven't got any really
@@ -73,7 +77,7 @@ pub(super) fn kill_all_tasks<S: Session>(sess: &S) {
     }
     while !sess.task_map.borrow().is_empty() {
         let (_, t) = sess.task_map.borrow_mut().pop_last().unwrap();
-        if !t.borrow().unstable.get() {
+        if !t.borrow().unstable.get() && !t.borrow().was_reaped {
             // Destroy the OS task backing this by sending it SIGKILL and
             // ensuring it was delivered.  After `kill()`, the only
             // meaningful thing that can be done with this task is to
@@ -95,6 +99,13 @@ pub(super) fn kill_all_tasks<S: Session>(sess: &S) {
         // NOTE: It is NOT necessary to call destroy() on the task here.
     }
 
+    forget_vms_and_thread_groups(sess);
+}
+
+/// Once the task map is empty, drop the session's address spaces and
+/// thread groups.
+pub(super) fn forget_vms_and_thread_groups<S: Session>(sess: &S) {
+    debug_assert!(sess.task_map.borrow().is_empty());
     // Manually clean up the vm map and thread group map
     // We have to do this ourselves because the session is probably
     // getting drop()-ed and the thread group and address spaces would
@@ -1,6 +1,9 @@
 use crate::{
     bindings::signal::siginfo_t,
-    session::{address_space::WatchConfig, task::TaskSharedWeakPtr},
+    session::{
+        address_space::{WatchType, WatchpointHit},
+        task::TaskSharedWeakPtr,
+    },
 };
 
 #[derive(Clone)]
@@ -10,8 +13,9 @@ pub struct BreakStatus {
     /// @TODO Must this be an Option<>??
     pub task: Option<TaskSharedWeakPtr>,
     /// List of watchpoints hit; any watchpoint hit causes a stop after the
-    /// instruction that triggered the watchpoint has completed.
-    pub watchpoints_hit: Vec<WatchConfig>,
+    /// instruction that triggered the watchpoint has completed. Write
+    /// watchpoints carry the values from before and after the write.
+    pub watchpoints_hit: Vec<WatchpointHit>,
     /// When non-`None`, we stopped because a signal was delivered to `task`.
     pub signal: Option<Box<siginfo_t>>,
     /// True when we stopped because we hit a software breakpoint at `task`'s
@@ -43,12 +47,20 @@ impl BreakStatus {
     /// True when we stopped because we hit a software or hardware breakpoint at
     /// `task`'s current ip().
     pub fn hardware_or_software_breakpoint_hit(&self) -> bool {
-        unimplemented!()
+        self.breakpoint_hit
+            || self
+                .watchpoints_hit
+                .iter()
+                .any(|w| w.config.type_ == WatchType::WatchExec)
     }
 
     /// Returns just the data watchpoints hit.
-    pub fn data_watchpoints_hit(&self) -> Vec<WatchConfig> {
-        unimplemented!()
+    pub fn data_watchpoints_hit(&self) -> Vec<WatchpointHit> {
+        self.watchpoints_hit
+            .iter()
+            .filter(|w| w.config.type_ != WatchType::WatchExec)
+            .cloned()
+            .collect()
     }
 
     pub fn any_break(&self) -> bool {
@@ -80,9 +92,11 @@ pub fn is_singlestep(command: RunCommand) -> bool {
 pub mod session_inner {
     use super::{is_singlestep, BreakStatus, RunCommand};
     use crate::{
+        fd_table::FdTableSharedPtr,
         flags::Flags,
         log::LogLevel::LogDebug,
         perf_counters::{self, PerfCounters, TicksSemantics},
+        property_table::PropertyTable,
         remote_ptr::{RemotePtr, Void},
         scoped_fd::ScopedFd,
         session::{
@@ -90,6 +104,7 @@ pub mod session_inner {
                 address_space::{AddressSpace, AddressSpaceSharedPtr, AddressSpaceSharedWeakPtr},
                 BreakpointType,
             },
+            spawn_error::SpawnError,
             task::{
                 task_inner::{task_inner::CapturedState, TrapReasons},
                 Task,
@@ -98,7 +113,7 @@ pub mod session_inner {
             },
             SessionSharedWeakPtr,
         },
-        taskish_uid::{AddressSpaceUid, ThreadGroupUid},
+        taskish_uid::{AddressSpaceUid, FdTableUid, ThreadGroupUid},
         thread_group::{ThreadGroup, ThreadGroupSharedPtr, ThreadGroupSharedWeakPtr},
         ticks::Ticks,
         util::cpuid_faulting_works,
@@ -111,8 +126,7 @@ pub mod session_inner {
     use std::{
         cell::{Cell, RefCell},
         collections::{BTreeMap, HashMap},
-        ffi::{OsStr, OsString},
-        os::unix::ffi::OsStringExt,
+        ffi::OsStr,
         rc::Rc,
     };
 
@@ -145,6 +159,9 @@ pub mod session_inner {
         pub clone_leader_state: CapturedState,
         pub member_states: Vec<CapturedState>,
         pub captured_memory: Vec<(RemotePtr<Void>, Vec<u8>)>,
+        /// The source session's fd tables of members that don't share the
+        /// group leader's.
+        pub fd_tables: HashMap<FdTableUid, FdTableSharedPtr>,
     }
 
     /// struct is NOT pub
@@ -169,6 +186,10 @@ pub mod session_inner {
             self.done_initial_exec_.get()
         }
 
+        pub fn properties(&self) -> &PropertyTable {
+            &self.properties
+        }
+
         /// Create and return a new address space that's constructed
         /// from `t`'s actual OS address space. When spawning, `exe` is the empty
         /// string; it will be replaced during the first execve(), when we first
@@ -260,6 +281,15 @@ pub mod session_inner {
             val
         }
 
+        /// Unlike address spaces, fd tables are created by unshare(CLONE_FILES)
+        /// too, so a task can create several without an exec in between. Each
+        /// gets its own serial instead. See `FdTable::uid()`.
+        pub fn next_fd_table_serial(&self) -> u32 {
+            let val = self.next_fd_table_serial_.get();
+            self.next_fd_table_serial_.set(val + 1);
+            val
+        }
+
         /// Call these functions from the objects' drop impl in order
         /// to notify this session that the objects are dying.
         /// DIFF NOTE: Method is simply called on_Session::on_destroy() in rr.
@@ -315,15 +345,22 @@ pub mod session_inner {
             *self.statistics_.borrow()
         }
 
-        pub fn read_spawned_task_error(&self) -> OsString {
+        /// What a tracee that failed to spawn wrote to the error pipe, if
+        /// anything. See `spawn_error`.
+        pub fn read_spawned_task_error(&self) -> Option<SpawnError> {
             let mut buf: Vec<u8> = vec![0; 1000];
             let res = read(self.spawned_task_error_fd_.borrow().as_raw(), &mut buf);
             match res {
-                Ok(nread) => {
-                    buf.truncate(nread);
-                    OsString::from_vec(buf)
-                }
-                Err(_) => OsString::new(),
+                Ok(nread) => SpawnError::parse(&String::from_utf8_lossy(&buf[..nread])),
+                Err(_) => None,
+            }
+        }
+
+        /// `read_spawned_task_error()` as a line to add to a fatal error.
+        pub fn spawned_task_error_message(&self) -> String {
+            match self.read_spawned_task_error() {
+                Some(e) => format!("\n{}", e),
+                None => String::new(),
             }
         }
 
@@ -361,11 +398,39 @@ pub mod session_inner {
                 tracee_socket: Default::default(),
                 tracee_socket_fd_number: Cell::new(-1),
                 next_task_serial_: Cell::new(1),
+                next_fd_table_serial_: Cell::new(1),
                 spawned_task_error_fd_: Default::default(),
                 syscall_seccomp_ordering_: Default::default(),
                 ticks_semantics_: PerfCounters::default_ticks_semantics(),
                 done_initial_exec_: Default::default(),
                 visible_execution_: true,
+                properties: PropertyTable::new(),
+            };
+            log!(LogDebug, "Session @TODO unique identifier created");
+            s
+        }
+
+        /// The state a checkpoint of this session starts with. It has no tasks
+        /// or address spaces yet; see `Session::copy_state_to_session()`.
+        /// NOTE: This is the Session copy constructor in rr.
+        pub(in super::super) fn clone_for_checkpoint(&self) -> SessionInner {
+            let s = SessionInner {
+                weak_self: Default::default(),
+                vm_map: Default::default(),
+                task_map: Default::default(),
+                thread_group_map: Default::default(),
+                clone_completion: Default::default(),
+                statistics_: RefCell::new(*self.statistics_.borrow()),
+                tracee_socket: self.tracee_socket.clone(),
+                tracee_socket_fd_number: Cell::new(self.tracee_socket_fd_number.get()),
+                next_task_serial_: Cell::new(self.next_task_serial_.get()),
+                next_fd_table_serial_: Cell::new(self.next_fd_table_serial_.get()),
+                spawned_task_error_fd_: Default::default(),
+                syscall_seccomp_ordering_: Cell::new(self.syscall_seccomp_ordering_.get()),
+                ticks_semantics_: self.ticks_semantics_,
+                done_initial_exec_: Cell::new(self.done_initial_exec_.get()),
+                visible_execution_: self.visible_execution_,
+                properties: PropertyTable::new(),
             };
             log!(LogDebug, "Session @TODO unique identifier created");
             s
@@ -545,6 +610,7 @@ pub mod session_inner {
         pub(in super::super) tracee_socket: Rc<RefCell<ScopedFd>>,
         pub(in super::super) tracee_socket_fd_number: Cell<i32>,
         pub(in super::super) next_task_serial_: Cell<u32>,
+        pub(in super::super) next_fd_table_serial_: Cell<u32>,
         // @TODO Should this be an Option?
         pub(in super::super) spawned_task_error_fd_: RefCell<ScopedFd>,
 
@@ -558,6 +624,9 @@ pub mod session_inner {
 
         /// True while the execution of this session is visible to users.
         pub(in super::super) visible_execution_: bool,
+
+        /// Extension state attached by other subsystems. See PropertyTable.
+        pub(in super::super) properties: PropertyTable,
     }
 
     impl Default for SessionInner {
@@ -0,0 +1,151 @@
+//! How a tracee being spawned tells rd why it couldn't get as far as running
+//! the program. Between fork() and exec the child writes a single
+//! `<step> <errno> <detail>` line to the session's spawned task error pipe
+//! and exits. When the spawn fails rd reads the line back with
+//! `SessionInner::read_spawned_task_error()` and reports the step that
+//! failed, rather than just that the tracee died.
+
+use crate::kernel_metadata::errno_name;
+use libc::{ENOENT, EPERM};
+use std::{fmt, str::FromStr};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SpawnStep {
+    Personality,
+    OpenDevNull,
+    DupMagicSaveDataFd,
+    OpenRootDir,
+    DupReservedRootDirFd,
+    DupSocketFd,
+    IgnoreSigchld,
+    ParentDeathSignal,
+    TrapRdtsc,
+    NoNewPrivs,
+    Seccomp,
+    Exec,
+}
+
+impl SpawnStep {
+    const ALL: [SpawnStep; 12] = [
+        SpawnStep::Personality,
+        SpawnStep::OpenDevNull,
+        SpawnStep::DupMagicSaveDataFd,
+        SpawnStep::OpenRootDir,
+        SpawnStep::DupReservedRootDirFd,
+        SpawnStep::DupSocketFd,
+        SpawnStep::IgnoreSigchld,
+        SpawnStep::ParentDeathSignal,
+        SpawnStep::TrapRdtsc,
+        SpawnStep::NoNewPrivs,
+        SpawnStep::Seccomp,
+        SpawnStep::Exec,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SpawnStep::Personality => "personality",
+            SpawnStep::OpenDevNull => "open-dev-null",
+            SpawnStep::DupMagicSaveDataFd => "dup-magic-save-data-fd",
+            SpawnStep::OpenRootDir => "open-root-dir",
+            SpawnStep::DupReservedRootDirFd => "dup-reserved-root-dir-fd",
+            SpawnStep::DupSocketFd => "dup-socket-fd",
+            SpawnStep::IgnoreSigchld => "ignore-sigchld",
+            SpawnStep::ParentDeathSignal => "parent-death-signal",
+            SpawnStep::TrapRdtsc => "trap-rdtsc",
+            SpawnStep::NoNewPrivs => "no-new-privs",
+            SpawnStep::Seccomp => "seccomp",
+            SpawnStep::Exec => "exec",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            SpawnStep::Personality => "disabling address space randomization",
+            SpawnStep::OpenDevNull => "opening /dev/null",
+            SpawnStep::DupMagicSaveDataFd => "duping to RD_MAGIC_SAVE_DATA_FD",
+            SpawnStep::OpenRootDir => "opening the root directory",
+            SpawnStep::DupReservedRootDirFd => "duping to RD_RESERVED_ROOT_DIR_FD",
+            SpawnStep::DupSocketFd => "duping to RD_RESERVED_SOCKET_FD",
+            SpawnStep::IgnoreSigchld => "ignoring SIGCHLD",
+            SpawnStep::ParentDeathSignal => "setting the parent-death signal",
+            SpawnStep::TrapRdtsc => "prctl(PR_SET_TSC)",
+            SpawnStep::NoNewPrivs => "prctl(NO_NEW_PRIVS)",
+            SpawnStep::Seccomp => "installing the seccomp filter",
+            SpawnStep::Exec => "execve",
+        }
+    }
+}
+
+impl FromStr for SpawnStep {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<SpawnStep, ()> {
+        SpawnStep::ALL
+            .iter()
+            .copied()
+            .find(|step| step.as_str() == s)
+            .ok_or(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SpawnError {
+    pub step: SpawnStep,
+    pub errno: i32,
+    /// E.g. the program that couldn't be exec'd. May be empty.
+    pub detail: String,
+}
+
+impl SpawnError {
+    /// The line the child writes to the error pipe.
+    pub fn line(step: SpawnStep, errno: i32, detail: &str) -> String {
+        format!("{} {} {}\n", step.as_str(), errno, detail)
+    }
+
+    /// Parse what the child wrote. `None` if it wrote nothing, e.g. because
+    /// it was killed.
+    pub fn parse(msg: &str) -> Option<SpawnError> {
+        let mut it = msg.trim_end_matches('\n').splitn(3, ' ');
+        let step = it.next()?.parse::<SpawnStep>().ok()?;
+        let errno = it.next()?.parse::<i32>().ok()?;
+        let detail = it.next().unwrap_or("").to_owned();
+        Some(SpawnError {
+            step,
+            errno,
+            detail,
+        })
+    }
+
+    fn hint(&self) -> Option<&'static str> {
+        match (self.step, self.errno) {
+            (SpawnStep::Exec, ENOENT) => Some("the program (or its interpreter) wasn't found"),
+            (SpawnStep::Exec, EPERM) => Some("is the program's filesystem mounted noexec?"),
+            (SpawnStep::NoNewPrivs, _) => Some(
+                "SECCOMP_FILTER is not available: your kernel is too old. Use `record -n` \
+                 to disable the filter",
+            ),
+            (SpawnStep::Seccomp, _) => {
+                Some("SECCOMP_FILTER is not available: your kernel is too old")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Tracee setup failed: {} failed with {}",
+            self.step.description(),
+            errno_name(self.errno)
+        )?;
+        if !self.detail.is_empty() {
+            write!(f, " ({})", self.detail)?;
+        }
+        if let Some(hint) = self.hint() {
+            write!(f, "; {}", hint)?;
+        }
+        Ok(())
+    }
+}
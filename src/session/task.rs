@@ -3,6 +3,7 @@ use crate::{
         kernel::{itimerval, setitimer, user_desc, ITIMER_REAL},
         ptrace::{PTRACE_EVENT_EXIT, PTRACE_INTERRUPT},
     },
+    flags::Flags,
     kernel_abi::{
         common::preload_interface::{syscallbuf_record, PRELOAD_THREAD_LOCALS_SIZE},
         syscall_instruction_length,
@@ -28,8 +29,9 @@ use crate::{
             },
         },
     },
+    trace::trace_task_event::TraceTaskEvent,
     util::{is_zombie_process, to_timeval},
-    wait_status::{MaybeStopSignal, WaitStatus},
+    wait_status::{MaybeStopSignal, TaskDeath, WaitStatus},
 };
 use libc::{pid_t, waitpid, ENOSYS, SIGSTOP, SIGTRAP};
 use nix::errno::errno;
@@ -405,14 +407,15 @@ pub trait Task: DerefMut<Target = TaskInner> {
             }
         }
 
-        if ret >= 0 && status.exit_code().is_some() {
-            // Unexpected non-stopping exit code returned in wait_status.
-            // This shouldn't happen; a PTRACE_EXIT_EVENT for this task
-            // should be observed first, and then we would kill the task
-            // before wait()ing again, so we'd only see the exit
+        if ret >= 0 && status.task_death() == Some(TaskDeath::Reaped) {
+            // Unexpected non-stopping exit status returned in wait_status.
+            // With PTRACE_O_TRACEEXIT this shouldn't happen; a PTRACE_EXIT_EVENT
+            // for this task should be observed first, and then we would kill the
+            // task before wait()ing again, so we'd only see the exit
             // code in detach_and_reap. But somehow we see it here in
             // grandchild_threads and async_kill_with_threads tests (and
             // maybe others), when a PTRACE_EXIT_EVENT has not been sent.
+            // Without PTRACE_O_TRACEEXIT it's how every task exit shows up.
             // Verify that we have not actually seen a PTRACE_EXIT_EVENT.
             ed_assert!(
                 self,
@@ -422,11 +425,16 @@ pub trait Task: DerefMut<Target = TaskInner> {
 
             // Turn this into a PTRACE_EXIT_EVENT.
             log!(
-                LogWarn,
-                "Synthesizing PTRACE_EVENT_EXIT for process {} exited with {}",
+                if Flags::get().disable_ptrace_exit_events {
+                    LogDebug
+                } else {
+                    LogWarn
+                },
+                "Synthesizing PTRACE_EVENT_EXIT for process {} that ended with {}",
                 self.tid,
-                status.exit_code().unwrap()
+                status
             );
+            self.was_reaped = true;
             status = WaitStatus::for_ptrace_event(PTRACE_EVENT_EXIT);
         }
 
@@ -497,13 +505,27 @@ pub trait Task: DerefMut<Target = TaskInner> {
     /// `prctl(PR_SET_NAME)` call to change the task name to the
     /// string pointed at in the tracee's address space by
     /// `child_addr`.
+    ///
+    /// During recording the new name is also written to the trace as a task
+    /// event so that e.g. `rd ps` can show it.
     fn update_prname(&mut self, child_addr: RemotePtr<Void>) {
         let mut buf = vec![0u8; 16];
         let res = self.read_bytes_fallible(child_addr, &mut buf);
         ed_assert!(self, res.is_ok());
         let bytes_read = res.unwrap();
         ed_assert!(self, bytes_read > 0);
+        // The kernel truncates the name to 15 bytes plus a NUL terminator.
+        buf.truncate(bytes_read.min(15));
+        if let Some(nul) = buf.iter().position(|&c| c == 0) {
+            buf.truncate(nul);
+        }
         self.prname = OsString::from_vec(buf);
+
+        let session = self.session();
+        if let Some(record) = session.as_record() {
+            let event = TraceTaskEvent::for_rename(self.rec_tid, &self.prname);
+            record.trace_writer_mut().write_task_event(&event);
+        }
     }
 
     fn compute_trap_reasons(&mut self) -> TrapReasons;
@@ -1,7 +1,7 @@
 use crate::{
     arch::{Architecture, NativeArch},
     kernel_abi::{syscall_number_for_rt_sigaction, SupportedArch},
-    kernel_supplement::{SA_RESETHAND, SA_SIGINFO, _NSIG},
+    kernel_supplement::{_NSIG, SA_RESETHAND, SA_SIGINFO},
     remote_code_ptr::RemoteCodePtr,
     remote_ptr::{RemotePtr, Void},
 };
@@ -211,9 +211,26 @@ pub mod record_task {
     use super::*;
     use crate::{
         bindings::{kernel::user_desc, signal::siginfo_t},
-        event::{Event, EventType, SignalDeterministic, SignalResolvedDisposition},
-        kernel_abi::{common::preload_interface::syscallbuf_record, SupportedArch},
+        event::{
+            Event,
+            EventType,
+            SignalDeterministic,
+            SignalResolvedDisposition,
+            SyscallbufFlushEventData,
+        },
+        kernel_abi::{
+            common::preload_interface::{
+                mprotect_record,
+                preload_globals,
+                syscallbuf_hdr,
+                syscallbuf_record,
+            },
+            is_x32_syscall,
+            SupportedArch,
+        },
+        kernel_metadata::syscall_name,
         kernel_supplement::sig_set_t,
+        log::LogLevel::LogDebug,
         registers::Registers,
         remote_code_ptr::RemoteCodePtr,
         remote_ptr::{RemotePtr, Void},
@@ -227,6 +244,7 @@ pub mod record_task {
                     destroy_buffers,
                     did_waitpid,
                     next_syscallbuf_record,
+                    on_syscall_exit,
                     open_mem_fd,
                     post_exec_for_exe,
                     post_exec_syscall,
@@ -234,6 +252,8 @@ pub mod record_task {
                     read_bytes_helper,
                     read_bytes_helper_for,
                     read_c_str,
+                    read_mem,
+                    read_val_mem,
                     resume_execution,
                     set_thread_area,
                     stored_record_size,
@@ -257,6 +277,7 @@ pub mod record_task {
         wait_status::WaitStatus,
     };
     use libc::pid_t;
+    use nix::sys::mman::ProtFlags;
     use std::{
         cell::RefCell,
         collections::{HashSet, VecDeque},
@@ -470,7 +491,10 @@ pub mod record_task {
 
         /// Forwarded method
         fn did_waitpid(&mut self, status: WaitStatus) {
-            did_waitpid(self, status)
+            did_waitpid(self, status);
+            if self.is_stopped && (status.is_syscall() || self.is_ptrace_seccomp_event()) {
+                self.reject_x32_syscall();
+            }
         }
 
         /// Forwarded method
@@ -494,8 +518,9 @@ pub mod record_task {
             Some(self)
         }
 
-        fn on_syscall_exit(&mut self, _syscallno: i32, _arch: SupportedArch, _regs: &Registers) {
-            unimplemented!()
+        /// Forwarded method
+        fn on_syscall_exit(&mut self, syscallno: i32, arch: SupportedArch, regs: &Registers) {
+            on_syscall_exit(self, syscallno, arch, regs)
         }
 
         fn did_wait(&mut self) {
@@ -603,6 +628,23 @@ pub mod record_task {
             unimplemented!()
         }
 
+        /// The x32 ABI is not supported. Fail the recording with a message that names
+        /// the offending syscall rather than letting an unknown syscall number
+        /// trip an assertion later during replay.
+        pub fn reject_x32_syscall(&self) {
+            let syscallno = self.regs_ref().original_syscallno();
+            if is_x32_syscall(syscallno, self.arch()) {
+                clean_fatal!(
+                    "Task {} made x32 ABI syscall `{}` (number {:#x}) at ip {}. \
+                     rd does not support recording x32 programs.",
+                    self.tid,
+                    syscall_name(syscallno as i32, self.arch()),
+                    syscallno,
+                    self.regs_ref().ip()
+                );
+            }
+        }
+
         /// Initialize tracee buffers in this, i.e., implement
         /// RRCALL_init_syscall_buffer.  This task must be at the point
         /// of *exit from* the rrcall.  Registers will be updated with
@@ -989,7 +1031,7 @@ pub mod record_task {
         pub fn push_syscall_eventsyscallno(&self, _no: i32) {
             unimplemented!()
         }
-        pub fn pop_eventexpected_type(&self) -> EventType {
+        pub fn pop_event(&self, _expected_type: EventType) {
             unimplemented!()
         }
         pub fn pop_noop(&self) {
@@ -1033,8 +1075,80 @@ pub mod record_task {
         /// a chance to reset the syscallbuf (i.e. record some other kind of event)
         /// before the tracee runs again in a way that might append another buffered
         /// syscall --- so we can't flush too early
-        pub fn maybe_flush_syscallbuf(&self) {
-            unimplemented!()
+        pub fn maybe_flush_syscallbuf(&mut self) {
+            if EventType::EvSyscallbufFlush == self.ev().event_type() {
+                // Already flushing.
+                return;
+            }
+            if self.syscallbuf_child.is_null() {
+                return;
+            }
+
+            // This can be called while the task is not stopped, when we prematurely
+            // terminate the trace. In that case, the tracee could be concurrently
+            // modifying the header. We'll take a snapshot of the header now.
+            // The syscallbuf code ensures that writes to syscallbuf records
+            // complete before num_rec_bytes is incremented.
+            let child = self.syscallbuf_child;
+            let hdr: syscallbuf_hdr = read_val_mem(self, child, None);
+            let num_rec_bytes = hdr.num_rec_bytes;
+            let mprotect_record_count = hdr.mprotect_record_count;
+            ed_assert!(
+                self,
+                !self.flushed_syscallbuf || self.flushed_num_rec_bytes == num_rec_bytes
+            );
+
+            if num_rec_bytes == 0 || self.flushed_syscallbuf {
+                // no records, or we've already flushed.
+                return;
+            }
+
+            let mut flush = SyscallbufFlushEventData::new();
+            // Apply buffered mprotect operations. They didn't trap, so this is
+            // the first we hear of them.
+            if mprotect_record_count > 0 {
+                let records_ptr = RemotePtr::<mprotect_record>::cast(
+                    RemotePtr::<u8>::cast(self.preload_globals.unwrap())
+                        + offset_of!(preload_globals, mprotect_records),
+                );
+                flush.mprotect_records =
+                    read_mem(self, records_ptr, mprotect_record_count as usize, None);
+                for r in &flush.mprotect_records {
+                    self.vm_shr_ptr().protect(
+                        self,
+                        RemotePtr::new_from_val(r.start as usize),
+                        r.size as usize,
+                        ProtFlags::from_bits_truncate(r.prot),
+                    );
+                }
+            }
+            self.push_event(&Event::new_syscallbuf_flush_event(flush));
+
+            // Write the entire buffer in one shot without parsing it,
+            // because replay will take care of that.
+            if self.is_running() {
+                let hdr_size = size_of::<syscallbuf_hdr>();
+                let mut buf = vec![0u8; hdr_size + num_rec_bytes as usize];
+                unsafe {
+                    copy_nonoverlapping((&raw const hdr).cast::<u8>(), buf.as_mut_ptr(), hdr_size)
+                };
+                self.read_bytes_helper(RemotePtr::cast(child + 1usize), &mut buf[hdr_size..], None);
+                self.record_local(RemotePtr::cast(child), &buf);
+            } else {
+                let data_size = self.syscallbuf_data_size();
+                self.record_remote(RemotePtr::cast(child), data_size);
+            }
+            self.record_current_event();
+            self.pop_event(EventType::EvSyscallbufFlush);
+
+            self.flushed_syscallbuf = true;
+            self.flushed_num_rec_bytes = num_rec_bytes;
+
+            log!(
+                LogDebug,
+                "Syscallbuf flushed with num_rec_bytes={}",
+                num_rec_bytes
+            );
         }
 
         /// Call this after recording an event when it might be safe to reset the
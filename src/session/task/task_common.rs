@@ -13,7 +13,11 @@
 
 use crate::{
     arch::Architecture,
-    auto_remote_syscalls::{AutoRemoteSyscalls, AutoRestoreMem},
+    auto_remote_syscalls::{
+        AutoRemoteSyscalls,
+        AutoRestoreMem,
+        MemParamsEnabled::DisableMemoryParams,
+    },
     bindings::{
         kernel::{
             user_desc,
@@ -42,26 +46,34 @@ use crate::{
     extra_registers::{ExtraRegisters, Format},
     fast_forward::at_x86_string_instruction,
     file_monitor,
+    flags::Flags,
     kernel_abi::{
         common::{
             preload_interface,
-            preload_interface::{preload_globals, syscallbuf_hdr, syscallbuf_record},
+            preload_interface::{
+                preload_globals,
+                syscallbuf_hdr,
+                syscallbuf_locked_why,
+                syscallbuf_record,
+            },
         },
         is_at_syscall_instruction,
         is_mprotect_syscall,
         syscall_instruction_length,
         syscall_number_for_arch_prctl,
+        syscall_number_for_clone,
         syscall_number_for_close,
         syscall_number_for_mprotect,
         syscall_number_for_munmap,
         syscall_number_for_openat,
+        syscall_number_for_prctl,
         x64,
         x86,
         CloneTLSType,
         FcntlOperation,
         SupportedArch,
     },
-    kernel_metadata::{ptrace_req_name, signal_name},
+    kernel_metadata::{errno_name, ptrace_req_name, signal_name},
     kernel_supplement::ARCH_SET_CPUID,
     log::LogLevel::{LogDebug, LogInfo, LogWarn},
     perf_counters::TIME_SLICE_SIGNAL,
@@ -97,14 +109,17 @@ use crate::{
             PRELOAD_THREAD_LOCALS_SIZE,
         },
         Session,
+        SessionSharedPtr,
     },
     ticks::Ticks,
     util::{
         ceil_page_size,
+        clone_flags_to_task_flags,
         cpuid,
         floor_page_size,
         is_kernel_trap,
         pwrite_all_fallible,
+        sysemu_supported,
         trapped_instruction_at,
         trapped_instruction_len,
         u8_raw_slice,
@@ -115,24 +130,33 @@ use crate::{
         XSaveLayout,
         CPUID_GETFEATURES,
     },
-    wait_status::WaitStatus,
+    wait_status::{TaskDeath, WaitStatus},
 };
 use file_monitor::LazyOffset;
 use libc::{
     pid_t,
     pread64,
     waitpid,
+    __WALL,
     CLONE_FILES,
+    CLONE_FS,
+    CLONE_SIGHAND,
+    CLONE_SYSVSEM,
+    CLONE_THREAD,
+    CLONE_VM,
+    EAGAIN,
     ECHILD,
     EPERM,
     ESRCH,
+    PR_SET_CHILD_SUBREAPER,
     PR_SET_NAME,
     PR_SET_SECCOMP,
     SECCOMP_MODE_FILTER,
+    SEEK_SET,
+    SIGCHLD,
     SIGKILL,
     SIGTRAP,
     WNOHANG,
-    __WALL,
 };
 use nix::{
     errno::{errno, Errno},
@@ -144,9 +168,9 @@ use std::{
     cmp::min,
     convert::TryInto,
     ffi::{c_void, CStr, CString, OsStr},
+    fs::read_to_string,
     mem::{size_of, size_of_val, zeroed},
     os::unix::ffi::OsStrExt,
-    path::Path,
     ptr,
     rc::Rc,
     slice,
@@ -206,8 +230,7 @@ pub(super) fn open_mem_fd<T: Task>(task: &mut T) -> bool {
             // This can happen when a process fork()s after setuid; it can no longer
             // open its own /proc/self/mem. Hopefully we can read the child's
             // mem file in this case (because rd is probably running as root).
-            let buf: String = format!("/proc/{}/mem", remote.task().tid);
-            fd = ScopedFd::open_path(Path::new(&buf), OFlag::O_RDWR);
+            fd = ScopedFd::open_proc(remote.task().tid, "mem", OFlag::O_RDWR);
         } else {
             fd = rd_arch_function!(remote, retrieve_fd_arch, arch, remote_fd);
             // Leak fd if the syscall fails due to the task being SIGKILLed unexpectedly
@@ -222,7 +245,7 @@ pub(super) fn open_mem_fd<T: Task>(task: &mut T) -> bool {
         );
         return false;
     }
-    remote.task().vm().set_mem_fd(fd.try_into().unwrap());
+    remote.task().vm().set_mem_fd(fd);
     true
 }
 
@@ -554,6 +577,27 @@ pub(super) fn syscallbuf_data_size<T: Task>(task: &mut T) -> usize {
         + size_of::<syscallbuf_hdr>()
 }
 
+/// Lock or unlock the syscallbuf to prevent the preload library from using it.
+/// Only has an effect if the syscallbuf has been initialized.
+pub fn set_syscallbuf_locked(t: &mut dyn Task, locked: bool) {
+    if t.syscallbuf_child.is_null() {
+        return;
+    }
+
+    let remote_addr =
+        RemotePtr::<u8>::cast(t.syscallbuf_child) + offset_of!(syscallbuf_hdr, locked);
+    let locked_before = read_val_mem(t, remote_addr, None);
+    let tracer_bit = syscallbuf_locked_why::SyscallbufLockedTracer as u8;
+    let new_locked = if locked {
+        locked_before | tracer_bit
+    } else {
+        locked_before & !tracer_bit
+    };
+    if new_locked != locked_before {
+        write_val_mem(t, remote_addr, &new_locked, None);
+    }
+}
+
 /// Forwarded method definition
 ///
 /// Write `N` bytes from `buf` to `child_addr`, or don't return.
@@ -887,6 +931,28 @@ pub(super) fn resume_execution<T: Task>(
             task.activate_preload_thread_locals(None);
         }
     }
+    if task.sysemu_fallback_pending {
+        task.sysemu_fallback_pending = false;
+        if task.status().is_syscall() {
+            skip_syscall_without_sysemu(task);
+        }
+    }
+    let ptrace_how = if sysemu_supported() {
+        how
+    } else {
+        match how {
+            ResumeRequest::ResumeSysemu => ResumeRequest::ResumeSyscall,
+            // Singlestepping a syscall instruction would execute it, so stop at
+            // its entry instead.
+            ResumeRequest::ResumeSysemuSinglestep if is_at_syscall_instruction(task, task.ip()) => {
+                ResumeRequest::ResumeSyscall
+            }
+            ResumeRequest::ResumeSysemuSinglestep => ResumeRequest::ResumeSinglestep,
+            _ => how,
+        }
+    };
+    task.sysemu_fallback_pending = ptrace_how != how && ptrace_how == ResumeRequest::ResumeSyscall;
+
     let sig_string = match maybe_sig {
         Some(sig) => format!(", signal: {}", signal_name(sig)),
         None => String::new(),
@@ -896,7 +962,7 @@ pub(super) fn resume_execution<T: Task>(
         LogDebug,
         "resuming execution of tid: {} with: {}{} tick_period: {:?}",
         task.tid,
-        ptrace_req_name(how as u32),
+        ptrace_req_name(ptrace_how as u32),
         sig_string,
         tick_period
     );
@@ -947,14 +1013,19 @@ pub(super) fn resume_execution<T: Task>(
         let status = WaitStatus::new(raw_status);
         if wait_ret == task.tid {
             // In some (but not all) cases where the child was killed with SIGKILL,
-            // we don't get PTRACE_EVENT_EXIT before it just exits.
+            // we don't get PTRACE_EVENT_EXIT before it just exits. Without
+            // PTRACE_O_TRACEEXIT we never do.
             ed_assert!(
                 task,
                 status.maybe_ptrace_event() == PTRACE_EVENT_EXIT
-                    || status.fatal_sig().unwrap_or(0) == SIGKILL,
+                    || status.fatal_sig().unwrap_or(0) == SIGKILL
+                    || (Flags::get().disable_ptrace_exit_events && status.task_death().is_some()),
                 "got {:?}",
                 status
             );
+            if status.task_death() == Some(TaskDeath::Reaped) {
+                task.was_reaped = true;
+            }
         } else {
             // 0 here means that no pids have changed state (WNOHANG)
             ed_assert!(
@@ -975,11 +1046,11 @@ pub(super) fn resume_execution<T: Task>(
     } else {
         match maybe_sig {
             None => {
-                task.ptrace_if_alive(how as u32, RemotePtr::null(), PtraceData::None);
+                task.ptrace_if_alive(ptrace_how as u32, RemotePtr::null(), PtraceData::None);
             }
             Some(sig) => {
                 task.ptrace_if_alive(
-                    how as u32,
+                    ptrace_how as u32,
                     RemotePtr::null(),
                     PtraceData::ReadFrom(u8_raw_slice(&sig)),
                 );
@@ -994,6 +1065,35 @@ pub(super) fn resume_execution<T: Task>(
     }
 }
 
+/// The task is at a syscall-entry stop that stands in for a PTRACE_SYSEMU
+/// stop, and its registers may already hold the emulated syscall's result.
+/// Run through to the syscall-exit stop with an invalid syscall number so the
+/// kernel doesn't execute anything, then put the registers back.
+fn skip_syscall_without_sysemu<T: Task>(task: &mut T) {
+    let saved_regs = task.regs_ref().clone();
+    let mut r = saved_regs.clone();
+    r.set_original_syscallno(-1);
+    task.set_regs(&r);
+    task.flush_regs();
+    if !task.ptrace_if_alive(
+        ResumeRequest::ResumeSyscall as u32,
+        RemotePtr::null(),
+        PtraceData::None,
+    ) {
+        return;
+    }
+    task.is_stopped = false;
+    task.extra_registers = None;
+    task.wait(None);
+    ed_assert!(
+        task,
+        task.status().is_syscall(),
+        "Expected syscall-exit stop while skipping syscall, got {:?}",
+        task.status()
+    );
+    task.set_regs(&saved_regs);
+}
+
 fn work_around_knl_string_singlestep_bug<T: Task>(task: &mut T) {
     let cx: usize = task.regs_ref().cx();
     let cutoff: usize = single_step_coalesce_cutoff();
@@ -1032,8 +1132,255 @@ fn cpu_has_knl_string_singlestep_bug() -> bool {
     *CPU_HAS_KNL_STRING_SINGLESTEP_BUG_INIT
 }
 
-pub fn os_clone_into(_state: &CapturedState, _remote: &mut AutoRemoteSyscalls) -> TaskSharedPtr {
-    unimplemented!()
+/// Grab state from `t` into a structure that we can use to
+/// initialize a new task via os_clone_into/os_fork_into and copy_state.
+pub fn capture_state(t: &mut dyn Task) -> CapturedState {
+    let num_syscallbuf_bytes = if t.syscallbuf_child.is_null() {
+        0
+    } else {
+        t.syscallbuf_data_size()
+    };
+    let cloned_file_data_offset = if t.cloned_file_data_fd_child >= 0 {
+        fd_offset(t.tid, t.cloned_file_data_fd_child)
+    } else {
+        0
+    };
+    let thread_locals = *t.fetch_preload_thread_locals();
+    CapturedState {
+        ticks: t.ticks,
+        regs: t.regs_ref().clone(),
+        extra_regs: t.extra_regs_ref().clone(),
+        prname: t.prname.clone(),
+        thread_areas: t.thread_areas(),
+        syscallbuf_child: t.syscallbuf_child,
+        syscallbuf_size: t.syscallbuf_size,
+        num_syscallbuf_bytes,
+        preload_globals: t.preload_globals.unwrap_or_else(RemotePtr::null),
+        scratch_ptr: t.scratch_ptr,
+        scratch_size: t.scratch_size as isize,
+        top_of_stack: t.top_of_stack,
+        cloned_file_data_offset,
+        thread_locals,
+        rec_tid: t.rec_tid,
+        serial: t.tuid().serial(),
+        fd_table_uid: t.fd_table().uid(),
+        desched_fd_child: t.desched_fd_child,
+        cloned_file_data_fd_child: t.cloned_file_data_fd_child,
+        wait_status: t.status(),
+    }
+}
+
+/// The file offset of `fd` in task `tid`, from /proc/{tid}/fdinfo/{fd}.
+fn fd_offset(tid: pid_t, fd: i32) -> u64 {
+    let fdinfo_path = format!("/proc/{}/fdinfo/{}", tid, fd);
+    let fdinfo = match read_to_string(&fdinfo_path) {
+        Ok(fdinfo) => fdinfo,
+        Err(e) => {
+            fatal!("Failed to read {}: {}", fdinfo_path, e);
+            unreachable!()
+        }
+    };
+    match fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("pos:"))
+        .and_then(|pos| pos.trim().parse::<u64>().ok())
+    {
+        Some(offset) => offset,
+        None => {
+            fatal!("Failed to read position from {}", fdinfo_path);
+            unreachable!()
+        }
+    }
+}
+
+/// Make `t` look like an identical copy of the task whose state
+/// was captured by capture_state(), in
+/// every way relevant to replay.  `t` should have been
+/// created by calling os_clone_into() or os_fork_into(),
+/// and if it wasn't results are undefined.
+///
+/// Some task state must be copied into `t` by injecting and
+/// running syscalls in it.  Other state is metadata
+/// that can simply be copied over in local memory.
+pub fn copy_state(t: &mut dyn Task, state: &CapturedState) {
+    t.set_regs(&state.regs);
+    t.set_extra_regs(&state.extra_regs);
+    {
+        let mut remote = AutoRemoteSyscalls::new(t);
+        {
+            let arch = remote.arch();
+            let mut prname = [0u8; 16];
+            let len = min(state.prname.len(), prname.len() - 1);
+            prname[0..len].copy_from_slice(&state.prname.as_bytes()[0..len]);
+            log!(LogDebug, "    setting name to {:?}", state.prname);
+            let mut remote_prname = AutoRestoreMem::new(&mut remote, Some(&prname), prname.len());
+            let addr = remote_prname.get().unwrap();
+            rd_infallible_syscall!(
+                remote_prname,
+                syscall_number_for_prctl(arch),
+                PR_SET_NAME,
+                addr.as_usize()
+            );
+        }
+        let t = remote.task_mut();
+        t.prname = state.prname.clone();
+        t.restore_thread_areas(&state.thread_areas);
+        t.thread_areas_ = state.thread_areas.clone();
+        t.syscallbuf_size = state.syscallbuf_size;
+
+        ed_assert!(t, t.syscallbuf_child.is_null());
+        if !state.syscallbuf_child.is_null() {
+            // All these fields are preserved by the fork.
+            t.desched_fd_child = state.desched_fd_child;
+            t.cloned_file_data_fd_child = state.cloned_file_data_fd_child;
+            if state.cloned_file_data_fd_child >= 0 {
+                remote.infallible_lseek_syscall(
+                    state.cloned_file_data_fd_child,
+                    state.cloned_file_data_offset as i64,
+                    SEEK_SET,
+                );
+            }
+            remote.task_mut().syscallbuf_child = state.syscallbuf_child;
+        }
+    }
+    t.preload_globals = if state.preload_globals.is_null() {
+        None
+    } else {
+        Some(state.preload_globals)
+    };
+    ed_assert!(t, t.vm().thread_locals_tuid() != t.tuid());
+    t.thread_locals = state.thread_locals;
+    // The scratch buffer (for now) is merely a private mapping in
+    // the remote task.  The CoW copy made by fork()'s semantics
+    // mean that it will be copied over *if* the region is shared.
+    t.scratch_ptr = state.scratch_ptr;
+    t.scratch_size = state.scratch_size as usize;
+    // Only clones made by os_clone_into() got this from os_clone(). Keep it
+    // for the leader too, so that a clone of this session is complete.
+    t.top_of_stack = state.top_of_stack;
+
+    // Whatever `from`'s last wait status was is what ours would
+    // have been.
+    t.set_status(state.wait_status);
+
+    t.ticks = state.ticks;
+}
+
+/// Make the OS-level calls to create a new fork or clone that
+/// will eventually be a copy of `t` and return that Task
+/// metadata.  These methods are used in concert with
+/// `copy_state()` to create task copies during
+/// checkpointing.
+///
+/// For `os_fork_into()`, `session` will be tracking the
+/// returned fork child. The fork shares all of `t`'s memory
+/// copy-on-write, so this is cheap however big the address space is.
+pub fn os_fork_into(t: &mut dyn Task, session: SessionSharedPtr) -> TaskSharedPtr {
+    let rec_tid = t.rec_tid;
+    let serial = t.tuid().serial();
+    let mut remote = AutoRemoteSyscalls::new_with_mem_params(t, DisableMemoryParams);
+    let child = os_clone(
+        CloneReason::SessionCloneLeader,
+        session,
+        &mut remote,
+        rec_tid,
+        serial,
+        // Most likely, we'll be setting up a
+        // CLEARTID futex.  That's not done
+        // here, but rather later in
+        // `copy_state()`.
+        //
+        // We also don't use any of the SETTID
+        // flags because that earlier work will
+        // be copied by fork()ing the address
+        // space.
+        SIGCHLD,
+        RemotePtr::null(),
+    );
+    // When we forked ourselves, the child inherited the setup we
+    // did to make the clone() call.  So we have to "finish" the
+    // remote calls (i.e. undo fudged state) in the child too,
+    // even though we never made any syscalls there.
+    remote.restore_state_to(Some(child.borrow_mut().as_mut()));
+    child
+}
+
+/// For `os_clone_into()`, the task of `remote` is the "main thread"
+/// in the process into which the copy of the task captured in `state` will
+/// be created.  It will perform the actual OS calls to
+/// create the new child.
+pub fn os_clone_into(state: &CapturedState, remote: &mut AutoRemoteSyscalls) -> TaskSharedPtr {
+    let session = remote.task().session();
+    os_clone(
+        CloneReason::SessionCloneNonleader,
+        session,
+        remote,
+        state.rec_tid,
+        state.serial,
+        // We don't actually /need/ to specify the
+        // SIGHAND/SYSVMEM flags because those things
+        // are emulated in the tracee.  But we use the
+        // same flags as glibc to be on the safe side
+        // wrt kernel bugs.
+        //
+        // CLONE_THREAD and CLONE_FILES are needed though: without them the
+        // copy would get its own ThreadGroup and FdTable (see
+        // `clone_task_common()`) instead of sharing the new leader's, and fds
+        // that replay opens in one thread would be missing in the others.
+        //
+        // We don't pass CLONE_SETTLS here *only*
+        // because we'll do it later in
+        // `copy_state()`.
+        //
+        // See `os_fork_into()` above for discussion
+        // of the CTID flags.
+        CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD | CLONE_SYSVSEM,
+        state.top_of_stack,
+    )
+}
+
+fn os_clone(
+    reason: CloneReason,
+    session: SessionSharedPtr,
+    remote: &mut AutoRemoteSyscalls,
+    rec_child_tid: pid_t,
+    new_serial: u32,
+    base_flags: i32,
+    stack: RemotePtr<Void>,
+) -> TaskSharedPtr {
+    // We never pass ptid, tls or ctid, so the architecture's clone parameter
+    // ordering doesn't matter here.
+    let clone_syscallno = syscall_number_for_clone(remote.arch());
+    let mut ret;
+    loop {
+        ret = remote.syscall(
+            clone_syscallno,
+            &[base_flags as usize, stack.as_usize(), 0, 0, 0],
+        );
+        if ret != -EAGAIN as isize {
+            break;
+        }
+    }
+    ed_assert!(
+        remote.task(),
+        ret >= 0,
+        "remote clone failed with errno {}",
+        errno_name(-ret as i32)
+    );
+
+    let new_tid = remote.new_tid().unwrap();
+    clone_task_common(
+        remote.task_mut(),
+        reason,
+        clone_flags_to_task_flags(base_flags),
+        stack,
+        RemotePtr::null(),
+        RemotePtr::null(),
+        new_tid,
+        Some(rec_child_tid),
+        new_serial,
+        Some(session),
+    )
 }
 
 fn on_syscall_exit_arch<Arch: Architecture>(t: &mut dyn Task, sys: i32, regs: &Registers) {
@@ -1063,10 +1410,32 @@ fn on_syscall_exit_arch<Arch: Architecture>(t: &mut dyn Task, sys: i32, regs: &R
     }
 
     if sys == Arch::RDCALL_MPROTECT_RECORD {
-        unimplemented!()
+        // When we record an rd replay of a tracee which does a syscallbuf'ed
+        // `mprotect`, neither the replay nor its recording see the mprotect
+        // syscall, since it's untraced during both recording and replay. rd
+        // replay is notified of the syscall via the `mprotect_records`
+        // mechanism; if it's being recorded, it forwards that notification to
+        // the recorder by calling this syscall.
+        let tid = regs.arg1() as pid_t;
+        let addr: RemotePtr<Void> = regs.arg2().into();
+        let num_bytes: usize = regs.arg3();
+        let prot = regs.arg4_signed() as i32;
+        let prot_flags = ProtFlags::from_bits(prot).unwrap();
+        if tid == t.rec_tid {
+            t.vm_shr_ptr().protect(t, addr, num_bytes, prot_flags);
+        } else {
+            let maybe_target = t.session().find_task_from_rec_tid(tid);
+            ed_assert!(t, maybe_target.is_some());
+            let target = maybe_target.unwrap();
+            let target_ref = target.borrow();
+            target_ref
+                .vm_shr_ptr()
+                .protect(&**target_ref, addr, num_bytes, prot_flags);
+        }
+        return;
     }
 
-    if sys == Arch::MPROTECT {
+    if sys == Arch::MPROTECT || sys == Arch::PKEY_MPROTECT {
         let addr: RemotePtr<Void> = regs.arg1().into();
         let num_bytes: usize = regs.arg2();
         let prot = regs.arg3_signed() as i32;
@@ -1116,6 +1485,10 @@ fn on_syscall_exit_arch<Arch: Architecture>(t: &mut dyn Task, sys: i32, regs: &R
                 t.update_prname(t.regs_ref().arg2().into());
             }
 
+            PR_SET_CHILD_SUBREAPER => {
+                t.thread_group_mut().child_subreaper = t.regs_ref().arg2() != 0;
+            }
+
             _ => (),
         }
         return;
@@ -1150,10 +1523,26 @@ fn on_syscall_exit_arch<Arch: Architecture>(t: &mut dyn Task, sys: i32, regs: &R
         return;
     }
 
+    if sys == Arch::CLOSE_RANGE {
+        let flags = regs.arg3() as u32;
+        if flags & CLOSE_RANGE_UNSHARE != 0 {
+            unshare_fd_table(t);
+        }
+        // With CLOSE_RANGE_CLOEXEC the fds stay open until the next exec,
+        // where fds_to_close_after_exec() notices they are gone.
+        if flags & CLOSE_RANGE_CLOEXEC == 0 {
+            t.fd_table_shr_ptr().borrow_mut().did_close_range(
+                regs.arg1() as u32,
+                regs.arg2() as u32,
+                t,
+            );
+        }
+        return;
+    }
+
     if sys == Arch::UNSHARE {
         if regs.arg1() & CLONE_FILES as usize != 0 {
-            t.fd_table_mut().task_set_mut().erase(t.weak_self_ptr());
-            t.fds = Some(t.fd_table_shr_ptr().borrow().clone_into_task(t));
+            unshare_fd_table(t);
         }
         return;
     }
@@ -1177,6 +1566,28 @@ fn on_syscall_exit_arch<Arch: Architecture>(t: &mut dyn Task, sys: i32, regs: &R
         return;
     }
 
+    if sys == Arch::SENDFILE
+        || sys == Arch::SENDFILE64
+        || sys == Arch::SPLICE
+        || sys == Arch::COPY_FILE_RANGE
+    {
+        let fd: i32 = if sys == Arch::SENDFILE || sys == Arch::SENDFILE64 {
+            regs.arg1_signed() as i32
+        } else {
+            regs.arg3_signed() as i32
+        };
+        let amount: isize = regs.syscall_result_signed();
+        if amount > 0 {
+            let mut offset = LazyOffset::new(t, &regs, sys);
+            offset
+                .task_mut()
+                .fd_table_shr_ptr()
+                .borrow()
+                .did_zero_copy_write(fd, amount as usize, &mut offset);
+        }
+        return;
+    }
+
     if sys == Arch::PWRITEV || sys == Arch::WRITEV {
         let fd: i32 = regs.arg1_signed() as i32;
         let mut ranges: Vec<file_monitor::Range> = Vec::new();
@@ -1296,7 +1707,6 @@ pub(super) fn post_exec_for_exe<T: Task>(t: &mut T, exe_file: &OsStr) {
     t.session().post_exec(t);
 
     t.vm().task_set_mut().erase(t.weak_self_ptr());
-    t.fd_table_mut().task_set_mut().erase(t.weak_self_ptr());
 
     t.extra_registers = None;
     let mut e = t.extra_regs_ref().clone();
@@ -1306,21 +1716,44 @@ pub(super) fn post_exec_for_exe<T: Task>(t: &mut T, exe_file: &OsStr) {
     t.syscallbuf_child = RemotePtr::null();
     t.syscallbuf_size = 0;
     t.scratch_ptr = RemotePtr::null();
+    t.scratch_size = 0;
     t.cloned_file_data_fd_child = -1;
     t.stopping_breakpoint_table = RemoteCodePtr::null();
     t.stopping_breakpoint_table_entry_size = 0;
     t.preload_globals = None;
     t.thread_group_mut().execed = true;
     t.thread_areas_.clear();
+    t.properties.on_exec();
     t.thread_locals = [0u8; PRELOAD_THREAD_LOCALS_SIZE];
     let exec_count = t.vm().uid().exec_count() + 1;
     t.as_ = Some(t.session().create_vm(t, Some(exe_file), Some(exec_count)));
     // It's barely-documented, but Linux unshares the fd table on exec
-    t.fds = Some(t.fd_table_shr_ptr().borrow().clone_into_task(t));
+    unshare_fd_table(t);
     let prname = prname_from_exe_image(t.vm().exe_image());
     t.prname = prname.to_owned();
 }
 
+/// close_range() flags, from linux/close_range.h which older kernel headers lack.
+const CLOSE_RANGE_UNSHARE: u32 = 1 << 1;
+const CLOSE_RANGE_CLOEXEC: u32 = 1 << 2;
+
+/// Give `t` its own copy of its fd table if other tasks share it, as the
+/// kernel does for unshare(CLONE_FILES) and execve(). A table only `t` uses
+/// is kept as it is.
+fn unshare_fd_table(t: &mut dyn Task) {
+    let shared = t
+        .fd_table()
+        .task_set()
+        .iter_except(t.weak_self_ptr())
+        .next()
+        .is_some();
+    if !shared {
+        return;
+    }
+    t.fd_table_mut().task_set_mut().erase(t.weak_self_ptr());
+    t.fds = Some(t.fd_table_shr_ptr().borrow().clone_into_task(t));
+}
+
 fn prname_from_exe_image(exe_image: &OsStr) -> &OsStr {
     let len = exe_image.as_bytes().len();
     debug_assert!(len > 0);
@@ -1604,6 +2037,7 @@ pub(in super::super) fn clone_task_common(
 
     ref_t.open_mem_fd_if_needed();
     ref_t.thread_areas_ = clone_this.thread_areas_.clone();
+    ref_t.properties = clone_this.properties.clone_for_new_task(flags);
     if flags.contains(CloneFlags::CLONE_SET_TLS) {
         set_thread_area_from_clone(ref_t.as_mut(), tls);
     }
@@ -1806,7 +2240,9 @@ pub(super) fn destroy_buffers<T: Task>(t: &mut T) {
 pub(super) fn task_drop_common<T: Task>(t: &T) {
     log!(LogDebug, "task {} (rec:{}) is dying ...", t.tid, t.rec_tid);
 
-    t.fallible_ptrace(PTRACE_DETACH, RemotePtr::null(), PtraceData::None);
+    if !t.was_reaped {
+        t.fallible_ptrace(PTRACE_DETACH, RemotePtr::null(), PtraceData::None);
+    }
 
     if t.unstable.get() {
         log!(
@@ -1826,7 +2262,7 @@ pub(super) fn task_drop_common<T: Task>(t: &T) {
         ed_assert!(t, t.seen_ptrace_exit_event);
         ed_assert!(t, t.syscallbuf_child.is_null());
 
-        if t.thread_group().task_set().is_empty() && !t.session().is_recording() {
+        if t.thread_group().task_set().is_empty() && !t.session().is_recording() && !t.was_reaped {
             // Reap the zombie.
             let ret = unsafe { waitpid(t.thread_group().real_tgid, ptr::null_mut(), __WALL) };
             if ret == -1 {
@@ -1837,6 +2273,8 @@ pub(super) fn task_drop_common<T: Task>(t: &T) {
         }
     }
 
+    t.properties.on_exit();
+
     // Session Rc may be getting drop()-ed so we may not have access to it
     // via weak pointer
     t.try_session().map(|sess| sess.on_destroy_task(t.tuid()));
@@ -2,6 +2,7 @@ use crate::{
     arch::Architecture,
     bindings::ptrace::{
         PTRACE_CONT,
+        PTRACE_GET_THREAD_AREA,
         PTRACE_SET_THREAD_AREA,
         PTRACE_SINGLESTEP,
         PTRACE_SYSCALL,
@@ -16,8 +17,9 @@ use crate::{
     },
     remote_ptr::Void,
     session::address_space::{address_space::AddressSpace, MappingFlags},
+    wait_status::WaitStatus,
 };
-use std::{ffi::c_void, mem::size_of, ptr::NonNull};
+use std::{ffi::c_void, fmt, mem::size_of, ptr::NonNull};
 use task_inner::TaskInner;
 
 bitflags! {
@@ -108,6 +110,32 @@ pub struct TrapReasons {
     pub breakpoint: bool,
 }
 
+/// Why a ptrace request, or a syscall injected with `AutoRemoteSyscalls`,
+/// couldn't be completed. Apart from `UnexpectedStop` these are things the
+/// tracee can do to us at any time, so callers should give up on what they
+/// were doing to it rather than assert.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TraceeError {
+    /// The tracee died, or is dying. It will be reaped when the session
+    /// sees its exit.
+    TraceeDead,
+    /// A signal we can't ignore stopped the tracee before an injected
+    /// syscall completed.
+    Interrupted,
+    /// The tracee stopped in some other way we didn't expect.
+    UnexpectedStop { status: WaitStatus },
+}
+
+impl fmt::Display for TraceeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceeError::TraceeDead => write!(f, "tracee died"),
+            TraceeError::Interrupted => write!(f, "interrupted by a signal"),
+            TraceeError::UnexpectedStop { status } => write!(f, "unexpected status {}", status),
+        }
+    }
+}
+
 pub mod task_inner {
     use super::*;
     use crate::{
@@ -159,6 +187,7 @@ pub mod task_inner {
         kernel_supplement::PTRACE_EVENT_SECCOMP_OBSOLETE,
         log::LogLevel::{LogDebug, LogWarn},
         perf_counters::PerfCounters,
+        property_table::PropertyTable,
         rd::{RD_MAGIC_SAVE_DATA_FD, RD_RESERVED_ROOT_DIR_FD, RD_RESERVED_SOCKET_FD},
         registers::Registers,
         remote_code_ptr::RemoteCodePtr,
@@ -174,15 +203,20 @@ pub mod task_inner {
                 WatchType,
             },
             session_inner::session_inner::SessionInner,
+            spawn_error::{SpawnError, SpawnStep},
             task::{task_common::set_thread_area_core, Task, TaskSharedPtr, TaskSharedWeakPtr},
             Session,
             SessionSharedPtr,
             SessionSharedWeakPtr,
         },
-        taskish_uid::TaskUid,
+        taskish_uid::{FdTableUid, TaskUid},
         thread_group::{ThreadGroupRef, ThreadGroupRefMut, ThreadGroupSharedPtr},
         ticks::Ticks,
-        trace::{trace_frame::FrameTime, trace_stream::TraceStream},
+        trace::{
+            trace_frame::FrameTime,
+            trace_inherited_state::PERSONALITY_QUERY,
+            trace_stream::TraceStream,
+        },
         util::{
             choose_cpu,
             has_effective_caps,
@@ -204,8 +238,10 @@ pub mod task_inner {
     use libc::{
         __errno_location,
         _exit,
+        c_ulong,
         fork,
         iovec,
+        personality,
         pid_t,
         prctl,
         syscall,
@@ -231,14 +267,13 @@ pub mod task_inner {
     };
     use nix::{
         errno::{errno, Errno},
-        fcntl::{fcntl, open, readlink, FcntlArg, OFlag},
+        fcntl::{fcntl, readlink, FcntlArg, OFlag},
         sys::{
             signal::{kill, sigaction, signal, SaFlags, SigAction, SigHandler, SigSet, Signal},
             socket::{socketpair, AddressFamily, SockFlag, SockType},
-            stat::{lstat, stat, FileStat, Mode},
+            stat::{lstat, stat, FileStat},
         },
-        unistd::{dup2, execve, getpid, getuid, setsid, Pid},
-        Error,
+        unistd::{execve, getpid, getuid, setsid, Pid},
     };
     use owning_ref::OwningHandle;
     use rand::random;
@@ -267,6 +302,9 @@ pub mod task_inner {
     const NUM_X86_DEBUG_REGS: usize = 8;
     const NUM_X86_WATCHPOINTS: usize = 4;
 
+    /// From <linux/personality.h>.
+    const ADDR_NO_RANDOMIZE: c_ulong = 0x0040000;
+
     #[derive(Copy, Clone, Debug)]
     pub enum PtraceData {
         WriteInto(*mut [u8]),
@@ -399,6 +437,10 @@ pub mod task_inner {
         /// Where we last resumed execution
         pub(in super::super::super) address_of_last_execution_resume: RemoteCodePtr,
         pub(in super::super::super) how_last_execution_resumed: ResumeRequest,
+        /// Set when we resumed with PTRACE_SYSCALL in place of an unsupported
+        /// PTRACE_SYSEMU*. If that produced a syscall-entry stop, the syscall
+        /// must be skipped rather than executed when the task is next resumed.
+        pub(in super::super::super) sysemu_fallback_pending: bool,
         /// In certain circumstances, due to hardware bugs, we need to fudge the
         /// cx register. If so, we record the orginal value here. See comments in
         /// Task.cc
@@ -434,6 +476,8 @@ pub mod task_inner {
         /// (when that's a user_desc). May be more than one due to different
         /// entry_numbers.
         pub(in super::super::super) thread_areas_: Vec<user_desc>,
+        /// Extension state attached by other subsystems. See PropertyTable.
+        pub(in super::super::super) properties: PropertyTable,
         /// The `stack` argument passed to `clone()`, which for
         /// "threads" is the top of the user-allocated stack.
         pub(in super::super::super) top_of_stack: RemotePtr<Void>,
@@ -446,6 +490,11 @@ pub mod task_inner {
         /// True when a PTRACE_EXIT_EVENT has been observed in the wait_status
         /// for this task.
         pub(in super::super::super) seen_ptrace_exit_event: bool,
+        /// True when we learned of this task's death from its exit status
+        /// rather than a PTRACE_EVENT_EXIT, so our waitpid() has already reaped
+        /// it. Its tid may be reused and must not be ptraced, signalled or
+        /// waited for again.
+        pub(in super::super::super) was_reaped: bool,
         /// A counter for the number of stops for which the stop may have been caused
         /// by PTRACE_INTERRUPT. See description in do_waitpid
         pub(in super::super::super) expecting_ptrace_interrupt_stop: u32,
@@ -466,6 +515,9 @@ pub mod task_inner {
     pub struct CapturedState {
         pub ticks: Ticks,
         pub regs: Registers,
+        /// Includes PKRU, see `ExtraRegisters::read_pkru()`. Which
+        /// protection keys are allocated and which pages they guard belong to
+        /// the address space, which checkpoints copy by forking.
         pub extra_regs: ExtraRegisters,
         pub prname: OsString,
         pub thread_areas: Vec<user_desc>,
@@ -480,6 +532,7 @@ pub mod task_inner {
         pub thread_locals: ThreadLocals,
         pub rec_tid: pid_t,
         pub serial: u32,
+        pub fd_table_uid: FdTableUid,
         pub desched_fd_child: i32,
         pub cloned_file_data_fd_child: i32,
         pub wait_status: WaitStatus,
@@ -604,8 +657,7 @@ pub mod task_inner {
 
         /// Open `fd` in the context of this task's fd table.
         pub fn open_fd(&self, fd: i32, flags: OFlag) -> ScopedFd {
-            let path = format!("/proc/{}/fd/{}", self.tid, fd);
-            ScopedFd::open_path(path.as_str(), flags)
+            ScopedFd::open_proc(self.tid, &format!("fd/{}", fd), flags)
         }
 
         /// Get the name of the file referenced by `fd` in the context of this
@@ -1063,12 +1115,50 @@ pub mod task_inner {
 
         /// Get the thread area from the remote process.
         /// Returns 0 on success, errno otherwise.
-        pub fn emulate_get_thread_area(&self, _idx: i32, _desc: &mut user_desc) -> i32 {
-            unimplemented!()
+        pub fn emulate_get_thread_area(&self, idx: u32, desc: &mut user_desc) -> i32 {
+            log!(LogDebug, "Emulating PTRACE_GET_THREAD_AREA");
+            unsafe { Errno::clear() };
+            self.fallible_ptrace(
+                PTRACE_GET_THREAD_AREA,
+                RemotePtr::from(idx as usize),
+                PtraceData::WriteInto(u8_raw_slice_mut(desc)),
+            );
+            errno()
+        }
+
+        pub fn properties(&self) -> &PropertyTable {
+            &self.properties
         }
 
         pub fn thread_areas(&self) -> Vec<user_desc> {
-            unimplemented!()
+            self.thread_areas_.clone()
+        }
+
+        /// Install `areas` into the OS task with PTRACE_SET_THREAD_AREA.
+        /// Used when a task has to be made to look like another task whose
+        /// thread areas were set by syscalls we did not replay in this task
+        /// e.g. after os_clone_into()/os_fork_into().
+        pub fn restore_thread_areas(&mut self, areas: &[user_desc]) {
+            for desc in areas {
+                let err = self.emulate_set_thread_area(desc.entry_number, *desc);
+                ed_assert!(
+                    self,
+                    err == 0,
+                    "Failed to restore thread area {}: {}",
+                    desc.entry_number,
+                    errno_name(err)
+                );
+            }
+        }
+
+        /// The thread area a 32-bit tracee's %gs currently selects, if any.
+        /// This is what makes `__thread` variables work for x86 pthreads.
+        pub fn tls_thread_area(&self) -> Option<user_desc> {
+            let entry_number = self.regs_ref().tls_entry_number()?;
+            self.thread_areas_
+                .iter()
+                .find(|desc| desc.entry_number == entry_number)
+                .copied()
         }
 
         pub fn set_status(&mut self, status: WaitStatus) {
@@ -1228,16 +1318,6 @@ pub mod task_inner {
             false
         }
 
-        /// Lock or unlock the syscallbuf to prevent the preload library from using it.
-        /// Only has an effect if the syscallbuf has been initialized.
-        pub fn set_syscallbuf_locked(&self, _locked: bool) {
-            if self.syscallbuf_child.is_null() {
-                return;
-            }
-
-            unimplemented!()
-        }
-
         /// Like `fallible_ptrace()` but infallible for most purposes.
         /// Errors other than ESRCH are treated as fatal. Returns false if
         /// we got ESRCH. This can happen any time during recording when the
@@ -1390,6 +1470,7 @@ pub mod task_inner {
                 ticks: 0,
                 registers: Registers::new(a),
                 how_last_execution_resumed: ResumeRequest::ResumeCont,
+                sysemu_fallback_pending: false,
                 last_resume_orig_cx: 0,
                 did_set_breakpoint_after_cpuid: false,
                 is_stopped: false,
@@ -1400,6 +1481,7 @@ pub mod task_inner {
                 session_: session.weak_self.clone(),
                 top_of_stack: Default::default(),
                 seen_ptrace_exit_event: false,
+                was_reaped: false,
                 thread_locals: array_init::array_init(|_| 0),
                 expecting_ptrace_interrupt_stop: 0,
                 // DIFF NOTE: These are not explicitly set in rr
@@ -1411,6 +1493,7 @@ pub mod task_inner {
                 singlestepping_instruction: TrappedInstruction::None,
                 tg: Default::default(),
                 thread_areas_: vec![],
+                properties: PropertyTable::new(),
                 wait_status: Default::default(),
                 pending_siginfo: Default::default(),
                 weak_self: Weak::new(),
@@ -1423,25 +1506,6 @@ pub mod task_inner {
             unimplemented!()
         }
 
-        /// Grab state from this task into a structure that we can use to
-        /// initialize a new task via os_clone_into/os_fork_into and copy_state.
-        pub(in super::super::super) fn capture_state(&self) -> CapturedState {
-            unimplemented!()
-        }
-
-        /// Make this task look like an identical copy of the task whose state
-        /// was captured by capture_task_state(), in
-        /// every way relevant to replay.  This task should have been
-        /// created by calling os_clone_into() or os_fork_into(),
-        /// and if it wasn't results are undefined.
-        ///
-        /// Some task state must be copied into this by injecting and
-        /// running syscalls in this task.  Other state is metadata
-        /// that can simply be copied over in local memory.
-        pub(in super::super::super) fn copy_state(&mut self, _stat: &CapturedState) {
-            unimplemented!()
-        }
-
         /// Make the ptrace `request` with `addr` and `data`, return
         /// the ptrace return value.
         pub(in super::super::super) fn fallible_ptrace(
@@ -1463,9 +1527,34 @@ pub mod task_inner {
             addr: RemotePtr<Void>,
             data: PtraceData,
         ) {
+            if let Err(e) = self.try_xptrace(request, addr, data) {
+                ed_assert!(
+                    self,
+                    false,
+                    "ptrace({}, {}, addr={}) failed: {}",
+                    ptrace_req_name(request),
+                    self.tid,
+                    addr,
+                    e
+                );
+            }
+        }
+
+        /// Like `xptrace()`, but returns `TraceeError::TraceeDead` rather than
+        /// asserting if the tracee has died. Any other failure is still fatal.
+        pub(in super::super::super) fn try_xptrace(
+            &self,
+            request: u32,
+            addr: RemotePtr<Void>,
+            data: PtraceData,
+        ) -> Result<(), TraceeError> {
             unsafe { Errno::clear() };
             self.fallible_ptrace(request, addr, data);
             let errno = errno();
+            if errno == libc::ESRCH {
+                log!(LogDebug, "ptrace tid {} was not alive", self.tid);
+                return Err(TraceeError::TraceeDead);
+            }
             ed_assert!(
                 self,
                 errno == 0,
@@ -1476,6 +1565,7 @@ pub mod task_inner {
                 data.get_data_slice(),
                 errno
             );
+            Ok(())
         }
 
         /// Read tracee memory using PTRACE_PEEKDATA calls. Slow, only use
@@ -1595,23 +1685,6 @@ pub mod task_inner {
             unimplemented!()
         }
 
-        /// Make the OS-level calls to create a new fork or clone that
-        /// will eventually be a copy of this task and return that Task
-        /// metadata.  These methods are used in concert with
-        /// `Task::copy_state()` to create task copies during
-        /// checkpointing.
-        ///
-        /// For `os_fork_into()`, `session` will be tracking the
-        /// returned fork child.
-        ///
-        /// For `os_clone_into()`, `task_leader` is the "main thread"
-        /// in the process into which the copy of this task will be
-        /// created.  `task_leader` will perform the actual OS calls to
-        /// create the new child.
-        pub(in super::super::super) fn os_fork_into(&self, _session: &dyn Session) -> &TaskInner {
-            unimplemented!()
-        }
-
         /// Return the TraceStream that we're using, if in recording or replay.
         /// Returns `None` if we're not in record or replay.
         pub(in super::super::super) fn trace_stream(
@@ -1633,28 +1706,6 @@ pub mod task_inner {
             Some(owning_handle)
         }
 
-        /// Make the OS-level calls to clone `parent` into `session`
-        /// and return the resulting Task metadata for that new
-        /// process.  This is as opposed to `Task::clone()`, which only
-        /// attaches Task metadata to an /existing/ process.
-        ///
-        /// The new clone will be tracked in `session`.  The other
-        /// arguments are as for `Task::clone()` above.
-        pub(in super::super::super) fn os_clone(
-            _reason: CloneReason,
-            _session: &dyn Session,
-            _remote: &AutoRemoteSyscalls,
-            _rec_child_tid: pid_t,
-            _new_serial: u32,
-            _base_flags: u32,
-            _stack: RemotePtr<Void>,
-            _ptid: RemotePtr<i32>,
-            _tls: RemotePtr<Void>,
-            _ctid: RemotePtr<i32>,
-        ) {
-            unimplemented!()
-        }
-
         /// Fork and exec the initial task. If something goes wrong later
         /// (i.e. an exec does not occur before an exit), an error may be
         /// readable from the other end of the pipe whose write end is error_fd.
@@ -1814,8 +1865,8 @@ pub mod task_inner {
                 let mut hint = String::new();
                 if errno() == EPERM {
                     hint = format!(
-                        "; child probably died before reaching SIGSTOP\nChild's message: {:?}",
-                        session.read_spawned_task_error()
+                        "; child probably died before reaching SIGSTOP{}",
+                        session.spawned_task_error_message()
                     );
                 }
                 fatal!("PTRACE_SEIZE failed for tid `{}`{}", tid, hint);
@@ -1830,8 +1881,8 @@ pub mod task_inner {
             wrapped_t.borrow_mut().tg = Some(tg);
             let addr_space = session.create_vm(wrapped_t.borrow_mut().as_mut(), None, None);
             wrapped_t.borrow_mut().as_ = Some(addr_space);
-            let weak_t_ptr = wrapped_t.borrow().weak_self.clone();
-            wrapped_t.borrow_mut().fds = Some(FdTable::create(weak_t_ptr));
+            let fds = FdTable::create(wrapped_t.borrow_mut().as_mut());
+            wrapped_t.borrow_mut().fds = Some(fds);
             {
                 let mut ref_task = wrapped_t.borrow_mut();
                 let fds: FdTableSharedPtr = ref_task.fds.as_ref().unwrap().clone();
@@ -1852,8 +1903,8 @@ pub mod task_inner {
                 t.wait(None);
                 if t.maybe_ptrace_event() == PTRACE_EVENT_EXIT {
                     fatal!(
-                        "Tracee died before reaching SIGSTOP\nChild's message: {:?}",
-                        session.read_spawned_task_error()
+                        "Tracee died before reaching SIGSTOP{}",
+                        session.spawned_task_error_message()
                     );
                 }
                 // SIGSTOP can be reported as a signal-stop or group-stop depending on
@@ -1862,9 +1913,9 @@ pub mod task_inner {
                     && t.status().maybe_group_stop_sig() != SIGSTOP
                 {
                     fatal!(
-                        "Unexpected stop {}\n Child's message: {:?}",
+                        "Unexpected stop {}{}",
                         t.status(),
-                        session.read_spawned_task_error()
+                        session.spawned_task_error_message()
                     );
                 }
 
@@ -1874,8 +1925,13 @@ pub mod task_inner {
             wrapped_t
         }
 
-        pub(in super::super::super) fn preload_thread_locals(&self) -> &mut u8 {
-            unimplemented!()
+        /// This task's preload_thread_locals, where rd has the area mapped, or
+        /// `None` if the tracee has unmapped it. The area is shared by all the
+        /// tasks in the address space, so this makes our locals the active ones
+        /// first.
+        pub(in super::super::super) fn preload_thread_locals(&mut self) -> Option<NonNull<u8>> {
+            self.activate_preload_thread_locals(None);
+            preload_thread_locals_local_addr(&self.vm()).map(|addr| addr.cast::<u8>())
         }
     }
 
@@ -1917,38 +1973,28 @@ pub mod task_inner {
 
         CPUIDBugDetector::run_detection_code();
 
-        match execve(exe_path_cstr, argv_array, envp_array) {
-            Err(Error::Sys(Errno::ENOENT)) => {
-                spawned_child_fatal_error(
-                    error_fd,
-                    &format!(
-                        "execve failed: '{:?}' (or interpreter) not found",
-                        exe_path_cstr
-                    ),
-                );
-            }
-            _ => {
-                spawned_child_fatal_error(
-                    error_fd,
-                    &format!("execve of '{:?}' failed", exe_path_cstr),
-                );
-            }
-        }
-
-        // Never returns!
+        // Only returns if it failed.
+        execve(exe_path_cstr, argv_array, envp_array).unwrap_or_else(|_| {
+            spawned_child_fatal_error(error_fd, SpawnStep::Exec, &exe_path_cstr.to_string_lossy());
+            unreachable!()
+        });
     }
 
+    /// The filter installed in spawned tracees, see
+    /// `SeccompFilter::for_recording()`. A 64-bit rd can record 32-bit
+    /// tracees, and 64-bit tracees can make 32-bit syscalls, so both
+    /// conventions are covered there.
     fn create_seccomp_filter() -> SeccompFilter {
-        let mut f = SeccompFilter::new();
-        for e in AddressSpace::rd_page_syscalls() {
-            if e.traced == Traced::Untraced {
-                let ip =
-                    AddressSpace::rd_page_syscall_exit_point(e.traced, e.privileged, e.enabled);
-                f.allow_syscalls_from_callsite(ip);
-            }
-        }
-        f.trace();
-        f
+        let arches: &[SupportedArch] = match RD_NATIVE_ARCH {
+            SupportedArch::X64 => &[SupportedArch::X64, SupportedArch::X86],
+            SupportedArch::X86 => &[SupportedArch::X86],
+        };
+        let callsites: Vec<RemoteCodePtr> = AddressSpace::rd_page_syscalls()
+            .iter()
+            .filter(|e| e.traced == Traced::Untraced)
+            .map(|e| AddressSpace::rd_page_syscall_exit_point(e.traced, e.privileged, e.enabled))
+            .collect();
+        SeccompFilter::for_recording(arches, &callsites)
     }
 
     // This function doesn't really need to do anything. The signal will cause
@@ -1988,20 +2034,32 @@ pub mod task_inner {
         // ...
         restore_initial_resource_limits();
 
-        // CLOEXEC so that the original fd here will be closed by the exec that's
-        // about to happen.
-        let maybe_fd_magic = open(
-            "/dev/null",
-            OFlag::O_WRONLY | OFlag::O_CLOEXEC,
-            Mode::empty(),
-        );
-        if maybe_fd_magic.is_err() {
-            spawned_child_fatal_error(err_fd, "error opening /dev/null");
+        if let Some(replay) = session.as_replay() {
+            // The trace is already open, so this doesn't allocate.
+            if let Some(state) = replay.trace_reader().inherited_state() {
+                state.apply();
+            }
+        }
+
+        // Run the initial exec with a predictable address space layout (the
+        // kernel picks the same stack, vdso, mmap base and brk every time).
+        // The resulting mappings are recorded as part of the exec and replay
+        // maps everything MAP_FIXED at the recorded addresses, so this makes it
+        // much less likely that those addresses are unavailable in replay.
+        // Inherited by all descendants.
+        let persona = unsafe { personality(PERSONALITY_QUERY) };
+        if persona == -1 || 0 > unsafe { personality(persona as c_ulong | ADDR_NO_RANDOMIZE) } {
+            spawned_child_fatal_error(err_fd, SpawnStep::Personality, "");
+        }
+
+        // ScopedFd is CLOEXEC so that the original fd here will be closed by the
+        // exec that's about to happen.
+        let fd_magic = ScopedFd::open_path("/dev/null", OFlag::O_WRONLY);
+        if !fd_magic.is_open() {
+            spawned_child_fatal_error(err_fd, SpawnStep::OpenDevNull, "");
         }
-        let fd_magic = maybe_fd_magic.unwrap();
-        let maybe_dup_magic = dup2(fd_magic, RD_MAGIC_SAVE_DATA_FD);
-        if maybe_dup_magic.is_err() || RD_MAGIC_SAVE_DATA_FD != maybe_dup_magic.unwrap() {
-            spawned_child_fatal_error(err_fd, "error duping to RD_MAGIC_SAVE_DATA_FD");
+        if fd_magic.dup_over(RD_MAGIC_SAVE_DATA_FD).is_err() {
+            spawned_child_fatal_error(err_fd, SpawnStep::DupMagicSaveDataFd, "");
         }
 
         // If we're running under rd then don't try to set up RD_RESERVED_ROOT_DIR_FD;
@@ -2009,33 +2067,24 @@ pub mod task_inner {
         // which would be crazy ... though we could fix it by dynamically
         // assigning RR_RESERVED_ROOT_DIR_FD.)
         if !running_under_rd() {
-            // CLOEXEC so that the original fd here will be closed by the exec that's
-            // about to happen.
-            let maybe_fd_root = open(
-                "/",
-                OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
-                Mode::empty(),
-            );
-            if maybe_fd_root.is_err() {
-                spawned_child_fatal_error(err_fd, "error opening root directory");
+            let fd_root = ScopedFd::open_path("/", OFlag::O_PATH | OFlag::O_DIRECTORY);
+            if !fd_root.is_open() {
+                spawned_child_fatal_error(err_fd, SpawnStep::OpenRootDir, "");
             }
-            let maybe_dup_reserved = dup2(maybe_fd_root.unwrap(), RD_RESERVED_ROOT_DIR_FD);
-            if maybe_dup_reserved.is_err() || RD_RESERVED_ROOT_DIR_FD != maybe_dup_reserved.unwrap()
-            {
-                spawned_child_fatal_error(err_fd, "error duping to RD_RESERVED_ROOT_DIR_FD");
+            if fd_root.dup_over(RD_RESERVED_ROOT_DIR_FD).is_err() {
+                spawned_child_fatal_error(err_fd, SpawnStep::DupReservedRootDirFd, "");
             }
         }
 
-        let maybe_dup_sock_fd = dup2(sock_fd.as_raw(), sock_fd_number);
-        if maybe_dup_sock_fd.is_err() || sock_fd_number != maybe_dup_sock_fd.unwrap() {
-            spawned_child_fatal_error(err_fd, "error duping to RD_RESERVED_SOCKET_FD");
+        if sock_fd.dup_over(sock_fd_number).is_err() {
+            spawned_child_fatal_error(err_fd, SpawnStep::DupSocketFd, "");
         }
 
         if session.is_replaying() {
             // This task and all its descendants should silently reap any terminating
             // children.
             if unsafe { signal(Signal::SIGCHLD, SigHandler::SigIgn) }.is_err() {
-                spawned_child_fatal_error(err_fd, "error doing signal()");
+                spawned_child_fatal_error(err_fd, SpawnStep::IgnoreSigchld, "");
             }
 
             // If the rd process dies, prevent runaway tracee processes
@@ -2043,7 +2092,7 @@ pub mod task_inner {
             //
             // TODO: this isn't inherited across fork().
             if 0 > unsafe { prctl(PR_SET_PDEATHSIG, SIGKILL) } {
-                spawned_child_fatal_error(err_fd, "Couldn't set parent-death signal");
+                spawned_child_fatal_error(err_fd, SpawnStep::ParentDeathSignal, "");
             }
 
             // Put the replaying processes into their own session. This will stop
@@ -2057,7 +2106,7 @@ pub mod task_inner {
         // That allows rd to record the tsc and replay it
         // deterministically.
         if 0 > unsafe { prctl(PR_SET_TSC, PR_TSC_SIGSEGV, 0, 0, 0) } {
-            spawned_child_fatal_error(err_fd, "error setting up prctl");
+            spawned_child_fatal_error(err_fd, SpawnStep::TrapRdtsc, "");
         }
 
         // If we're in setuid_sudo mode, we have CAP_SYS_ADMIN, so we don't need to
@@ -2066,19 +2115,16 @@ pub mod task_inner {
         // no_new_privs
         if !session.is_recording() || !has_effective_caps(1 << CAP_SYS_ADMIN) {
             if 0 > unsafe { prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } {
-                spawned_child_fatal_error(
-                    err_fd,
-                    "prctl(NO_NEW_PRIVS) failed, SECCOMP_FILTER is not available: your\n\
-           kernel is too old. Use `record -n` to disable the filter.",
-                );
+                spawned_child_fatal_error(err_fd, SpawnStep::NoNewPrivs, "");
             }
         }
     }
 
-    fn spawned_child_fatal_error(err_fd: &ScopedFd, msg: &str) {
-        write_all(err_fd.as_raw(), msg.as_bytes());
-        let errno_name = format!(" ({}) ", errno_name(errno()));
-        write_all(err_fd.as_raw(), errno_name.as_bytes());
+    /// Tell rd which `step` of setting up the tracee failed, with the current
+    /// errno, and exit. See `spawn_error`.
+    fn spawned_child_fatal_error(err_fd: &ScopedFd, step: SpawnStep, detail: &str) {
+        let line = SpawnError::line(step, errno(), detail);
+        write_all(err_fd.as_raw(), line.as_bytes());
         unsafe { _exit(1) };
     }
 
@@ -2090,11 +2136,7 @@ pub mod task_inner {
         // Note: the filter is installed only for record. This call
         // will be emulated (not passed to the kernel) in the replay. */
         if 0 > unsafe { prctl(PR_SET_SECCOMP, SECCOMP_MODE_FILTER, prog as *const _, 0, 0) } {
-            spawned_child_fatal_error(
-                err_fd,
-                "prctl(SECCOMP) failed, SECCOMP_FILTER is not available: your\n\
-kernel is too old.",
-            );
+            spawned_child_fatal_error(err_fd, SpawnStep::Seccomp, "");
         }
         // anything that happens from this point on gets filtered!
     }
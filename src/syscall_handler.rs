@@ -0,0 +1,49 @@
+//! Handlers for syscalls that rd doesn't know how to replay itself, e.g. the
+//! ioctls of a proprietary driver that write to tracee memory in ways rd
+//! can't infer from the arguments. A handler is registered for a syscall
+//! number of one architecture and is consulted before rd's own handling of
+//! that syscall, which it replaces. Register handlers before the
+//! ReplaySession is created.
+
+use crate::{
+    kernel_abi::SupportedArch,
+    session::{replay_session::ReplayTraceStep, task::replay_task::ReplayTask},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+pub trait SyscallHandler: Send + Sync {
+    /// `t` is at the exit of the syscall during replay. The recorded
+    /// registers and memory are restored after this returns, so only
+    /// side effects beyond those (e.g. on rd's view of the address space)
+    /// need to be handled here.
+    fn replay(&self, _t: &mut ReplayTask, _step: &mut ReplayTraceStep) {}
+}
+
+lazy_static! {
+    static ref SYSCALL_HANDLERS: Mutex<HashMap<(SupportedArch, i32), Arc<dyn SyscallHandler>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Use `handler` for syscall `syscallno` of `arch`, instead of rd's own
+/// handling. Replaces any handler registered for it before.
+pub fn register_syscall_handler(
+    arch: SupportedArch,
+    syscallno: i32,
+    handler: Arc<dyn SyscallHandler>,
+) {
+    SYSCALL_HANDLERS
+        .lock()
+        .unwrap()
+        .insert((arch, syscallno), handler);
+}
+
+pub fn syscall_handler(arch: SupportedArch, syscallno: i32) -> Option<Arc<dyn SyscallHandler>> {
+    SYSCALL_HANDLERS
+        .lock()
+        .unwrap()
+        .get(&(arch, syscallno))
+        .cloned()
+}
@@ -1,4 +1,5 @@
 use crate::{
+    fd_table::FdTable,
     session::{address_space::address_space::AddressSpace, task::Task},
     thread_group::ThreadGroup,
 };
@@ -141,6 +142,9 @@ impl Deref for AddressSpaceUid {
 
 pub type TaskUid = TaskishUid<Box<dyn Task>>;
 pub type ThreadGroupUid = TaskishUid<ThreadGroup>;
+/// The tid of the task that created the fd table and a serial from
+/// `SessionInner::next_fd_table_serial()`.
+pub type FdTableUid = TaskishUid<FdTable>;
 
 #[cfg(test)]
 mod test {
@@ -43,6 +43,10 @@ pub struct ThreadGroup {
     /// couldn't push a signal handler frame. Only used during recording.
     pub received_sigframe_sigsegv: bool,
 
+    /// Set by prctl(PR_SET_CHILD_SUBREAPER). Orphaned descendants are
+    /// reparented to the closest such ancestor.
+    pub child_subreaper: bool,
+
     /// private fields
     /// In rr, nullptr is used to indicate no session.
     /// However, in rd we always assume there is a session.
@@ -61,8 +65,15 @@ pub struct ThreadGroup {
 
 impl Drop for ThreadGroup {
     fn drop(&mut self) {
+        // Like the kernel, hand our children to the closest child subreaper
+        // among our ancestors. Without one they are inherited by init (rd
+        // isn't a subreaper), which isn't a tracee.
+        let new_parent = self.closest_child_subreaper();
         for tg in self.children() {
-            tg.borrow_mut().parent_ = None;
+            tg.borrow_mut().parent_ = new_parent.as_ref().map(Rc::downgrade);
+            if let Some(p) = &new_parent {
+                p.borrow_mut().children_mut().insert(Rc::downgrade(&tg));
+            }
         }
         match &self.parent_ {
             Some(parent) => {
@@ -112,6 +123,7 @@ impl ThreadGroup {
             dumpable: true,
             execed: false,
             received_sigframe_sigsegv: false,
+            child_subreaper: false,
             session_: session.clone(),
             parent_: maybe_parent,
             serial,
@@ -244,6 +256,43 @@ impl ThreadGroup {
         &mut self.children_
     }
 
+    /// True if `ancestor` is this thread group or one of its ancestors.
+    pub fn is_descendant_of(&self, ancestor: ThreadGroupUid) -> bool {
+        if self.tguid() == ancestor {
+            return true;
+        }
+        let mut maybe_tg = self.parent();
+        while let Some(tg) = maybe_tg {
+            if tg.borrow().tguid() == ancestor {
+                return true;
+            }
+            maybe_tg = tg.borrow().parent();
+        }
+        false
+    }
+
+    /// All the thread groups below this one in the process tree, each one
+    /// before its own descendants.
+    pub fn descendants(&self) -> Vec<ThreadGroupSharedPtr> {
+        let mut result = Vec::new();
+        for child in self.children() {
+            result.push(child.clone());
+            result.extend(child.borrow().descendants());
+        }
+        result
+    }
+
+    fn closest_child_subreaper(&self) -> Option<ThreadGroupSharedPtr> {
+        let mut maybe_tg = self.parent();
+        while let Some(tg) = maybe_tg {
+            if tg.borrow().child_subreaper {
+                return Some(tg);
+            }
+            maybe_tg = tg.borrow().parent();
+        }
+        None
+    }
+
     pub fn tguid(&self) -> ThreadGroupUid {
         ThreadGroupUid::new_with(self.tgid, self.serial)
     }
@@ -1,7 +1,14 @@
 pub mod compressed_reader;
 pub mod compressed_writer;
+pub mod trace_encryption;
+pub mod trace_export;
 pub mod trace_frame;
+pub mod trace_inherited_state;
+pub mod trace_intel_pt;
+pub mod trace_mark;
 pub mod trace_reader;
+pub mod trace_rename;
 pub mod trace_stream;
 pub mod trace_task_event;
+pub mod trace_verify;
 pub mod trace_writer;
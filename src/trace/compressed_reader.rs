@@ -1,6 +1,6 @@
 use crate::{
     scoped_fd::{ScopedFd, ScopedFdSharedPtr},
-    trace::compressed_writer::BlockHeader,
+    trace::{compressed_writer::BlockHeader, trace_encryption::TraceKey},
     util::read_to_end,
 };
 use brotli_sys::{BrotliDecoderDecompress, BROTLI_DECODER_RESULT_SUCCESS};
@@ -33,6 +33,8 @@ pub struct CompressedReader {
     eof: bool,
     buffer: Vec<u8>,
     buffer_read_pos: usize,
+    /// Decrypts each block before it is decompressed, for encrypted traces.
+    key: Option<TraceKey>,
     // Note that the struct members for saving state are not here as we have a separate struct
     // to handle that
 }
@@ -126,9 +128,14 @@ impl CompressedReader {
             eof,
             buffer: Vec::new(),
             buffer_read_pos,
+            key: None,
         }
     }
 
+    pub fn set_key(&mut self, key: Option<TraceKey>) {
+        self.key = key;
+    }
+
     pub fn at_end(&self) -> bool {
         self.eof && self.buffer_read_pos == self.buffer.len()
     }
@@ -267,6 +274,18 @@ impl CompressedReader {
             Err(e) => return Err(io::Error::new(ErrorKind::Other, e)),
         };
 
+        if let Some(key) = &self.key {
+            compressed_buf = match key.decrypt(&compressed_buf) {
+                Some(decrypted) => decrypted,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Could not decrypt block in CompressedReader; the trace is corrupted",
+                    ))
+                }
+            };
+        }
+
         self.buffer.resize(header.uncompressed_length as usize, 0);
         self.buffer_read_pos = 0;
         if !do_decompress(compressed_buf.as_slice(), &mut self.buffer) {
@@ -1,4 +1,4 @@
-use crate::{scoped_fd::ScopedFd, util::write_all};
+use crate::{scoped_fd::ScopedFd, trace::trace_encryption::TraceKey, util::write_all};
 use brotli_sys::{
     BrotliEncoderCompressStream,
     BrotliEncoderCreateInstance,
@@ -12,7 +12,7 @@ use nix::{fcntl::OFlag, sys::stat::Mode, unistd::fsync};
 use std::{
     cmp::min,
     convert::TryInto,
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
     io::{Error, ErrorKind, Result, Write},
     mem::size_of,
     ptr,
@@ -53,10 +53,19 @@ pub struct BlockHeader {
 /// 'write'. The producer thread may block in 'write' if 'buffer_size' bytes are
 /// being compressed.
 ///
-/// Each data block is compressed independently using brotli.
+/// Each data block is compressed independently using brotli. If we have a
+/// `TraceKey` each compressed block is then encrypted, and the block header's
+/// `compressed_length` is the length of the encrypted data.
+///
+/// If created with `new_with_checksums()`, the `block_checksum()` of each
+/// block is appended to a second file (see `checksums_path()`) just before
+/// the block itself is written, so the checksums of everything written so
+/// far survive a crash.
 pub struct CompressedWriter {
     /// Immutable while threads are running
     fd: ScopedFd,
+    /// Closed if we aren't writing block checksums.
+    checksum_fd: ScopedFd,
     block_size: usize,
     mutex: Arc<Mutex<CompressedWriterData>>,
     cond_var: Arc<Condvar>,
@@ -96,7 +105,12 @@ impl CompressedWriter {
     pub fn good(&self) -> bool {
         self.error
     }
-    pub fn new(filename: &OsStr, block_size: usize, num_threads: usize) -> CompressedWriter {
+    pub fn new(
+        filename: &OsStr,
+        block_size: usize,
+        num_threads: usize,
+        key: Option<TraceKey>,
+    ) -> CompressedWriter {
         let fd = ScopedFd::open_path_with_mode(
             filename,
             OFlag::O_CLOEXEC
@@ -106,6 +120,42 @@ impl CompressedWriter {
                 | OFlag::O_LARGEFILE,
             Mode::S_IRUSR,
         );
+        Self::with_output(fd, ScopedFd::new(), block_size, num_threads, key)
+    }
+
+    /// Like `new()` but also writes the checksum of each block to
+    /// `checksums_path(filename)`.
+    pub fn new_with_checksums(
+        filename: &OsStr,
+        block_size: usize,
+        num_threads: usize,
+        key: Option<TraceKey>,
+    ) -> CompressedWriter {
+        let flags = OFlag::O_CLOEXEC
+            | OFlag::O_WRONLY
+            | OFlag::O_CREAT
+            | OFlag::O_EXCL
+            | OFlag::O_LARGEFILE;
+        let mut fd = ScopedFd::open_path_with_mode(filename, flags, Mode::S_IRUSR);
+        let checksum_fd = ScopedFd::open_path_with_mode(
+            checksums_path(filename).as_os_str(),
+            flags,
+            Mode::S_IRUSR,
+        );
+        if !checksum_fd.is_open() {
+            // Fail the same way as if we couldn't open `filename`.
+            fd.close();
+        }
+        Self::with_output(fd, checksum_fd, block_size, num_threads, key)
+    }
+
+    fn with_output(
+        fd: ScopedFd,
+        checksum_fd: ScopedFd,
+        block_size: usize,
+        num_threads: usize,
+        key: Option<TraceKey>,
+    ) -> CompressedWriter {
         let mut buffer: Vec<u8> = Vec::with_capacity(block_size * (num_threads + 2));
         buffer.resize(block_size * (num_threads + 2), 0);
 
@@ -127,6 +177,7 @@ impl CompressedWriter {
 
         let mut cw = CompressedWriter {
             fd,
+            checksum_fd,
             block_size,
             mutex: Arc::new(Mutex::new(CompressedWriterData {
                 thread_pos,
@@ -157,6 +208,8 @@ impl CompressedWriter {
                 let cond_var = cw.cond_var.clone();
                 let shared_buffer = SharedBuf(cw.buffer.as_mut_ptr(), cw.buffer.len());
                 let fd_raw = cw.fd.as_raw();
+                let checksum_fd_raw = cw.checksum_fd.as_raw();
+                let key = key.clone();
                 cw.threads.push(
                     thread::Builder::new()
                         .name("@TODO".into())
@@ -196,7 +249,7 @@ impl CompressedWriter {
 
                                     let offset_in_input_buf = g.thread_pos[thread_index].unwrap();
                                     drop(g);
-                                    let compressed_length: usize = unsafe {
+                                    let mut compressed_length: usize = unsafe {
                                         do_compress(
                                             buffer,
                                             offset_in_input_buf,
@@ -204,6 +257,28 @@ impl CompressedWriter {
                                             &mut outputbuf[size_of::<BlockHeader>()..],
                                         )
                                     };
+                                    if let (Some(key), true) = (&key, compressed_length > 0) {
+                                        let encrypted = key.encrypt(
+                                            &outputbuf[size_of::<BlockHeader>()
+                                                ..size_of::<BlockHeader>() + compressed_length],
+                                        );
+                                        compressed_length = encrypted.len();
+                                        if outputbuf.len()
+                                            < size_of::<BlockHeader>() + compressed_length
+                                        {
+                                            outputbuf.resize(
+                                                size_of::<BlockHeader>() + compressed_length,
+                                                0,
+                                            );
+                                        }
+                                        outputbuf[size_of::<BlockHeader>()
+                                            ..size_of::<BlockHeader>() + compressed_length]
+                                            .copy_from_slice(&encrypted);
+                                    }
+                                    let checksum = block_checksum(
+                                        &outputbuf[size_of::<BlockHeader>()
+                                            ..size_of::<BlockHeader>() + compressed_length],
+                                    );
                                     g = mutex.lock().unwrap();
 
                                     if 0 == compressed_length {
@@ -239,11 +314,15 @@ impl CompressedWriter {
 
                                     if !g.write_error {
                                         drop(g);
-                                        write_all(
-                                            fd_raw,
-                                            &outputbuf[0..size_of::<BlockHeader>()
-                                                + header.compressed_length as usize],
-                                        );
+                                        let block = &outputbuf[0..size_of::<BlockHeader>()
+                                            + header.compressed_length as usize];
+                                        // We're the next to write, so the checksums stay in
+                                        // file order. Writing the checksum first means a
+                                        // block is never on disk without its checksum.
+                                        if checksum_fd_raw >= 0 {
+                                            write_all(checksum_fd_raw, &checksum.to_le_bytes());
+                                        }
+                                        write_all(fd_raw, block);
                                         g = mutex.lock().unwrap();
                                     }
 
@@ -293,6 +372,9 @@ impl CompressedWriter {
             if fsync(self.fd.as_raw()).is_err() {
                 self.error = true;
             }
+            if self.checksum_fd.is_open() && fsync(self.checksum_fd.as_raw()).is_err() {
+                self.error = true;
+            }
         }
 
         g = self.mutex.lock().unwrap();
@@ -301,6 +383,7 @@ impl CompressedWriter {
         }
 
         self.fd.close();
+        self.checksum_fd.close();
     }
 
     pub fn update_reservation(&mut self, wait_flag: WaitFlag) {
@@ -383,6 +466,26 @@ impl Write for CompressedWriter {
     }
 }
 
+/// 64-bit FNV-1a of the compressed (and possibly encrypted) data of a block
+/// (without its BlockHeader).
+/// Stored next to the file so that `rd verify` can find corrupted blocks.
+pub fn block_checksum(compressed: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in compressed {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// The file that `new_with_checksums()` writes the block checksums of
+/// `filename` to: a sequence of little-endian `u64`s, one per block.
+pub fn checksums_path(filename: &OsStr) -> OsString {
+    let mut path = filename.to_owned();
+    path.push(".checksums");
+    path
+}
+
 /// See http://robert.ocallahan.org/2017/07/selecting-compression-algorithm-for-rr.html
 const RD_BROTLI_LEVEL: u32 = 5;
 
@@ -0,0 +1,119 @@
+//! Optional encryption at rest of the trace's substream files (events, data,
+//! mmaps and tasks), for traces of proprietary workloads that have to be
+//! stored on shared machines. If `RD_TRACE_KEY` (64 hex digits) or
+//! `RD_TRACE_KEY_FILE` (a file holding 32 raw bytes or 64 hex digits) is set
+//! when recording, every compressed block is encrypted with AES-256-GCM
+//! before it is written. The same key must be set to replay the trace.
+//!
+//! The trace header and the copies of mapped files that are saved alongside
+//! the substreams are not encrypted.
+
+use aes_gcm::{
+    aead::{Aead, NewAead},
+    Aes256Gcm,
+    Key,
+    Nonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use std::{env, fs};
+
+pub const TRACE_KEY_ENV: &str = "RD_TRACE_KEY";
+pub const TRACE_KEY_FILE_ENV: &str = "RD_TRACE_KEY_FILE";
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// Encrypted and stored in the trace header so that replay can tell a wrong
+/// key from a corrupted trace.
+const KEY_CHECK_PLAINTEXT: &[u8] = b"rd trace encryption key check";
+
+#[derive(Clone)]
+pub struct TraceKey {
+    cipher: Aes256Gcm,
+}
+
+impl TraceKey {
+    /// The key given by `RD_TRACE_KEY` or `RD_TRACE_KEY_FILE`, `None` if
+    /// neither is set.
+    pub fn from_env() -> Result<Option<TraceKey>, String> {
+        if let Some(hex) = env::var_os(TRACE_KEY_ENV) {
+            let hex = hex
+                .into_string()
+                .map_err(|_| format!("{} is not valid hex", TRACE_KEY_ENV))?;
+            return parse_hex_key(hex.trim())
+                .map(|key| Some(TraceKey::new(&key)))
+                .ok_or_else(|| format!("{} must be {} hex digits", TRACE_KEY_ENV, KEY_LEN * 2));
+        }
+        if let Some(path) = env::var_os(TRACE_KEY_FILE_ENV) {
+            let contents = fs::read(&path)
+                .map_err(|e| format!("Can't read {} {:?}: {}", TRACE_KEY_FILE_ENV, path, e))?;
+            if contents.len() == KEY_LEN {
+                return Ok(Some(TraceKey::new(&contents)));
+            }
+            return std::str::from_utf8(&contents)
+                .ok()
+                .and_then(|s| parse_hex_key(s.trim()))
+                .map(|key| Some(TraceKey::new(&key)))
+                .ok_or_else(|| {
+                    format!(
+                        "{} {:?} must hold {} bytes or {} hex digits",
+                        TRACE_KEY_FILE_ENV,
+                        path,
+                        KEY_LEN,
+                        KEY_LEN * 2
+                    )
+                });
+        }
+        Ok(None)
+    }
+
+    fn new(key: &[u8]) -> TraceKey {
+        TraceKey {
+            cipher: Aes256Gcm::new(Key::from_slice(key)),
+        }
+    }
+
+    /// Returns a random nonce followed by the ciphertext and its tag.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .unwrap();
+        let mut result = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        result.extend_from_slice(&nonce);
+        result.extend_from_slice(&ciphertext);
+        result
+    }
+
+    /// `None` if `data` wasn't produced by `encrypt()` with this key or was
+    /// modified since.
+    pub fn decrypt(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+
+    pub fn key_check(&self) -> Vec<u8> {
+        self.encrypt(KEY_CHECK_PLAINTEXT)
+    }
+
+    /// Whether `key_check` was made by `key_check()` with this key.
+    pub fn matches(&self, key_check: &[u8]) -> bool {
+        self.decrypt(key_check).as_deref() == Some(KEY_CHECK_PLAINTEXT)
+    }
+}
+
+fn parse_hex_key(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() != KEY_LEN * 2 || !hex.is_ascii() {
+        return None;
+    }
+    (0..KEY_LEN)
+        .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok())
+        .collect()
+}
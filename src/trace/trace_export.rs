@@ -0,0 +1,79 @@
+//! Converting an rd trace into one rr can replay, so that the two can be run
+//! on the same recording to find where they diverge. The formats only differ
+//! in rd's extensions, which are dropped:
+//! - the `rd_header` file, see `RdHeader` in the schema
+//! - the marks and renames files
+//! - the block checksums of the substream files, which are recompressed
+//! Encrypted substreams are decrypted, since rr can't read them.
+
+use crate::trace::{
+    compressed_reader::CompressedReader,
+    compressed_writer::{checksums_path, CompressedWriter},
+    trace_mark::MARKS_FILE,
+    trace_reader::TraceReader,
+    trace_rename::RENAMES_FILE,
+    trace_stream::{substream, RD_HEADER_FILE, SUBSTREAMS},
+};
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    fs::hard_link,
+    io,
+    path::Path,
+};
+
+/// What had to be left out.
+#[derive(Default)]
+pub struct ExportReport {
+    pub dropped_files: Vec<OsString>,
+    pub decrypted: bool,
+}
+
+/// Write an rr version of `trace` into the new directory `output_dir`.
+pub fn export_to_rr(trace: &TraceReader, output_dir: &Path) -> io::Result<ExportReport> {
+    fs::create_dir(output_dir)?;
+    let mut report = ExportReport::default();
+    report.decrypted = trace.encryption_key().is_some();
+
+    for &s in &SUBSTREAMS {
+        let mut reader = CompressedReader::new(&trace.path(s));
+        reader.set_key(trace.encryption_key().cloned());
+        let data = substream(s);
+        let mut writer = CompressedWriter::new(
+            output_dir.join(data.name).as_os_str(),
+            data.block_size,
+            data.threads,
+            None,
+        );
+        io::copy(&mut reader, &mut writer)?;
+        writer.close(None);
+    }
+
+    // Header only has fields rr knows about, rd's are in RD_HEADER_FILE.
+    fs::copy(trace.version_path(), output_dir.join("version"))?;
+
+    let mut names: Vec<OsString> = Vec::new();
+    for &s in &SUBSTREAMS {
+        let name = OsStr::new(substream(s).name);
+        names.push(name.to_owned());
+        names.push(checksums_path(name));
+    }
+    for entry in fs::read_dir(trace.dir())? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "version" || name == RD_HEADER_FILE || names.contains(&name) {
+            continue;
+        }
+        if name == MARKS_FILE || name == RENAMES_FILE || name == "incomplete" {
+            report.dropped_files.push(name);
+            continue;
+        }
+        // Saved copies of mapped files. Link them if we can, they can be big.
+        let dest = output_dir.join(&name);
+        if hard_link(entry.path(), &dest).is_err() {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,118 @@
+//! The initial tracee inherits more from rd than its argv and environment:
+//! its working directory, umask, resource limits and personality flags all
+//! affect how it behaves (e.g. relative paths, file modes, `getrlimit()`
+//! results). We store them in the trace header so that replay can set up the
+//! replayed exec the same way.
+
+use crate::trace_capnp::{inherited_state, rlimit as trace_rlimit};
+use libc::{mode_t, rlimit};
+use std::{env::current_dir, ffi::CString, os::unix::ffi::OsStrExt};
+
+/// RLIMIT_CPU up to and including RLIMIT_RTTIME.
+const RLIMIT_COUNT: u32 = 16;
+
+/// Query the personality without changing it.
+pub const PERSONALITY_QUERY: libc::c_ulong = 0xffff_ffff;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RlimitRecord {
+    pub resource: u32,
+    pub cur: u64,
+    pub max: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InheritedState {
+    /// A `CString` so that `apply()` doesn't need to allocate.
+    pub cwd: CString,
+    pub umask: u32,
+    pub personality: u32,
+    pub rlimits: Vec<RlimitRecord>,
+}
+
+impl InheritedState {
+    /// The state of this (the rd) process, which is what the initial tracee
+    /// inherits.
+    pub fn capture() -> InheritedState {
+        let cwd = current_dir().unwrap_or_default();
+        // There is no way to read the umask without setting it.
+        let umask = unsafe { libc::umask(0) };
+        unsafe { libc::umask(umask) };
+        let personality = unsafe { libc::personality(PERSONALITY_QUERY) } as u32;
+
+        let mut rlimits = Vec::new();
+        for resource in 0..RLIMIT_COUNT {
+            let mut limit = rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            if unsafe { libc::getrlimit(resource, &mut limit) } == 0 {
+                rlimits.push(RlimitRecord {
+                    resource,
+                    cur: limit.rlim_cur,
+                    max: limit.rlim_max,
+                });
+            }
+        }
+
+        InheritedState {
+            cwd: CString::new(cwd.as_os_str().as_bytes()).unwrap(),
+            umask: umask as u32,
+            personality,
+            rlimits,
+        }
+    }
+
+    /// Set up this process the way the recorded initial tracee was set up.
+    /// Called in the forked child before exec, so this must not allocate.
+    /// Failures are ignored: e.g. the recorded working directory may not exist
+    /// on this machine, and hard limits can't be raised without privileges.
+    /// Replay emulates most syscalls anyway; this just gets the ones that
+    /// really execute closer to the recording.
+    pub fn apply(&self) {
+        unsafe {
+            libc::chdir(self.cwd.as_ptr());
+            libc::umask(self.umask as mode_t);
+            libc::personality(self.personality as libc::c_ulong);
+        }
+        for r in &self.rlimits {
+            let limit = rlimit {
+                rlim_cur: r.cur,
+                rlim_max: r.max,
+            };
+            unsafe { libc::setrlimit(r.resource, &limit) };
+        }
+    }
+
+    pub fn write_to(&self, mut state: inherited_state::Builder) {
+        state.set_cwd(self.cwd.as_bytes());
+        state.set_umask(self.umask);
+        state.set_personality(self.personality);
+        let mut rlimits = state.init_rlimits(self.rlimits.len() as u32);
+        for (i, r) in self.rlimits.iter().enumerate() {
+            let mut builder: trace_rlimit::Builder = rlimits.reborrow().get(i as u32);
+            builder.set_resource(r.resource);
+            builder.set_cur(r.cur);
+            builder.set_max(r.max);
+        }
+    }
+
+    pub fn read_from(state: inherited_state::Reader) -> InheritedState {
+        let rlimits = state
+            .get_rlimits()
+            .unwrap()
+            .iter()
+            .map(|r| RlimitRecord {
+                resource: r.get_resource(),
+                cur: r.get_cur(),
+                max: r.get_max(),
+            })
+            .collect();
+        InheritedState {
+            cwd: CString::new(state.get_cwd().unwrap()).unwrap(),
+            umask: state.get_umask(),
+            personality: state.get_personality(),
+            rlimits,
+        }
+    }
+}
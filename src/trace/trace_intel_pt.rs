@@ -0,0 +1,37 @@
+//! Tick counts measured with Intel PT while recording. They are kept in a
+//! small text file in the trace directory, one `<time> <tid> <ticks>` line
+//! per event, where `<ticks>` is the number of conditional branches the
+//! recorded thread `<tid>` had retired by the event at global time `<time>`,
+//! the same quantity as `TraceFrame::ticks()`. Replay checks its own tick
+//! counts against them. rd doesn't measure them itself, so only traces from a
+//! recorder that does have such a file.
+
+use crate::{ticks::Ticks, trace::trace_frame::FrameTime};
+use libc::pid_t;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const INTEL_PT_FILE: &str = "intel_pt";
+
+fn intel_pt_path(trace_dir: &OsStr) -> PathBuf {
+    Path::new(trace_dir).join(INTEL_PT_FILE)
+}
+
+/// The PT tick counts of the trace in `trace_dir` by event time.
+pub fn read_pt_ticks(trace_dir: &OsStr) -> HashMap<FrameTime, (pid_t, Ticks)> {
+    let contents = fs::read_to_string(intel_pt_path(trace_dir)).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut it = line.split(' ');
+            let time = it.next()?.parse::<FrameTime>().ok()?;
+            let tid = it.next()?.parse::<pid_t>().ok()?;
+            let ticks = it.next()?.parse::<Ticks>().ok()?;
+            Some((time, (tid, ticks)))
+        })
+        .collect()
+}
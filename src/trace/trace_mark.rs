@@ -0,0 +1,62 @@
+//! Named marks let the tracee annotate a recording while it is being made,
+//! via `SYS_rdcall_annotate`. They are kept in a small text file in the trace
+//! directory, one `<time> <tid> <label>` line per mark, where `<time>` is the
+//! global time of the first event recorded after the mark was set and `<tid>`
+//! is the annotating tracee or `-`.
+
+use crate::trace::trace_frame::FrameTime;
+use libc::pid_t;
+use std::{
+    ffi::OsStr,
+    fs,
+    fs::OpenOptions,
+    io,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+pub(super) const MARKS_FILE: &str = "marks";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceMark {
+    pub time: FrameTime,
+    /// The recorded tid of the tracee that made the mark, if it was a tracee.
+    pub tid: Option<pid_t>,
+    pub label: String,
+}
+
+fn marks_path(trace_dir: &OsStr) -> PathBuf {
+    Path::new(trace_dir).join(MARKS_FILE)
+}
+
+/// Read all the marks of the trace in `trace_dir`, in the order they were
+/// made. A trace without marks has no marks file.
+pub fn read_marks(trace_dir: &OsStr) -> Vec<TraceMark> {
+    let contents = fs::read_to_string(marks_path(trace_dir)).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut it = line.splitn(3, ' ');
+            let time = it.next()?.parse::<FrameTime>().ok()?;
+            let tid = match it.next()? {
+                "-" => None,
+                tid => Some(tid.parse::<pid_t>().ok()?),
+            };
+            let label = it.next().unwrap_or("").to_owned();
+            Some(TraceMark { time, tid, label })
+        })
+        .collect()
+}
+
+pub(super) fn append_mark(trace_dir: &OsStr, mark: &TraceMark) -> io::Result<()> {
+    // Labels are free form but must not break the one-mark-per-line format.
+    let label = mark.label.replace('\n', " ");
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(marks_path(trace_dir))?;
+    match mark.tid {
+        Some(tid) => write!(f, "{} {} {}\n", mark.time, tid, label),
+        None => write!(f, "{} - {}\n", mark.time, label),
+    }
+}
@@ -12,15 +12,30 @@ use crate::{
         SyscallbufFlushEventData,
     },
     extra_registers::{ExtraRegisters, Format},
-    kernel_abi::{common::preload_interface::mprotect_record, SupportedArch, RD_NATIVE_ARCH},
+    hypervisor::Hypervisor,
+    kernel_abi::{
+        common::preload_interface::{
+            mprotect_record,
+            SYS_rdcall_init_preload,
+            SYS_rdcall_mprotect_record,
+        },
+        SupportedArch,
+        RD_NATIVE_ARCH,
+    },
     log::LogLevel::{LogDebug, LogError},
     perf_counters::TicksSemantics,
     registers::Registers,
     remote_ptr::{RemotePtr, Void},
     session::{address_space::kernel_mapping::KernelMapping, record_session::TraceUuid},
+    ticks::Ticks,
     trace::{
         compressed_reader::{CompressedReader, CompressedReaderState},
+        trace_encryption::{TraceKey, TRACE_KEY_ENV, TRACE_KEY_FILE_ENV},
         trace_frame::{FrameTime, TraceFrame},
+        trace_inherited_state::InheritedState,
+        trace_intel_pt::read_pt_ticks,
+        trace_mark::{read_marks, TraceMark},
+        trace_rename::read_renames,
         trace_stream::{
             latest_trace_symlink,
             to_trace_arch,
@@ -28,6 +43,7 @@ use crate::{
             MappedData,
             MappedDataSource::{SourceFile, SourceTrace, SourceZero},
             RawDataMetadata,
+            Recorder,
             Substream,
             TraceRemoteFd,
             TraceStream,
@@ -35,6 +51,7 @@ use crate::{
             TRACE_VERSION,
         },
         trace_task_event::{
+            TraceTask,
             TraceTaskEvent,
             TraceTaskEventClone,
             TraceTaskEventExec,
@@ -46,12 +63,15 @@ use crate::{
         frame,
         header,
         m_map,
+        rd_header,
         signal,
         task_event,
         Arch as TraceArch,
+        Hypervisor as TraceHypervisor,
         SignalDisposition as TraceSignalDisposition,
         SyscallState as TraceSyscallState,
         TicksSemantics as TraceTicksSemantics,
+        VsyscallMode as TraceVsyscallMode,
     },
     util::{
         dir_exists,
@@ -61,9 +81,10 @@ use crate::{
         CPUIDRecord,
         CPUID_GETXSAVE,
     },
+    vsyscall::VsyscallMode,
     wait_status::WaitStatus,
 };
-use capnp::{message::ReaderOptions, serialize_packed::read_message};
+use capnp::{message::ReaderOptions, serialize_packed::read_message, NotInSchema};
 use libc::{ino_t, pid_t, time_t};
 use nix::{
     errno::errno,
@@ -79,6 +100,7 @@ use std::{
     ffi::{OsStr, OsString},
     fs::File,
     io::{stderr, BufRead, BufReader, Read, Write},
+    iter,
     mem::size_of,
     ops::{Deref, DerefMut},
     os::unix::ffi::{OsStrExt, OsStringExt},
@@ -130,6 +152,15 @@ pub struct TraceReader {
     uuid_: TraceUuid,
     trace_uses_cpuid_faulting: bool,
     preload_thread_locals_recorded_: bool,
+    rrcall_base_: i32,
+    syscallbuf_layout_hash_: u64,
+    inherited_state_: Option<InheritedState>,
+    vsyscall_mode_: Option<VsyscallMode>,
+    hypervisor_: Option<Hypervisor>,
+    cgroup_: Option<String>,
+    kernel_knobs_: Option<String>,
+    recorded_by_: Recorder,
+    encryption_key_: Option<TraceKey>,
 }
 
 impl Deref for TraceReader {
@@ -147,6 +178,32 @@ impl DerefMut for TraceReader {
 }
 
 impl TraceReader {
+    /// The named marks made while recording this trace.
+    pub fn marks(&self) -> Vec<TraceMark> {
+        read_marks(self.dir())
+    }
+
+    /// The ticks Intel PT counted at each event, by event time. Empty unless
+    /// the trace has them, see `trace_intel_pt`.
+    pub fn pt_ticks(&self) -> HashMap<FrameTime, (pid_t, Ticks)> {
+        read_pt_ticks(self.dir())
+    }
+
+    /// rr traces number the rrcalls from the header's `rrcallBase`. They come
+    /// in the same order as the rdcalls, so map them onto those.
+    fn rdcall_number(&self, number: i32) -> i32 {
+        let rdcall_base = SYS_rdcall_init_preload as i32;
+        let count = (SYS_rdcall_mprotect_record - SYS_rdcall_init_preload + 1) as i32;
+        if self.rrcall_base_ != rdcall_base
+            && number >= self.rrcall_base_
+            && number < self.rrcall_base_ + count
+        {
+            number - self.rrcall_base_ + rdcall_base
+        } else {
+            number
+        }
+    }
+
     /// Read relevant data from the trace.
     ///
     /// NB: reading a trace frame has the side effect of ticking
@@ -202,7 +259,17 @@ impl TraceReader {
         }
 
         let event = frame.get_event();
-        let which = event.which().unwrap();
+        let which = match event.which() {
+            Ok(which) => which,
+            Err(NotInSchema(n)) => {
+                clean_fatal!(
+                    "Event {} has type {}, which this rd doesn't know. The trace was probably \
+                     recorded by a newer rr.",
+                    self.time(),
+                    n
+                );
+            }
+        };
         match which {
             frame::event::InstructionTrap(()) => ret.ev = Event::instruction_trap(),
             frame::event::PatchSyscall(()) => ret.ev = Event::patch_syscall(),
@@ -236,7 +303,7 @@ impl TraceReader {
             }
             frame::event::Syscall(r) => {
                 ret.ev = Event::new_syscall_event(SyscallEventData::new(
-                    r.get_number(),
+                    self.rdcall_number(r.get_number()),
                     from_trace_arch(r.get_arch().unwrap()),
                 ));
                 let syscall_ev = ret.ev.syscall_event_mut();
@@ -451,7 +518,18 @@ impl TraceReader {
         let tid_ = i32_to_tid(task.get_tid());
         maybe_time.map(|frame_time| *frame_time = task.get_frame_time() as u64);
         let te: TraceTaskEvent;
-        match task.which().unwrap() {
+        let which = match task.which() {
+            Ok(which) => which,
+            Err(NotInSchema(n)) => {
+                clean_fatal!(
+                    "Task event at {} has type {}, which this rd doesn't know. The trace was \
+                     probably recorded by a newer rr.",
+                    task.get_frame_time(),
+                    n
+                );
+            }
+        };
+        match which {
             task_event::Clone(r) => {
                 let clone_flags_ = r.get_flags();
                 let parent_tid_ = i32_to_tid(r.get_parent_tid());
@@ -544,6 +622,106 @@ impl TraceReader {
         Some(d)
     }
 
+    /// Skip the mapped regions and raw data of the last frame read, which
+    /// must be done before reading the next frame if they aren't read.
+    pub fn skip_frame_data(&mut self) {
+        while self
+            .read_mapped_region(
+                None,
+                Some(ValidateSourceFile::DontValidate),
+                None,
+                None,
+                None,
+            )
+            .is_some()
+        {}
+        while self.read_raw_data_metadata_for_frame().is_some() {}
+    }
+
+    /// The frames from here on of the task with `rec_tid`. Reads (and skips
+    /// the data of) all frames.
+    pub fn frames_for_tid(&mut self, rec_tid: pid_t) -> impl Iterator<Item = TraceFrame> + '_ {
+        iter::from_fn(move || {
+            while !self.at_end() {
+                let frame = self.read_frame();
+                self.skip_frame_data();
+                if frame.tid() == rec_tid {
+                    return Some(frame);
+                }
+            }
+            None
+        })
+    }
+
+    /// All the task events of the trace, including the renames (which aren't
+    /// in the tasks substream, see `trace_rename`), with their times. Doesn't
+    /// change the position of this reader.
+    pub fn task_events(&self) -> Vec<(FrameTime, TraceTaskEvent)> {
+        let mut reader = self.clone();
+        reader.rewind();
+        let mut renames = read_renames(self.dir())
+            .into_iter()
+            .map(|r| (r.time, TraceTaskEvent::for_rename(r.tid, &r.name)))
+            .peekable();
+        let mut events = Vec::new();
+        let mut time: FrameTime = 0;
+        while let Some(e) = reader.read_task_event(Some(&mut time)) {
+            while let Some(rename) = renames.next_if(|&(t, _)| t < time) {
+                events.push(rename);
+            }
+            if let Some(&(last_time, _)) = events.last() {
+                if time < last_time {
+                    fatal!(
+                        "TraceTaskEvent times non-monotonic (time:{}, last time:{})",
+                        time,
+                        last_time
+                    );
+                }
+            }
+            events.push((time, e));
+        }
+        events.extend(renames);
+        events
+    }
+
+    /// Every task of the trace, in the order they were created, with its
+    /// task events.
+    pub fn tasks(&self) -> Vec<TraceTask> {
+        let mut tasks: Vec<TraceTask> = Vec::new();
+        // Index into `tasks` of the live task with each tid.
+        let mut live: HashMap<pid_t, usize> = HashMap::new();
+        for (time, e) in self.task_events() {
+            let tid = e.tid();
+            let index = match (e.event_variant(), live.get(&tid)) {
+                (TraceTaskEventVariant::Clone(c), _) => {
+                    let parent = live.get(&c.parent_tid()).copied();
+                    tasks.push(TraceTask {
+                        tid,
+                        parent,
+                        events: Vec::new(),
+                    });
+                    tasks.len() - 1
+                }
+                (_, Some(&index)) => index,
+                (_, None) => {
+                    tasks.push(TraceTask {
+                        tid,
+                        parent: None,
+                        events: Vec::new(),
+                    });
+                    tasks.len() - 1
+                }
+            };
+            if let TraceTaskEventVariant::Exit(_) = e.event_variant() {
+                live.remove(&tid);
+            } else {
+                live.insert(tid, index);
+            }
+            tasks[index].events.push((time, e));
+        }
+        tasks
+    }
+
     /// Return true if we're at the end of the trace file.
     pub fn at_end(&self) -> bool {
         self.reader(Substream::Events).at_end()
@@ -663,16 +841,24 @@ impl TraceReader {
         }
 
         if TRACE_VERSION != version {
+            // rr (since 5.0) and rd both write TRACE_VERSION, so this tells us
+            // which side of it the recorder was on.
+            let recorder = if version < TRACE_VERSION {
+                "an older version of rd, or an rr older than 5.0"
+            } else {
+                "a newer version of rd or rr"
+            };
             write!(
                 stderr(),
                 "\nrd: error: Recorded trace `{:?}' has an incompatible version {}; expected\n\
-                 {}.  Did you record `{:?}' with an older version of rd?  If so,\n\
-                 you'll need to replay `{:?}' with that older version.  Otherwise,\n\
+                 {}.  Did you record `{:?}' with {}?  If so,\n\
+                 you'll need to replay `{:?}' with that version.  Otherwise,\n\
                  your trace is likely corrupted.\n\n",
                 path,
                 version,
                 TRACE_VERSION,
                 path,
+                recorder,
                 path
             )
             .unwrap();
@@ -718,6 +904,72 @@ impl TraceReader {
         }
         let xcr0_ = header.get_xcr0();
         let preload_thread_locals_recorded_ = header.get_preload_thread_locals_recorded();
+        let rrcall_base_ = header.get_rrcall_base();
+        // Only traces recorded by rd have rd's header extensions, see
+        // `RdHeader` in the schema.
+        let rd_header_path = trace_stream.rd_header_path();
+        let rd_header_msg = match File::open(&rd_header_path) {
+            Ok(f) => match read_message(&mut BufReader::new(f), ReaderOptions::new()) {
+                Ok(msg) => Some(msg),
+                Err(_) => {
+                    fatal!("Could not read {:?}", rd_header_path);
+                    unreachable!()
+                }
+            },
+            Err(_) => None,
+        };
+        let rd_header = rd_header_msg
+            .as_ref()
+            .map(|msg| msg.get_root::<rd_header::Reader>().unwrap());
+        let recorded_by_ = match rd_header {
+            Some(_) => Recorder::Rd,
+            None => Recorder::Rr,
+        };
+        let syscallbuf_layout_hash_ = rd_header.map_or(0, |h| h.get_syscallbuf_layout_hash());
+        let inherited_state_ = rd_header
+            .filter(|h| h.has_inherited_state())
+            .map(|h| InheritedState::read_from(h.get_inherited_state().unwrap()));
+        // Values a newer rd added to these enums read as unknown.
+        let vsyscall_mode_ = rd_header
+            .and_then(|h| h.get_vsyscall_mode().ok())
+            .and_then(from_trace_vsyscall_mode);
+        let hypervisor_ = rd_header
+            .and_then(|h| h.get_hypervisor().ok())
+            .and_then(from_trace_hypervisor);
+        let cgroup_ = rd_header
+            .filter(|h| h.has_cgroup())
+            .map(|h| h.get_cgroup().unwrap().to_owned());
+        let kernel_knobs_ = rd_header
+            .filter(|h| h.has_kernel_knobs())
+            .map(|h| h.get_kernel_knobs().unwrap().to_owned());
+        let encryption_key_ = match rd_header {
+            Some(h) if h.has_encryption_key_check() => {
+                let key_check = h.get_encryption_key_check().unwrap();
+                match TraceKey::from_env() {
+                    Ok(Some(key)) if key.matches(key_check) => Some(key),
+                    Ok(Some(_)) => {
+                        clean_fatal!(
+                            "The trace key in the environment isn't the one the trace was \
+                             encrypted with"
+                        );
+                    }
+                    Ok(None) => {
+                        clean_fatal!(
+                            "Trace is encrypted; set {} or {} to its key",
+                            TRACE_KEY_ENV,
+                            TRACE_KEY_FILE_ENV
+                        );
+                    }
+                    Err(e) => {
+                        clean_fatal!("{}", e);
+                    }
+                }
+            }
+            _ => None,
+        };
+        for r in readers.values_mut() {
+            r.set_key(encryption_key_.clone());
+        }
         let ticks_semantics_ = from_trace_ticks_semantics(header.get_ticks_semantics().unwrap());
         let uuid_from_trace = header.get_uuid().unwrap();
         let mut uuid_ = TraceUuid::new();
@@ -738,6 +990,15 @@ impl TraceReader {
             uuid_,
             trace_uses_cpuid_faulting,
             preload_thread_locals_recorded_,
+            rrcall_base_,
+            syscallbuf_layout_hash_,
+            inherited_state_,
+            vsyscall_mode_,
+            hypervisor_,
+            cgroup_,
+            kernel_knobs_,
+            recorded_by_,
+            encryption_key_,
             // @TODO Is this what we want?
             monotonic_time_: 0.0,
             raw_recs: vec![],
@@ -774,6 +1035,47 @@ impl TraceReader {
     pub fn preload_thread_locals_recorded(&self) -> bool {
         self.preload_thread_locals_recorded_
     }
+    /// The working directory, umask etc. the initial tracee inherited during
+    /// recording. `None` for traces recorded by rr.
+    pub fn inherited_state(&self) -> Option<&InheritedState> {
+        self.inherited_state_.as_ref()
+    }
+    /// Whether rr or rd recorded the trace.
+    pub fn recorded_by(&self) -> Recorder {
+        self.recorded_by_
+    }
+    /// The key the substream files are encrypted with, `None` if they aren't.
+    pub fn encryption_key(&self) -> Option<&TraceKey> {
+        self.encryption_key_.as_ref()
+    }
+    /// The vsyscall mode of the kernel the trace was recorded on, or `None`
+    /// if the trace doesn't say.
+    pub fn vsyscall_mode(&self) -> Option<VsyscallMode> {
+        self.vsyscall_mode_
+    }
+    /// The hypervisor the trace was recorded under, or `None` if the trace
+    /// doesn't say.
+    pub fn hypervisor(&self) -> Option<Hypervisor> {
+        self.hypervisor_
+    }
+    /// A description of the cgroup the trace was recorded in, or `None` if
+    /// the trace doesn't say.
+    pub fn cgroup(&self) -> Option<&str> {
+        self.cgroup_.as_deref()
+    }
+    /// The /proc/sys settings rd depends on, as they were when the trace was
+    /// recorded (see `KernelKnobs`), or `None` if the trace doesn't say.
+    pub fn kernel_knobs(&self) -> Option<&str> {
+        self.kernel_knobs_.as_deref()
+    }
+    /// The `syscallbuf_layout_hash()` of the rd that recorded this trace, or
+    /// `None` if the trace doesn't say.
+    pub fn syscallbuf_layout_hash(&self) -> Option<u64> {
+        match self.syscallbuf_layout_hash_ {
+            0 => None,
+            hash => Some(hash),
+        }
+    }
     pub fn uuid(&self) -> &TraceUuid {
         &self.uuid_
     }
@@ -864,6 +1166,27 @@ fn from_trace_ticks_semantics(semantics: TraceTicksSemantics) -> TicksSemantics
     }
 }
 
+fn from_trace_vsyscall_mode(mode: TraceVsyscallMode) -> Option<VsyscallMode> {
+    match mode {
+        TraceVsyscallMode::Unknown => None,
+        TraceVsyscallMode::Emulate => Some(VsyscallMode::Emulate),
+        TraceVsyscallMode::Xonly => Some(VsyscallMode::XOnly),
+        TraceVsyscallMode::None => Some(VsyscallMode::None),
+    }
+}
+
+fn from_trace_hypervisor(hypervisor: TraceHypervisor) -> Option<Hypervisor> {
+    match hypervisor {
+        TraceHypervisor::Unknown => None,
+        TraceHypervisor::None => Some(Hypervisor::None),
+        TraceHypervisor::Kvm => Some(Hypervisor::Kvm),
+        TraceHypervisor::HyperV => Some(Hypervisor::HyperV),
+        TraceHypervisor::Xen => Some(Hypervisor::Xen),
+        TraceHypervisor::Vmware => Some(Hypervisor::VMware),
+        TraceHypervisor::Other => Some(Hypervisor::Other),
+    }
+}
+
 fn i32_to_tid(tid: i32) -> pid_t {
     if tid <= 0 {
         fatal!("Invalid tid");
@@ -0,0 +1,71 @@
+//! Task renames (prctl(PR_SET_NAME)), for `rd ps` and `rd dump`. rr's task
+//! events have no variant for them, so they are kept in a small file in the
+//! trace directory instead, one `<time> <tid> <name>` line per rename, where
+//! `<time>` is the global time of the prctl event. Names are the raw bytes
+//! the tracee set.
+
+use crate::trace::trace_frame::FrameTime;
+use libc::pid_t;
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    fs::OpenOptions,
+    io,
+    io::Write,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    str,
+};
+
+pub(super) const RENAMES_FILE: &str = "renames";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceRename {
+    pub time: FrameTime,
+    pub tid: pid_t,
+    pub name: OsString,
+}
+
+fn renames_path(trace_dir: &OsStr) -> PathBuf {
+    Path::new(trace_dir).join(RENAMES_FILE)
+}
+
+/// Read all the renames of the trace in `trace_dir`, in the order they were
+/// made. A trace without renames has no renames file.
+pub fn read_renames(trace_dir: &OsStr) -> Vec<TraceRename> {
+    let contents = fs::read(renames_path(trace_dir)).unwrap_or_default();
+    contents
+        .split(|&c| c == b'\n')
+        .filter_map(|line| {
+            let mut it = line.splitn(3, |&c| c == b' ');
+            let time = str::from_utf8(it.next()?).ok()?.parse::<FrameTime>().ok()?;
+            let tid = str::from_utf8(it.next()?).ok()?.parse::<pid_t>().ok()?;
+            let name = OsString::from_vec(it.next().unwrap_or(b"").to_vec());
+            Some(TraceRename { time, tid, name })
+        })
+        .collect()
+}
+
+pub(super) fn append_rename(trace_dir: &OsStr, rename: &TraceRename) -> io::Result<()> {
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(renames_path(trace_dir))?;
+    f.write_all(&rename_line(rename))
+}
+
+/// The line of the renames file for `rename`.
+fn rename_line(rename: &TraceRename) -> Vec<u8> {
+    let mut line = format!("{} {} ", rename.time, rename.tid).into_bytes();
+    // Names can be any bytes but must not break the one-rename-per-line
+    // format.
+    line.extend(
+        rename
+            .name
+            .as_bytes()
+            .iter()
+            .map(|&c| if c == b'\n' { b' ' } else { c }),
+    );
+    line.push(b'\n');
+    line
+}
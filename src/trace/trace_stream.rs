@@ -19,6 +19,10 @@ use std::{
 
 pub const TRACE_VERSION: u32 = 85;
 
+/// The file with rd's additions to the trace header, see `RdHeader` in the
+/// schema. Only traces recorded by rd have one.
+pub(super) const RD_HEADER_FILE: &str = "rd_header";
+
 pub const SUBSTREAM_COUNT: usize = 4;
 
 /// Update `substreams` and TRACE_VERSION when you update this list.
@@ -85,6 +89,14 @@ pub(super) struct SubstreamData {
     pub(super) threads: usize,
 }
 
+/// The program that recorded a trace. Traces recorded by rd have an
+/// `RD_HEADER_FILE`, rr traces don't.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Recorder {
+    Rr,
+    Rd,
+}
+
 /// For REMAP_MAPPING maps, the memory contents are preserved so we don't
 /// need a source. We use SourceZero for that case and it's ignored.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -159,6 +171,14 @@ impl TraceStream {
         OsString::from_vec(version_path)
     }
 
+    /// Return the path of the `RD_HEADER_FILE`.
+    pub(super) fn rd_header_path(&self) -> OsString {
+        let mut path: Vec<u8> = self.trace_dir.clone().into_vec();
+        path.extend_from_slice(b"/");
+        path.extend_from_slice(RD_HEADER_FILE.as_bytes());
+        OsString::from_vec(path)
+    }
+
     /// While the trace is being built, the version file is stored under this name.
     /// When the trace is closed we rename it to the correct name. This lets us
     /// detect incomplete traces.
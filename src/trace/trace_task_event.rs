@@ -1,9 +1,13 @@
 use crate::{
     remote_ptr::{RemotePtr, Void},
+    trace::trace_frame::FrameTime,
     wait_status::WaitStatus,
 };
-use libc::pid_t;
-use std::ffi::{OsStr, OsString};
+use libc::{pid_t, CLONE_THREAD};
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+};
 
 #[derive(Clone)]
 pub enum TraceTaskEventVariant {
@@ -13,6 +17,8 @@ pub enum TraceTaskEventVariant {
     Clone(TraceTaskEventClone),
     Exec(TraceTaskEventExec),
     Exit(TraceTaskEventExit),
+    /// DIFF NOTE: rd only. Created by prctl(PR_SET_NAME)
+    Rename(TraceTaskEventRename),
 }
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -20,6 +26,7 @@ pub enum TraceTaskEventType {
     Clone,
     Exec,
     Exit,
+    Rename,
 }
 
 impl TraceTaskEvent {
@@ -41,6 +48,12 @@ impl TraceTaskEvent {
             _ => panic!("Not a TraceTaskEventTypeExit"),
         }
     }
+    pub fn rename_variant(&self) -> &TraceTaskEventRename {
+        match &self.variant {
+            TraceTaskEventVariant::Rename(v) => v,
+            _ => panic!("Not a TraceTaskEventTypeRename"),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -92,12 +105,31 @@ impl TraceTaskEventExit {
     }
 }
 
+#[derive(Clone)]
+pub struct TraceTaskEventRename {
+    pub(super) name_: OsString,
+}
+
+impl TraceTaskEventRename {
+    pub fn name(&self) -> &OsStr {
+        &self.name_
+    }
+}
+
 pub struct TraceTaskEvent {
     pub(super) variant: TraceTaskEventVariant,
     pub(super) tid_: pid_t,
 }
 
 impl TraceTaskEvent {
+    pub fn for_rename(tid: pid_t, name: &OsStr) -> TraceTaskEvent {
+        TraceTaskEvent {
+            variant: TraceTaskEventVariant::Rename(TraceTaskEventRename {
+                name_: name.to_owned(),
+            }),
+            tid_: tid,
+        }
+    }
     pub fn tid(&self) -> pid_t {
         self.tid_
     }
@@ -109,6 +141,78 @@ impl TraceTaskEvent {
             TraceTaskEventVariant::Clone(_) => TraceTaskEventType::Clone,
             TraceTaskEventVariant::Exit(_) => TraceTaskEventType::Exit,
             TraceTaskEventVariant::Exec(_) => TraceTaskEventType::Exec,
+            TraceTaskEventVariant::Rename(_) => TraceTaskEventType::Rename,
         }
     }
 }
+
+/// A recorded task and its task events, from `TraceReader::tasks()`. A tid
+/// that is reused after its task exits gets a new `TraceTask`.
+pub struct TraceTask {
+    pub tid: pid_t,
+    /// Index of the task that cloned this one, `None` for the initial task.
+    pub parent: Option<usize>,
+    /// With their times, in order. Starts with the clone, except for the
+    /// initial task.
+    pub events: Vec<(FrameTime, TraceTaskEvent)>,
+}
+
+impl TraceTask {
+    pub fn exit_status(&self) -> Option<WaitStatus> {
+        self.events
+            .iter()
+            .find_map(|(_, e)| match e.event_variant() {
+                TraceTaskEventVariant::Exit(ex) => Some(ex.exit_status()),
+                _ => None,
+            })
+    }
+}
+
+/// The indices of the task at `index` in `tasks` and its ancestors, nearest
+/// first.
+pub fn task_lineage(tasks: &[TraceTask], index: usize) -> Vec<usize> {
+    let mut lineage = vec![index];
+    while let Some(parent) = tasks[*lineage.last().unwrap()].parent {
+        lineage.push(parent);
+    }
+    lineage
+}
+
+/// The tasks that `--tid` and `--pid` options select: one task, or all the
+/// threads of one process. Everything is selected if neither is given.
+#[derive(Clone, Default)]
+pub struct TaskFilter {
+    tids: Option<HashSet<pid_t>>,
+}
+
+impl TaskFilter {
+    pub fn new(tasks: &[TraceTask], tid: Option<pid_t>, pid: Option<pid_t>) -> TaskFilter {
+        if tid.is_none() && pid.is_none() {
+            return Default::default();
+        }
+        let mut tids: HashSet<pid_t> = tid.into_iter().collect();
+        if let Some(pid) = pid {
+            // A task's pid is its own tid, unless it was cloned as a thread.
+            let mut pids: Vec<pid_t> = Vec::with_capacity(tasks.len());
+            for task in tasks {
+                let task_pid = match (task.parent, task.events.first()) {
+                    (Some(parent), Some((_, e)))
+                        if e.clone_variant().clone_flags() & CLONE_THREAD != 0 =>
+                    {
+                        pids[parent]
+                    }
+                    _ => task.tid,
+                };
+                pids.push(task_pid);
+                if task_pid == pid {
+                    tids.insert(task.tid);
+                }
+            }
+        }
+        TaskFilter { tids: Some(tids) }
+    }
+
+    pub fn matches(&self, tid: pid_t) -> bool {
+        self.tids.as_ref().map_or(true, |tids| tids.contains(&tid))
+    }
+}
@@ -0,0 +1,304 @@
+//! Checks that the files of a trace are complete and undamaged. Each
+//! substream file is a sequence of compressed blocks; `CompressedWriter`
+//! writes a checksum of every block to the substream's `.checksums` file
+//! as it goes, so they survive a recording that crashed. A replay that fails
+//! on a damaged trace looks just like an rd bug, so `rd verify` uses this to
+//! tell them apart.
+
+use crate::{
+    scoped_fd::ScopedFd,
+    trace::{
+        compressed_reader::{do_decompress, read_all},
+        compressed_writer::{block_checksum, checksums_path, BlockHeader},
+        trace_frame::FrameTime,
+        trace_reader::TraceReader,
+        trace_stream::{substream, Substream, SUBSTREAMS},
+    },
+    trace_capnp::{frame, m_map, task_event},
+};
+use capnp::{message::ReaderOptions, serialize_packed::read_message};
+use nix::{fcntl::OFlag, sys::stat::fstat};
+use std::{
+    cmp::min,
+    collections::HashMap,
+    convert::TryInto,
+    fs,
+    mem::size_of,
+    ptr::copy_nonoverlapping,
+};
+
+pub struct SubstreamReport {
+    pub name: &'static str,
+    /// The number of blocks that were read back intact.
+    pub intact_blocks: usize,
+    /// The total uncompressed size of those blocks.
+    pub intact_bytes: u64,
+    /// What is wrong with the file after the intact blocks, if anything.
+    pub problem: Option<String>,
+}
+
+pub struct TraceVerification {
+    pub substreams: Vec<SubstreamReport>,
+    /// The time of the last event that can be read in full, along with the
+    /// task events, mappings and raw data it and the events before it need.
+    /// Replay can't get past this point. Only meaningful if the trace is
+    /// damaged.
+    pub last_intact_event: FrameTime,
+    /// False for traces without `.checksums` files (e.g. recorded by rr), in
+    /// which case only truncation and undecodable blocks are found.
+    pub has_checksums: bool,
+}
+
+impl TraceVerification {
+    pub fn is_intact(&self) -> bool {
+        self.substreams.iter().all(|s| s.problem.is_none())
+    }
+}
+
+pub fn verify_trace(trace: &TraceReader) -> TraceVerification {
+    let mut substreams = Vec::new();
+    let mut contents = HashMap::new();
+    let mut has_checksums = true;
+    for &s in &SUBSTREAMS {
+        let checksums = read_block_checksums(trace, s);
+        has_checksums &= checksums.is_some();
+        let mut data = Vec::new();
+        let report = verify_substream(
+            trace,
+            s,
+            checksums.as_deref(),
+            // The raw data can be huge and we only need its size.
+            if s == Substream::RawData {
+                None
+            } else {
+                Some(&mut data)
+            },
+        );
+        contents.insert(s, data);
+        substreams.push(report);
+    }
+
+    let frames = read_frames(&contents[&Substream::Events]);
+    // Global time starts at 1 and each frame advances it by one.
+    let mut last_intact_event = frames.len() as FrameTime;
+    for (&s, report) in SUBSTREAMS.iter().zip(&substreams) {
+        if report.problem.is_none() {
+            continue;
+        }
+        let last_needing_nothing_lost = match s {
+            Substream::Events => continue,
+            Substream::RawData => {
+                let mut needed: u64 = 0;
+                frames
+                    .iter()
+                    .take_while(|&&size| {
+                        needed += size;
+                        needed <= report.intact_bytes
+                    })
+                    .count() as FrameTime
+            }
+            // We don't know the times of the records that were lost, only
+            // that they come at or after the last intact record.
+            Substream::Mmaps | Substream::Tasks => {
+                last_record_time(s, &contents[&s]).map_or(0, |t| t.saturating_sub(1))
+            }
+        };
+        last_intact_event = min(last_intact_event, last_needing_nothing_lost);
+    }
+
+    TraceVerification {
+        substreams,
+        last_intact_event,
+        has_checksums,
+    }
+}
+
+/// The checksums in the substream's `.checksums` file, or `None` if there
+/// isn't one. A checksum is written before its block, so a recording that
+/// crashed may leave a torn checksum at the end, which is ignored.
+fn read_block_checksums(trace: &TraceReader, s: Substream) -> Option<Vec<u64>> {
+    let bytes = fs::read(checksums_path(trace.path(s).as_os_str())).ok()?;
+    Some(
+        bytes
+            .chunks_exact(size_of::<u64>())
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+/// Reads every block of the substream's file, checking it against
+/// `checksums` if we have them. The uncompressed contents of the intact
+/// blocks are appended to `uncompressed`, if given.
+fn verify_substream(
+    trace: &TraceReader,
+    s: Substream,
+    checksums: Option<&[u64]>,
+    mut uncompressed: Option<&mut Vec<u8>>,
+) -> SubstreamReport {
+    let name = substream(s).name;
+    let mut intact_bytes: u64 = 0;
+    let report = |intact_blocks, intact_bytes, problem| SubstreamReport {
+        name,
+        intact_blocks,
+        intact_bytes,
+        problem,
+    };
+
+    let fd = ScopedFd::open_path(
+        trace.path(s).as_os_str(),
+        OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+    );
+    if !fd.is_open() {
+        return report(0, 0, Some("file is missing or unreadable".into()));
+    }
+
+    let file_size = match fstat(fd.as_raw()) {
+        Ok(st) => st.st_size as u64,
+        Err(e) => return report(0, 0, Some(format!("can't stat file: {}", e))),
+    };
+
+    let mut offset: u64 = 0;
+    let mut block: usize = 0;
+    while offset < file_size {
+        let mut header_bytes = [0u8; size_of::<BlockHeader>()];
+        match read_all(&fd, &mut header_bytes, &mut offset) {
+            Ok(true) => (),
+            Ok(false) => {
+                return report(
+                    block,
+                    intact_bytes,
+                    Some(format!("truncated in the header of block {}", block)),
+                )
+            }
+            Err(e) => return report(block, intact_bytes, Some(format!("read error: {}", e))),
+        }
+        let mut header: BlockHeader = Default::default();
+        unsafe {
+            copy_nonoverlapping(
+                header_bytes.as_ptr(),
+                &raw mut header as *mut u8,
+                size_of::<BlockHeader>(),
+            );
+        }
+
+        let mut compressed = vec![0u8; header.compressed_length as usize];
+        match read_all(&fd, &mut compressed, &mut offset) {
+            Ok(true) => (),
+            Ok(false) => {
+                return report(
+                    block,
+                    intact_bytes,
+                    Some(format!("truncated in block {}", block)),
+                );
+            }
+            Err(e) => return report(block, intact_bytes, Some(format!("read error: {}", e))),
+        }
+
+        if let Some(checksums) = checksums {
+            match checksums.get(block) {
+                None => {
+                    return report(
+                        block,
+                        intact_bytes,
+                        Some(format!(
+                            "has more blocks than the {} recorded",
+                            checksums.len()
+                        )),
+                    )
+                }
+                Some(&expected) if expected != block_checksum(&compressed) => {
+                    return report(
+                        block,
+                        intact_bytes,
+                        Some(format!("checksum mismatch in block {}", block)),
+                    );
+                }
+                Some(_) => (),
+            }
+        }
+
+        if let Some(key) = trace.encryption_key() {
+            compressed = match key.decrypt(&compressed) {
+                Some(decrypted) => decrypted,
+                None => {
+                    return report(
+                        block,
+                        intact_bytes,
+                        Some(format!("can't decrypt block {}", block)),
+                    )
+                }
+            };
+        }
+
+        let mut data = vec![0u8; header.uncompressed_length as usize];
+        if !do_decompress(&compressed, &mut data) {
+            return report(
+                block,
+                intact_bytes,
+                Some(format!("can't decompress block {}", block)),
+            );
+        }
+        if let Some(out) = uncompressed.as_mut() {
+            out.extend_from_slice(&data);
+        }
+        intact_bytes += data.len() as u64;
+        block += 1;
+    }
+
+    match checksums {
+        Some(checksums) if block < checksums.len() => report(
+            block,
+            intact_bytes,
+            Some(format!(
+                "truncated after block {} of {}",
+                block,
+                checksums.len()
+            )),
+        ),
+        _ => report(block, intact_bytes, None),
+    }
+}
+
+/// The amount of raw data each of the complete event frames at the start
+/// of `events` needs.
+fn read_frames(mut events: &[u8]) -> Vec<u64> {
+    let mut frames = Vec::new();
+    while !events.is_empty() {
+        let msg = match read_message(&mut events, ReaderOptions::new()) {
+            Ok(msg) => msg,
+            Err(_) => break,
+        };
+        let size = match msg
+            .get_root::<frame::Reader>()
+            .and_then(|f| f.get_mem_writes())
+        {
+            Ok(mem_writes) => mem_writes.iter().map(|w| w.get_size()).sum(),
+            Err(_) => break,
+        };
+        frames.push(size);
+    }
+    frames
+}
+
+/// The frame time of the last complete record at the start of the tasks or
+/// mmaps substream `records`, if any.
+fn last_record_time(s: Substream, mut records: &[u8]) -> Option<FrameTime> {
+    let mut last = None;
+    while !records.is_empty() {
+        let msg = match read_message(&mut records, ReaderOptions::new()) {
+            Ok(msg) => msg,
+            Err(_) => break,
+        };
+        let time = match s {
+            Substream::Tasks => msg
+                .get_root::<task_event::Reader>()
+                .map(|t| t.get_frame_time()),
+            _ => msg.get_root::<m_map::Reader>().map(|m| m.get_frame_time()),
+        };
+        match time {
+            Ok(time) => last = Some(time as FrameTime),
+            Err(_) => break,
+        }
+    }
+    last
+}
@@ -2,12 +2,20 @@ use crate::extra_registers::ExtraRegisters;
 
 use crate::{
     bindings::signal::siginfo_t,
+    cgroup::CgroupContext,
     event::{Event, EventType, SignalDeterministic, SignalResolvedDisposition, SyscallState},
+    hypervisor::{host_hypervisor, Hypervisor},
     kernel_abi::{
-        common::preload_interface::{mprotect_record, SYSCALLBUF_PROTOCOL_VERSION},
+        common::preload_interface::{
+            mprotect_record,
+            syscallbuf_layout_hash,
+            SYS_rdcall_init_preload,
+            SYSCALLBUF_PROTOCOL_VERSION,
+        },
         syscall_number_for_restart_syscall,
         RD_NATIVE_ARCH,
     },
+    kernel_knobs::KernelKnobs,
     kernel_supplement::{btrfs_ioctl_clone_range_args, BTRFS_IOC_CLONE_, BTRFS_IOC_CLONE_RANGE_},
     log::LogLevel::LogDebug,
     perf_counters::{PerfCounters, TicksSemantics},
@@ -21,6 +29,10 @@ use crate::{
     },
     trace::{
         compressed_writer::CompressedWriter,
+        trace_encryption::TraceKey,
+        trace_inherited_state::InheritedState,
+        trace_mark::{append_mark, TraceMark},
+        trace_rename::{append_rename, TraceRename},
         trace_stream::{
             latest_trace_symlink,
             make_trace_dir,
@@ -42,11 +54,14 @@ use crate::{
         header,
         m_map,
         m_map::source::Which::Trace,
+        rd_header,
         signal,
         task_event,
+        Hypervisor as TraceHypervisor,
         SignalDisposition as TraceSignalDisposition,
         SyscallState as TraceSyscallState,
         TicksSemantics as TraceTicksSemantics,
+        VsyscallMode as TraceVsyscallMode,
     },
     util::{
         all_cpuid_records,
@@ -58,6 +73,7 @@ use crate::{
         xcr0,
         CPUIDRecord,
     },
+    vsyscall::{host_vsyscall_mode, VsyscallMode},
 };
 use capnp::{
     message,
@@ -79,7 +95,9 @@ use std::{
     collections::HashMap,
     convert::TryInto,
     ffi::{OsStr, OsString},
+    fs,
     fs::{hard_link, rename, File},
+    io,
     io::Write,
     mem::size_of,
     ops::{Deref, DerefMut},
@@ -165,6 +183,12 @@ pub struct TraceWriter {
     mmap_count: u32,
     has_cpuid_faulting_: bool,
     supports_file_data_cloning_: bool,
+    /// Captured when the trace is created, i.e. right before the initial
+    /// tracee is spawned.
+    inherited_state: InheritedState,
+    /// Set from the environment when the trace is created. See
+    /// `trace_encryption`.
+    encryption_key: Option<TraceKey>,
 }
 
 impl Deref for TraceWriter {
@@ -522,6 +546,18 @@ impl TraceWriter {
 
     /// Write a task event (clone or exec record) to the trace.
     pub fn write_task_event(&mut self, event: &TraceTaskEvent) {
+        if let TraceTaskEventVariant::Rename(e) = event.event_variant() {
+            let record = TraceRename {
+                time: self.global_time,
+                tid: event.tid(),
+                name: e.name().to_owned(),
+            };
+            if append_rename(self.dir(), &record).is_err() {
+                fatal!("Unable to write renames");
+            }
+            return;
+        }
+
         let mut task_msg = message::Builder::new_default();
         let mut task = task_msg.init_root::<task_event::Builder>();
         // DIFF NOTE: This is a u64 in rd and an i64 in rr
@@ -548,6 +584,8 @@ impl TraceWriter {
             TraceTaskEventVariant::Exit(e) => {
                 task.init_exit().set_exit_status(e.exit_status().get());
             }
+            // See above.
+            TraceTaskEventVariant::Rename(_) => unreachable!(),
         }
 
         let tasks = self.writer_mut(Substream::Tasks);
@@ -556,6 +594,17 @@ impl TraceWriter {
         }
     }
 
+    /// Add a named mark at the current global time. Replay can seek to it.
+    /// `maybe_tid` is the recorded tid of the tracee making the mark, if any.
+    pub fn write_mark(&mut self, maybe_tid: Option<pid_t>, label: &str) -> io::Result<()> {
+        let mark = TraceMark {
+            time: self.global_time,
+            tid: maybe_tid,
+            label: label.to_owned(),
+        };
+        append_mark(self.dir(), &mark)
+    }
+
     /// Return true iff all trace files are "good".
     pub fn good(&self) -> bool {
         for w in self.writers.values() {
@@ -577,6 +626,12 @@ impl TraceWriter {
         output_trace_dir: &OsStr,
         ticks_semantics_: TicksSemantics,
     ) -> TraceWriter {
+        let encryption_key = match TraceKey::from_env() {
+            Ok(key) => key,
+            Err(e) => {
+                clean_fatal!("{}", e);
+            }
+        };
         let mut tw = TraceWriter {
             trace_stream: TraceStream::new(&make_trace_dir(file_name, output_trace_dir), 1),
             ticks_semantics_,
@@ -588,6 +643,8 @@ impl TraceWriter {
             cpuid_records: vec![],
             version_fd: ScopedFd::new(),
             supports_file_data_cloning_: false,
+            inherited_state: InheritedState::capture(),
+            encryption_key,
         };
 
         tw.bind_to_cpu = bind_to_cpu;
@@ -595,7 +652,12 @@ impl TraceWriter {
         for &s in Substream::iter() {
             tw.writers.insert(
                 s,
-                CompressedWriter::new(&tw.path(s), substream(s).block_size, substream(s).threads),
+                CompressedWriter::new_with_checksums(
+                    &tw.path(s),
+                    substream(s).block_size,
+                    substream(s).threads,
+                    tw.encryption_key.clone(),
+                ),
             );
         }
 
@@ -706,6 +768,7 @@ impl TraceWriter {
         ));
         header.set_syscallbuf_protocol_version(SYSCALLBUF_PROTOCOL_VERSION);
         header.set_preload_thread_locals_recorded(true);
+        header.set_rrcall_base(SYS_rdcall_init_preload as i32);
         // Add a random UUID to the trace metadata. This lets tools identify a trace
         // easily.
         match maybe_uuid {
@@ -717,6 +780,14 @@ impl TraceWriter {
             }
         }
         header.set_ok(status == CloseStatus::CloseOk);
+
+        let rd_header_msg = self.rd_header();
+        let mut rd_header_bytes = Vec::new();
+        write_message(&mut rd_header_bytes, &rd_header_msg).unwrap();
+        let rd_header_path = self.rd_header_path();
+        if fs::write(&rd_header_path, &rd_header_bytes).is_err() {
+            fatal!("Unable to write {:?}", rd_header_path);
+        }
         let mut f = unsafe { File::from_raw_fd(self.version_fd.as_raw()) };
         if write_message(&mut f, &header_msg).is_err() {
             fatal!("Unable to write {:?}", self.incomplete_version_path());
@@ -730,6 +801,23 @@ impl TraceWriter {
         self.version_fd.close();
     }
 
+    /// rd's additions to the trace header, see `RdHeader` in the schema.
+    fn rd_header(&self) -> message::Builder<message::HeapAllocator> {
+        let mut msg = message::Builder::new_default();
+        let mut header = msg.init_root::<rd_header::Builder>();
+        header.set_syscallbuf_layout_hash(syscallbuf_layout_hash());
+        self.inherited_state
+            .write_to(header.reborrow().init_inherited_state());
+        header.set_vsyscall_mode(to_trace_vsyscall_mode(host_vsyscall_mode()));
+        header.set_hypervisor(to_trace_hypervisor(host_hypervisor()));
+        header.set_cgroup(&CgroupContext::current().to_string());
+        header.set_kernel_knobs(&KernelKnobs::read().to_string());
+        if let Some(key) = &self.encryption_key {
+            header.set_encryption_key_check(&key.key_check());
+        }
+        msg
+    }
+
     /// We got far enough into recording that we should set this as the latest
     /// trace.
     pub fn make_latest_trace(&self) {
@@ -940,3 +1028,22 @@ fn to_trace_ticks_semantics(semantics: TicksSemantics) -> TraceTicksSemantics {
         TicksSemantics::TicksTakenBranches => TraceTicksSemantics::TakenBranches,
     }
 }
+
+fn to_trace_hypervisor(hypervisor: Hypervisor) -> TraceHypervisor {
+    match hypervisor {
+        Hypervisor::None => TraceHypervisor::None,
+        Hypervisor::Kvm => TraceHypervisor::Kvm,
+        Hypervisor::HyperV => TraceHypervisor::HyperV,
+        Hypervisor::Xen => TraceHypervisor::Xen,
+        Hypervisor::VMware => TraceHypervisor::Vmware,
+        Hypervisor::Other => TraceHypervisor::Other,
+    }
+}
+
+fn to_trace_vsyscall_mode(mode: VsyscallMode) -> TraceVsyscallMode {
+    match mode {
+        VsyscallMode::Emulate => TraceVsyscallMode::Emulate,
+        VsyscallMode::XOnly => TraceVsyscallMode::Xonly,
+        VsyscallMode::None => TraceVsyscallMode::None,
+    }
+}
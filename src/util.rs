@@ -1,9 +1,17 @@
 use crate::{
     arch::Architecture,
     bindings::{
-        kernel::timeval,
+        kernel::{
+            timeval,
+            __user_cap_data_struct,
+            __user_cap_header_struct,
+            _LINUX_CAPABILITY_U32S_3,
+            _LINUX_CAPABILITY_VERSION_3,
+        },
+        ptrace::PTRACE_SYSEMU,
         signal::{SI_KERNEL, TRAP_BRKPT},
     },
+    cgroup::CgroupContext,
     event::{Event, EventType},
     flags::{DumpOn, Flags},
     kernel_abi::CloneParameterOrdering,
@@ -30,6 +38,7 @@ use libc::{
     syscall,
     ucontext_t,
     SYS_arch_prctl,
+    SYS_capget,
     CLONE_CHILD_CLEARTID,
     CLONE_CHILD_SETTID,
     CLONE_FILES,
@@ -127,11 +136,16 @@ pub const OSXSAVE_FEATURE_FLAG: u32 = 1 << 27;
 pub const AVX_FEATURE_FLAG: u32 = 1 << 28;
 pub const HLE_FEATURE_FLAG: u32 = 1 << 4;
 pub const XSAVEC_FEATURE_FLAG: u32 = 1 << 1;
+/// CPUID_GETEXTENDEDFEATURES ecx: the CPU has protection keys and the kernel
+/// enabled them.
+pub const OSPKE_FEATURE_FLAG: u32 = 1 << 4;
 
 lazy_static! {
     static ref CPUID_FAULTING_WORKS: bool = cpuid_faulting_works_init();
+    static ref SYSEMU_SUPPORTED: bool = sysemu_supported_init();
     static ref XSAVE_NATIVE_LAYOUT: XSaveLayout = xsave_native_layout_init();
     static ref SYSTEM_PAGE_SIZE: usize = page_size_init();
+    static ref DEFAULT_HUGE_PAGE_SIZE: usize = default_huge_page_size_init();
     static ref SAVED_FD_LIMIT: Mutex<Option<libc::rlimit>> = Mutex::new(None);
 }
 
@@ -216,6 +230,8 @@ pub enum Completion {
 pub struct XSaveFeatureLayout {
     pub offset: u32,
     pub size: u32,
+    /// Starts on a 64 byte boundary in the compacted format.
+    pub align_64: bool,
 }
 
 #[derive(Default, Clone)]
@@ -306,11 +322,12 @@ pub fn xsave_layout_from_trace(records: &[CPUIDRecord]) -> XSaveLayout {
             while layout.feature_layouts.len() < i {
                 layout
                     .feature_layouts
-                    .push(XSaveFeatureLayout { offset: 0, size: 0 });
+                    .push(XSaveFeatureLayout::default());
             }
             layout.feature_layouts.push(XSaveFeatureLayout {
                 offset: cpuid_data.out.ebx,
                 size: cpuid_data.out.eax,
+                align_64: cpuid_data.out.ecx & 2 != 0,
             });
         }
     }
@@ -464,6 +481,46 @@ pub fn ceil_page_u64(size: u64) -> u64 {
     (size + page_size() as u64 - 1) & !(page_size() as u64 - 1)
 }
 
+/// mmap() flags encode the log2 of the page size of a MAP_HUGETLB mapping in
+/// these bits. 0 means the default huge page size.
+const MAP_HUGE_SHIFT: i32 = 26;
+const MAP_HUGE_MASK: i32 = 0x3f;
+
+fn default_huge_page_size_init() -> usize {
+    // Every x86 machine has 2MB huge pages.
+    read_meminfo_field("Hugepagesize").map_or(2 << 20, |kb| kb * 1024)
+}
+
+/// The page size of a MAP_HUGETLB mapping made with mmap() flags `flags_raw`.
+pub fn huge_page_size(flags_raw: i32) -> usize {
+    let log2 = (flags_raw >> MAP_HUGE_SHIFT) & MAP_HUGE_MASK;
+    if log2 != 0 {
+        1 << log2
+    } else {
+        *DEFAULT_HUGE_PAGE_SIZE
+    }
+}
+
+/// The kernel rounds the length of a MAP_HUGETLB mapping up to whole huge
+/// pages, not just whole pages.
+pub fn ceil_huge_page_size(size: usize, flags_raw: i32) -> usize {
+    let huge_page_size = huge_page_size(flags_raw);
+    (size + huge_page_size - 1) & !(huge_page_size - 1)
+}
+
+/// The number on the `key:` line of /proc/meminfo. Sizes are in kB.
+pub fn read_meminfo_field(key: &str) -> Option<usize> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    meminfo.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        if fields.next()?.strip_suffix(':')? == key {
+            fields.next()?.parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
 pub fn floor_page_size<T: Into<usize> + From<usize>>(sz: T) -> T {
     let page_mask: usize = !(page_size() - 1);
     (sz.into() & page_mask).into()
@@ -908,25 +965,6 @@ pub fn real_path(path: &OsStr) -> OsString {
         .to_os_string()
 }
 
-pub fn resource_path() -> &'static OsStr {
-    let resource_path = Flags::get().resource_path.as_ref();
-    if resource_path.is_none() {
-        return RD_EXE_PATH.as_os_str();
-    }
-
-    resource_path.unwrap().as_os_str()
-}
-
-lazy_static! {
-    static ref RD_EXE_PATH: OsString = rd_exe_path_init();
-}
-
-fn rd_exe_path_init() -> OsString {
-    let mut exe_path = Vec::from(read_exe_dir().as_bytes());
-    exe_path.extend_from_slice(b"../");
-    OsString::from_vec(exe_path)
-}
-
 pub fn read_exe_dir() -> OsString {
     // Get the mapping corresponding to the `read_exe_dir` method i.e. the method we're in!
     let km: KernelMapping = AddressSpace::read_local_kernel_mapping(
@@ -1224,8 +1262,26 @@ pub fn choose_cpu(bind_cpu: BindCPU) -> Option<u32> {
         // performance win in certain circumstances,
         // presumably due to cheaper context switching and/or
         // better interaction with CPU frequency scaling.
-        BindCPU::BindToCPU(num) => Some(num),
+        BindCPU::BindToCPU(num) => {
+            let cgroup = CgroupContext::current();
+            if cgroup.allows_cpu(num) {
+                return Some(num);
+            }
+            let maybe_cpu = choose_cpu(BindCPU::RandomCPU);
+            log!(
+                LogWarn,
+                "CPU {} isn't in our cgroup's cpuset ({}); binding to {:?} instead",
+                num,
+                cgroup,
+                maybe_cpu
+            );
+            maybe_cpu
+        }
         BindCPU::RandomCPU => {
+            // This covers cgroup v2, and v1 where it's mounted in the usual place.
+            if let Some(cpus) = CgroupContext::current().cpus {
+                return Some(cpus[random::<usize>() % cpus.len()]);
+            }
             let maybe_cpu = get_random_cpu_cgroup();
             match maybe_cpu {
                 Ok(cpu) => Some(cpu),
@@ -1515,6 +1571,54 @@ pub fn cpuid_faulting_works() -> bool {
     *CPUID_FAULTING_WORKS
 }
 
+fn sysemu_supported_init() -> bool {
+    // PTRACE_SYSEMU is only accepted for a stopped tracee, so make one. The
+    // kernel answers EIO for ptrace requests it doesn't implement.
+    let child = unsafe { libc::fork() };
+    if child < 0 {
+        fatal!("fork() failed while probing for PTRACE_SYSEMU");
+    }
+    if child == 0 {
+        unsafe {
+            libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0);
+            libc::raise(libc::SIGSTOP);
+            libc::_exit(0);
+        }
+    }
+
+    let mut raw_status: i32 = 0;
+    let ret = unsafe { libc::waitpid(child, &mut raw_status, 0) };
+    let supported = if ret == child && libc::WIFSTOPPED(raw_status) {
+        let ret = unsafe { libc::ptrace(PTRACE_SYSEMU, child, 0, 0) };
+        ret == 0 || errno() != libc::EIO
+    } else {
+        // Couldn't get a tracee to test with. Assume the common case.
+        true
+    };
+
+    unsafe {
+        libc::kill(child, libc::SIGKILL);
+        while libc::waitpid(child, &mut raw_status, 0) == child && libc::WIFSTOPPED(raw_status) {}
+    }
+    if supported {
+        log!(LogDebug, "PTRACE_SYSEMU supported");
+    } else {
+        log!(
+            LogWarn,
+            "PTRACE_SYSEMU not supported by kernel; replay will skip syscalls with PTRACE_SYSCALL"
+        );
+    }
+    supported
+}
+
+/// Whether the kernel implements PTRACE_SYSEMU and PTRACE_SYSEMU_SINGLESTEP
+/// for rd's native arch. When it doesn't, resuming with `ResumeSysemu*`
+/// falls back to `ResumeSyscall` and the syscall is skipped by rewriting
+/// registers. See `Task::resume_execution`.
+pub fn sysemu_supported() -> bool {
+    *SYSEMU_SUPPORTED
+}
+
 pub fn cpuid_compatible(trace_records: &[CPUIDRecord]) -> bool {
     // We could compare all CPUID records but that might be fragile (it's hard to
     // be sure the values don't change in ways applications don't care about).
@@ -1534,8 +1638,23 @@ pub fn cpuid_compatible(trace_records: &[CPUIDRecord]) -> bool {
     }
 }
 
-pub fn has_effective_caps(_caps: u64) -> bool {
-    unimplemented!()
+pub fn has_effective_caps(mut caps: u64) -> bool {
+    let mut header = __user_cap_header_struct {
+        version: _LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data: [__user_cap_data_struct; _LINUX_CAPABILITY_U32S_3 as usize] =
+        unsafe { zeroed() };
+    if 0 != unsafe { syscall(SYS_capget, &mut header, data.as_mut_ptr()) } {
+        fatal!("FAILED to read capabilities");
+    }
+    for d in &data {
+        if d.effective & caps as u32 != caps as u32 {
+            return false;
+        }
+        caps >>= 32;
+    }
+    true
 }
 
 pub fn should_dump_memory(event: &Event, time: FrameTime) -> bool {
@@ -0,0 +1,81 @@
+//! The legacy vsyscall page is a fixed page at the top of the x86-64 address
+//! space with entry points for `gettimeofday()`, `time()` and `getcpu()`. Only
+//! old statically linked binaries still call it. Depending on the kernel's
+//! `vsyscall=` boot parameter it is
+//! - `emulate`: readable and executable; calls trap into the kernel which
+//!   emulates the syscall (subject to seccomp, so we record them as syscalls)
+//! - `xonly`: like `emulate` but not readable
+//! - `none`: not mapped; calls raise SIGSEGV
+//!
+//! A trace recorded with vsyscalls available replays on a `vsyscall=none`
+//! machine by treating the SIGSEGV at a vsyscall entry point as the entry to
+//! the recorded syscall. See `ReplaySession::handle_vsyscall_fault()`.
+
+use crate::{
+    kernel_abi::{
+        syscall_number_for_getcpu,
+        syscall_number_for_gettimeofday,
+        syscall_number_for_time,
+        SupportedArch,
+    },
+    remote_code_ptr::RemoteCodePtr,
+};
+use std::{fmt, fmt::Display, fs::read_to_string};
+
+pub const VSYSCALL_PAGE_START: usize = 0xffff_ffff_ff60_0000;
+
+const VSYSCALL_GETTIMEOFDAY_OFFSET: usize = 0x0;
+const VSYSCALL_TIME_OFFSET: usize = 0x400;
+const VSYSCALL_GETCPU_OFFSET: usize = 0x800;
+
+lazy_static! {
+    static ref HOST_VSYSCALL_MODE: VsyscallMode = detect_vsyscall_mode();
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum VsyscallMode {
+    Emulate,
+    XOnly,
+    None,
+}
+
+impl Display for VsyscallMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VsyscallMode::Emulate => write!(f, "emulate"),
+            VsyscallMode::XOnly => write!(f, "xonly"),
+            VsyscallMode::None => write!(f, "none"),
+        }
+    }
+}
+
+/// The vsyscall mode of the kernel we're running on.
+pub fn host_vsyscall_mode() -> VsyscallMode {
+    *HOST_VSYSCALL_MODE
+}
+
+/// Our own mappings show what every process gets: there's no per-process
+/// setting.
+fn detect_vsyscall_mode() -> VsyscallMode {
+    let maps = read_to_string("/proc/self/maps").unwrap_or_default();
+    match maps.lines().find(|l| l.ends_with("[vsyscall]")) {
+        None => VsyscallMode::None,
+        // e.g. "ffffffffff600000-ffffffffff601000 --xp 00000000 00:00 0 [vsyscall]"
+        Some(line) if line.split_whitespace().nth(1) == Some("--xp") => VsyscallMode::XOnly,
+        Some(_) => VsyscallMode::Emulate,
+    }
+}
+
+/// If `ip` is one of the vsyscall entry points, the syscall the kernel would
+/// emulate for it.
+pub fn vsyscall_entry_syscallno(arch: SupportedArch, ip: RemoteCodePtr) -> Option<i32> {
+    if arch != SupportedArch::X64 {
+        return None;
+    }
+    match ip.as_usize().wrapping_sub(VSYSCALL_PAGE_START) {
+        VSYSCALL_GETTIMEOFDAY_OFFSET => Some(syscall_number_for_gettimeofday(arch)),
+        VSYSCALL_TIME_OFFSET => Some(syscall_number_for_time(arch)),
+        VSYSCALL_GETCPU_OFFSET => Some(syscall_number_for_getcpu(arch)),
+        _ => None,
+    }
+}
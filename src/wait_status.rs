@@ -1,5 +1,5 @@
 use crate::{
-    bindings::ptrace::{PTRACE_EVENT_STOP, PTRACE_O_TRACESYSGOOD},
+    bindings::ptrace::{PTRACE_EVENT_EXIT, PTRACE_EVENT_STOP, PTRACE_O_TRACESYSGOOD},
     kernel_metadata::{ptrace_event_name, signal_name},
     session::task::record_task::record_task::RecordTask,
 };
@@ -31,6 +31,18 @@ pub enum WaitType {
     PtraceEvent,
 }
 
+/// How we learn that a task is dying.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TaskDeath {
+    /// A PTRACE_EVENT_EXIT stop. The task can still be inspected and must be
+    /// reaped later.
+    ExitEvent,
+    /// The task's exit status. This is all we get without PTRACE_O_TRACEEXIT
+    /// (see `Flags::disable_ptrace_exit_events`), and sometimes even with it
+    /// for SIGKILLed tasks. The waitpid() that returned it reaped the task.
+    Reaped,
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct WaitStatus {
     status: i32,
@@ -189,6 +201,28 @@ impl WaitStatus {
         }
     }
 
+    /// The status a shell reports for a process that ended like this: its exit
+    /// code, or 128 + the signal number if a signal killed it. None if the
+    /// process didn't end.
+    pub fn shell_exit_code(&self) -> Option<i32> {
+        match (self.exit_code(), self.fatal_sig()) {
+            (Some(code), _) => Some(code as i32),
+            (None, Some(sig)) => Some(128 + sig),
+            (None, None) => None,
+        }
+    }
+
+    /// None if this status doesn't mean the task is dying.
+    pub fn task_death(&self) -> Option<TaskDeath> {
+        if self.exit_code().is_some() || self.fatal_sig().is_some() {
+            Some(TaskDeath::Reaped)
+        } else if self.maybe_ptrace_event() == PTRACE_EVENT_EXIT {
+            Some(TaskDeath::ExitEvent)
+        } else {
+            None
+        }
+    }
+
     /// Return a WaitStatus for a process exit.
     pub fn for_exit_code(code: i32) -> WaitStatus {
         debug_assert!(code >= 0 && code < 0x100);
@@ -386,3 +420,38 @@ impl Display for MaybeStopSignal {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libc::{SIGKILL, SIGSEGV};
+
+    #[test]
+    fn task_death_with_exit_events() {
+        let status = WaitStatus::for_ptrace_event(PTRACE_EVENT_EXIT);
+        assert_eq!(Some(TaskDeath::ExitEvent), status.task_death());
+        assert_eq!(None, status.exit_code());
+    }
+
+    #[test]
+    fn task_death_without_exit_events() {
+        assert_eq!(
+            Some(TaskDeath::Reaped),
+            WaitStatus::for_exit_code(0).task_death()
+        );
+        assert_eq!(
+            Some(TaskDeath::Reaped),
+            WaitStatus::for_exit_code(3).task_death()
+        );
+        assert_eq!(
+            Some(TaskDeath::Reaped),
+            WaitStatus::for_fatal_sig(SIGKILL).task_death()
+        );
+    }
+
+    #[test]
+    fn task_death_not_dying() {
+        assert_eq!(None, WaitStatus::for_stop_sig(SIGSEGV).task_death());
+        assert_eq!(None, WaitStatus::for_stop_sig(SIGSTOP).task_death());
+    }
+}
@@ -1,10 +1,12 @@
 use crate::log::LogLevel::LogDebug;
 use std::{
     cell::RefCell,
-    collections::{hash_set::Iter, HashSet},
+    collections::HashMap,
     hash::{Hash, Hasher},
+    iter::Flatten,
     ops::Deref,
     rc::{Rc, Weak},
+    slice,
 };
 
 pub struct WeakPtrWrap<T>(pub Weak<RefCell<T>>);
@@ -45,56 +47,87 @@ impl<T> Deref for WeakPtrWrap<T> {
     }
 }
 
-impl<T> Deref for WeakPtrSet<T> {
-    type Target = HashSet<WeakPtrWrap<T>>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
+/// A set of weak pointers that iterates in insertion order, so that walking
+/// e.g. the tasks of a thread group visits them in the same order in every run
+/// of rd. Erasing leaves a hole that iteration skips; holes are compacted away
+/// once they outnumber the members, so insert and erase are amortized O(1).
+pub struct WeakPtrSet<T> {
+    entries: Vec<Option<WeakPtrWrap<T>>>,
+    /// Index into `entries` of each member.
+    index: HashMap<WeakPtrWrap<T>, usize>,
 }
 
-pub struct WeakPtrSet<T>(HashSet<WeakPtrWrap<T>>);
-
 impl<T> Clone for WeakPtrSet<T> {
     fn clone(&self) -> Self {
-        WeakPtrSet(self.0.clone())
+        WeakPtrSet {
+            entries: self.entries.clone(),
+            index: self.index.clone(),
+        }
     }
 }
 
 impl<T> WeakPtrSet<T> {
     pub fn new() -> WeakPtrSet<T> {
-        WeakPtrSet(HashSet::new())
+        WeakPtrSet {
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
     }
-    pub fn inner_hashset(&self) -> &HashSet<WeakPtrWrap<T>> {
-        &self.0
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
     }
     pub fn iter(&self) -> SetIterator<T> {
         self.into_iter()
     }
     pub fn iter_except(&self, tw: Weak<RefCell<T>>) -> ExceptSetIterator<T> {
         ExceptSetIterator {
-            hash_set_iterator: self.0.iter(),
+            entries_iterator: self.entries.iter().flatten(),
             except: tw,
         }
     }
     pub fn iter_except_vec(&self, tw_vec: Vec<Weak<RefCell<T>>>) -> ExceptVecSetIterator<T> {
         ExceptVecSetIterator {
-            hash_set_iterator: self.0.iter(),
+            entries_iterator: self.entries.iter().flatten(),
             except: tw_vec,
         }
     }
 
     pub fn insert(&mut self, t: Weak<RefCell<T>>) -> bool {
         log!(LogDebug, "adding a task to task set {:?}", t.as_ptr());
-        self.0.insert(WeakPtrWrap(t))
+        let w = WeakPtrWrap(t);
+        if self.index.contains_key(&w) {
+            return false;
+        }
+        self.index.insert(w.clone(), self.entries.len());
+        self.entries.push(Some(w));
+        true
     }
 
     pub fn erase(&mut self, t: Weak<RefCell<T>>) -> bool {
         log!(LogDebug, "removing a task from task set {:?}", t.as_ptr());
-        self.0.remove(&WeakPtrWrap(t))
+        match self.index.remove(&WeakPtrWrap(t)) {
+            Some(i) => {
+                self.entries[i] = None;
+                if self.entries.len() > 2 * self.index.len() {
+                    self.compact();
+                }
+                true
+            }
+            None => false,
+        }
     }
     pub fn has(&self, t: Weak<RefCell<T>>) -> bool {
-        self.0.contains(&WeakPtrWrap(t))
+        self.index.contains_key(&WeakPtrWrap(t))
+    }
+
+    fn compact(&mut self) {
+        self.entries.retain(Option::is_some);
+        for (i, w) in self.entries.iter().flatten().enumerate() {
+            *self.index.get_mut(w).unwrap() = i;
+        }
     }
 }
 
@@ -104,22 +137,24 @@ impl<'a, T> IntoIterator for &'a WeakPtrSet<T> {
 
     fn into_iter(self) -> Self::IntoIter {
         SetIterator {
-            hash_set_iterator: self.0.iter(),
+            entries_iterator: self.entries.iter().flatten(),
         }
     }
 }
 
+type EntriesIter<'a, T> = Flatten<slice::Iter<'a, Option<WeakPtrWrap<T>>>>;
+
 pub struct SetIterator<'a, T> {
-    hash_set_iterator: Iter<'a, WeakPtrWrap<T>>,
+    entries_iterator: EntriesIter<'a, T>,
 }
 
 pub struct ExceptSetIterator<'a, T> {
-    hash_set_iterator: Iter<'a, WeakPtrWrap<T>>,
+    entries_iterator: EntriesIter<'a, T>,
     except: Weak<RefCell<T>>,
 }
 
 pub struct ExceptVecSetIterator<'a, T> {
-    hash_set_iterator: Iter<'a, WeakPtrWrap<T>>,
+    entries_iterator: EntriesIter<'a, T>,
     except: Vec<Weak<RefCell<T>>>,
 }
 
@@ -127,7 +162,7 @@ impl<T> Iterator for SetIterator<'_, T> {
     type Item = Rc<RefCell<T>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.hash_set_iterator.next().map(|t| t.upgrade().unwrap())
+        self.entries_iterator.next().map(|t| t.upgrade().unwrap())
     }
 }
 
@@ -135,7 +170,7 @@ impl<T> Iterator for ExceptSetIterator<'_, T> {
     type Item = Rc<RefCell<T>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(WeakPtrWrap(it)) = self.hash_set_iterator.next() {
+        while let Some(WeakPtrWrap(it)) = self.entries_iterator.next() {
             if it.ptr_eq(&self.except) {
                 continue;
             } else {
@@ -150,7 +185,7 @@ impl<T> Iterator for ExceptVecSetIterator<'_, T> {
     type Item = Rc<RefCell<T>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(WeakPtrWrap(it)) = self.hash_set_iterator.next() {
+        while let Some(WeakPtrWrap(it)) = self.entries_iterator.next() {
             if self.except.iter().any(|v| v.ptr_eq(it)) {
                 continue;
             } else {
@@ -163,6 +198,50 @@ impl<T> Iterator for ExceptVecSetIterator<'_, T> {
 
 impl<T> Default for WeakPtrSet<T> {
     fn default() -> Self {
-        WeakPtrSet(Default::default())
+        WeakPtrSet::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WeakPtrSet;
+    use std::{cell::RefCell, rc::Rc};
+
+    fn values(set: &WeakPtrSet<u32>) -> Vec<u32> {
+        set.iter().map(|v| *v.borrow()).collect()
+    }
+
+    #[test]
+    pub fn test_iterates_in_insertion_order() {
+        let items: Vec<Rc<RefCell<u32>>> = (0..100).map(|i| Rc::new(RefCell::new(i))).collect();
+        let mut set = WeakPtrSet::new();
+        for item in items.iter().rev() {
+            assert!(set.insert(Rc::downgrade(item)));
+        }
+        assert!(!set.insert(Rc::downgrade(&items[0])));
+        assert_eq!(values(&set), (0..100).rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn test_erase_keeps_order() {
+        let items: Vec<Rc<RefCell<u32>>> = (0..100).map(|i| Rc::new(RefCell::new(i))).collect();
+        let mut set = WeakPtrSet::new();
+        for item in &items {
+            set.insert(Rc::downgrade(item));
+        }
+        // Erase enough to compact the set at least once.
+        for item in items.iter().filter(|i| *i.borrow() % 4 != 0) {
+            assert!(set.erase(Rc::downgrade(item)));
+        }
+        assert!(!set.erase(Rc::downgrade(&items[1])));
+        assert_eq!(set.len(), 25);
+        assert!(set.has(Rc::downgrade(&items[96])));
+        assert!(!set.has(Rc::downgrade(&items[97])));
+        assert_eq!(values(&set), (0..100).step_by(4).collect::<Vec<_>>());
+        let except: Vec<u32> = set
+            .iter_except(Rc::downgrade(&items[4]))
+            .map(|v| *v.borrow())
+            .collect();
+        assert_eq!(except[..2], [0, 8]);
     }
 }